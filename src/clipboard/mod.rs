@@ -1,3 +1,144 @@
 pub mod persist;
+pub mod state;
 
-pub use persist::{ClipboardData, persist_clipboard};
+pub use persist::{ClipboardData, PersistenceResult, persist_clipboard};
+use serde::{Deserialize, Serialize};
+use wl_clipboard_rs::{
+  copy::{self, MimeSource, MimeType, Source},
+  paste,
+};
+
+use crate::db::Representation;
+
+/// Which Wayland selection an entry was copied to.
+#[derive(
+  Debug,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  Default,
+  clap::ValueEnum,
+  Serialize,
+  Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Selection {
+  /// The regular clipboard (Ctrl+C / Ctrl+V).
+  #[default]
+  Regular,
+
+  /// The primary selection (select / middle-click paste).
+  Primary,
+}
+
+impl Selection {
+  /// Name stored in the `selection` column.
+  pub const fn as_str(self) -> &'static str {
+    match self {
+      Self::Regular => "regular",
+      Self::Primary => "primary",
+    }
+  }
+
+  /// Parse a stored `selection` value. Unknown values fall back to the
+  /// regular clipboard.
+  pub fn from_db(value: &str) -> Self {
+    match value {
+      "primary" => Self::Primary,
+      _ => Self::Regular,
+    }
+  }
+
+  pub const fn copy_type(self) -> copy::ClipboardType {
+    match self {
+      Self::Regular => copy::ClipboardType::Regular,
+      Self::Primary => copy::ClipboardType::Primary,
+    }
+  }
+
+  pub const fn paste_type(self) -> paste::ClipboardType {
+    match self {
+      Self::Regular => paste::ClipboardType::Regular,
+      Self::Primary => paste::ClipboardType::Primary,
+    }
+  }
+}
+
+/// Copy a stored entry to `selection`, offering all of its MIME types, and
+/// keep serving it from a forked background process so the copy outlives the
+/// calling process.
+pub fn copy_detached(
+  content: Vec<u8>,
+  mime: Option<String>,
+  representations: Vec<Representation>,
+  selection: Selection,
+) -> PersistenceResult<()> {
+  let selected_mime =
+    mime.unwrap_or_else(|| "application/octet-stream".to_owned());
+  let mut mime_types = vec![selected_mime.clone()];
+  mime_types.extend(representations.iter().map(|rep| rep.mime.clone()));
+  persist_clipboard(
+    ClipboardData::new(content, mime_types, selected_mime)
+      .with_representations(representations)
+      .with_selection(selection),
+  )
+}
+
+/// Build the set of offers for re-copying an entry: `content` under its
+/// stored `mime` first, followed by every additional representation.
+///
+/// `text/*` content is offered as [`MimeType::Text`] so the usual plain text
+/// aliases (`UTF8_STRING`, `text/plain;charset=utf-8`, ...) are offered as
+/// well; unknown content falls back to autodetection.
+pub fn mime_sources(
+  content: Vec<u8>,
+  mime: Option<&str>,
+  representations: Vec<Representation>,
+) -> Vec<MimeSource> {
+  let mime_type = match mime {
+    Some(m) if m.starts_with("text/") => MimeType::Text,
+    Some(m) => MimeType::Specific(m.to_owned()),
+    None => MimeType::Autodetect,
+  };
+
+  let mut sources = Vec::with_capacity(representations.len() + 1);
+  sources.push(MimeSource {
+    source: Source::Bytes(content.into()),
+    mime_type,
+  });
+  sources.extend(representations.into_iter().map(|rep| {
+    MimeSource {
+      source:    Source::Bytes(rep.contents.into()),
+      mime_type: MimeType::Specific(rep.mime),
+    }
+  }));
+  sources
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_mime_sources_offer_entry_first() {
+    let sources = mime_sources(b"bold".to_vec(), Some("text/plain"), vec![
+      Representation {
+        mime:     "text/html".to_string(),
+        contents: b"<b>bold</b>".to_vec(),
+      },
+    ]);
+    let types: Vec<&MimeType> = sources.iter().map(|s| &s.mime_type).collect();
+    assert_eq!(types, vec![
+      &MimeType::Text,
+      &MimeType::Specific("text/html".to_string())
+    ]);
+
+    let sources = mime_sources(b"a,b".to_vec(), Some("text/csv"), Vec::new());
+    assert_eq!(sources[0].mime_type, MimeType::Text);
+
+    let sources = mime_sources(b"\x89PNG".to_vec(), None, Vec::new());
+    assert_eq!(sources.len(), 1);
+    assert_eq!(sources[0].mime_type, MimeType::Autodetect);
+  }
+}
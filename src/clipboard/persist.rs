@@ -3,22 +3,19 @@ use std::{
   sync::atomic::{AtomicI32, Ordering},
 };
 
-use wl_clipboard_rs::copy::{
-  ClipboardType,
-  MimeType as CopyMimeType,
-  Options,
-  PreparedCopy,
-  ServeRequests,
-  Source,
-};
+use wl_clipboard_rs::copy::{Options, PreparedCopy, ServeRequests};
+
+use super::Selection;
+use crate::db::Representation;
 
 /// Maximum number of paste requests to serve before exiting. This (hopefully)
 /// prevents runaway processes while still providing persistence.
 const MAX_SERVE_REQUESTS: usize = 1000;
 
-/// PID of the current clipboard persistence child process. Used to detect when
-/// clipboard content is from our own serve process.
-static SERVING_PID: AtomicI32 = AtomicI32::new(0);
+/// PIDs of the current clipboard persistence child processes, one per
+/// [`Selection`]. Used to detect when clipboard content is from our own serve
+/// process.
+static SERVING_PID: [AtomicI32; 2] = [AtomicI32::new(0), AtomicI32::new(0)];
 
 /// Result type for persistence operations.
 pub type PersistenceResult<T> = Result<T, PersistenceError>;
@@ -53,6 +50,13 @@ pub struct ClipboardData {
 
   /// The MIME type that was selected for storage.
   pub selected_mime: String,
+
+  /// Contents of the other offered MIME types that were captured, served
+  /// alongside `content`.
+  pub representations: Vec<Representation>,
+
+  /// The selection the content was read from, and is served on.
+  pub selection: Selection,
 }
 
 impl ClipboardData {
@@ -66,9 +70,26 @@ impl ClipboardData {
       content,
       mime_types,
       selected_mime,
+      representations: Vec::new(),
+      selection: Selection::Regular,
     }
   }
 
+  /// Serve on `selection` instead of the regular clipboard.
+  pub fn with_selection(mut self, selection: Selection) -> Self {
+    self.selection = selection;
+    self
+  }
+
+  /// Also serve the given additional representations.
+  pub fn with_representations(
+    mut self,
+    representations: Vec<Representation>,
+  ) -> Self {
+    self.representations = representations;
+    self
+  }
+
   /// Check if data is valid for persistence.
   pub fn is_valid(&self) -> Result<(), PersistenceError> {
     const MAX_SIZE: usize = 100 * 1024 * 1024; // 100MB
@@ -91,7 +112,7 @@ impl ClipboardData {
 
 /// Persist clipboard data by forking a background process that serves it.
 ///
-/// 1. Prepares a clipboard copy operation with the selected MIME type
+/// 1. Prepares a clipboard copy operation offering every captured MIME type
 /// 2. Forks a child process
 /// 3. The child serves clipboard data indefinitely (until MAX_SERVE_REQUESTS)
 /// 4. The parent returns immediately
@@ -103,28 +124,28 @@ pub fn persist_clipboard(data: ClipboardData) -> PersistenceResult<()> {
   let prepared = prepare_clipboard_copy(&data)?;
 
   // Fork and serve
-  fork_and_serve(prepared)
+  fork_and_serve(prepared, data.selection)
 }
 
-/// Prepare a clipboard copy operation with the selected MIME type.
+/// Prepare a clipboard copy operation offering the selected MIME type and all
+/// captured representations.
 fn prepare_clipboard_copy(
   data: &ClipboardData,
 ) -> PersistenceResult<PreparedCopy> {
   let mut opts = Options::new();
-  opts.clipboard(ClipboardType::Regular);
+  opts.clipboard(data.selection.copy_type());
   opts.serve_requests(ServeRequests::Only(MAX_SERVE_REQUESTS));
   opts.foreground(true); // we'll fork manually for better control
 
-  // Determine MIME type for the primary offer
-  let mime_type = if data.selected_mime.starts_with("text/") {
-    CopyMimeType::Text
-  } else {
-    CopyMimeType::Specific(data.selected_mime.clone())
-  };
+  let sources = super::mime_sources(
+    data.content.clone(),
+    Some(&data.selected_mime),
+    data.representations.clone(),
+  );
 
   // Prepare the copy
   let prepared = opts
-    .prepare_copy(Source::Bytes(data.content.clone().into()), mime_type)
+    .prepare_copy_multi(sources)
     .map_err(|e| PersistenceError::PrepareFailed(e.to_string()))?;
 
   Ok(prepared)
@@ -139,7 +160,12 @@ fn prepare_clipboard_copy(
 /// 3. Exit cleanly
 ///
 /// The parent stores the child `PID` in `SERVING_PID` and returns immediately.
-fn fork_and_serve(prepared: PreparedCopy) -> PersistenceResult<()> {
+fn fork_and_serve(
+  prepared: PreparedCopy,
+  selection: Selection,
+) -> PersistenceResult<()> {
+  let serving_pid = &SERVING_PID[selection as usize];
+
   // Enable automatic child reaping to prevent zombie processes
   // SAFETY: installing SIG_IGN for SIGCHLD is process-global and intentional
   // for this helper, which only needs fire-and-forget clipboard children.
@@ -151,7 +177,7 @@ fn fork_and_serve(prepared: PreparedCopy) -> PersistenceResult<()> {
   // old offer (the compositor will invalidate it anyway the moment the new
   // selection is taken). Without this, the old child lingers serving stale
   // data until MAX_SERVE_REQUESTS or invalidation.
-  let prior = SERVING_PID.swap(0, Ordering::SeqCst);
+  let prior = serving_pid.swap(0, Ordering::SeqCst);
   // SAFETY: signal 0 checks process existence without delivering a signal.
   if prior > 0 && unsafe { libc::kill(prior, 0) } == 0 {
     // SAFETY: `prior` is a live child PID recorded by this process.
@@ -166,7 +192,7 @@ fn fork_and_serve(prepared: PreparedCopy) -> PersistenceResult<()> {
   match unsafe { libc::fork() } {
     0 => {
       // Child process - clear serving PID
-      SERVING_PID.store(0, Ordering::SeqCst);
+      serving_pid.store(0, Ordering::SeqCst);
       serve_clipboard_child(prepared);
       exit(0);
     },
@@ -180,7 +206,7 @@ fn fork_and_serve(prepared: PreparedCopy) -> PersistenceResult<()> {
     pid => {
       // Parent process, store child PID for loop detection
       log::debug!("forked clipboard persistence process (pid: {pid})");
-      SERVING_PID.store(pid, Ordering::SeqCst);
+      serving_pid.store(pid, Ordering::SeqCst);
       Ok(())
     },
   }
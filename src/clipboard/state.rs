@@ -0,0 +1,265 @@
+//! Markers applications put in a clipboard offer to keep it out of clipboard
+//! histories, and the `CLIPBOARD_STATE` values `wl-paste --watch` passes on
+//! for them.
+//!
+//! `stash watch` checks offers itself, Stash's `wl-paste --watch` sets
+//! `CLIPBOARD_STATE` (and `STASH_CLIPBOARD_STATE`) for the command it runs,
+//! and `stash store` reads those variables, so the same offers are kept out
+//! of the history whichever way entries are recorded.
+
+use std::{env, io::Read};
+
+use wl_clipboard_rs::paste::{self, MimeType};
+
+/// Types whose contents are a password manager hint, as offered by KeePassXC
+/// and other password managers. A value of `secret` marks the offer as
+/// sensitive.
+const PASSWORD_MANAGER_HINTS: &[&str] = &[
+  "x-kde-passwordManagerHint",
+  "application/x-kde-passwordManagerHint",
+];
+
+/// Types whose presence alone marks the offer as sensitive: the concealed
+/// types of Chromium and GNOME applications, after the nspasteboard.org
+/// conventions.
+const CONCEALED_TYPES: &[&str] = &[
+  "org.nspasteboard.ConcealedType",
+  "application/x-nspasteboard-concealed-type",
+  "x-gnome-concealed",
+];
+
+/// Types marking the offer as transient: it only stands in for an empty
+/// clipboard and is not worth recording.
+const TRANSIENT_TYPES: &[&str] =
+  &["x-kde-onlyReplaceEmpty", "org.nspasteboard.TransientType"];
+
+/// Longest password manager hint value that is read.
+const MAX_HINT_SIZE: u64 = 64;
+
+/// Clipboard state as passed to `wl-paste --watch` commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClipboardState {
+  /// Regular contents.
+  #[default]
+  Data,
+  /// The clipboard is empty.
+  Nil,
+  /// The clipboard was cleared.
+  Clear,
+  /// The contents are a password or other secret.
+  Sensitive,
+  /// The contents only stand in for an empty clipboard.
+  Transient,
+}
+
+impl ClipboardState {
+  pub const fn as_str(self) -> &'static str {
+    match self {
+      Self::Data => "data",
+      Self::Nil => "nil",
+      Self::Clear => "clear",
+      Self::Sensitive => "sensitive",
+      Self::Transient => "transient",
+    }
+  }
+
+  /// Parse a `CLIPBOARD_STATE` value. Unknown values are regular contents.
+  pub fn parse(value: &str) -> Self {
+    match value.trim() {
+      "nil" => Self::Nil,
+      "clear" => Self::Clear,
+      "sensitive" => Self::Sensitive,
+      "transient" => Self::Transient,
+      _ => Self::Data,
+    }
+  }
+
+  /// The state passed by `wl-paste --watch`: `STASH_CLIPBOARD_STATE`, or
+  /// `CLIPBOARD_STATE` as set by wl-clipboard's own `wl-paste`.
+  pub fn from_env() -> Self {
+    env::var("STASH_CLIPBOARD_STATE")
+      .or_else(|_| env::var("CLIPBOARD_STATE"))
+      .map(|value| Self::parse(&value))
+      .unwrap_or_default()
+  }
+
+  /// State of a non-empty offer of the `offered` types. `read_hint` fetches
+  /// the contents of an offered password manager hint; hints that cannot be
+  /// read are taken as `secret`.
+  pub fn of_offer(
+    offered: &[String],
+    mut read_hint: impl FnMut(&str) -> Option<Vec<u8>>,
+  ) -> Self {
+    for mime in offered {
+      if CONCEALED_TYPES.contains(&mime.as_str()) {
+        return Self::Sensitive;
+      }
+      if PASSWORD_MANAGER_HINTS.contains(&mime.as_str())
+        && read_hint(mime).is_none_or(|value| is_secret(&value))
+      {
+        return Self::Sensitive;
+      }
+    }
+    if offered
+      .iter()
+      .any(|mime| TRANSIENT_TYPES.contains(&mime.as_str()))
+    {
+      return Self::Transient;
+    }
+    Self::Data
+  }
+
+  /// Whether contents in this state belong in the clipboard history.
+  pub const fn is_recorded(self) -> bool {
+    matches!(self, Self::Data)
+  }
+}
+
+/// Whether `mime` is one of the markers rather than a representation of the
+/// contents, so it is neither picked nor stored.
+pub fn is_marker(mime: &str) -> bool {
+  PASSWORD_MANAGER_HINTS.contains(&mime)
+    || CONCEALED_TYPES.contains(&mime)
+    || TRANSIENT_TYPES.contains(&mime)
+}
+
+fn is_secret(value: &[u8]) -> bool {
+  String::from_utf8_lossy(value)
+    .trim_matches(|c: char| c.is_whitespace() || c == '\0')
+    .eq_ignore_ascii_case("secret")
+}
+
+/// Read the password manager hint `mime` from the clipboard, for
+/// [`ClipboardState::of_offer`].
+pub fn read_hint(
+  clipboard: paste::ClipboardType,
+  seat: paste::Seat<'_>,
+  mime: &str,
+) -> Option<Vec<u8>> {
+  let (reader, _) =
+    paste::get_contents(clipboard, seat, MimeType::Specific(mime)).ok()?;
+  let mut value = Vec::new();
+  reader.take(MAX_HINT_SIZE).read_to_end(&mut value).ok()?;
+  Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn offers(mimes: &[&str]) -> Vec<String> {
+    mimes.iter().map(|m| (*m).to_owned()).collect()
+  }
+
+  #[test]
+  fn test_marker_matrix() {
+    // Offered types, the hint value read back (None: unreadable), and the
+    // expected state.
+    type Case = (
+      &'static [&'static str],
+      Option<&'static [u8]>,
+      ClipboardState,
+    );
+    let cases: &[Case] = &[
+      (&["text/plain"], None, ClipboardState::Data),
+      (
+        &["text/plain", "x-kde-passwordManagerHint"],
+        Some(b"secret"),
+        ClipboardState::Sensitive,
+      ),
+      (
+        &["x-kde-passwordManagerHint", "text/plain"],
+        Some(b"secret\n"),
+        ClipboardState::Sensitive,
+      ),
+      (
+        &["text/plain", "application/x-kde-passwordManagerHint"],
+        Some(b"SECRET"),
+        ClipboardState::Sensitive,
+      ),
+      (
+        &["text/plain", "x-kde-passwordManagerHint"],
+        None,
+        ClipboardState::Sensitive,
+      ),
+      (
+        &["text/plain", "x-kde-passwordManagerHint"],
+        Some(b"public"),
+        ClipboardState::Data,
+      ),
+      (
+        &["text/plain", "org.nspasteboard.ConcealedType"],
+        None,
+        ClipboardState::Sensitive,
+      ),
+      (
+        &["text/plain", "application/x-nspasteboard-concealed-type"],
+        None,
+        ClipboardState::Sensitive,
+      ),
+      (
+        &["UTF8_STRING", "x-gnome-concealed"],
+        None,
+        ClipboardState::Sensitive,
+      ),
+      (
+        &["text/plain", "x-kde-onlyReplaceEmpty"],
+        None,
+        ClipboardState::Transient,
+      ),
+      (
+        &["text/plain", "org.nspasteboard.TransientType"],
+        None,
+        ClipboardState::Transient,
+      ),
+      (
+        &[
+          "x-kde-onlyReplaceEmpty",
+          "text/plain",
+          "x-kde-passwordManagerHint",
+        ],
+        Some(b"secret"),
+        ClipboardState::Sensitive,
+      ),
+    ];
+
+    for (offered, hint, expected) in cases {
+      let state = ClipboardState::of_offer(&offers(offered), |mime| {
+        assert!(PASSWORD_MANAGER_HINTS.contains(&mime), "read {mime}");
+        hint.map(<[u8]>::to_vec)
+      });
+      assert_eq!(state, *expected, "offer {offered:?} with hint {hint:?}");
+      assert_eq!(
+        state.is_recorded(),
+        *expected == ClipboardState::Data,
+        "{offered:?}"
+      );
+    }
+  }
+
+  #[test]
+  fn test_state_round_trips_through_env_values() {
+    for state in [
+      ClipboardState::Data,
+      ClipboardState::Nil,
+      ClipboardState::Clear,
+      ClipboardState::Sensitive,
+      ClipboardState::Transient,
+    ] {
+      assert_eq!(ClipboardState::parse(state.as_str()), state);
+    }
+    assert_eq!(ClipboardState::parse("something-new"), ClipboardState::Data);
+  }
+
+  #[test]
+  fn test_markers_are_not_contents() {
+    for marker in PASSWORD_MANAGER_HINTS
+      .iter()
+      .chain(CONCEALED_TYPES)
+      .chain(TRANSIENT_TYPES)
+    {
+      assert!(is_marker(marker), "{marker}");
+    }
+    assert!(!is_marker("text/plain"));
+  }
+}
@@ -0,0 +1,221 @@
+use std::io::{BufRead, BufReader, Read, Write};
+
+use crate::{
+  clipboard::Selection,
+  commands::decode,
+  db::{SearchQuery, StashError, extract_id},
+  ipc::{self, Response},
+};
+
+/// The commands a running watch daemon serves, run through its socket
+/// instead of opening the database.
+pub trait DaemonCommand {
+  /// Print what the watch daemon reports about itself.
+  fn daemon_status(&mut self, out: impl Write) -> Result<(), StashError>;
+
+  /// Print every new entry the watch daemon stores as a JSON line, until the
+  /// daemon exits.
+  fn print_new_entries(&mut self, out: impl Write) -> Result<(), StashError>;
+
+  /// `stash list` in TSV mode.
+  #[expect(
+    clippy::too_many_arguments,
+    reason = "list filters mirror the CLI options"
+  )]
+  fn list_previews(
+    &mut self,
+    out: impl Write,
+    preview_width: u32,
+    include_expired: bool,
+    reverse: bool,
+    pinned_first: bool,
+    selection: Option<Selection>,
+    tags: &[String],
+    search: Option<&SearchQuery>,
+  ) -> Result<(), StashError>;
+
+  /// `stash pin` and `stash unpin`.
+  fn pin_input(
+    &mut self,
+    input: impl Read,
+    pinned: bool,
+  ) -> Result<usize, StashError>;
+
+  /// `stash delete` with ids.
+  fn delete_input(&mut self, input: impl Read) -> Result<usize, StashError>;
+
+  /// `stash decode`.
+  fn decode_input(
+    &mut self,
+    in_: impl Read,
+    out: impl Write,
+    input: Option<String>,
+  ) -> Result<(), StashError>;
+
+  /// `stash decode --copy`.
+  fn copy_input(
+    &mut self,
+    in_: impl Read,
+    input: Option<String>,
+  ) -> Result<(), StashError>;
+}
+
+/// Connect to the watch daemon serving `db_path`, for commands that only it
+/// can answer.
+pub fn require_daemon(
+  db_path: &std::path::Path,
+) -> Result<ipc::Client, StashError> {
+  ipc::Client::connect(db_path).ok_or_else(|| {
+    StashError::Ipc(
+      format!("no watch daemon is serving {}", db_path.display()).into(),
+    )
+  })
+}
+
+fn write_err(e: impl ToString) -> StashError {
+  StashError::Ipc(e.to_string().into())
+}
+
+/// Ids at the start of the lines of `input`, skipping blank lines.
+fn input_ids(
+  input: impl Read,
+  input_err: fn(Box<str>) -> StashError,
+) -> impl Iterator<Item = Result<i64, StashError>> {
+  BufReader::new(input).lines().filter_map(move |line| {
+    match line {
+      Ok(line) if line.trim().is_empty() => None,
+      Ok(line) => Some(extract_id(&line).map_err(|e| input_err(e.into()))),
+      Err(e) => Some(Err(input_err(e.to_string().into()))),
+    }
+  })
+}
+
+impl DaemonCommand for ipc::Client {
+  fn daemon_status(&mut self, mut out: impl Write) -> Result<(), StashError> {
+    let status = self.status()?;
+    let selections: Vec<&str> =
+      status.selections.iter().map(|s| s.as_str()).collect();
+    writeln!(
+      out,
+      "pid: {}\nversion: {}\ndatabase: {}\nentries: {}\nselections: \
+       {}\npersist: {}\nsubscribers: {}",
+      status.pid,
+      status.version,
+      status.db_path.display(),
+      status.entries,
+      selections.join(", "),
+      status.persist,
+      status.subscribers,
+    )
+    .map_err(write_err)
+  }
+
+  fn print_new_entries(
+    &mut self,
+    mut out: impl Write,
+  ) -> Result<(), StashError> {
+    self.subscribe()?;
+    loop {
+      match self.next_response()? {
+        Response::NewEntry { entry } => {
+          let line = serde_json::to_string(&entry).map_err(write_err)?;
+          if writeln!(out, "{line}").and_then(|()| out.flush()).is_err() {
+            // The reader went away, e.g. `stash subscribe | head`.
+            return Ok(());
+          }
+        },
+        other => log::debug!("ignoring unexpected response: {other:?}"),
+      }
+    }
+  }
+
+  fn list_previews(
+    &mut self,
+    mut out: impl Write,
+    preview_width: u32,
+    include_expired: bool,
+    reverse: bool,
+    pinned_first: bool,
+    selection: Option<Selection>,
+    tags: &[String],
+    search: Option<&SearchQuery>,
+  ) -> Result<(), StashError> {
+    let mut offset = 0;
+    loop {
+      let (entries, total) = self.list(
+        include_expired,
+        reverse,
+        pinned_first,
+        selection,
+        tags,
+        search,
+        preview_width,
+        offset,
+        ipc::MAX_LIST_LIMIT,
+      )?;
+      if entries.is_empty() {
+        return Ok(());
+      }
+      offset += entries.len();
+      for entry in entries {
+        if writeln!(out, "{}\t{}", entry.id, entry.preview).is_err() {
+          return Ok(());
+        }
+      }
+      if offset >= total {
+        return Ok(());
+      }
+    }
+  }
+
+  fn pin_input(
+    &mut self,
+    input: impl Read,
+    pinned: bool,
+  ) -> Result<usize, StashError> {
+    let mut updated = 0;
+    for id in input_ids(input, StashError::PinInput) {
+      self.pin(id?, pinned)?;
+      updated += 1;
+    }
+    let action = if pinned { "pinned" } else { "unpinned" };
+    log::info!("{action} {updated} entries through the watch daemon");
+    Ok(updated)
+  }
+
+  fn delete_input(&mut self, input: impl Read) -> Result<usize, StashError> {
+    let mut deleted = 0;
+    for id in input_ids(input, StashError::DeleteInput) {
+      self.delete(id?)?;
+      deleted += 1;
+    }
+    log::info!("deleted {deleted} entries through the watch daemon");
+    Ok(deleted)
+  }
+
+  fn decode_input(
+    &mut self,
+    in_: impl Read,
+    out: impl Write,
+    input: Option<String>,
+  ) -> Result<(), StashError> {
+    decode::decode_or_relay(in_, out, input, |input, out| {
+      let id =
+        extract_id(input).map_err(|e| StashError::DecodeExtractId(e.into()))?;
+      let contents = self.get(id)?;
+      out
+        .write_all(&contents)
+        .map_err(|e| StashError::DecodeWrite(e.to_string().into()))
+    })
+  }
+
+  fn copy_input(
+    &mut self,
+    in_: impl Read,
+    input: Option<String>,
+  ) -> Result<(), StashError> {
+    let id = self.copy(decode::read_id(in_, input)?)?;
+    log::info!("copied entry with id {id} through the watch daemon");
+    Ok(())
+  }
+}
@@ -2,87 +2,133 @@ use std::io::{Read, Write};
 
 use wl_clipboard_rs::paste::{ClipboardType, MimeType, Seat, get_contents};
 
-use crate::db::{ClipboardDb, SqliteClipboardDb, StashError};
+use crate::{
+  clipboard,
+  db::{ClipboardDb, SqliteClipboardDb, StashError, extract_id},
+};
 
 pub trait DecodeCommand {
+  /// Write the contents of an entry to `out`. The selection it was recorded
+  /// from only matters to [`Self::decode_to_clipboard`].
   fn decode(
     &self,
     in_: impl Read,
     out: impl Write,
     input: Option<String>,
   ) -> Result<(), StashError>;
+
+  /// Copy an entry back to the selection it was recorded from, offering all
+  /// of its stored MIME types, instead of writing it out.
+  fn decode_to_clipboard(
+    &self,
+    in_: impl Read,
+    input: Option<String>,
+  ) -> Result<(), StashError>;
 }
 
 impl DecodeCommand for SqliteClipboardDb {
   fn decode(
     &self,
-    mut in_: impl Read,
-    mut out: impl Write,
+    in_: impl Read,
+    out: impl Write,
+    input: Option<String>,
+  ) -> Result<(), StashError> {
+    decode_or_relay(in_, out, input, |input, out| {
+      self.decode_entry(input.as_bytes(), out, Some(input.to_owned()))
+    })
+  }
+
+  fn decode_to_clipboard(
+    &self,
+    in_: impl Read,
     input: Option<String>,
   ) -> Result<(), StashError> {
-    let input_str = if let Some(s) = input {
-      s
-    } else {
-      let mut buf = String::new();
-      in_
-        .read_to_string(&mut buf)
-        .map_err(|e| StashError::DecodeRead(e.to_string().into()))?;
-      buf
-    };
+    let id = read_id(in_, input)?;
+    let selection = self.selection(id)?;
+    let (id, contents, mime, representations) = self.copy_entry(id)?;
+    clipboard::copy_detached(contents, mime, representations, selection)
+      .map_err(|e| StashError::Copy(id, e.to_string().into()))?;
+    log::info!(
+      "copied entry with id {id} to the {} selection",
+      selection.as_str()
+    );
+    Ok(())
+  }
+}
+
+/// Read the input of `stash decode`: `input` if given, or else all of `in_`.
+fn read_input(
+  mut in_: impl Read,
+  input: Option<String>,
+) -> Result<String, StashError> {
+  if let Some(s) = input {
+    return Ok(s);
+  }
+  let mut buf = String::new();
+  in_
+    .read_to_string(&mut buf)
+    .map_err(|e| StashError::DecodeRead(e.to_string().into()))?;
+  Ok(buf)
+}
 
-    // If input is empty or whitespace, treat as error and trigger fallback
-    if input_str.trim().is_empty() {
-      log::debug!("no input provided to decode; relaying clipboard to stdout");
-      if let Ok((mut reader, _mime)) =
-        get_contents(ClipboardType::Regular, Seat::Unspecified, MimeType::Any)
-      {
-        let mut buf = Vec::new();
-        reader.read_to_end(&mut buf).map_err(|e| {
-          StashError::DecodeRead(
-            format!("failed to read clipboard for relay: {e}").into(),
-          )
-        })?;
-        out.write_all(&buf).map_err(|e| {
-          StashError::DecodeWrite(
-            format!("failed to write clipboard relay: {e}").into(),
-          )
-        })?;
-      } else {
-        return Err(StashError::DecodeGet(
-          "failed to get clipboard contents for relay".into(),
-        ));
-      }
-      return Ok(());
-    }
+/// Read the id of the entry `stash decode --copy` copies.
+pub(crate) fn read_id(
+  in_: impl Read,
+  input: Option<String>,
+) -> Result<i64, StashError> {
+  let input_str = read_input(in_, input)?;
+  extract_id(&input_str).map_err(|e| StashError::DecodeExtractId(e.into()))
+}
+
+/// Write the entry the input of `stash decode` names to `out` with `decode`.
+/// The clipboard is relayed instead when there is no input, or when the entry
+/// cannot be decoded.
+pub(crate) fn decode_or_relay(
+  in_: impl Read,
+  mut out: impl Write,
+  input: Option<String>,
+  decode: impl FnOnce(&str, &mut dyn Write) -> Result<(), StashError>,
+) -> Result<(), StashError> {
+  let input_str = read_input(in_, input)?;
+
+  // If input is empty or whitespace, treat as error and trigger fallback
+  if input_str.trim().is_empty() {
+    log::debug!("no input provided to decode; relaying clipboard to stdout");
+    return relay_clipboard(&mut out).unwrap_or_else(|| {
+      Err(StashError::DecodeGet(
+        "failed to get clipboard contents for relay".into(),
+      ))
+    });
+  }
 
-    // Try decode as usual
-    match self.decode_entry(
-      input_str.as_bytes(),
-      &mut out,
-      Some(input_str.clone()),
-    ) {
-      Ok(()) => Ok(()),
-      Err(e) => {
-        // On decode failure, relay clipboard as fallback
-        if let Ok((mut reader, _mime)) =
-          get_contents(ClipboardType::Regular, Seat::Unspecified, MimeType::Any)
-        {
-          let mut buf = Vec::new();
-          reader.read_to_end(&mut buf).map_err(|err| {
-            StashError::DecodeRead(
-              format!("failed to read clipboard for relay: {err}").into(),
-            )
-          })?;
-          out.write_all(&buf).map_err(|err| {
-            StashError::DecodeWrite(
-              format!("failed to write clipboard relay: {err}").into(),
-            )
-          })?;
-          Ok(())
-        } else {
-          Err(e)
-        }
-      },
-    }
+  // Try decode as usual
+  match decode(&input_str, &mut out) {
+    Ok(()) => Ok(()),
+    // On decode failure, relay clipboard as fallback
+    Err(e) => relay_clipboard(&mut out).unwrap_or(Err(e)),
   }
 }
+
+/// Copy the clipboard contents to `out`, or return `None` when there are none
+/// to relay.
+fn relay_clipboard(out: &mut impl Write) -> Option<Result<(), StashError>> {
+  let (mut reader, _mime) =
+    get_contents(ClipboardType::Regular, Seat::Unspecified, MimeType::Any)
+      .ok()?;
+  let mut buf = Vec::new();
+  let relayed = reader
+    .read_to_end(&mut buf)
+    .map_err(|e| {
+      StashError::DecodeRead(
+        format!("failed to read clipboard for relay: {e}").into(),
+      )
+    })
+    .and_then(|_| {
+      out.write_all(&buf).map_err(|e| {
+        StashError::DecodeWrite(
+          format!("failed to write clipboard relay: {e}").into(),
+        )
+      })
+    });
+  Some(relayed)
+}
@@ -1,66 +1,574 @@
-use std::io::{self, BufRead};
+mod bolt;
+mod cliphist;
+mod clipman;
+mod copyq;
+mod gpaste;
+mod klipper;
+mod qdatastream;
 
-use crate::db::{ClipboardDb, Entry, SqliteClipboardDb, StashError};
+use std::{
+  collections::HashSet,
+  fmt,
+  fs,
+  io::{self, BufRead},
+  path::{Path, PathBuf},
+};
+
+use crate::db::{ClipboardDb, SqliteClipboardDb, StashError};
+
+/// History formats of other clipboard managers that can be imported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryFormat {
+  /// cliphist's BoltDB database.
+  CliphistDb,
+  /// clipman's JSON history.
+  Clipman,
+  /// GPaste's XML history.
+  Gpaste,
+  /// Klipper's `history2.lst`.
+  Klipper,
+  /// A `.cpq` file exported from CopyQ.
+  Copyq,
+}
+
+impl HistoryFormat {
+  /// The format of an `import --type` value.
+  pub fn from_type(name: &str) -> Option<Self> {
+    match name {
+      "cliphist-db" => Some(Self::CliphistDb),
+      "clipman" => Some(Self::Clipman),
+      "gpaste" => Some(Self::Gpaste),
+      "klipper" => Some(Self::Klipper),
+      "copyq" => Some(Self::Copyq),
+      _ => None,
+    }
+  }
+
+  /// Where the clipboard manager keeps its history by default. CopyQ exports
+  /// have no default location.
+  pub fn default_path(self) -> Option<PathBuf> {
+    match self {
+      Self::CliphistDb => {
+        dirs::cache_dir().map(|dir| dir.join("cliphist").join("db"))
+      },
+      Self::Clipman => dirs::data_dir().map(|dir| dir.join("clipman.json")),
+      Self::Gpaste => {
+        dirs::data_dir().map(|dir| dir.join("gpaste").join("history.xml"))
+      },
+      Self::Klipper => {
+        dirs::data_dir().map(|dir| dir.join("klipper").join("history2.lst"))
+      },
+      Self::Copyq => None,
+    }
+  }
+
+  fn parse(self, data: &[u8]) -> Result<History, String> {
+    match self {
+      Self::CliphistDb => cliphist::parse(data),
+      Self::Clipman => clipman::parse(data),
+      Self::Gpaste => gpaste::parse(data),
+      Self::Klipper => klipper::parse(data),
+      Self::Copyq => copyq::parse(data),
+    }
+  }
+}
+
+impl fmt::Display for HistoryFormat {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Self::CliphistDb => "cliphist",
+      Self::Clipman => "clipman",
+      Self::Gpaste => "GPaste",
+      Self::Klipper => "Klipper",
+      Self::Copyq => "CopyQ",
+    })
+  }
+}
+
+/// Entries read from another clipboard manager's history, oldest first.
+#[derive(Debug, Default)]
+struct History {
+  entries: Vec<Vec<u8>>,
+  /// Items that cannot be imported: empty ones, passwords, or kinds Stash
+  /// does not store.
+  skipped: usize,
+}
+
+impl History {
+  fn push(&mut self, contents: Vec<u8>) {
+    if contents.is_empty() {
+      self.skip();
+    } else {
+      self.entries.push(contents);
+    }
+  }
+
+  fn skip(&mut self) {
+    self.skipped += 1;
+  }
+}
+
+/// What importing another clipboard manager's history did.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportCounts {
+  pub imported:       usize,
+  /// Entries whose contents were already stored.
+  pub already_stored: usize,
+  /// Items that cannot be imported.
+  pub skipped:        usize,
+}
+
+/// How a TSV import treats its input.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TsvOptions {
+  /// Store the entries in the order of their ids rather than of the lines, so
+  /// that a newest-first listing such as `stash list` keeps its order.
+  pub preserve_order: bool,
+  /// Take values as they are instead of decoding `\n`, `\t`, `\r` and `\\`.
+  pub raw:            bool,
+  /// Report what would be imported without changing the database.
+  pub dry_run:        bool,
+}
+
+/// Limits applied to imported entries, the same as for `stash store`.
+#[derive(Debug, Clone, Copy)]
+pub struct StoreLimits {
+  pub max_dedupe_search: u64,
+  pub max_items:         u64,
+  pub min_size:          Option<usize>,
+  pub max_size:          usize,
+}
 
 pub trait ImportCommand {
-  /// Import clipboard entries from TSV format.
+  /// Import clipboard entries from TSV format. Every entry goes through the
+  /// same filters, deduplication and encryption as `stash store`, and the
+  /// import is all or nothing.
   fn import_tsv(
     &self,
     input: impl io::Read,
+    limits: StoreLimits,
+    options: TsvOptions,
+  ) -> Result<ImportCounts, StashError>;
+
+  /// Import the history of another clipboard manager from `path`, keeping the
+  /// exact bytes of every entry, binary ones included, and the order they
+  /// were copied in. Entries larger than `max_size` or kept out by the
+  /// filters of `stash store` are skipped.
+  fn import_history(
+    &self,
+    format: HistoryFormat,
+    path: &Path,
     max_items: u64,
-  ) -> Result<(), StashError>;
+    max_size: usize,
+  ) -> Result<ImportCounts, StashError>;
 }
 
 impl ImportCommand for SqliteClipboardDb {
   fn import_tsv(
     &self,
     input: impl io::Read,
-    max_items: u64,
-  ) -> Result<(), StashError> {
-    let reader = io::BufReader::new(input);
-    let mut imported = 0;
-    for (lineno, line) in reader.lines().enumerate() {
+    limits: StoreLimits,
+    options: TsvOptions,
+  ) -> Result<ImportCounts, StashError> {
+    let mut lines = Vec::new();
+    for (lineno, line) in io::BufReader::new(input).split(b'\n').enumerate() {
+      let lineno = lineno + 1;
       let line = line.map_err(|e| {
-        StashError::Store(format!("failed to read line {lineno}: {e}").into())
+        StashError::Import(format!("failed to read line {lineno}: {e}").into())
       })?;
-      let mut parts = line.splitn(2, '\t');
-      let (Some(id_str), Some(val)) = (parts.next(), parts.next()) else {
-        return Err(StashError::Store(
-          format!("malformed tsv line {lineno}: {line:?}").into(),
-        ));
-      };
-
-      let Ok(_id) = id_str.parse::<u64>() else {
-        return Err(StashError::Store(
-          format!("failed to parse id from line {lineno}: {id_str}").into(),
-        ));
-      };
-
-      let entry = Entry {
-        contents: val.as_bytes().to_vec(),
-        mime:     crate::mime::detect_mime(val.as_bytes()),
-      };
-
-      self
-        .conn
-        .execute(
-          "INSERT INTO clipboard (contents, mime) VALUES (?1, ?2)",
-          rusqlite::params![entry.contents, entry.mime],
-        )
-        .map_err(|e| {
-          StashError::Store(
-            format!("failed to insert entry at line {lineno}: {e}").into(),
-          )
-        })?;
-      imported += 1;
+      if line.is_empty() {
+        continue;
+      }
+      lines.push(parse_tsv_line(lineno, &line, options.raw)?);
+    }
+    if options.preserve_order {
+      // Stable, so lines sharing an id keep their relative order.
+      lines.sort_by_key(|line| line.id);
+    }
+
+    let tx = self
+      .conn
+      .unchecked_transaction()
+      .map_err(|e| StashError::Import(e.to_string().into()))?;
+    // Ids only grow, so anything above the current maximum is a new entry.
+    let max_id: i64 = self
+      .conn
+      .query_row("SELECT COALESCE(MAX(id), 0) FROM clipboard", [], |row| {
+        row.get(0)
+      })
+      .map_err(|e| StashError::Import(e.to_string().into()))?;
+    let mut new_ids = HashSet::new();
+    let mut counts = ImportCounts::default();
+
+    for line in lines {
+      if line.value.starts_with(b"[[ ") && line.value.ends_with(b" ]]") {
+        // A preview of binary data, not the data itself.
+        log::info!("skipping binary preview at line {}", line.lineno);
+        counts.skipped += 1;
+        continue;
+      }
+      let result = self.store_entry(
+        line.value.as_slice(),
+        limits.max_dedupe_search,
+        limits.max_items,
+        None,
+        limits.min_size,
+        limits.max_size,
+        None,
+        None,
+        None,
+      );
+      match result {
+        Ok(id) if id > max_id && new_ids.insert(id) => counts.imported += 1,
+        Ok(_) => counts.already_stored += 1,
+        Err(e) if is_filtered(&e) => {
+          log::info!("skipping line {}: {e}", line.lineno);
+          counts.skipped += 1;
+        },
+        Err(e) => {
+          return Err(StashError::Import(
+            format!("line {}: {e}", line.lineno).into(),
+          ));
+        },
+      }
+    }
+
+    if options.dry_run {
+      tx.rollback()
+        .map_err(|e| StashError::Import(e.to_string().into()))?;
+    } else {
+      tx.commit()
+        .map_err(|e| StashError::Import(e.to_string().into()))?;
+      log::info!(
+        "imported {} records from tsv ({} already stored, {} skipped)",
+        counts.imported,
+        counts.already_stored,
+        counts.skipped
+      );
+    }
+    Ok(counts)
+  }
+
+  fn import_history(
+    &self,
+    format: HistoryFormat,
+    path: &Path,
+    max_items: u64,
+    max_size: usize,
+  ) -> Result<ImportCounts, StashError> {
+    let data = fs::read(path).map_err(|e| {
+      StashError::Import(
+        format!("failed to read {}: {e}", path.display()).into(),
+      )
+    })?;
+    let history = format.parse(&data).map_err(|e| {
+      StashError::Import(
+        format!("{} is not a {format} history: {e}", path.display()).into(),
+      )
+    })?;
+
+    let tx = self
+      .conn
+      .unchecked_transaction()
+      .map_err(|e| StashError::Import(e.to_string().into()))?;
+    let mut counts = ImportCounts {
+      skipped: history.skipped,
+      ..ImportCounts::default()
+    };
+    for contents in &history.entries {
+      if contents.len() > max_size {
+        log::info!("skipping entry of {} bytes", contents.len());
+        counts.skipped += 1;
+        continue;
+      }
+      match self.import_entry(contents) {
+        Ok(Some(_)) => counts.imported += 1,
+        Ok(None) => counts.already_stored += 1,
+        Err(e) if is_filtered(&e) => {
+          log::info!("skipping entry: {e}");
+          counts.skipped += 1;
+        },
+        Err(e) => return Err(e),
+      }
     }
+    tx.commit()
+      .map_err(|e| StashError::Import(e.to_string().into()))?;
 
-    log::info!("imported {imported} records from tsv into sqlite database");
+    log::info!(
+      "imported {} records from {format} history ({} already stored, {} \
+       skipped)",
+      counts.imported,
+      counts.already_stored,
+      counts.skipped
+    );
 
-    // Trim database to max_items after import
     self.trim_db(max_items)?;
     log::info!("trimmed clipboard database to max_items = {max_items}");
 
-    Ok(())
+    Ok(counts)
+  }
+}
+
+/// A line of TSV input.
+struct TsvLine {
+  lineno: usize,
+  id:     u64,
+  value:  Vec<u8>,
+}
+
+fn parse_tsv_line(
+  lineno: usize,
+  line: &[u8],
+  raw: bool,
+) -> Result<TsvLine, StashError> {
+  let Some(tab) = line.iter().position(|&b| b == b'\t') else {
+    return Err(StashError::Import(
+      format!(
+        "malformed tsv line {lineno}: {:?}",
+        String::from_utf8_lossy(line)
+      )
+      .into(),
+    ));
+  };
+  let (id, value) = (&line[..tab], &line[tab + 1..]);
+  let Some(id) = std::str::from_utf8(id).ok().and_then(|id| id.parse().ok())
+  else {
+    return Err(StashError::Import(
+      format!(
+        "failed to parse id from line {lineno}: {}",
+        String::from_utf8_lossy(id)
+      )
+      .into(),
+    ));
+  };
+  let value = if raw { value.to_vec() } else { unescape(value) };
+  Ok(TsvLine { lineno, id, value })
+}
+
+/// Decode `\n`, `\t`, `\r` and `\\`. Other backslashes are kept as they are.
+fn unescape(value: &[u8]) -> Vec<u8> {
+  let mut out = Vec::with_capacity(value.len());
+  let mut bytes = value.iter().copied();
+  while let Some(b) = bytes.next() {
+    if b != b'\\' {
+      out.push(b);
+      continue;
+    }
+    match bytes.next() {
+      Some(b'n') => out.push(b'\n'),
+      Some(b't') => out.push(b'\t'),
+      Some(b'r') => out.push(b'\r'),
+      Some(b'\\') => out.push(b'\\'),
+      Some(other) => out.extend_from_slice(&[b'\\', other]),
+      None => out.push(b'\\'),
+    }
+  }
+  out
+}
+
+/// Whether `error` is `store_entry` turning an entry away, rather than a
+/// failure of the import.
+fn is_filtered(error: &StashError) -> bool {
+  match error {
+    StashError::EmptyOrTooLarge
+    | StashError::AllWhitespace
+    | StashError::TooSmall(_)
+    | StashError::TooLarge(_)
+    | StashError::ExcludedByApp(_)
+    | StashError::SecretDetected(_)
+    | StashError::DroppedByRule(_)
+    | StashError::RuleNeedsEncryption(_) => true,
+    StashError::Store(msg) => msg.contains("filtered by sensitive regex"),
+    _ => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    bolt::tests::{bucket, file, leaf_page},
+    *,
+  };
+
+  const LIMITS: StoreLimits = StoreLimits {
+    max_dedupe_search: 100,
+    max_items:         100,
+    min_size:          None,
+    max_size:          5_000_000,
+  };
+
+  fn contents(db: &SqliteClipboardDb) -> Vec<Vec<u8>> {
+    db.conn
+      .prepare("SELECT contents FROM clipboard ORDER BY id")
+      .expect("prepare")
+      .query_map([], |row| row.get(0))
+      .expect("query")
+      .collect::<Result<_, _>>()
+      .expect("rows")
+  }
+
+  #[test]
+  fn test_import_tsv() {
+    let conn = rusqlite::Connection::open_in_memory().expect("open db");
+    let db = SqliteClipboardDb::new(conn, ":memory:".into()).expect("new db");
+    db.import_entry(b"stored").expect("seed");
+    let tsv: &[u8] = b"9\tnewest\\nline\tC:\\\\x \\q\n\
+                       8\tstored\n\
+                       7\t[[ binary data 3 KiB image/png ]]\n\
+                       \n\
+                       5\t \n\
+                       4\toldest \xff\n\
+                       6\tnewest\\nline\tC:\\\\x \\q\n";
+
+    let dry_run = TsvOptions {
+      dry_run: true,
+      ..TsvOptions::default()
+    };
+    let counts = db.import_tsv(tsv, LIMITS, dry_run).expect("dry run");
+    assert_eq!(counts, ImportCounts {
+      imported:       2,
+      already_stored: 2,
+      skipped:        2,
+    });
+    assert_eq!(contents(&db), [b"stored".to_vec()]);
+
+    let ordered = TsvOptions {
+      preserve_order: true,
+      ..TsvOptions::default()
+    };
+    let counts = db.import_tsv(tsv, LIMITS, ordered).expect("import");
+    assert_eq!(counts.imported, 2);
+    assert_eq!(contents(&db), [
+      b"stored".to_vec(),
+      b"oldest \xff".to_vec(),
+      b"newest\nline\tC:\\x \\q".to_vec(),
+    ]);
+
+    let raw = TsvOptions {
+      raw: true,
+      ..TsvOptions::default()
+    };
+    db.import_tsv(&b"1\ta\\tb\n"[..], LIMITS, raw)
+      .expect("raw import");
+    assert_eq!(contents(&db).last().expect("entry"), b"a\\tb");
+
+    // A malformed line leaves the database untouched.
+    let result = db.import_tsv(&b"1\tfine\nno tab\n"[..], LIMITS, raw);
+    assert!(matches!(result, Err(StashError::Import(_))));
+    assert!(!contents(&db).contains(&b"fine".to_vec()));
+  }
+
+  fn import(
+    db: &SqliteClipboardDb,
+    path: &Path,
+  ) -> Result<ImportCounts, StashError> {
+    db.import_history(HistoryFormat::CliphistDb, path, 100, 5_000_000)
+  }
+
+  #[test]
+  fn test_import_cliphist_db_keeps_binary_entries_in_order() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let conn = rusqlite::Connection::open_in_memory().expect("open db");
+    let db = SqliteClipboardDb::new(conn, ":memory:".into()).expect("new db");
+    db.import_entry(b"already here").expect("seed");
+
+    let png =
+      b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01\x08\x06\0\0\0";
+    let ids: Vec<[u8; 8]> = (1..=4u64).map(u64::to_be_bytes).collect();
+    let history = file(2, &[
+      leaf_page(2, &[(1, cliphist::BUCKET, &bucket(3))]),
+      leaf_page(3, &[
+        (0, &ids[0], b"first"),
+        (0, &ids[1], png),
+        (0, &ids[2], b"already here"),
+        (0, &ids[3], b"\xff\x00binary"),
+      ]),
+    ]);
+    let history_path = dir.path().join("cliphist.db");
+    fs::write(&history_path, history).expect("write history");
+
+    let counts = import(&db, &history_path).expect("import");
+    assert_eq!(counts, ImportCounts {
+      imported:       3,
+      already_stored: 1,
+      skipped:        0,
+    });
+
+    let rows: Vec<(Vec<u8>, Option<String>)> = db
+      .conn
+      .prepare("SELECT contents, mime FROM clipboard ORDER BY id")
+      .expect("prepare")
+      .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+      .expect("query")
+      .collect::<Result<_, _>>()
+      .expect("rows");
+    assert_eq!(rows, [
+      (b"already here".to_vec(), Some("text/plain".to_owned())),
+      (b"first".to_vec(), Some("text/plain".to_owned())),
+      (png.to_vec(), Some("image/png".to_owned())),
+      (b"\xff\x00binary".to_vec(), None),
+    ]);
+
+    let missing = import(&db, &dir.path().join("missing"));
+    assert!(matches!(missing, Err(StashError::Import(_))));
+    fs::write(dir.path().join("empty"), b"").expect("write empty file");
+    let not_bolt = import(&db, &dir.path().join("empty"));
+    assert!(matches!(not_bolt, Err(StashError::Import(_))));
+  }
+
+  #[test]
+  fn test_import_history_applies_store_filters() {
+    let table: toml::Table = r#"
+      [[rules]]
+      name = "secrets"
+      regex = "^secret"
+      action = "drop"
+
+      [[rules]]
+      name = "tokens"
+      regex = "tok_[a-z0-9]+"
+      action = "redact"
+
+      [[rules]]
+      name = "terminal"
+      app = "foot"
+      action = "drop"
+    "#
+    .parse()
+    .expect("valid toml");
+    let rules =
+      crate::rules::Rule::parse_all(&table["rules"]).expect("valid rules");
+    let dir = tempfile::tempdir().expect("temp dir");
+    let conn = rusqlite::Connection::open_in_memory().expect("open db");
+    let db = SqliteClipboardDb::new(conn, ":memory:".into())
+      .expect("new db")
+      .with_rules(Vec::leak(rules));
+
+    let ids: Vec<[u8; 8]> = (1..=4u64).map(u64::to_be_bytes).collect();
+    let history = file(2, &[
+      leaf_page(2, &[(1, cliphist::BUCKET, &bucket(3))]),
+      leaf_page(3, &[
+        (0, &ids[0], b"secret password"),
+        (0, &ids[1], b"key tok_abc123"),
+        (0, &ids[2], b"far too large an entry"),
+        (0, &ids[3], b"kept"),
+      ]),
+    ]);
+    let history_path = dir.path().join("cliphist.db");
+    fs::write(&history_path, history).expect("write history");
+
+    let counts = db
+      .import_history(HistoryFormat::CliphistDb, &history_path, 100, 16)
+      .expect("import");
+    assert_eq!(counts, ImportCounts {
+      imported:       2,
+      already_stored: 0,
+      skipped:        2,
+    });
+    // Rules on the focused app do not apply to imported entries.
+    assert_eq!(contents(&db), [
+      b"key [redacted]".to_vec(),
+      b"kept".to_vec()
+    ]);
   }
 }
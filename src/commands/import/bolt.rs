@@ -0,0 +1,412 @@
+//! Read-only access to BoltDB files, as written by cliphist.
+//!
+//! Only what is needed to read a bucket is implemented: the newer of the two
+//! meta pages gives the root bucket, and branch, leaf and inline bucket pages
+//! are walked in key order. The free list is never consulted, so a file must
+//! not be written to while it is read.
+
+use std::{cmp, hash::Hasher as _};
+
+use crate::hash::Fnv1aHasher;
+
+const MAGIC: u32 = 0xED0C_DAED;
+const VERSION: u32 = 2;
+
+const PAGE_HEADER_SIZE: usize = 16;
+const ELEMENT_SIZE: usize = 16;
+const BUCKET_HEADER_SIZE: usize = 16;
+/// Size of a meta page's contents, the last 8 bytes being its checksum.
+const META_SIZE: usize = 64;
+
+const BRANCH_PAGE: u16 = 0x01;
+const LEAF_PAGE: u16 = 0x02;
+const BUCKET_LEAF: u32 = 0x01;
+
+/// Page size assumed when the first meta page cannot be read.
+const DEFAULT_PAGE_SIZE: usize = 4096;
+/// Deepest tree that is walked, so corrupt files cannot recurse forever.
+const MAX_DEPTH: usize = 64;
+
+/// An open BoltDB file.
+pub struct Bolt<'a> {
+  data:      &'a [u8],
+  page_size: usize,
+  root:      u64,
+}
+
+/// A key and its value.
+pub type Pair<'a> = (&'a [u8], &'a [u8]);
+
+/// A bucket's root page, stored in a page of its own or inline in the
+/// bucket's value.
+#[derive(Clone, Copy)]
+enum Root<'a> {
+  Page(u64),
+  Inline(&'a [u8]),
+}
+
+/// A page of the B+tree.
+struct Page<'a> {
+  bytes: &'a [u8],
+  flags: u16,
+  count: usize,
+}
+
+/// A key/value pair of a leaf page.
+struct Leaf<'a> {
+  flags: u32,
+  key:   &'a [u8],
+  value: &'a [u8],
+}
+
+struct Meta {
+  page_size: usize,
+  root:      u64,
+  txid:      u64,
+}
+
+impl<'a> Bolt<'a> {
+  /// Open the database in `data`, the contents of a BoltDB file.
+  pub fn open(data: &'a [u8]) -> Result<Self, String> {
+    let first = Meta::parse(data.get(PAGE_HEADER_SIZE..).unwrap_or_default());
+    let page_size = first.as_ref().map_or(DEFAULT_PAGE_SIZE, |m| m.page_size);
+    let second = data
+      .get(page_size + PAGE_HEADER_SIZE..)
+      .and_then(Meta::parse);
+
+    let meta = match (first, second) {
+      (Some(a), Some(b)) => cmp::max_by_key(a, b, |meta| meta.txid),
+      (Some(meta), None) | (None, Some(meta)) => meta,
+      (None, None) => return Err("not a BoltDB database".into()),
+    };
+
+    Ok(Self {
+      data,
+      page_size: meta.page_size,
+      root: meta.root,
+    })
+  }
+
+  /// Key/value pairs of the top-level bucket `name` in key order, or `None`
+  /// if there is no such bucket. Nested buckets are left out.
+  pub fn bucket(&self, name: &[u8]) -> Result<Option<Vec<Pair<'a>>>, String> {
+    let mut root = None;
+    self.walk(Root::Page(self.root), 0, &mut |leaf| {
+      if root.is_none() && leaf.flags & BUCKET_LEAF != 0 && leaf.key == name {
+        root = Some(bucket_root(leaf.value)?);
+      }
+      Ok(())
+    })?;
+    let Some(root) = root else {
+      return Ok(None);
+    };
+
+    let mut pairs = Vec::new();
+    self.walk(root, 0, &mut |leaf| {
+      if leaf.flags & BUCKET_LEAF == 0 {
+        pairs.push((leaf.key, leaf.value));
+      }
+      Ok(())
+    })?;
+    Ok(Some(pairs))
+  }
+
+  /// Visit the leaves below `root` in key order.
+  fn walk(
+    &self,
+    root: Root<'a>,
+    depth: usize,
+    visit: &mut impl FnMut(Leaf<'a>) -> Result<(), String>,
+  ) -> Result<(), String> {
+    if depth > MAX_DEPTH {
+      return Err("tree is too deep, the database is corrupt".into());
+    }
+
+    let page = match root {
+      Root::Page(id) => self.page(id)?,
+      Root::Inline(bytes) => Page::parse(bytes)?,
+    };
+    if page.flags & LEAF_PAGE != 0 {
+      for index in 0..page.count {
+        visit(page.leaf(index)?)?;
+      }
+    } else if page.flags & BRANCH_PAGE != 0 {
+      for index in 0..page.count {
+        self.walk(Root::Page(page.child(index)?), depth + 1, visit)?;
+      }
+    } else {
+      return Err(format!("unexpected page type {:#x}", page.flags));
+    }
+    Ok(())
+  }
+
+  fn page(&self, id: u64) -> Result<Page<'a>, String> {
+    let out_of_bounds = || format!("page {id} is out of bounds");
+    let start = usize::try_from(id)
+      .ok()
+      .and_then(|id| id.checked_mul(self.page_size))
+      .filter(|&start| start < self.data.len())
+      .ok_or_else(out_of_bounds)?;
+    let header = Page::parse(&self.data[start..])?;
+
+    // Overflow pages continue the page directly after it.
+    let overflow = read_u32(&self.data[start..], 12).unwrap_or_default();
+    let end = usize::try_from(overflow)
+      .ok()
+      .and_then(|overflow| overflow.checked_add(1))
+      .and_then(|pages| pages.checked_mul(self.page_size))
+      .and_then(|size| size.checked_add(start))
+      .ok_or_else(out_of_bounds)?
+      .min(self.data.len());
+    Ok(Page {
+      bytes: &self.data[start..end],
+      ..header
+    })
+  }
+}
+
+impl Meta {
+  fn parse(bytes: &[u8]) -> Option<Self> {
+    let bytes = bytes.get(..META_SIZE)?;
+    if read_u32(bytes, 0)? != MAGIC || read_u32(bytes, 4)? != VERSION {
+      return None;
+    }
+
+    let mut hasher = Fnv1aHasher::new();
+    hasher.write(&bytes[..META_SIZE - 8]);
+    if hasher.finish() != read_u64(bytes, META_SIZE - 8)? {
+      return None;
+    }
+
+    let page_size = usize::try_from(read_u32(bytes, 8)?).ok()?;
+    (page_size >= PAGE_HEADER_SIZE + META_SIZE).then_some(Self {
+      page_size,
+      root: read_u64(bytes, 16)?,
+      txid: read_u64(bytes, 48)?,
+    })
+  }
+}
+
+impl<'a> Page<'a> {
+  /// Parse the header of the page starting at `bytes`.
+  fn parse(bytes: &'a [u8]) -> Result<Self, String> {
+    let truncated = || "truncated page header".to_owned();
+    let flags = read_u16(bytes, 8).ok_or_else(truncated)?;
+    let count = read_u16(bytes, 10).ok_or_else(truncated)?;
+    Ok(Self {
+      bytes,
+      flags,
+      count: usize::from(count),
+    })
+  }
+
+  /// Start of element `index`, from which its data is addressed.
+  fn element(&self, index: usize) -> usize {
+    PAGE_HEADER_SIZE + index * ELEMENT_SIZE
+  }
+
+  fn leaf(&self, index: usize) -> Result<Leaf<'a>, String> {
+    let at = self.element(index);
+    let fields = (|| {
+      let flags = read_u32(self.bytes, at)?;
+      let pos = usize::try_from(read_u32(self.bytes, at + 4)?).ok()?;
+      let key_size = usize::try_from(read_u32(self.bytes, at + 8)?).ok()?;
+      let value_size = usize::try_from(read_u32(self.bytes, at + 12)?).ok()?;
+      let key_start = at.checked_add(pos)?;
+      let value_start = key_start.checked_add(key_size)?;
+      Some(Leaf {
+        flags,
+        key: self.bytes.get(key_start..value_start)?,
+        value: self
+          .bytes
+          .get(value_start..value_start.checked_add(value_size)?)?,
+      })
+    })();
+    fields.ok_or_else(|| format!("leaf element {index} is out of bounds"))
+  }
+
+  fn child(&self, index: usize) -> Result<u64, String> {
+    read_u64(self.bytes, self.element(index) + 8)
+      .ok_or_else(|| format!("branch element {index} is out of bounds"))
+  }
+}
+
+/// The root of the bucket whose header is `value`.
+fn bucket_root(value: &[u8]) -> Result<Root<'_>, String> {
+  match read_u64(value, 0) {
+    Some(0) => Ok(Root::Inline(&value[BUCKET_HEADER_SIZE.min(value.len())..])),
+    Some(id) => Ok(Root::Page(id)),
+    None => Err("truncated bucket header".into()),
+  }
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> Option<u16> {
+  Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+  Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> Option<u64> {
+  Some(u64::from_le_bytes(bytes.get(at..at + 8)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+  use super::*;
+
+  pub const PAGE_SIZE: usize = 256;
+
+  /// A page holding `count` elements, followed by `data`.
+  fn page(
+    id: u64,
+    flags: u16,
+    count: usize,
+    elements: &[u8],
+    data: &[u8],
+  ) -> (u64, Vec<u8>) {
+    let size = PAGE_HEADER_SIZE + elements.len() + data.len();
+    let overflow = size.div_ceil(PAGE_SIZE).saturating_sub(1);
+    let mut bytes = Vec::with_capacity(size);
+    bytes.extend_from_slice(&id.to_le_bytes());
+    bytes.extend_from_slice(&flags.to_le_bytes());
+    bytes.extend_from_slice(&u16::try_from(count).unwrap().to_le_bytes());
+    bytes.extend_from_slice(&u32::try_from(overflow).unwrap().to_le_bytes());
+    bytes.extend_from_slice(elements);
+    bytes.extend_from_slice(data);
+    (id, bytes)
+  }
+
+  /// A leaf page of `(flags, key, value)` items.
+  pub fn leaf_page(id: u64, items: &[(u32, &[u8], &[u8])]) -> (u64, Vec<u8>) {
+    let (elements, data) = leaf_body(items);
+    page(id, LEAF_PAGE, items.len(), &elements, &data)
+  }
+
+  fn leaf_body(items: &[(u32, &[u8], &[u8])]) -> (Vec<u8>, Vec<u8>) {
+    let mut elements = Vec::new();
+    let mut data = Vec::new();
+    for (index, (flags, key, value)) in items.iter().enumerate() {
+      let pos = (items.len() - index) * ELEMENT_SIZE + data.len();
+      for field in [*flags, pos as u32, key.len() as u32, value.len() as u32] {
+        elements.extend_from_slice(&field.to_le_bytes());
+      }
+      data.extend_from_slice(key);
+      data.extend_from_slice(value);
+    }
+    (elements, data)
+  }
+
+  /// A branch page pointing at `children`, keyed by their first key.
+  pub fn branch_page(id: u64, children: &[(&[u8], u64)]) -> (u64, Vec<u8>) {
+    let mut elements = Vec::new();
+    let mut data = Vec::new();
+    for (index, (key, child)) in children.iter().enumerate() {
+      let pos = (children.len() - index) * ELEMENT_SIZE + data.len();
+      elements.extend_from_slice(&(pos as u32).to_le_bytes());
+      elements.extend_from_slice(&(key.len() as u32).to_le_bytes());
+      elements.extend_from_slice(&child.to_le_bytes());
+      data.extend_from_slice(key);
+    }
+    page(id, BRANCH_PAGE, children.len(), &elements, &data)
+  }
+
+  /// The value of a bucket rooted at page `root`.
+  pub fn bucket(root: u64) -> Vec<u8> {
+    let mut value = root.to_le_bytes().to_vec();
+    value.extend_from_slice(&0u64.to_le_bytes());
+    value
+  }
+
+  /// The value of a bucket holding `items` inline.
+  pub fn inline_bucket(items: &[(u32, &[u8], &[u8])]) -> Vec<u8> {
+    let (elements, data) = leaf_body(items);
+    let mut value = bucket(0);
+    value.extend(page(0, LEAF_PAGE, items.len(), &elements, &data).1);
+    value
+  }
+
+  fn meta_page(id: u64, root: u64, txid: u64) -> (u64, Vec<u8>) {
+    let mut meta = Vec::with_capacity(META_SIZE);
+    meta.extend_from_slice(&MAGIC.to_le_bytes());
+    meta.extend_from_slice(&VERSION.to_le_bytes());
+    meta.extend_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+    meta.extend_from_slice(&0u32.to_le_bytes());
+    for field in [root, 0, 2, 16, txid] {
+      meta.extend_from_slice(&field.to_le_bytes());
+    }
+    let mut hasher = Fnv1aHasher::new();
+    hasher.write(&meta);
+    meta.extend_from_slice(&hasher.finish().to_le_bytes());
+    page(id, 0x04, 0, &meta, &[])
+  }
+
+  /// A database file whose root bucket is page `root`, with both meta pages
+  /// and the given pages at their ids.
+  pub fn file(root: u64, pages: &[(u64, Vec<u8>)]) -> Vec<u8> {
+    let mut file = Vec::new();
+    for (id, bytes) in [meta_page(0, root, 1), meta_page(1, root, 2)]
+      .iter()
+      .chain(pages)
+    {
+      let start = *id as usize * PAGE_SIZE;
+      let end = start + bytes.len().div_ceil(PAGE_SIZE) * PAGE_SIZE;
+      if file.len() < end {
+        file.resize(end, 0);
+      }
+      file[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+    file
+  }
+
+  #[test]
+  fn test_read_buckets_in_key_order() {
+    let large = vec![0xAB; PAGE_SIZE * 2];
+    let file = file(3, &[
+      leaf_page(3, &[
+        (BUCKET_LEAF, b"a", &inline_bucket(&[(0, b"k", b"inline")])),
+        (BUCKET_LEAF, b"b", &bucket(4)),
+      ]),
+      branch_page(4, &[(b"1", 5), (b"3", 8)]),
+      leaf_page(5, &[(0, b"1", b"one"), (0, b"2", &large)]),
+      leaf_page(8, &[
+        (0, b"3", b"three"),
+        (BUCKET_LEAF, b"nested", &inline_bucket(&[])),
+      ]),
+    ]);
+
+    let db = Bolt::open(&file).expect("valid database");
+    assert_eq!(
+      db.bucket(b"a").expect("readable"),
+      Some(vec![(&b"k"[..], &b"inline"[..])])
+    );
+    let pairs = db.bucket(b"b").expect("readable").expect("bucket exists");
+    let keys: Vec<&[u8]> = pairs.iter().map(|(key, _)| *key).collect();
+    assert_eq!(keys, [&b"1"[..], b"2", b"3"]);
+    assert_eq!(pairs[1].1, &large[..]);
+    assert_eq!(pairs[2].1, b"three");
+    assert!(db.bucket(b"c").expect("readable").is_none());
+  }
+
+  #[test]
+  fn test_reject_invalid_files() {
+    assert!(Bolt::open(b"").is_err());
+    assert!(Bolt::open(&[0; PAGE_SIZE * 4]).is_err());
+
+    // Corrupt the newest meta page: the older one is used instead.
+    let mut file = file(2, &[leaf_page(2, &[])]);
+    file[PAGE_SIZE + PAGE_HEADER_SIZE + 20] ^= 1;
+    let db = Bolt::open(&file).expect("first meta page is valid");
+    assert!(db.bucket(b"b").expect("readable").is_none());
+
+    // A bucket pointing past the end of the file.
+    let file = super::tests::file(2, &[leaf_page(2, &[(
+      BUCKET_LEAF,
+      b"b",
+      &bucket(99),
+    )])]);
+    let db = Bolt::open(&file).expect("valid meta pages");
+    assert!(db.bucket(b"b").unwrap_err().contains("out of bounds"));
+  }
+}
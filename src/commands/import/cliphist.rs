@@ -0,0 +1,18 @@
+//! cliphist's BoltDB database.
+
+use super::{History, bolt::Bolt};
+
+/// Bucket holding the history, keyed by big-endian ids in the order entries
+/// were stored.
+pub const BUCKET: &[u8] = b"b";
+
+pub fn parse(data: &[u8]) -> Result<History, String> {
+  let entries = Bolt::open(data)?
+    .bucket(BUCKET)?
+    .ok_or("no clipboard history found")?;
+  let mut history = History::default();
+  for (_, contents) in entries {
+    history.push(contents.to_vec());
+  }
+  Ok(history)
+}
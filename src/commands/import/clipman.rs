@@ -0,0 +1,31 @@
+//! clipman's history: a JSON array of strings, oldest first.
+
+use super::History;
+
+pub fn parse(data: &[u8]) -> Result<History, String> {
+  let items: Vec<String> =
+    serde_json::from_slice(data).map_err(|e| e.to_string())?;
+  let mut history = History::default();
+  for item in items {
+    history.push(item.into_bytes());
+  }
+  Ok(history)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse() {
+    let history =
+      parse(r#"["first", "", "second\nline", "é"]"#.as_bytes()).expect("valid");
+    assert_eq!(history.entries, [
+      b"first".to_vec(),
+      b"second\nline".to_vec(),
+      "é".as_bytes().to_vec()
+    ]);
+    assert_eq!(history.skipped, 1);
+    assert!(parse(b"{}").is_err());
+  }
+}
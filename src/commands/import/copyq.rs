@@ -0,0 +1,237 @@
+//! CopyQ's exported `.cpq` files.
+//!
+//! An export is a `QDataStream` that starts with the byte array `CopyQ v3` (or
+//! `v4`), followed by a variant map whose `tabs` list holds a map per tab. A
+//! tab's items are serialized in its `data` byte array, newest first: a count,
+//! then for each item its MIME types and contents, optionally compressed with
+//! `qCompress`.
+//!
+//! Newer exports abbreviate MIME types by replacing the common `text/`,
+//! `image/`, ... part with a single character, so representations are chosen
+//! by their subtype. The MIME type stored is detected from the contents, as for
+//! the other imports.
+
+use super::{
+  History,
+  qdatastream::{Reader, Variant},
+};
+
+/// Marker of the item serialization with compressed MIME types.
+const ITEM_V2: i32 = -2;
+
+/// Subtypes of the representations worth importing, most preferred first.
+const PREFERRED_SUBTYPES: &[&str] = &[
+  "plain", "uri-list", "png", "jpeg", "gif", "webp", "bmp", "svg+xml",
+];
+
+/// The MIME types and contents of one item.
+type Item = Vec<(String, Vec<u8>)>;
+
+pub fn parse(data: &[u8]) -> Result<History, String> {
+  let mut reader = Reader::new(data);
+  let header = reader.byte_array()?;
+  if !header.starts_with(b"CopyQ v") {
+    return Err("not a CopyQ export".into());
+  }
+  if !matches!(header, b"CopyQ v3" | b"CopyQ v4") {
+    return Err(format!(
+      "unsupported export version `{}`, export again with a recent CopyQ",
+      String::from_utf8_lossy(header)
+    ));
+  }
+
+  let root = reader.variant()?;
+  let Some(Variant::List(tabs)) = root.get("tabs") else {
+    return Err("the export contains no tabs".into());
+  };
+
+  let mut history = History::default();
+  for tab in tabs {
+    let items = match tab.get("data") {
+      Some(Variant::ByteArray(data)) => read_items(data)?,
+      Some(Variant::List(items)) => items.iter().map(variant_item).collect(),
+      _ => Vec::new(),
+    };
+    for item in items.into_iter().rev() {
+      match pick(item) {
+        Some(contents) => history.push(contents),
+        None => history.skip(),
+      }
+    }
+  }
+  Ok(history)
+}
+
+/// Items serialized in a tab's `data`.
+fn read_items(data: &[u8]) -> Result<Vec<Item>, String> {
+  let mut reader = Reader::new(data);
+  let count = reader.i32()?;
+  let mut items = Vec::new();
+  for _ in 0..count {
+    items.push(read_item(&mut reader)?);
+  }
+  Ok(items)
+}
+
+fn read_item(reader: &mut Reader) -> Result<Item, String> {
+  let mut item = Vec::new();
+  match reader.i32()? {
+    ITEM_V2 => {
+      for _ in 0..reader.i32()? {
+        let mime = String::from_utf8_lossy(reader.byte_array()?).into_owned();
+        let compressed = reader.bool()?;
+        let contents = reader.byte_array()?;
+        let contents = if compressed {
+          uncompress(contents)?
+        } else {
+          contents.to_vec()
+        };
+        item.push((mime, contents));
+      }
+    },
+    count if count >= 0 => {
+      // Original serialization: full MIME types, contents always compressed.
+      for _ in 0..count {
+        let mime = reader.string()?;
+        let contents = reader.byte_array()?;
+        let contents = if contents.is_empty() {
+          Vec::new()
+        } else {
+          uncompress(contents)?
+        };
+        item.push((mime, contents));
+      }
+    },
+    other => return Err(format!("unsupported item serialization {other}")),
+  }
+  Ok(item)
+}
+
+/// An item stored as a variant map from MIME type to contents.
+fn variant_item(item: &Variant) -> Item {
+  let Variant::Map(entries) = item else {
+    return Vec::new();
+  };
+  entries
+    .iter()
+    .filter_map(|(mime, value)| {
+      match value {
+        Variant::ByteArray(contents) => Some((mime.clone(), contents.clone())),
+        Variant::String(text) => {
+          Some((mime.clone(), text.clone().into_bytes()))
+        },
+        _ => None,
+      }
+    })
+    .collect()
+}
+
+/// Contents of the most preferred representation of `item`.
+fn pick(item: Item) -> Option<Vec<u8>> {
+  let rank = |mime: &str| {
+    if mime.contains("x-copyq-") {
+      return None;
+    }
+    let subtype = mime.rsplit('/').next().unwrap_or(mime);
+    let subtype = subtype.split(';').next().unwrap_or(subtype).trim();
+    PREFERRED_SUBTYPES.iter().position(|preferred| {
+      // Abbreviated types keep their one-character prefix.
+      subtype == *preferred
+        || (!mime.contains('/') && subtype.get(1..) == Some(*preferred))
+    })
+  };
+  item
+    .into_iter()
+    .filter(|(_, contents)| !contents.is_empty())
+    .filter_map(|(mime, contents)| rank(&mime).map(|rank| (rank, contents)))
+    .min_by_key(|(rank, _)| *rank)
+    .map(|(_, contents)| contents)
+}
+
+/// Undo `qCompress`: a big-endian length followed by a zlib stream.
+fn uncompress(data: &[u8]) -> Result<Vec<u8>, String> {
+  let stream = data.get(4..).ok_or("truncated compressed data")?;
+  miniz_oxide::inflate::decompress_to_vec_zlib(stream)
+    .map_err(|e| format!("invalid compressed data: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{super::qdatastream::tests::Writer, *};
+
+  fn compress(data: &[u8]) -> Vec<u8> {
+    let mut compressed = (data.len() as u32).to_be_bytes().to_vec();
+    compressed.extend(miniz_oxide::deflate::compress_to_vec_zlib(data, 6));
+    compressed
+  }
+
+  #[test]
+  fn test_parse() {
+    let mut items = Writer::default();
+    items.u32(4);
+    // Newest: abbreviated MIME types, the text compressed.
+    items.u32(ITEM_V2 as u32).u32(3);
+    items.byte_array(b"0owner").bool(false).byte_array(b"me");
+    items
+      .byte_array(b"Thtml")
+      .bool(false)
+      .byte_array(b"<b>new</b>");
+    items
+      .byte_array(b"Tplain")
+      .bool(true)
+      .byte_array(&compress(b"new"));
+    // An image.
+    items.u32(ITEM_V2 as u32).u32(1);
+    items
+      .byte_array(b"image/png")
+      .bool(false)
+      .byte_array(b"\x89PNG");
+    // Only CopyQ's own data.
+    items.u32(ITEM_V2 as u32).u32(1);
+    items
+      .byte_array(b"application/x-copyq-tags")
+      .bool(false)
+      .byte_array(b"tag");
+    // Oldest: original serialization.
+    items
+      .u32(1)
+      .string("text/plain")
+      .byte_array(&compress(b"old"));
+
+    let tab = |name: &str, data: Variant| {
+      Variant::Map(vec![
+        ("name".to_owned(), Variant::String(name.to_owned())),
+        ("data".to_owned(), data),
+      ])
+    };
+    let export = Variant::Map(vec![(
+      "tabs".to_owned(),
+      Variant::List(vec![
+        tab("&clipboard", Variant::ByteArray(items.0)),
+        tab(
+          "notes",
+          Variant::List(vec![Variant::Map(vec![(
+            "text/plain".to_owned(),
+            Variant::ByteArray(b"note".to_vec()),
+          )])]),
+        ),
+      ]),
+    )]);
+    let mut file = Writer::default();
+    file.byte_array(b"CopyQ v3").variant(&export);
+
+    let history = parse(&file.0).expect("valid export");
+    assert_eq!(history.entries, [
+      b"old".to_vec(),
+      b"\x89PNG".to_vec(),
+      b"new".to_vec(),
+      b"note".to_vec(),
+    ]);
+    assert_eq!(history.skipped, 1);
+
+    let mut old = Writer::default();
+    old.byte_array(b"CopyQ v2");
+    assert!(parse(&old.0).unwrap_err().contains("CopyQ v2"));
+    assert!(parse(b"\0\0\0\x04nope").is_err());
+  }
+}
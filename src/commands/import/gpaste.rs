@@ -0,0 +1,157 @@
+//! GPaste's `history.xml`, newest item first.
+//!
+//! ```xml
+//! <history version="2.0">
+//!   <item kind="Text" uuid="..."><value><![CDATA[hello]]></value></item>
+//!   <item kind="Image" uuid="..."><value><![CDATA[/path/to.png]]></value></item>
+//! </history>
+//! ```
+//!
+//! Version 1.0 files hold the value directly in `<item>`. Images are stored
+//! as files next to the history and read from there; password items are
+//! skipped.
+
+use std::fs;
+
+use quick_xml::{Reader, escape, events::Event};
+
+use super::History;
+
+/// An `<item>` being read.
+#[derive(Default)]
+struct Item {
+  kind:  String,
+  /// Text directly inside `<item>`, the value in version 1.0 files.
+  text:  String,
+  /// Text inside `<value>`.
+  value: Option<String>,
+}
+
+pub fn parse(data: &[u8]) -> Result<History, String> {
+  let data = std::str::from_utf8(data).map_err(|e| e.to_string())?;
+  let mut reader = Reader::from_str(data);
+  let mut items = Vec::new();
+  let mut item: Option<Item> = None;
+  let mut in_value = false;
+
+  loop {
+    let event = reader
+      .read_event()
+      .map_err(|e| format!("at offset {}: {e}", reader.error_position()))?;
+    let text = match event {
+      Event::Start(tag) if tag.name().as_ref() == b"item" => {
+        let kind = tag
+          .try_get_attribute("kind")
+          .map_err(|e| e.to_string())?
+          .map(|kind| kind.unescape_value().map(|kind| kind.into_owned()))
+          .transpose()
+          .map_err(|e| e.to_string())?
+          .unwrap_or_default();
+        item = Some(Item {
+          kind,
+          ..Item::default()
+        });
+        continue;
+      },
+      Event::Start(tag) if tag.name().as_ref() == b"value" => {
+        in_value = true;
+        if let Some(item) = &mut item {
+          item.value.get_or_insert_default();
+        }
+        continue;
+      },
+      Event::End(tag) if tag.name().as_ref() == b"value" => {
+        in_value = false;
+        continue;
+      },
+      Event::End(tag) if tag.name().as_ref() == b"item" => {
+        items.extend(item.take());
+        continue;
+      },
+      Event::Eof => break,
+      Event::Text(text) => text.decode().map_err(|e| e.to_string())?,
+      Event::CData(text) => text.decode().map_err(|e| e.to_string())?,
+      Event::GeneralRef(reference) => {
+        if let Some(c) =
+          reference.resolve_char_ref().map_err(|e| e.to_string())?
+        {
+          c.to_string().into()
+        } else {
+          let name = reference.decode().map_err(|e| e.to_string())?;
+          escape::unescape(&format!("&{name};"))
+            .map_err(|e| e.to_string())?
+            .into_owned()
+            .into()
+        }
+      },
+      _ => continue,
+    };
+
+    if let Some(item) = &mut item {
+      match &mut item.value {
+        Some(value) if in_value => value.push_str(&text),
+        Some(_) => {},
+        None => item.text.push_str(&text),
+      }
+    }
+  }
+
+  let mut history = History::default();
+  for item in items.into_iter().rev() {
+    let value = item.value.unwrap_or(item.text);
+    match item.kind.as_str() {
+      "Text" | "Uris" => history.push(value.into_bytes()),
+      "Image" => {
+        match fs::read(value.trim()) {
+          Ok(image) => history.push(image),
+          Err(e) => {
+            log::warn!("skipping GPaste image {}: {e}", value.trim());
+            history.skip();
+          },
+        }
+      },
+      _ => history.skip(),
+    }
+  }
+  Ok(history)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_both_versions() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let image = dir.path().join("image.png");
+    fs::write(&image, b"\x89PNG data").expect("write image");
+
+    let xml = format!(
+      r#"<?xml version="1.0" encoding="UTF-8"?>
+<history version="2.0">
+  <item kind="Text" uuid="3"><value><![CDATA[newest <b>]]></value></item>
+  <item kind="Password" uuid="2" name="bank"><value><![CDATA[hunter2]]></value></item>
+  <item kind="Image" uuid="1"><value><![CDATA[{}]]></value></item>
+  <item kind="Image" uuid="1"><value><![CDATA[/nonexistent.png]]></value></item>
+  <item kind="Uris" uuid="0"><value>file:///a&#10;file:///b&amp;c</value></item>
+</history>"#,
+      image.display()
+    );
+    let history = parse(xml.as_bytes()).expect("valid history");
+    assert_eq!(history.entries, [
+      b"file:///a\nfile:///b&c".to_vec(),
+      b"\x89PNG data".to_vec(),
+      b"newest <b>".to_vec(),
+    ]);
+    assert_eq!(history.skipped, 2);
+
+    let v1 = r#"<history version="1.0">
+  <item kind="Text" date="1"><![CDATA[second]]></item>
+  <item kind="Text" date="0">first &lt;1&gt;</item>
+</history>"#;
+    let history = parse(v1.as_bytes()).expect("valid history");
+    assert_eq!(history.entries, [b"first <1>".to_vec(), b"second".to_vec()]);
+
+    assert!(parse(b"<history><item></history>").is_err());
+  }
+}
@@ -0,0 +1,134 @@
+//! Klipper's `history2.lst`, newest item first.
+//!
+//! The file is a `QDataStream`: a CRC-16 checksum, then a byte array holding
+//! Klipper's version string followed by the items. Each item starts with its
+//! type, `string`, `url` or `image`, which decides how the rest is laid out.
+//! Plasma 6 moved the history to an SQLite database, which is not read here.
+
+use super::{History, qdatastream::Reader};
+
+/// Signature of a PNG file, which images are serialized as.
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+pub fn parse(data: &[u8]) -> Result<History, String> {
+  let mut file = Reader::new(data);
+  let _checksum = file.u32()?;
+  let mut reader = Reader::new(file.byte_array()?);
+  let _version = reader.byte_array()?;
+
+  let mut items = Vec::new();
+  while !reader.is_at_end() {
+    let kind = reader.string()?;
+    match kind.as_str() {
+      "string" => items.push(Some(reader.string()?.into_bytes())),
+      "url" => {
+        let count = reader.u32()?;
+        let mut urls = Vec::new();
+        for _ in 0..count {
+          urls.push(String::from_utf8_lossy(reader.byte_array()?).into_owned());
+        }
+        // Metadata (a map of strings), then whether the URLs were cut.
+        for _ in 0..reader.u32()? {
+          reader.string()?;
+          reader.string()?;
+        }
+        reader.i32()?;
+        items.push(Some(urls.join("\n").into_bytes()));
+      },
+      "image" => {
+        if reader.i32()? == 0 {
+          // A null image.
+          items.push(None);
+        } else {
+          let len = png_len(reader.remaining())?;
+          let png = reader.remaining()[..len].to_vec();
+          reader.skip(len)?;
+          items.push(Some(png));
+        }
+      },
+      other => return Err(format!("unsupported item type `{other}`")),
+    }
+  }
+
+  let mut history = History::default();
+  for item in items.into_iter().rev() {
+    match item {
+      Some(contents) => history.push(contents),
+      None => history.skip(),
+    }
+  }
+  Ok(history)
+}
+
+/// Length of the PNG image at the start of `data`, which is only known by
+/// walking its chunks up to `IEND`.
+fn png_len(data: &[u8]) -> Result<usize, String> {
+  if !data.starts_with(PNG_SIGNATURE) {
+    return Err("image item is not a PNG".into());
+  }
+  let mut chunks = Reader::new(&data[PNG_SIGNATURE.len()..]);
+  loop {
+    let len = chunks.u32()? as usize;
+    let kind = chunks.u32()?;
+    // Chunk data and its CRC.
+    chunks.skip(len.saturating_add(4))?;
+    if kind.to_be_bytes() == *b"IEND" {
+      return Ok(data.len() - chunks.remaining().len());
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{super::qdatastream::tests::Writer, *};
+
+  /// A minimal PNG: signature, one empty chunk, then `IEND`.
+  fn png() -> Vec<u8> {
+    let mut png = PNG_SIGNATURE.to_vec();
+    for (kind, data) in [(b"IHDR", &b"header"[..]), (b"IEND", &b""[..])] {
+      png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+      png.extend_from_slice(kind);
+      png.extend_from_slice(data);
+      png.extend_from_slice(&[0; 4]);
+    }
+    png
+  }
+
+  #[test]
+  fn test_parse() {
+    let mut items = Writer::default();
+    items.byte_array(b"5.27.10\0");
+    items.string("string").string("newest");
+    items.string("image").u32(1);
+    items.0.extend(png());
+    items.string("image").u32(0);
+    items
+      .string("url")
+      .u32(2)
+      .byte_array(b"file:///a")
+      .byte_array(b"file:///b")
+      .u32(1)
+      .string("key")
+      .string("value")
+      .u32(0);
+    items.string("string").string("oldest ✓");
+
+    let mut file = Writer::default();
+    file.u32(0xBEEF).byte_array(&items.0);
+
+    let history = parse(&file.0).expect("valid history");
+    assert_eq!(history.entries, [
+      "oldest ✓".as_bytes().to_vec(),
+      b"file:///a\nfile:///b".to_vec(),
+      png(),
+      b"newest".to_vec(),
+    ]);
+    assert_eq!(history.skipped, 1);
+
+    let mut unknown = Writer::default();
+    unknown.byte_array(b"6.0\0").string("sound");
+    let mut file = Writer::default();
+    file.u32(0).byte_array(&unknown.0);
+    assert!(parse(&file.0).unwrap_err().contains("sound"));
+  }
+}
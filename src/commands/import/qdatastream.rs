@@ -0,0 +1,307 @@
+//! Reading Qt's `QDataStream` serialization, used by Klipper's history and
+//! CopyQ's exports.
+//!
+//! Only the types those files contain are supported. Everything is big
+//! endian, strings are UTF-16 and the variant encoding is the one of
+//! `QDataStream::Qt_4_7` and later.
+
+/// Length marking a null `QString` or `QByteArray`.
+const NULL_LENGTH: u32 = u32::MAX;
+
+/// A `QVariant` of one of the supported types.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Variant {
+  Invalid,
+  Bool(bool),
+  Int(i64),
+  Double(f64),
+  String(String),
+  StringList(Vec<String>),
+  ByteArray(Vec<u8>),
+  List(Vec<Self>),
+  Map(Vec<(String, Self)>),
+}
+
+impl Variant {
+  /// The value of `key`, if this is a map containing it.
+  pub fn get(&self, key: &str) -> Option<&Self> {
+    match self {
+      Self::Map(entries) => {
+        entries
+          .iter()
+          .find(|(k, _)| k == key)
+          .map(|(_, value)| value)
+      },
+      _ => None,
+    }
+  }
+}
+
+/// A cursor over serialized data.
+pub struct Reader<'a> {
+  data: &'a [u8],
+  pos:  usize,
+}
+
+impl<'a> Reader<'a> {
+  pub fn new(data: &'a [u8]) -> Self {
+    Self { data, pos: 0 }
+  }
+
+  pub fn is_at_end(&self) -> bool {
+    self.pos >= self.data.len()
+  }
+
+  /// The data that has not been read yet.
+  pub fn remaining(&self) -> &'a [u8] {
+    &self.data[self.pos.min(self.data.len())..]
+  }
+
+  pub fn skip(&mut self, len: usize) -> Result<(), String> {
+    self.take(len).map(|_| ())
+  }
+
+  fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+    let bytes = self
+      .pos
+      .checked_add(len)
+      .and_then(|end| self.data.get(self.pos..end))
+      .ok_or_else(|| {
+        format!("unexpected end of data at offset {}", self.pos)
+      })?;
+    self.pos += len;
+    Ok(bytes)
+  }
+
+  fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+    let bytes = self.take(N)?;
+    Ok(bytes.try_into().unwrap_or([0; N]))
+  }
+
+  pub fn u8(&mut self) -> Result<u8, String> {
+    Ok(self.array::<1>()?[0])
+  }
+
+  pub fn bool(&mut self) -> Result<bool, String> {
+    Ok(self.u8()? != 0)
+  }
+
+  pub fn u32(&mut self) -> Result<u32, String> {
+    Ok(u32::from_be_bytes(self.array()?))
+  }
+
+  pub fn i32(&mut self) -> Result<i32, String> {
+    Ok(i32::from_be_bytes(self.array()?))
+  }
+
+  fn len(&mut self) -> Result<Option<usize>, String> {
+    match self.u32()? {
+      NULL_LENGTH => Ok(None),
+      len => Ok(Some(len as usize)),
+    }
+  }
+
+  /// A `QByteArray`; null arrays are read as empty.
+  pub fn byte_array(&mut self) -> Result<&'a [u8], String> {
+    match self.len()? {
+      Some(len) => self.take(len),
+      None => Ok(&[]),
+    }
+  }
+
+  /// A `QString`; null strings are read as empty.
+  pub fn string(&mut self) -> Result<String, String> {
+    let Some(len) = self.len()? else {
+      return Ok(String::new());
+    };
+    if len % 2 != 0 {
+      return Err(format!("odd string length {len} at offset {}", self.pos));
+    }
+    let units: Vec<u16> = self
+      .take(len)?
+      .chunks_exact(2)
+      .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+      .collect();
+    Ok(String::from_utf16_lossy(&units))
+  }
+
+  fn count(&mut self) -> Result<usize, String> {
+    let count = self.u32()? as usize;
+    // Every element takes at least four bytes, which bounds preallocation on
+    // corrupt input.
+    if count > self.remaining().len() / 4 {
+      return Err(format!("too many elements at offset {}", self.pos));
+    }
+    Ok(count)
+  }
+
+  /// A `QVariant`.
+  pub fn variant(&mut self) -> Result<Variant, String> {
+    let type_id = self.u32()?;
+    let _is_null = self.bool()?;
+    Ok(match type_id {
+      0 => {
+        // Invalid variants are followed by an empty string.
+        self.string()?;
+        Variant::Invalid
+      },
+      1 => Variant::Bool(self.bool()?),
+      2 => Variant::Int(self.i32()?.into()),
+      3 => Variant::Int(self.u32()?.into()),
+      4 => Variant::Int(i64::from_be_bytes(self.array()?)),
+      6 => Variant::Double(f64::from_be_bytes(self.array()?)),
+      8 => {
+        let count = self.count()?;
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+          entries.push((self.string()?, self.variant()?));
+        }
+        Variant::Map(entries)
+      },
+      9 => {
+        let count = self.count()?;
+        let mut items = Vec::with_capacity(count);
+        for _ in 0..count {
+          items.push(self.variant()?);
+        }
+        Variant::List(items)
+      },
+      10 => Variant::String(self.string()?),
+      11 => {
+        let count = self.count()?;
+        let mut strings = Vec::with_capacity(count);
+        for _ in 0..count {
+          strings.push(self.string()?);
+        }
+        Variant::StringList(strings)
+      },
+      12 => Variant::ByteArray(self.byte_array()?.to_vec()),
+      other => {
+        return Err(format!(
+          "unsupported variant type {other} at offset {}",
+          self.pos
+        ));
+      },
+    })
+  }
+}
+
+/// Serialization of the same types, for building test data.
+#[cfg(test)]
+pub mod tests {
+  use super::*;
+
+  #[derive(Default)]
+  pub struct Writer(pub Vec<u8>);
+
+  impl Writer {
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+      self.0.extend_from_slice(&value.to_be_bytes());
+      self
+    }
+
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+      self.0.push(u8::from(value));
+      self
+    }
+
+    pub fn byte_array(&mut self, bytes: &[u8]) -> &mut Self {
+      self.u32(bytes.len() as u32);
+      self.0.extend_from_slice(bytes);
+      self
+    }
+
+    pub fn string(&mut self, string: &str) -> &mut Self {
+      let units: Vec<u16> = string.encode_utf16().collect();
+      self.u32(units.len() as u32 * 2);
+      for unit in units {
+        self.0.extend_from_slice(&unit.to_be_bytes());
+      }
+      self
+    }
+
+    pub fn variant(&mut self, variant: &Variant) -> &mut Self {
+      let type_id = match variant {
+        Variant::Invalid => 0,
+        Variant::Bool(_) => 1,
+        Variant::Int(_) => 4,
+        Variant::Double(_) => 6,
+        Variant::Map(_) => 8,
+        Variant::List(_) => 9,
+        Variant::String(_) => 10,
+        Variant::StringList(_) => 11,
+        Variant::ByteArray(_) => 12,
+      };
+      self.u32(type_id).bool(false);
+      match variant {
+        Variant::Invalid => self.string(""),
+        Variant::Bool(value) => self.bool(*value),
+        Variant::Int(value) => {
+          self.0.extend_from_slice(&value.to_be_bytes());
+          self
+        },
+        Variant::Double(value) => {
+          self.0.extend_from_slice(&value.to_be_bytes());
+          self
+        },
+        Variant::Map(entries) => {
+          self.u32(entries.len() as u32);
+          for (key, value) in entries {
+            self.string(key).variant(value);
+          }
+          self
+        },
+        Variant::List(items) => {
+          self.u32(items.len() as u32);
+          for item in items {
+            self.variant(item);
+          }
+          self
+        },
+        Variant::String(string) => self.string(string),
+        Variant::StringList(strings) => {
+          self.u32(strings.len() as u32);
+          for string in strings {
+            self.string(string);
+          }
+          self
+        },
+        Variant::ByteArray(bytes) => self.byte_array(bytes),
+      }
+    }
+  }
+
+  #[test]
+  fn test_variants_round_trip() {
+    let variant = Variant::Map(vec![
+      ("name".to_owned(), Variant::String("tab ✓".to_owned())),
+      (
+        "items".to_owned(),
+        Variant::List(vec![
+          Variant::ByteArray(b"\0\xff".to_vec()),
+          Variant::Bool(true),
+          Variant::Int(-3),
+          Variant::Double(0.5),
+          Variant::StringList(vec!["a".to_owned(), String::new()]),
+          Variant::Invalid,
+        ]),
+      ),
+    ]);
+    let mut writer = Writer::default();
+    writer.variant(&variant);
+
+    let mut reader = Reader::new(&writer.0);
+    assert_eq!(reader.variant().expect("valid variant"), variant);
+    assert!(reader.is_at_end());
+    assert_eq!(
+      variant.get("name"),
+      Some(&Variant::String("tab ✓".to_owned()))
+    );
+
+    let mut null = Reader::new(&[0xFF; 8]);
+    assert_eq!(null.byte_array().expect("null array"), b"");
+    assert_eq!(null.string().expect("null string"), "");
+    assert!(Reader::new(&[0, 0, 0, 9, 1]).byte_array().is_err());
+    assert!(Reader::new(&[0, 0, 0, 99, 0]).variant().is_err());
+  }
+}
@@ -1,9 +1,36 @@
-use std::io::Write;
+mod detail;
+mod keymap;
+mod marks;
+mod preview;
+mod theme;
+
+use std::{
+  collections::HashMap,
+  io::Write,
+  time::{Duration, Instant},
+};
 
 use unicode_segmentation::UnicodeSegmentation;
 use unicode_width::UnicodeWidthStr;
 
-use crate::db::{ClipboardDb, SqliteClipboardDb, StashError};
+use self::{
+  keymap::{Action, Key, Keymap},
+  theme::Theme,
+};
+use crate::{
+  clipboard::{Selection, mime_sources},
+  commands::snippet::{expand_placeholders, snippet_preview},
+  db::{
+    ClipboardDb,
+    ListEntry,
+    SearchMode,
+    SearchQuery,
+    SqliteClipboardDb,
+    StashError,
+    snippets::Snippet,
+  },
+  ipc,
+};
 
 #[cfg(feature = "notifications")]
 fn notify(summary: &str, body: &str) {
@@ -17,12 +44,20 @@ fn notify(summary: &str, body: &str) {
 fn notify(_summary: &str, _body: &str) {}
 
 pub trait ListCommand {
+  #[expect(
+    clippy::too_many_arguments,
+    reason = "list filters mirror the CLI options"
+  )]
   fn list(
     &self,
     out: impl Write,
     preview_width: u32,
     include_expired: bool,
     reverse: bool,
+    pinned_first: bool,
+    selection: Option<Selection>,
+    tags: &[String],
+    search: Option<&SearchQuery>,
   ) -> Result<(), StashError>;
 }
 
@@ -33,13 +68,47 @@ impl ListCommand for SqliteClipboardDb {
     preview_width: u32,
     include_expired: bool,
     reverse: bool,
+    pinned_first: bool,
+    selection: Option<Selection>,
+    tags: &[String],
+    search: Option<&SearchQuery>,
   ) -> Result<(), StashError> {
     self
-      .list_entries(out, preview_width, include_expired, reverse)
+      .list_entries(
+        out,
+        preview_width,
+        include_expired,
+        reverse,
+        pinned_first,
+        selection,
+        tags,
+        search,
+      )
       .map(|_| ())
   }
 }
 
+/// How long rows stored while the TUI is open stay highlighted.
+const FLASH_DURATION: Duration = Duration::from_millis(1500);
+
+/// Which list the TUI shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tab {
+  /// The clipboard history.
+  History,
+  /// Saved snippets, see `stash snippet`.
+  Snippets,
+}
+
+/// A move of the cursor by more than one entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Jump {
+  /// By this many screens, down if positive, stopping at either end.
+  Pages(i64),
+  Top,
+  Bottom,
+}
+
 /// All mutable state for the TUI list view.
 struct TuiState {
   /// Total number of entries matching the current filter in the DB.
@@ -51,8 +120,8 @@ struct TuiState {
   /// DB offset of `window[0]`, i.e., the first row currently loaded.
   viewport_offset: usize,
 
-  /// The loaded slice of entries: `(id, preview, mime)`.
-  window: Vec<(i64, String, String)>,
+  /// The loaded slice of entries.
+  window: Vec<ListEntry>,
 
   /// How many rows the window holds (== visible list height).
   window_size: usize,
@@ -66,63 +135,259 @@ struct TuiState {
   /// Whether we're currently in search input mode.
   search_mode: bool,
 
+  /// How the search query is matched.
+  match_mode: SearchMode,
+
+  /// The parsed search query, `None` when it does not filter anything.
+  search: Option<SearchQuery>,
+
+  /// Why the search query does not parse, e.g. an invalid regex. The previous
+  /// search stays in effect until it is fixed.
+  search_error: Option<String>,
+
   /// Whether to show entries in reverse order (oldest first).
   reverse: bool,
 
+  /// Whether pinned entries are listed before all others.
+  pinned_first: bool,
+
+  /// Only show entries copied to this selection.
+  selection: Option<Selection>,
+
+  /// Only show entries carrying all of these tags.
+  tags: Vec<String>,
+
   /// ID of entry currently being copied.
   copying_entry: Option<i64>,
 
-  /// Entry waiting for a second delete keypress.
-  pending_delete: Option<i64>,
+  /// Entries waiting for a second delete keypress.
+  pending_delete: Option<Vec<i64>>,
 
   /// Short status text shown in the TUI title.
   status: Option<String>,
+
+  /// Last seen `data_version`, to notice entries stored by other processes.
+  data_version: i64,
+
+  /// Largest entry id seen so far; larger ids are new arrivals.
+  newest_id: i64,
+
+  /// Newly arrived entries and when they arrived, for highlighting.
+  flashes: HashMap<i64, Instant>,
+
+  /// Whether image entries get a preview pane.
+  show_preview: bool,
+
+  /// Whether the detail pane with the full contents of the selected entry
+  /// is shown.
+  show_details: bool,
+
+  /// The list being shown.
+  tab: Tab,
+
+  /// All saved snippets. There are few enough to keep them loaded and
+  /// search them in memory.
+  snippets: Vec<Snippet>,
+
+  /// Index of the selected snippet among those matching the search.
+  snippet_cursor: usize,
+
+  /// Marked entries, in the order they were marked.
+  marked: Vec<i64>,
+
+  /// Id and position of the entry a visual range started at, while one is
+  /// being selected.
+  visual_anchor: Option<(i64, usize)>,
+
+  /// What the keys do.
+  keymap: Keymap,
+
+  /// Colours of the list and the panes.
+  theme: Theme,
+
+  /// Whether the key help is shown over the list.
+  show_help: bool,
+
+  /// The watch daemon serving the database, if one is running. The history
+  /// is then listed and changed through it.
+  daemon: Option<ipc::Client>,
 }
 
 impl TuiState {
   /// Create initial state: count total rows, load the first window.
+  #[expect(clippy::too_many_arguments)]
   fn new(
     db: &SqliteClipboardDb,
     include_expired: bool,
     window_size: usize,
     preview_width: u32,
     reverse: bool,
+    pinned_first: bool,
+    selection: Option<Selection>,
+    tags: &[String],
+    mut daemon: Option<ipc::Client>,
   ) -> Result<Self, StashError> {
-    let total = db.count_entries(include_expired, None)?;
-    let window = if total > 0 {
-      db.fetch_entries_window(
-        include_expired,
-        0,
-        window_size,
-        preview_width,
-        None,
-        reverse,
-      )?
-    } else {
-      Vec::new()
+    let (data_version, newest_id, snippets) = match &mut daemon {
+      Some(daemon) => {
+        let changes = daemon.changes(i64::MAX)?;
+        (changes.version, changes.newest_id, daemon.snippets()?)
+      },
+      None => (db.data_version()?, db.max_id()?, db.snippets(None)?),
     };
-    Ok(Self {
-      total,
+    let mut tui = Self {
+      total: 0,
       cursor: 0,
       viewport_offset: 0,
-      window,
+      window: Vec::new(),
       window_size,
-      dirty: false,
+      dirty: true,
       search_query: String::new(),
       search_mode: false,
+      match_mode: SearchMode::default(),
+      search: None,
+      search_error: None,
       reverse,
+      pinned_first,
+      selection,
+      tags: tags.to_vec(),
       copying_entry: None,
       pending_delete: None,
       status: None,
-    })
+      data_version,
+      newest_id,
+      flashes: HashMap::new(),
+      show_preview: true,
+      show_details: false,
+      tab: Tab::History,
+      snippets,
+      snippet_cursor: 0,
+      marked: Vec::new(),
+      visual_anchor: None,
+      keymap: Keymap::default(),
+      theme: Theme::default(),
+      show_help: false,
+      daemon,
+    };
+    tui.recount(db, include_expired)?;
+    tui.sync(db, include_expired, preview_width)?;
+    Ok(tui)
+  }
+
+  /// Count the entries matching the current filter.
+  fn recount(
+    &mut self,
+    db: &SqliteClipboardDb,
+    include_expired: bool,
+  ) -> Result<(), StashError> {
+    self.total = match &mut self.daemon {
+      Some(daemon) => {
+        let (_, total) = daemon.list(
+          include_expired,
+          self.reverse,
+          self.pinned_first,
+          self.selection,
+          &self.tags,
+          self.search.as_ref(),
+          0,
+          0,
+          0,
+        )?;
+        total
+      },
+      None => {
+        db.count_entries(
+          include_expired,
+          self.search.as_ref(),
+          self.selection,
+          &self.tags,
+        )?
+      },
+    };
+    Ok(())
+  }
+
+  /// Delete entry `id`. Returns how many entries were deleted.
+  fn delete_entry(
+    &mut self,
+    db: &SqliteClipboardDb,
+    id: i64,
+  ) -> Result<usize, StashError> {
+    match &mut self.daemon {
+      Some(daemon) => daemon.delete(id).map(|_| 1),
+      None => {
+        db.conn
+          .execute("DELETE FROM clipboard WHERE id = ?1", rusqlite::params![id])
+          .map_err(|e| StashError::DeleteEntry(id, e.to_string().into()))
+      },
+    }
+  }
+
+  /// Pin or unpin entry `id`.
+  fn set_pinned(
+    &mut self,
+    db: &SqliteClipboardDb,
+    id: i64,
+    pinned: bool,
+  ) -> Result<(), StashError> {
+    match &mut self.daemon {
+      Some(daemon) => daemon.pin(id, pinned).map(|_| ()),
+      None => db.set_pinned(id, pinned),
+    }
+  }
+
+  /// Move the cursor onto entry `id` if it is still listed, e.g. after
+  /// pinning moved it. Returns its position.
+  fn follow(
+    &mut self,
+    db: &SqliteClipboardDb,
+    id: i64,
+    include_expired: bool,
+  ) -> Result<Option<usize>, StashError> {
+    let position = match &mut self.daemon {
+      Some(daemon) => {
+        daemon.position(
+          id,
+          include_expired,
+          self.reverse,
+          self.pinned_first,
+          self.selection,
+          &self.tags,
+          self.search.as_ref(),
+        )?
+      },
+      None => {
+        db.entry_position(
+          id,
+          include_expired,
+          self.search.as_ref(),
+          self.reverse,
+          self.pinned_first,
+          self.selection,
+          &self.tags,
+        )?
+      },
+    };
+    if let Some(position) = position {
+      self.cursor = position;
+      self.dirty = true;
+    }
+    Ok(position)
   }
 
   /// Return the current search filter (`None` if empty).
-  fn search_filter(&self) -> Option<&str> {
-    if self.search_query.is_empty() {
-      None
-    } else {
-      Some(&self.search_query)
+  fn search_filter(&self) -> Option<&SearchQuery> {
+    self.search.as_ref()
+  }
+
+  /// Parse the search query in the current match mode, keeping the previous
+  /// search if it does not parse.
+  fn parse_search(&mut self) {
+    match SearchQuery::parse(self.match_mode, &self.search_query) {
+      Ok(search) => {
+        self.search = search;
+        self.search_error = None;
+      },
+      Err(e) => self.search_error = Some(e.to_string()),
     }
   }
 
@@ -131,7 +396,9 @@ impl TuiState {
     let changed = self.search_query != query;
     if changed {
       self.search_query = query;
+      self.parse_search();
       self.cursor = 0;
+      self.snippet_cursor = 0;
       self.viewport_offset = 0;
       self.dirty = true;
       self.pending_delete = None;
@@ -144,9 +411,12 @@ impl TuiState {
   fn clear_search(&mut self) -> bool {
     let had_search = !self.search_query.is_empty();
     self.search_query.clear();
+    self.search = None;
+    self.search_error = None;
     self.search_mode = false;
     if had_search {
       self.cursor = 0;
+      self.snippet_cursor = 0;
       self.viewport_offset = 0;
       self.dirty = true;
     }
@@ -162,12 +432,127 @@ impl TuiState {
       // When entering search mode, clear query if there was one
       // or start fresh
       self.search_query.clear();
+      self.search = None;
+      self.search_error = None;
       self.dirty = true;
       self.pending_delete = None;
       self.status = None;
     }
   }
 
+  /// Switch to the next match mode, re-running the search like a new one.
+  fn cycle_match_mode(&mut self) {
+    self.match_mode = self.match_mode.next();
+    self.parse_search();
+    self.cursor = 0;
+    self.viewport_offset = 0;
+    self.dirty = true;
+    self.pending_delete = None;
+    self.status = None;
+  }
+
+  /// Title of the search bar: the match mode, the query and why it does not
+  /// parse, if it does not.
+  fn search_title(&self) -> String {
+    let error = self
+      .search_error
+      .as_ref()
+      .map(|error| format!("  ({error})"))
+      .unwrap_or_default();
+    let switch = self
+      .keymap
+      .hint(&[Action::SearchMode])
+      .map(|keys| format!(", {keys} to switch"))
+      .unwrap_or_default();
+    format!(
+      "Search [{}{switch}]: {}{error}",
+      self.match_mode.as_str(),
+      self.search_query
+    )
+  }
+
+  /// Cycle the selection filter: all entries, regular clipboard only, primary
+  /// selection only. Resets the cursor like a new search.
+  fn cycle_selection(&mut self) {
+    self.selection = match self.selection {
+      None => Some(Selection::Regular),
+      Some(Selection::Regular) => Some(Selection::Primary),
+      Some(Selection::Primary) => None,
+    };
+    self.cursor = 0;
+    self.viewport_offset = 0;
+    self.dirty = true;
+    self.pending_delete = None;
+    self.status = None;
+  }
+
+  /// Switch between the history and the snippets, leaving any search.
+  /// Returns true if a search was cleared.
+  fn switch_tab(&mut self) -> bool {
+    self.tab = match self.tab {
+      Tab::History => Tab::Snippets,
+      Tab::Snippets => Tab::History,
+    };
+    self.clear_search()
+  }
+
+  /// Snippets matching the search query by name, tag or contents.
+  fn visible_snippets(&self) -> Vec<&Snippet> {
+    let query = self.search_query.to_lowercase();
+    self
+      .snippets
+      .iter()
+      .filter(|snippet| {
+        query.is_empty()
+          || snippet.name.to_lowercase().contains(&query)
+          || snippet
+            .tags
+            .iter()
+            .any(|tag| tag.to_lowercase().contains(&query))
+          || String::from_utf8_lossy(&snippet.contents)
+            .to_lowercase()
+            .contains(&query)
+      })
+      .collect()
+  }
+
+  /// Return the selected snippet, if any.
+  fn selected_snippet(&self) -> Option<&Snippet> {
+    self.visible_snippets().get(self.snippet_cursor).copied()
+  }
+
+  /// Move the snippet cursor by one in the direction of `delta`, wrapping
+  /// around at either end.
+  fn move_snippet_cursor(&mut self, delta: i64) {
+    let count = self.visible_snippets().len();
+    if count == 0 {
+      return;
+    }
+    self.snippet_cursor = if delta > 0 {
+      (self.snippet_cursor + 1) % count
+    } else {
+      self.snippet_cursor.checked_sub(1).unwrap_or(count - 1)
+    };
+    self.pending_delete = None;
+    self.status = None;
+  }
+
+  /// Reload the snippets, keeping the cursor in range.
+  fn reload_snippets(
+    &mut self,
+    db: &SqliteClipboardDb,
+  ) -> Result<(), StashError> {
+    self.snippets = match &mut self.daemon {
+      Some(daemon) => daemon.snippets()?,
+      None => db.snippets(None)?,
+    };
+    let count = self.visible_snippets().len();
+    if self.snippet_cursor >= count {
+      self.snippet_cursor = count.saturating_sub(1);
+    }
+    Ok(())
+  }
+
   /// Return the cursor position relative to the current window
   /// (`window[local_cursor]` == the selected entry).
   #[inline]
@@ -175,8 +560,8 @@ impl TuiState {
     self.cursor.saturating_sub(self.viewport_offset)
   }
 
-  /// Return the selected `(id, preview, mime)` if any entry is selected.
-  fn selected_entry(&self) -> Option<&(i64, String, String)> {
+  /// Return the selected entry, if any.
+  fn selected_entry(&self) -> Option<&ListEntry> {
     if self.total == 0 {
       return None;
     }
@@ -211,6 +596,43 @@ impl TuiState {
     self.status = None;
   }
 
+  /// Move the cursor of the list being shown by more than one entry.
+  fn jump(&mut self, jump: Jump) {
+    let (cursor, count, page) = match self.tab {
+      Tab::History => (self.cursor, self.total, self.window_size),
+      Tab::Snippets => {
+        (
+          self.snippet_cursor,
+          self.visible_snippets().len(),
+          self.window_size,
+        )
+      },
+    };
+    if count == 0 {
+      return;
+    }
+    let target = match jump {
+      Jump::Pages(pages) => {
+        let distance = page.saturating_mul(
+          usize::try_from(pages.unsigned_abs()).unwrap_or(usize::MAX),
+        );
+        if pages > 0 {
+          cursor.saturating_add(distance)
+        } else {
+          cursor.saturating_sub(distance)
+        }
+      },
+      Jump::Top => 0,
+      Jump::Bottom => count - 1,
+    };
+    match self.tab {
+      Tab::History => self.cursor = target.min(count - 1),
+      Tab::Snippets => self.snippet_cursor = target.min(count - 1),
+    }
+    self.pending_delete = None;
+    self.status = None;
+  }
+
   /// Resize the window (e.g. terminal resized).  Marks dirty so the
   /// viewport is reloaded on the next frame.
   fn resize(&mut self, new_size: usize) {
@@ -236,6 +658,73 @@ impl TuiState {
     self.dirty = true;
   }
 
+  /// Pick up entries that other processes stored, changed or removed since
+  /// the last call. The selection stays on the same entry (by id) when it is
+  /// still listed, and new entries are flashed. Returns true if anything
+  /// changed.
+  fn refresh(
+    &mut self,
+    db: &SqliteClipboardDb,
+    include_expired: bool,
+  ) -> Result<bool, StashError> {
+    // The daemon reports new entries along with its version, sparing a
+    // round trip; the database is only asked for them once it changed.
+    let (data_version, new_ids) = match &mut self.daemon {
+      Some(daemon) => {
+        let changes = daemon.changes(self.newest_id)?;
+        (changes.version, Some(changes.new_ids))
+      },
+      None => (db.data_version()?, None),
+    };
+    if data_version == self.data_version {
+      return Ok(false);
+    }
+    self.data_version = data_version;
+    let new_ids = match new_ids {
+      Some(ids) => ids,
+      None => db.ids_after(self.newest_id)?,
+    };
+
+    self.reload_snippets(db)?;
+    let anchor = self.selected_entry().map(|entry| entry.id);
+    self.recount(db, include_expired)?;
+
+    let arrived_at = Instant::now();
+    for id in new_ids {
+      self.newest_id = self.newest_id.max(id);
+      self.flashes.insert(id, arrived_at);
+    }
+
+    let position = match anchor {
+      Some(id) => self.follow(db, id, include_expired)?,
+      None => None,
+    };
+    if position.is_none() && self.cursor >= self.total {
+      self.cursor = self.total.saturating_sub(1);
+    }
+    if position.is_none() {
+      self.pending_delete = None;
+    }
+    self.refresh_marks(db, include_expired)?;
+    self.dirty = true;
+    Ok(true)
+  }
+
+  /// Whether entry `id` arrived recently enough to be highlighted.
+  fn is_flashing(&self, id: i64) -> bool {
+    self.flashes.contains_key(&id)
+  }
+
+  /// Drop highlights that have run their course. Returns true if any were
+  /// dropped, so the rows need redrawing.
+  fn expire_flashes(&mut self) -> bool {
+    let before = self.flashes.len();
+    self
+      .flashes
+      .retain(|_, arrived_at| arrived_at.elapsed() < FLASH_DURATION);
+    self.flashes.len() != before
+  }
+
   /// Reload the window from the DB if `dirty` is set or if the cursor
   /// has drifted outside the currently loaded range.
   fn sync(
@@ -260,18 +749,33 @@ impl TuiState {
       0
     };
 
-    let search = self.search_filter();
-    self.window = if self.total > 0 {
+    self.window = if self.total == 0 {
+      Vec::new()
+    } else if let Some(daemon) = &mut self.daemon {
+      let (window, _) = daemon.list(
+        include_expired,
+        self.reverse,
+        self.pinned_first,
+        self.selection,
+        &self.tags,
+        self.search.as_ref(),
+        preview_width,
+        self.viewport_offset,
+        self.window_size,
+      )?;
+      window
+    } else {
       db.fetch_entries_window(
         include_expired,
         self.viewport_offset,
         self.window_size,
         preview_width,
-        search,
+        self.search.as_ref(),
         self.reverse,
+        self.pinned_first,
+        self.selection,
+        &self.tags,
       )?
-    } else {
-      Vec::new()
     };
     self.dirty = false;
     Ok(())
@@ -301,28 +805,262 @@ fn global_column_widths(
   Ok((id_w.max(2) as usize, mime_w.max(8) as usize))
 }
 
+/// Draw the snippets tab: name, tags and a preview of every snippet matching
+/// the search.
+fn draw_snippets(f: &mut ratatui::Frame, tui: &TuiState, preview_width: u32) {
+  use ratatui::{
+    style::Style,
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, ListState},
+  };
+
+  let snippets = tui.visible_snippets();
+  let title = if tui.search_mode {
+    format!("Search: {}", tui.search_query)
+  } else if let Some(status) = &tui.status {
+    status.clone()
+  } else if tui.search_query.is_empty() {
+    format!(
+      "Snippets ({})",
+      tui.keymap.hints(&[
+        (&[Action::Down, Action::Up], "to move"),
+        (&[Action::Search], "to search"),
+        (&[Action::Copy], "to copy"),
+        (&[Action::Delete], "to delete"),
+        (&[Action::SwitchTab], "for history"),
+        (&[Action::Help], "for help"),
+        (&[Action::Quit], "to quit"),
+      ])
+    )
+  } else {
+    format!(
+      "Snippets (filtered: '{}' - {} results, {})",
+      tui.search_query,
+      snippets.len(),
+      tui.keymap.hints(&[
+        (&[Action::Search], "to search"),
+        (&[Action::Quit], "to quit"),
+      ])
+    )
+  };
+
+  let name_col = snippets
+    .iter()
+    .map(|snippet| UnicodeWidthStr::width(snippet.name.as_str()))
+    .max()
+    .unwrap_or(0)
+    .min(24);
+  let selected_style = tui.theme.selected;
+  let items: Vec<ListItem> = snippets
+    .iter()
+    .enumerate()
+    .map(|(i, snippet)| {
+      let is_selected = i == tui.snippet_cursor;
+      let mut name = String::new();
+      let mut width = 0;
+      for g in snippet.name.graphemes(true) {
+        let gw = UnicodeWidthStr::width(g);
+        if width + gw > name_col {
+          name.push('…');
+          width += 1;
+          break;
+        }
+        name.push_str(g);
+        width += gw;
+      }
+      name.push_str(&" ".repeat(name_col.saturating_sub(width)));
+
+      let mut spans = vec![
+        Span::styled(if is_selected { ">" } else { " " }, selected_style),
+        Span::styled(
+          name,
+          if is_selected {
+            selected_style
+          } else {
+            Style::default()
+          },
+        ),
+        Span::raw(" "),
+      ];
+      if !snippet.tags.is_empty() {
+        spans.push(Span::styled(
+          format!("[{}] ", snippet.tags.join(", ")),
+          tui.theme.tags,
+        ));
+      }
+      spans.push(Span::raw(snippet_preview(snippet, preview_width)));
+      ListItem::new(Line::from(spans))
+    })
+    .collect();
+
+  let mut state = ListState::default();
+  state.select((!snippets.is_empty()).then_some(tui.snippet_cursor));
+  let list = List::new(items)
+    .block(
+      Block::default()
+        .title(title)
+        .title_style(tui.theme.title)
+        .borders(Borders::ALL)
+        .border_style(tui.theme.border),
+    )
+    .highlight_symbol("");
+  f.render_stateful_widget(list, f.area(), &mut state);
+}
+
+/// Draw the key help over the middle of the screen: the keys and description
+/// of every bound action, in two columns if one does not fit.
+fn draw_help(f: &mut ratatui::Frame, tui: &TuiState) {
+  use ratatui::{
+    layout::{Constraint, Layout, Margin, Rect},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Paragraph},
+  };
+
+  let bound = |search: bool| {
+    Action::ALL
+      .iter()
+      .filter(move |action| action.in_search() == search)
+      .filter_map(|&action| {
+        tui
+          .keymap
+          .hint(&[action])
+          .map(|keys| (keys, action.description()))
+      })
+  };
+  let key_col = bound(false)
+    .chain(bound(true))
+    .map(|(keys, _)| UnicodeWidthStr::width(keys.as_str()))
+    .max()
+    .unwrap_or(0);
+  let line = |(keys, description): (String, &str)| {
+    let pad = key_col - UnicodeWidthStr::width(keys.as_str());
+    Line::from(vec![
+      Span::styled(keys, tui.theme.label),
+      Span::raw(format!("{}  {description}", " ".repeat(pad))),
+    ])
+  };
+  let mut lines: Vec<Line> = bound(false).map(line).collect();
+  lines.push(Line::default());
+  lines.push(Line::styled("While searching", tui.theme.title));
+  lines.extend(bound(true).map(line));
+
+  let area = f.area();
+  let columns = if lines.len() + 2 > area.height as usize {
+    2
+  } else {
+    1
+  };
+  let column_width = lines.iter().map(Line::width).max().unwrap_or(0) as u16;
+  let rows = lines.len().div_ceil(columns) as u16;
+  let width = (column_width * columns as u16 + 3 * (columns as u16 - 1) + 4)
+    .min(area.width);
+  let height = (rows + 2).min(area.height);
+  let popup = Rect::new(
+    area.x + (area.width - width) / 2,
+    area.y + (area.height - height) / 2,
+    width,
+    height,
+  );
+
+  let block = Block::default()
+    .title(" Keys - any key to close ")
+    .title_style(tui.theme.title)
+    .borders(Borders::ALL)
+    .border_style(tui.theme.border);
+  let inner = block.inner(popup).inner(Margin::new(1, 0));
+  f.render_widget(Clear, popup);
+  f.render_widget(block, popup);
+  let areas = Layout::horizontal(vec![Constraint::Fill(1); columns])
+    .spacing(3)
+    .split(inner);
+  for (lines, area) in lines.chunks(rows.max(1) as usize).zip(areas.iter()) {
+    f.render_widget(Paragraph::new(lines.to_vec()), *area);
+  }
+}
+
 impl SqliteClipboardDb {
+  /// Apply the keys pressed on the snippets tab.
+  fn handle_snippet_actions(
+    &self,
+    tui: &mut TuiState,
+    net_down: i64,
+    copy: bool,
+    delete: bool,
+  ) -> Result<(), StashError> {
+    use wl_clipboard_rs::copy::Options;
+
+    if net_down != 0 {
+      tui.move_snippet_cursor(net_down);
+    }
+
+    if delete && let Some(snippet) = tui.selected_snippet() {
+      let (id, name) = (snippet.id, snippet.name.clone());
+      if tui.pending_delete.as_deref() == Some(&[id]) {
+        self.remove_snippet(&name)?;
+        tui.reload_snippets(self)?;
+        tui.pending_delete = None;
+        tui.status = Some(format!("deleted snippet `{name}`"));
+        notify("stash", "deleted snippet");
+      } else {
+        tui.pending_delete = Some(vec![id]);
+        tui.status = Some(format!(
+          "press {} again to delete snippet `{name}`",
+          tui.keymap.hint(&[Action::Delete]).unwrap_or_default()
+        ));
+      }
+    }
+
+    if copy && let Some(snippet) = tui.selected_snippet() {
+      let (id, name) = (snippet.id, snippet.name.clone());
+      let contents = expand_placeholders(self, snippet.contents.clone())?;
+      tui.pending_delete = None;
+      let mime = crate::mime::detect_mime(&contents);
+      let mut opts = Options::new();
+      opts.clipboard(Selection::Regular.copy_type());
+      match opts.copy_multi(mime_sources(contents, mime.as_deref(), Vec::new()))
+      {
+        Ok(()) => {
+          self.mark_snippet_used(id)?;
+          tui.status = Some(format!("copied snippet `{name}`"));
+          notify("stash", "copied snippet to clipboard");
+        },
+        Err(e) => {
+          log::error!("failed to copy snippet to clipboard: {e}");
+          let body = format!("failed to copy to clipboard: {e}");
+          tui.status = Some(body.clone());
+          notify("stash", &body);
+        },
+      }
+    }
+    Ok(())
+  }
+
   #[expect(
     clippy::too_many_lines,
     reason = "ratatui event loop and rendering share local state"
   )]
+  #[expect(
+    clippy::too_many_arguments,
+    reason = "list filters mirror the CLI options"
+  )]
   pub fn list_tui(
     &self,
     preview_width: u32,
     include_expired: bool,
     reverse: bool,
+    pinned_first: bool,
+    selection: Option<Selection>,
+    tags: &[String],
+    match_mode: SearchMode,
+    search: &str,
+    join_separator: &str,
+    keys: &[String],
+    theme: &[String],
   ) -> Result<(), StashError> {
     use std::io::stdout;
 
     use crossterm::{
-      event::{
-        self,
-        DisableMouseCapture,
-        EnableMouseCapture,
-        Event,
-        KeyCode,
-        KeyModifiers,
-      },
+      event::{self, DisableMouseCapture, EnableMouseCapture, Event},
       execute,
       terminal::{
         EnterAlternateScreen,
@@ -334,18 +1072,28 @@ impl SqliteClipboardDb {
     use ratatui::{
       Terminal,
       backend::CrosstermBackend,
-      style::{Color, Modifier, Style},
+      layout::{Constraint, Layout, Position, Rect},
+      style::Modifier,
       text::{Line, Span},
       widgets::{Block, Borders, List, ListItem, ListState},
     };
-    use wl_clipboard_rs::copy::{MimeType, Options, Source};
+    use wl_clipboard_rs::copy::Options;
+
+    let keymap = Keymap::parse(keys)?;
+    let theme = Theme::parse(theme)?;
 
     // One-time column-width metadata (no blob reads).
-    let (max_id_width, max_mime_width) =
+    let (mut max_id_width, mut max_mime_width) =
       global_column_widths(self, include_expired)?;
 
     enable_raw_mode()
       .map_err(|e| StashError::ListDecode(e.to_string().into()))?;
+    let mut image_preview = preview::ImagePreview::new(if preview::SUPPORTED {
+      preview::Graphics::detect()
+    } else {
+      preview::Graphics::HalfBlocks
+    });
+    let mut detail_pane = detail::DetailPane::new();
     let mut stdout = stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)
       .map_err(|e| StashError::ListDecode(e.to_string().into()))?;
@@ -366,7 +1114,17 @@ impl SqliteClipboardDb {
       initial_height,
       preview_width,
       reverse,
+      pinned_first,
+      selection,
+      tags,
+      ipc::Client::connect(&self.db_path),
     )?;
+    tui.match_mode = match_mode;
+    tui.keymap = keymap;
+    tui.theme = theme;
+    if tui.set_search(search.to_owned()) {
+      tui.recount(self, include_expired)?;
+    }
 
     // ratatui ListState; only tracks selection within the *window* slice.
     let mut list_state = ListState::default();
@@ -378,12 +1136,27 @@ impl SqliteClipboardDb {
     struct EventActions {
       quit:             bool,
       net_down:         i64, // positive=down, negative=up, 0=none
+      jump:             Option<Jump>,
       copy:             bool,
       delete:           bool,
+      toggle_pin:       bool,
+      cycle_selection:  bool,
+      toggle_preview:   bool,
+      toggle_details:   bool,
+      scroll_details:   i64, // lines, positive=down
+      page_details:     i64, // pages, positive=down
+      switch_tab:       bool,
       toggle_search:    bool, // enter/exit search mode
       search_input:     Option<char>, // character typed in search mode
       search_backspace: bool, // backspace in search mode
       clear_search:     bool, // clear search query (ESC in search mode)
+      cycle_match_mode: bool, // next match mode (Tab in search mode)
+      toggle_mark:      bool,
+      toggle_visual:    bool,
+      clear_marks:      bool,
+      export:           bool,
+      copy_joined:      bool,
+      toggle_help:      bool,
     }
 
     /// Drain all pending key events and return what actions to perform.
@@ -393,13 +1166,29 @@ impl SqliteClipboardDb {
       let mut actions = EventActions {
         quit:             false,
         net_down:         0,
+        jump:             None,
         copy:             false,
         delete:           false,
+        toggle_pin:       false,
+        cycle_selection:  false,
+        toggle_preview:   false,
+        toggle_details:   false,
+        scroll_details:   0,
+        page_details:     0,
+        switch_tab:       false,
         toggle_search:    false,
         search_input:     None,
         search_backspace: false,
         clear_search:     false,
+        cycle_match_mode: false,
+        toggle_mark:      false,
+        toggle_visual:    false,
+        clear_marks:      false,
+        export:           false,
+        copy_joined:      false,
+        toggle_help:      false,
       };
+      let mut help_open = tui.show_help;
 
       while event::poll(std::time::Duration::from_millis(0))
         .map_err(|e| StashError::ListDecode(e.to_string().into()))?
@@ -407,42 +1196,66 @@ impl SqliteClipboardDb {
         if let Event::Key(key) = event::read()
           .map_err(|e| StashError::ListDecode(e.to_string().into()))?
         {
-          if tui.search_mode {
+          let key = Key::from(key);
+          if help_open {
+            // Any key closes the help.
+            actions.toggle_help = !actions.toggle_help;
+            help_open = false;
+          } else if tui.search_mode {
             // In search mode, handle text input
-            match (key.code, key.modifiers) {
-              (KeyCode::Esc, _) => {
-                actions.clear_search = true;
-              },
-              (KeyCode::Enter, _) => {
+            match tui.keymap.action(key, true, false) {
+              Some(Action::SearchCancel) => actions.clear_search = true,
+              Some(Action::SearchAccept) => {
                 actions.toggle_search = true; // exit search mode
               },
-              (KeyCode::Backspace, _) => {
-                actions.search_backspace = true;
+              // Snippets are only searched by substring.
+              Some(Action::SearchMode) if tui.tab == Tab::History => {
+                actions.cycle_match_mode = true;
               },
-              (KeyCode::Char(c), _) => {
-                actions.search_input = Some(c);
+              _ if key.is_backspace() => actions.search_backspace = true,
+              _ => {
+                if let Some(c) = key.typed() {
+                  actions.search_input = Some(c);
+                }
               },
-              _ => {},
             }
           } else {
             // Normal mode navigation commands
-            match (key.code, key.modifiers) {
-              (KeyCode::Char('q') | KeyCode::Esc, _) => actions.quit = true,
-              (KeyCode::Down | KeyCode::Char('j'), _)
-                // Cap at +1 per frame for smooth scrolling
-                if actions.net_down < 1 => {
-                  actions.net_down += 1;
-                },
-              (KeyCode::Up | KeyCode::Char('k'), _)
-                // Cap at -1 per frame for smooth scrolling
-                if actions.net_down > -1 => {
-                  actions.net_down -= 1;
-                },
-              (KeyCode::Enter, _) => actions.copy = true,
-              (KeyCode::Char('D'), KeyModifiers::SHIFT) => {
-                actions.delete = true;
+            match tui.keymap.action(key, false, tui.has_marks()) {
+              Some(Action::Unmark) => actions.clear_marks = true,
+              Some(Action::Quit) => actions.quit = true,
+              // Cap at +1 per frame for smooth scrolling
+              Some(Action::Down) if actions.net_down < 1 => {
+                actions.net_down += 1;
+              },
+              // Cap at -1 per frame for smooth scrolling
+              Some(Action::Up) if actions.net_down > -1 => {
+                actions.net_down -= 1;
+              },
+              Some(Action::PageDown) => actions.jump = Some(Jump::Pages(1)),
+              Some(Action::PageUp) => actions.jump = Some(Jump::Pages(-1)),
+              Some(Action::Top) => actions.jump = Some(Jump::Top),
+              Some(Action::Bottom) => actions.jump = Some(Jump::Bottom),
+              Some(Action::Copy) => actions.copy = true,
+              Some(Action::Delete) => actions.delete = true,
+              Some(Action::Pin) => actions.toggle_pin = true,
+              Some(Action::Selection) => actions.cycle_selection = true,
+              Some(Action::Preview) => actions.toggle_preview = true,
+              Some(Action::Details) => actions.toggle_details = true,
+              Some(Action::DetailDown) => actions.scroll_details += 1,
+              Some(Action::DetailUp) => actions.scroll_details -= 1,
+              Some(Action::DetailPageDown) => actions.page_details += 1,
+              Some(Action::DetailPageUp) => actions.page_details -= 1,
+              Some(Action::SwitchTab) => actions.switch_tab = true,
+              Some(Action::Search) => actions.toggle_search = true,
+              Some(Action::Mark) => actions.toggle_mark = true,
+              Some(Action::Visual) => actions.toggle_visual = true,
+              Some(Action::Export) => actions.export = true,
+              Some(Action::CopyJoined) => actions.copy_joined = true,
+              Some(Action::Help) => {
+                actions.toggle_help = true;
+                help_open = true;
               },
-              (KeyCode::Char('/'), _) => actions.toggle_search = true,
               _ => {},
             }
           }
@@ -451,195 +1264,347 @@ impl SqliteClipboardDb {
       Ok(actions)
     }
 
-    let draw_frame =
-      |terminal: &mut Terminal<CrosstermBackend<std::io::Stdout>>,
-       tui: &mut TuiState,
-       list_state: &mut ListState,
-       max_id_width: usize,
-       max_mime_width: usize|
-       -> Result<(), StashError> {
-        // Reserve 2 rows for search bar when in search mode
-        let search_bar_height = if tui.search_mode { 2 } else { 0 };
-        let term_height = terminal
-          .size()
-          .map(|r| r.height.saturating_sub(2 + search_bar_height) as usize)
-          .unwrap_or(24)
-          .max(1);
-        tui.resize(term_height);
-        tui.sync(self, include_expired, preview_width)?;
-
-        if tui.total == 0 {
-          list_state.select(None);
-        } else {
-          list_state.select(Some(tui.local_cursor()));
-        }
+    let draw_frame = |terminal: &mut Terminal<
+      CrosstermBackend<std::io::Stdout>,
+    >,
+                      tui: &mut TuiState,
+                      list_state: &mut ListState,
+                      image_preview: &mut preview::ImagePreview,
+                      detail_pane: &mut detail::DetailPane,
+                      max_id_width: usize,
+                      max_mime_width: usize|
+     -> Result<(), StashError> {
+      // Reserve 2 rows for search bar when in search mode
+      let search_bar_height = if tui.search_mode { 2 } else { 0 };
+      let term_height = terminal
+        .size()
+        .map(|size| {
+          let mut area = Rect::from((Position::ORIGIN, size));
+          if tui.show_details && tui.tab == Tab::History {
+            (area, _) = detail::split(area);
+          }
+          area.height.saturating_sub(2 + search_bar_height) as usize
+        })
+        .unwrap_or(24)
+        .max(1);
+      tui.resize(term_height);
+      tui.sync(self, include_expired, preview_width)?;
+
+      if tui.total == 0 {
+        list_state.select(None);
+      } else {
+        list_state.select(Some(tui.local_cursor()));
+      }
 
-        terminal
-          .draw(|f| {
-            let area = f.area();
-
-            // Build title based on search state
-            let title = if tui.search_mode {
-              format!("Search: {}", tui.search_query)
-            } else if let Some(status) = &tui.status {
-              status.clone()
-            } else if tui.search_query.is_empty() {
-              "Clipboard Entries (j/k/↑/↓ to move, / to search, Enter to copy, \
-               Shift+D to delete, q/ESC to quit)"
-                .to_string()
-            } else {
-              format!(
-                "Clipboard Entries (filtered: '{}' - {} results, / to search, \
-                 ESC to clear, q to quit)",
-                tui.search_query, tui.total
-              )
-            };
-
-            let block = Block::default().title(title).borders(Borders::ALL);
-
-            let border_width = 2;
-            let highlight_symbol = ">";
-            let highlight_width = 1;
-            let content_width =
-              (area.width as usize).saturating_sub(border_width);
-
-            let min_id_width = 2;
-            let min_mime_width = 6;
-            let min_preview_width = 4;
-            let spaces = 3;
-
-            let mut id_col = max_id_width.max(min_id_width);
-            let mut mime_col = max_mime_width.max(min_mime_width);
-            let mut preview_col = content_width
-              .saturating_sub(highlight_width)
-              .saturating_sub(id_col)
-              .saturating_sub(mime_col)
-              .saturating_sub(spaces);
-
-            if preview_col < min_preview_width {
-              let needed = min_preview_width - preview_col;
-              if mime_col > min_mime_width {
-                let reduce = mime_col - min_mime_width;
-                let take = reduce.min(needed);
-                mime_col -= take;
-                preview_col += take;
-              }
+      let selected = tui
+        .selected_entry()
+        .filter(|_| tui.tab == Tab::History)
+        .cloned();
+      let (detailed, previewed) = if tui.show_details {
+        (selected, None)
+      } else {
+        let previewed = selected.filter(|entry| {
+          // Images are drawn over everything else, so not under the help.
+          tui.show_preview
+            && !tui.show_help
+            && preview::ImagePreview::applies_to(entry)
+        });
+        (None, previewed)
+      };
+      image_preview.begin_frame();
+
+      terminal
+        .draw(|f| {
+          if tui.tab == Tab::Snippets {
+            draw_snippets(f, tui, preview_width);
+            if tui.show_help {
+              draw_help(f, tui);
             }
-            if preview_col < min_preview_width {
-              let needed = min_preview_width - preview_col;
-              if id_col > min_id_width {
-                let reduce = id_col - min_id_width;
-                let take = reduce.min(needed);
-                id_col -= take;
-                preview_col += take;
-              }
+            return;
+          }
+
+          let area = match (&detailed, &previewed) {
+            (Some(entry), _) => {
+              let (list_area, detail_area) = detail::split(f.area());
+              detail_pane.render(
+                f,
+                detail_area,
+                self,
+                entry,
+                tui,
+                (tui.show_preview && !tui.show_help)
+                  .then_some(&mut *image_preview),
+              );
+              list_area
+            },
+            (None, Some(entry)) => {
+              let [list_area, preview_area] = Layout::horizontal([
+                Constraint::Percentage(55),
+                Constraint::Percentage(45),
+              ])
+              .areas(f.area());
+              image_preview.render(f, preview_area, self, entry);
+              list_area
+            },
+            (None, None) => f.area(),
+          };
+
+          // Build title based on search state
+          let title = if tui.search_mode {
+            tui.search_title()
+          } else if let Some(status) = &tui.status {
+            status.clone()
+          } else if tui.has_marks() {
+            format!(
+              "Clipboard Entries ({} marked - {})",
+              tui.marked.len(),
+              tui.keymap.hints(&[
+                (&[Action::Mark, Action::Visual], "to mark"),
+                (&[Action::Delete], "to delete"),
+                (&[Action::Pin], "to pin"),
+                (&[Action::Export], "to export"),
+                (&[Action::CopyJoined], "to copy joined"),
+                (&[Action::Unmark], "to unmark"),
+              ])
+            )
+          } else if tui.search_query.is_empty() && tui.selection.is_none() {
+            format!(
+              "Clipboard Entries ({})",
+              tui.keymap.hints(&[
+                (&[Action::Down, Action::Up], "to move"),
+                (&[Action::Search], "to search"),
+                (&[Action::Copy], "to copy"),
+                (&[Action::Pin], "to pin"),
+                (&[Action::Mark, Action::Visual], "to mark"),
+                (&[Action::Details], "for details"),
+                (&[Action::Help], "for help"),
+                (&[Action::Quit], "to quit"),
+              ])
+            )
+          } else if tui.search_query.is_empty() {
+            format!(
+              "Clipboard Entries ({} selection - {} results, {})",
+              tui.selection.map_or("any", Selection::as_str),
+              tui.total,
+              tui.keymap.hints(&[
+                (&[Action::Selection], "to change"),
+                (&[Action::Quit], "to quit"),
+              ])
+            )
+          } else {
+            format!(
+              "Clipboard Entries ({} filter: '{}' - {} results, {})",
+              tui.match_mode.as_str(),
+              tui.search_query,
+              tui.total,
+              tui.keymap.hints(&[
+                (&[Action::Search], "to search"),
+                (&[Action::Quit], "to quit"),
+              ])
+            )
+          };
+
+          let block = Block::default()
+            .title(title)
+            .title_style(tui.theme.title)
+            .borders(Borders::ALL)
+            .border_style(tui.theme.border);
+
+          let border_width = 2;
+          let highlight_symbol = ">";
+          let mark_symbol = "+";
+          let highlight_width = 1;
+          let pin_symbol = "*";
+          let pin_width = 1;
+          let primary_symbol = "P";
+          let selection_width = 1;
+          let content_width =
+            (area.width as usize).saturating_sub(border_width);
+
+          let min_id_width = 2;
+          let min_mime_width = 6;
+          let min_preview_width = 4;
+          let spaces = 3;
+
+          let mut id_col = max_id_width.max(min_id_width);
+          let mut mime_col = max_mime_width.max(min_mime_width);
+          let mut preview_col = content_width
+            .saturating_sub(highlight_width)
+            .saturating_sub(pin_width)
+            .saturating_sub(selection_width)
+            .saturating_sub(id_col)
+            .saturating_sub(mime_col)
+            .saturating_sub(spaces);
+
+          if preview_col < min_preview_width {
+            let needed = min_preview_width - preview_col;
+            if mime_col > min_mime_width {
+              let reduce = mime_col - min_mime_width;
+              let take = reduce.min(needed);
+              mime_col -= take;
+              preview_col += take;
             }
-            if preview_col < min_preview_width {
-              preview_col = min_preview_width;
+          }
+          if preview_col < min_preview_width {
+            let needed = min_preview_width - preview_col;
+            if id_col > min_id_width {
+              let reduce = id_col - min_id_width;
+              let take = reduce.min(needed);
+              id_col -= take;
+              preview_col += take;
             }
+          }
+          if preview_col < min_preview_width {
+            preview_col = min_preview_width;
+          }
 
-            let selected = list_state.selected();
-
-            let list_items: Vec<ListItem> = tui
-              .window
-              .iter()
-              .enumerate()
-              .map(|(i, entry)| {
-                let mut preview = String::new();
-                let mut pwidth = 0usize;
-                for g in entry.1.graphemes(true) {
+          let selected = list_state.selected();
+
+          let list_items: Vec<ListItem> = tui
+            .window
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+              // Tags go in front of the preview, in at most half its column.
+              let mut tags = String::new();
+              if !entry.tags.is_empty() {
+                let label = format!("[{}]", entry.tags.join(","));
+                let max_tags = preview_col / 2;
+                let mut twidth = 0usize;
+                for g in label.graphemes(true) {
                   let gw = UnicodeWidthStr::width(g);
-                  if pwidth + gw > preview_col {
-                    preview.push('…');
-                    pwidth += 1;
+                  if twidth + gw + 2 > max_tags {
+                    tags.push('…');
                     break;
                   }
-                  preview.push_str(g);
-                  pwidth += gw;
+                  tags.push_str(g);
+                  twidth += gw;
                 }
-                let preview_pad = preview_col.saturating_sub(pwidth);
-                for _ in 0..preview_pad {
-                  preview.push(' ');
+                tags.push(' ');
+              }
+              let tags_width = UnicodeWidthStr::width(tags.as_str());
+              let preview_col = preview_col.saturating_sub(tags_width);
+
+              let mut preview = String::new();
+              let mut pwidth = 0usize;
+              for g in entry.preview.graphemes(true) {
+                let gw = UnicodeWidthStr::width(g);
+                if pwidth + gw > preview_col {
+                  preview.push('…');
+                  pwidth += 1;
+                  break;
                 }
+                preview.push_str(g);
+                pwidth += gw;
+              }
+              let preview_pad = preview_col.saturating_sub(pwidth);
+              for _ in 0..preview_pad {
+                preview.push(' ');
+              }
 
-                let mut mime_trunc = String::new();
-                let mut mwidth = 0usize;
-                for g in entry.2.graphemes(true) {
-                  let gw = UnicodeWidthStr::width(g);
-                  if mwidth + gw > mime_col {
-                    mime_trunc.push('…');
-                    mwidth += 1;
-                    break;
-                  }
-                  mime_trunc.push_str(g);
-                  mwidth += gw;
+              let mut mime_trunc = String::new();
+              let mut mwidth = 0usize;
+              for g in entry.mime.graphemes(true) {
+                let gw = UnicodeWidthStr::width(g);
+                if mwidth + gw > mime_col {
+                  mime_trunc.push('…');
+                  mwidth += 1;
+                  break;
                 }
-                let mime_pad = mime_col.saturating_sub(mwidth);
-                let mime_padded = if mime_pad > 0 {
-                  format!("{}{mime_trunc}", " ".repeat(mime_pad))
-                } else {
-                  mime_trunc
-                };
+                mime_trunc.push_str(g);
+                mwidth += gw;
+              }
+              let mime_pad = mime_col.saturating_sub(mwidth);
+              let mime_padded = if mime_pad > 0 {
+                format!("{}{mime_trunc}", " ".repeat(mime_pad))
+              } else {
+                mime_trunc
+              };
 
-                let id = entry.0;
-                let mut spans = Vec::new();
-                if Some(i) == selected {
-                  spans.push(Span::styled(
-                    highlight_symbol,
-                    Style::default()
-                      .fg(Color::Yellow)
-                      .add_modifier(Modifier::BOLD),
-                  ));
-                  spans.push(Span::styled(
-                    format!("{id:>id_col$}"),
-                    Style::default()
-                      .fg(Color::Yellow)
-                      .add_modifier(Modifier::BOLD),
-                  ));
-                  spans.push(Span::raw(" "));
-                  spans.push(Span::styled(
-                    preview,
-                    Style::default()
-                      .fg(Color::Yellow)
-                      .add_modifier(Modifier::BOLD),
-                  ));
-                  spans.push(Span::raw(" "));
-                  spans.push(Span::styled(
-                    mime_padded,
-                    Style::default().fg(Color::Green),
-                  ));
-                } else {
-                  spans.push(Span::raw(" "));
-                  spans.push(Span::raw(format!("{id:>id_col$}")));
-                  spans.push(Span::raw(" "));
-                  spans.push(Span::raw(preview));
-                  spans.push(Span::raw(" "));
-                  spans.push(Span::raw(mime_padded));
-                }
-                ListItem::new(Line::from(spans))
-              })
-              .collect();
-
-            let list = List::new(list_items)
-              .block(block)
-              .highlight_style(
-                Style::default()
-                  .fg(Color::Yellow)
-                  .add_modifier(Modifier::BOLD),
-              )
-              .highlight_symbol("");
-
-            f.render_stateful_widget(list, area, list_state);
-          })
-          .map_err(|e| StashError::ListDecode(e.to_string().into()))?;
-        Ok(())
-      };
+              let id = entry.id;
+              let pin = if entry.pinned {
+                Span::styled(pin_symbol, tui.theme.pinned)
+              } else {
+                Span::raw(" ")
+              };
+              let selection = match entry.selection {
+                Selection::Primary => {
+                  Span::styled(primary_symbol, tui.theme.primary)
+                },
+                Selection::Regular => Span::raw(" "),
+              };
+              let mut spans = Vec::new();
+              if Some(i) == selected {
+                let selected_style = tui.theme.selected;
+                spans.push(Span::styled(highlight_symbol, selected_style));
+                spans.push(pin);
+                spans.push(selection);
+                spans
+                  .push(Span::styled(format!("{id:>id_col$}"), selected_style));
+                spans.push(Span::raw(" "));
+                spans.push(Span::styled(
+                  tags,
+                  tui.theme.tags.add_modifier(Modifier::BOLD),
+                ));
+                spans.push(Span::styled(preview, selected_style));
+                spans.push(Span::raw(" "));
+                spans.push(Span::styled(mime_padded, tui.theme.mime));
+              } else if tui.is_marked(id, tui.viewport_offset + i) {
+                let marked_style = tui.theme.marked;
+                spans.push(Span::styled(mark_symbol, marked_style));
+                spans.push(pin);
+                spans.push(selection);
+                spans
+                  .push(Span::styled(format!("{id:>id_col$}"), marked_style));
+                spans.push(Span::raw(" "));
+                spans.push(Span::styled(tags, tui.theme.tags));
+                spans.push(Span::styled(preview, marked_style));
+                spans.push(Span::raw(" "));
+                spans.push(Span::raw(mime_padded));
+              } else {
+                spans.push(Span::raw(" "));
+                spans.push(pin);
+                spans.push(selection);
+                spans.push(Span::raw(format!("{id:>id_col$}")));
+                spans.push(Span::raw(" "));
+                spans.push(Span::styled(tags, tui.theme.tags));
+                spans.push(Span::raw(preview));
+                spans.push(Span::raw(" "));
+                spans.push(Span::raw(mime_padded));
+              }
+              let item = ListItem::new(Line::from(spans));
+              if tui.is_flashing(id) {
+                item.style(tui.theme.flash)
+              } else {
+                item
+              }
+            })
+            .collect();
+
+          let list = List::new(list_items)
+            .block(block)
+            .highlight_style(tui.theme.selected)
+            .highlight_symbol("");
+
+          f.render_stateful_widget(list, area, list_state);
+          if tui.show_help {
+            draw_help(f, tui);
+          }
+        })
+        .map_err(|e| StashError::ListDecode(e.to_string().into()))?;
+      image_preview
+        .flush(terminal.backend_mut())
+        .map_err(|e| StashError::ListDecode(e.to_string().into()))?;
+      Ok(())
+    };
 
     // Initial draw.
     draw_frame(
       &mut terminal,
       &mut tui,
       &mut list_state,
+      &mut image_preview,
+      &mut detail_pane,
       max_id_width,
       max_mime_width,
     )?;
@@ -647,32 +1612,43 @@ impl SqliteClipboardDb {
     let res = (|| -> Result<(), StashError> {
       loop {
         // Block waiting for events, then drain and process all queued input.
-        if event::poll(std::time::Duration::from_millis(250))
-          .map_err(|e| StashError::ListDecode(e.to_string().into()))?
-        {
+        let has_events = event::poll(std::time::Duration::from_millis(250))
+          .map_err(|e| StashError::ListDecode(e.to_string().into()))?;
+        let mut redraw = has_events;
+        if has_events {
           let actions = drain_events(&tui)?;
 
           if actions.quit {
             break;
           }
 
+          if actions.toggle_help {
+            tui.show_help = !tui.show_help;
+          }
+
           // Handle search mode actions
           if actions.toggle_search {
             tui.toggle_search_mode();
+            if tui.search_mode {
+              tui.recount(self, include_expired)?;
+            }
+          }
+
+          if actions.cycle_match_mode {
+            tui.cycle_match_mode();
+            tui.recount(self, include_expired)?;
           }
 
           if actions.clear_search && tui.clear_search() {
             // Search was cleared, refresh count
-            tui.total =
-              self.count_entries(include_expired, tui.search_filter())?;
+            tui.recount(self, include_expired)?;
           }
 
           if let Some(c) = actions.search_input {
             let new_query = format!("{}{}", tui.search_query, c);
             if tui.set_search(new_query) {
               // Search changed, refresh count and reset
-              tui.total =
-                self.count_entries(include_expired, tui.search_filter())?;
+              tui.recount(self, include_expired)?;
             }
           }
 
@@ -685,44 +1661,121 @@ impl SqliteClipboardDb {
               .unwrap_or_default();
             if tui.set_search(new_query) {
               // Search changed, refresh count and reset
-              tui.total =
-                self.count_entries(include_expired, tui.search_filter())?;
+              tui.recount(self, include_expired)?;
             }
           }
 
           // Apply navigation (capped at ±1 per frame for smooth scrolling).
-          if !tui.search_mode {
+          if !tui.search_mode && actions.switch_tab && tui.switch_tab() {
+            tui.recount(self, include_expired)?;
+          }
+
+          if !tui.search_mode
+            && let Some(jump) = actions.jump
+          {
+            tui.jump(jump);
+          }
+
+          if !tui.search_mode && tui.tab == Tab::Snippets {
+            self.handle_snippet_actions(
+              &mut tui,
+              actions.net_down,
+              actions.copy,
+              actions.delete,
+            )?;
+          } else if !tui.search_mode {
+            if actions.cycle_selection {
+              tui.cycle_selection();
+              tui.recount(self, include_expired)?;
+            }
+
+            if actions.toggle_preview {
+              tui.show_preview = !tui.show_preview;
+            }
+
+            if actions.toggle_details {
+              tui.show_details = !tui.show_details;
+            }
+
+            if tui.show_details {
+              detail_pane.scroll_by(actions.scroll_details);
+              detail_pane.scroll_pages(actions.page_details);
+            }
+
             if actions.net_down > 0 {
               tui.move_down();
             } else if actions.net_down < 0 {
               tui.move_up();
             }
 
-            if actions.delete
-              && let Some(&(id, ..)) = tui.selected_entry()
+            if actions.clear_marks {
+              tui.clear_marks();
+            }
+
+            if actions.toggle_mark {
+              if tui.visual_anchor.is_some() {
+                self.commit_visual(&mut tui, include_expired)?;
+              } else {
+                tui.toggle_mark();
+              }
+            }
+
+            if actions.toggle_visual {
+              if tui.visual_anchor.is_some() {
+                self.commit_visual(&mut tui, include_expired)?;
+              } else {
+                tui.start_visual();
+              }
+            }
+
+            if actions.export {
+              self.export_marked(&mut tui, include_expired)?;
+            }
+
+            if actions.copy_joined {
+              self.copy_marked_joined(
+                &mut tui,
+                include_expired,
+                join_separator,
+              )?;
+            }
+
+            if actions.delete && tui.has_marks() {
+              self.delete_marked(&mut tui, include_expired)?;
+            } else if actions.delete
+              && let Some(&ListEntry { id, .. }) = tui.selected_entry()
             {
-              if tui.pending_delete == Some(id) {
-                self
-                  .conn
-                  .execute(
-                    "DELETE FROM clipboard WHERE id = ?1",
-                    rusqlite::params![id],
-                  )
-                  .map_err(|e| {
-                    StashError::DeleteEntry(id, e.to_string().into())
-                  })?;
+              if tui.pending_delete.as_deref() == Some(&[id]) {
+                tui.delete_entry(self, id)?;
                 tui.on_delete();
                 tui.status = Some(format!("deleted entry {id}"));
                 notify("stash", "deleted entry");
               } else {
-                tui.pending_delete = Some(id);
-                tui.status =
-                  Some(format!("press shift+d again to delete entry {id}"));
+                tui.pending_delete = Some(vec![id]);
+                tui.status = Some(format!(
+                  "press {} again to delete entry {id}",
+                  tui.keymap.hint(&[Action::Delete]).unwrap_or_default()
+                ));
               }
             }
 
+            if actions.toggle_pin && tui.has_marks() {
+              self.pin_marked(&mut tui, include_expired)?;
+            } else if actions.toggle_pin
+              && let Some(&ListEntry { id, pinned, .. }) = tui.selected_entry()
+            {
+              tui.set_pinned(self, id, !pinned)?;
+              // Pinning can move the entry when pinned entries sort first.
+              tui.follow(self, id, include_expired)?;
+              tui.dirty = true;
+              tui.pending_delete = None;
+              let action = if pinned { "unpinned" } else { "pinned" };
+              tui.status = Some(format!("{action} entry {id}"));
+            }
+
             if actions.copy
-              && let Some(&(id, ..)) = tui.selected_entry()
+              && let Some(&ListEntry { id, selection, .. }) =
+                tui.selected_entry()
             {
               if tui.copying_entry == Some(id) {
                 log::debug!(
@@ -733,25 +1786,35 @@ impl SqliteClipboardDb {
                 tui.pending_delete = None;
                 tui.status = None;
                 tui.copying_entry = Some(id);
-                match self.copy_entry(id) {
-                  Ok((new_id, contents, mime)) => {
-                    // Copying updates `last_accessed`, which reorders the list,
-                    // and a running `stash watch` daemon may renumber the entry
-                    // (dedup deletes then reinserts identical content). Always
-                    // refresh so the window reflects current ids and ordering.
+                let copied = match &mut tui.daemon {
+                  // The daemon offers the entry on its selection itself.
+                  Some(daemon) => daemon.copy(id).map(|_| None),
+                  None => {
+                    self.copy_entry(id).map(
+                      |(_, contents, mime, representations)| {
+                        Some((contents, mime, representations))
+                      },
+                    )
+                  },
+                };
+                match copied {
+                  Ok(offer) => {
+                    // Copying updates `last_accessed`, which reorders the
+                    // list, so reload the window.
                     tui.dirty = true;
-                    if new_id != id {
-                      tui.total = self
-                        .count_entries(include_expired, tui.search_filter())?;
-                    }
-                    let opts = Options::new();
-                    let mime_type = match mime {
-                      Some(ref m) if m == "text/plain" => MimeType::Text,
-                      Some(ref m) => MimeType::Specific(m.clone().clone()),
-                      None => MimeType::Autodetect,
-                    };
-                    let copy_result = opts
-                      .copy(Source::Bytes(contents.clone().into()), mime_type);
+                    // Restore to the selection the entry was copied from.
+                    let copy_result = offer.map_or(Ok(()), |offer| {
+                      let (contents, mime, representations) = offer;
+                      let mut opts = Options::new();
+                      opts.clipboard(selection.copy_type());
+                      opts
+                        .copy_multi(mime_sources(
+                          contents,
+                          mime.as_deref(),
+                          representations,
+                        ))
+                        .map_err(|e| e.to_string())
+                    });
                     match copy_result {
                       Ok(()) => {
                         tui.status = Some(format!("copied entry {id}"));
@@ -766,21 +1829,13 @@ impl SqliteClipboardDb {
                     }
                   },
                   Err(StashError::DecodeGet(_)) => {
-                    // The row vanished between load and copy: a running
-                    // `stash watch` daemon renumbered it (dedup deletes then
-                    // reinserts the same content under a new id) after we
-                    // re-served the clipboard. Refresh the window rather than
-                    // surfacing an error; the entry now lives under a new id.
-                    log::debug!(
-                      "entry {id} vanished (likely renumbered by watch \
-                       daemon); refreshing list"
-                    );
-                    tui.total = self
-                      .count_entries(include_expired, tui.search_filter())?;
+                    // The entry was deleted since the window was loaded, e.g.
+                    // by another process or by trimming. Refresh the window
+                    // rather than surfacing an error.
+                    log::debug!("entry {id} was deleted; refreshing list");
+                    tui.recount(self, include_expired)?;
                     tui.dirty = true;
-                    tui.status = Some(
-                      "list changed, refreshed - press Enter again".into(),
-                    );
+                    tui.status = Some(format!("entry {id} was deleted"));
                   },
                   Err(e) => {
                     log::error!("failed to fetch entry {id}: {e}");
@@ -793,12 +1848,26 @@ impl SqliteClipboardDb {
               }
             }
           }
+        }
 
-          // Redraw once after processing all accumulated input.
+        // Pick up entries stored by `stash watch` while the list is open.
+        if tui.refresh(self, include_expired)? {
+          (max_id_width, max_mime_width) =
+            global_column_widths(self, include_expired)?;
+          redraw = true;
+        }
+        if tui.expire_flashes() {
+          redraw = true;
+        }
+
+        // Redraw once after processing all accumulated input.
+        if redraw {
           draw_frame(
             &mut terminal,
             &mut tui,
             &mut list_state,
+            &mut image_preview,
+            &mut detail_pane,
             max_id_width,
             max_mime_width,
           )?;
@@ -808,6 +1877,7 @@ impl SqliteClipboardDb {
     })();
 
     // Ignore errors during terminal restore, as we can't recover here.
+    let _ = image_preview.clear(terminal.backend_mut());
     let _ = disable_raw_mode();
     let _ = execute!(
       terminal.backend_mut(),
@@ -819,3 +1889,224 @@ impl SqliteClipboardDb {
     res
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::tests::{store_text, test_db, two_connections};
+
+  #[test]
+  fn test_refresh_keeps_selection_and_flashes_new_entries() {
+    let (_dir, tui_db, watcher) = two_connections();
+
+    let older = store_text(&watcher, "older");
+    let newer = store_text(&watcher, "newer");
+    let mut tui =
+      TuiState::new(&tui_db, false, 10, 100, false, false, None, &[], None)
+        .expect("tui");
+    tui.move_down();
+    tui.sync(&tui_db, false, 100).expect("sync");
+    assert_eq!(tui.selected_entry().map(|e| e.id), Some(older));
+
+    assert!(!tui.refresh(&tui_db, false).expect("refresh"));
+
+    let arrived = store_text(&watcher, "arrived");
+    assert!(tui.refresh(&tui_db, false).expect("refresh"));
+    tui.sync(&tui_db, false, 100).expect("sync");
+    assert_eq!(tui.total, 3);
+    assert_eq!(tui.selected_entry().map(|e| e.id), Some(older));
+    assert!(tui.is_flashing(arrived));
+    assert!(!tui.is_flashing(newer));
+
+    // The selected entry disappearing clamps the cursor instead.
+    watcher
+      .delete_entries(format!("{older}\n").as_bytes())
+      .expect("delete");
+    assert!(tui.refresh(&tui_db, false).expect("refresh"));
+    tui.sync(&tui_db, false, 100).expect("sync");
+    assert_eq!(tui.total, 2);
+    assert_eq!(tui.selected_entry().map(|e| e.id), Some(newer));
+  }
+
+  #[test]
+  fn test_snippets_tab_searches_and_follows_changes() {
+    let (_dir, tui_db, other) = two_connections();
+    other
+      .add_snippet("greeting", b"Hello, {clipboard}", &[], false)
+      .expect("add");
+    other
+      .add_snippet("sig", b"-- \nme", &["mail".to_owned()], false)
+      .expect("add");
+
+    let mut tui =
+      TuiState::new(&tui_db, false, 10, 100, false, false, None, &[], None)
+        .expect("tui");
+    tui.set_search("entry".to_owned());
+    assert!(tui.switch_tab());
+    assert_eq!(tui.tab, Tab::Snippets);
+    assert!(tui.search_query.is_empty());
+
+    tui.move_snippet_cursor(-1);
+    assert_eq!(tui.selected_snippet().map(|s| s.name.as_str()), Some("sig"));
+    tui.move_snippet_cursor(1);
+    assert_eq!(
+      tui.selected_snippet().map(|s| s.name.as_str()),
+      Some("greeting")
+    );
+
+    tui.set_search("MAIL".to_owned());
+    let names: Vec<&str> = tui
+      .visible_snippets()
+      .iter()
+      .map(|s| s.name.as_str())
+      .collect();
+    assert_eq!(names, ["sig"]);
+
+    other.remove_snippet("sig").expect("remove");
+    assert!(tui.refresh(&tui_db, false).expect("refresh"));
+    assert!(tui.visible_snippets().is_empty());
+    assert!(tui.selected_snippet().is_none());
+  }
+
+  #[test]
+  fn test_marks_follow_entries_through_reloads() {
+    let (dir, tui_db, watcher) = two_connections();
+
+    let ids: Vec<i64> = ["one", "two", "three", "four", "five"]
+      .into_iter()
+      .map(|text| store_text(&watcher, text))
+      .collect();
+    let mut tui =
+      TuiState::new(&tui_db, false, 10, 100, false, false, None, &[], None)
+        .expect("tui");
+
+    // Mark the newest entry, then the third to fifth newest as a range.
+    tui.toggle_mark();
+    tui.move_down();
+    tui.start_visual();
+    tui.move_down();
+    tui.move_down();
+    tui.sync(&tui_db, false, 100).expect("sync");
+    assert!(tui.is_marked(ids[1], 3));
+    assert!(!tui.is_marked(ids[3], 1));
+    tui_db.commit_visual(&mut tui, false).expect("commit");
+    assert_eq!(tui.marked, [ids[4], ids[2], ids[1], ids[0]]);
+
+    // New and removed entries shift positions, but marks stay with theirs.
+    let arrived = store_text(&watcher, "six");
+    watcher
+      .delete_entries(format!("{}\n", ids[2]).as_bytes())
+      .expect("delete");
+    assert!(tui.refresh(&tui_db, false).expect("refresh"));
+    tui.sync(&tui_db, false, 100).expect("sync");
+    assert_eq!(tui.marked, [ids[4], ids[1], ids[0]]);
+    assert!(tui.is_marked(ids[4], 1));
+    assert!(!tui.is_marked(arrived, 0));
+
+    tui_db.pin_marked(&mut tui, false).expect("pin");
+    assert!(tui_db.is_pinned(ids[1]).expect("pinned"));
+    assert!(!tui_db.is_pinned(ids[3]).expect("pinned"));
+    tui_db.pin_marked(&mut tui, false).expect("unpin");
+    assert!(!tui_db.is_pinned(ids[1]).expect("pinned"));
+
+    // Exports land in private files next to each other, never over one
+    // another.
+    let state = dir.path().join("state");
+    // SAFETY: no other test reads the state directory.
+    unsafe { std::env::set_var("XDG_STATE_HOME", &state) };
+    tui_db.export_marked(&mut tui, false).expect("export");
+    tui_db.export_marked(&mut tui, false).expect("export");
+    let exports = state.join("stash").join("exports");
+    let mode = |path: &std::path::Path| {
+      use std::os::unix::fs::PermissionsExt;
+      std::fs::metadata(path)
+        .expect("metadata")
+        .permissions()
+        .mode()
+        & 0o777
+    };
+    assert_eq!(mode(&exports), 0o700);
+    let files: Vec<_> = std::fs::read_dir(&exports)
+      .expect("read exports")
+      .map(|entry| entry.expect("export").path())
+      .collect();
+    assert_eq!(files.len(), 2);
+    assert!(files.iter().all(|file| mode(file) == 0o600));
+
+    // Deleting takes a second press.
+    tui_db.delete_marked(&mut tui, false).expect("delete");
+    assert_eq!(tui.total, 5);
+    tui_db.delete_marked(&mut tui, false).expect("delete");
+    assert!(!tui.has_marks());
+    assert_eq!(tui.total, 2);
+    tui.sync(&tui_db, false, 100).expect("sync");
+    let left: Vec<i64> = tui.window.iter().map(|entry| entry.id).collect();
+    assert_eq!(left, [arrived, ids[3]]);
+  }
+
+  #[test]
+  fn test_cursor_follows_entries_moved_by_pinning() {
+    let db = test_db();
+    let ids: Vec<i64> = ["one", "two", "three"]
+      .into_iter()
+      .map(|text| store_text(&db, text))
+      .collect();
+    let mut tui =
+      TuiState::new(&db, false, 10, 100, false, true, None, &[], None)
+        .expect("tui");
+    tui.jump(Jump::Bottom);
+    tui.sync(&db, false, 100).expect("sync");
+    assert_eq!(tui.selected_entry().map(|e| e.id), Some(ids[0]));
+
+    tui.set_pinned(&db, ids[0], true).expect("pin");
+    assert_eq!(tui.follow(&db, ids[0], false).expect("follow"), Some(0));
+    tui.sync(&db, false, 100).expect("sync");
+    assert_eq!(tui.selected_entry().map(|e| e.id), Some(ids[0]));
+
+    tui.set_pinned(&db, ids[0], false).expect("unpin");
+    tui.follow(&db, ids[0], false).expect("follow");
+    tui.sync(&db, false, 100).expect("sync");
+    assert_eq!(tui.cursor, 2);
+    assert_eq!(tui.selected_entry().map(|e| e.id), Some(ids[0]));
+  }
+
+  #[test]
+  fn test_jumps_move_by_pages_and_to_the_ends() {
+    let db = test_db();
+    let ids: Vec<i64> = (0..10)
+      .map(|i| store_text(&db, &format!("entry {i}")))
+      .collect();
+
+    let mut tui =
+      TuiState::new(&db, false, 4, 100, false, false, None, &[], None)
+        .expect("tui");
+    tui.jump(Jump::Pages(1));
+    tui.sync(&db, false, 100).expect("sync");
+    assert_eq!(tui.selected_entry().map(|e| e.id), Some(ids[5]));
+    tui.jump(Jump::Pages(3));
+    tui.sync(&db, false, 100).expect("sync");
+    assert_eq!(tui.selected_entry().map(|e| e.id), Some(ids[0]));
+    tui.jump(Jump::Pages(-1));
+    tui.sync(&db, false, 100).expect("sync");
+    assert_eq!(tui.selected_entry().map(|e| e.id), Some(ids[4]));
+    tui.jump(Jump::Top);
+    tui.sync(&db, false, 100).expect("sync");
+    assert_eq!(tui.selected_entry().map(|e| e.id), Some(ids[9]));
+    tui.jump(Jump::Bottom);
+    tui.sync(&db, false, 100).expect("sync");
+    assert_eq!(tui.selected_entry().map(|e| e.id), Some(ids[0]));
+
+    // The snippets tab keeps its own cursor.
+    for name in ["a", "b", "c"] {
+      db.add_snippet(name, b"text", &[], false).expect("add");
+    }
+    tui.reload_snippets(&db).expect("reload");
+    assert!(!tui.switch_tab());
+    tui.jump(Jump::Bottom);
+    assert_eq!(tui.snippet_cursor, 2);
+    tui.jump(Jump::Pages(-1));
+    assert_eq!(tui.snippet_cursor, 0);
+    assert!(!tui.switch_tab());
+    assert_eq!(tui.selected_entry().map(|e| e.id), Some(ids[0]));
+  }
+}
@@ -0,0 +1,540 @@
+//! Detail pane for the list TUI: the metadata and full contents of the
+//! selected entry.
+//!
+//! Contents are only loaded once an entry is selected, and the most recent
+//! ones are kept while it stays selected. Text that looks like code or JSON is
+//! syntax highlighted when the `syntax-highlighting` feature is enabled. The
+//! metadata is read again on every frame, since copying or pinning the entry
+//! changes it.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use ratatui::{
+  Frame,
+  layout::{Constraint, Layout, Rect},
+  style::Style,
+  text::{Line, Span},
+  widgets::{Block, Borders, Paragraph},
+};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use super::{TuiState, keymap::Action, preview::ImagePreview};
+use crate::db::{EntryDetails, ListEntry, SqliteClipboardDb, size_str};
+
+/// Terminals at least this wide get the pane next to the list, narrower ones
+/// below it.
+const SIDE_BY_SIDE_WIDTH: u16 = 100;
+
+/// Contents larger than this are shown without highlighting, which would make
+/// selecting them noticeably slow.
+#[cfg(feature = "syntax-highlighting")]
+const MAX_HIGHLIGHTED_BYTES: usize = 256 * 1024;
+
+/// Columns a tab is expanded to.
+const TAB_WIDTH: usize = 4;
+
+/// A line of text as styled pieces.
+type StyledLine = Vec<(Style, String)>;
+
+/// Split the TUI into the list and the detail pane.
+pub fn split(area: Rect) -> (Rect, Rect) {
+  let halves = [Constraint::Percentage(50), Constraint::Percentage(50)];
+  let [list, detail] = if area.width >= SIDE_BY_SIDE_WIDTH {
+    Layout::horizontal(halves).areas(area)
+  } else {
+    Layout::vertical(halves).areas(area)
+  };
+  (list, detail)
+}
+
+/// Replace tabs with spaces and other control characters with U+FFFD, which
+/// would otherwise garble the terminal.
+fn sanitize(text: &str) -> String {
+  let mut clean = String::with_capacity(text.len());
+  for c in text.chars() {
+    match c {
+      '\t' => clean.push_str(&" ".repeat(TAB_WIDTH)),
+      '\n' | '\r' => {},
+      c if c.is_control() => clean.push('\u{fffd}'),
+      c => clean.push(c),
+    }
+  }
+  clean
+}
+
+fn plain_lines(text: &str) -> Vec<StyledLine> {
+  text
+    .lines()
+    .map(|line| vec![(Style::default(), sanitize(line))])
+    .collect()
+}
+
+#[cfg(feature = "syntax-highlighting")]
+mod highlight {
+  use std::sync::LazyLock;
+
+  use ratatui::style::{Color, Modifier, Style};
+  use syntect::{
+    easy::HighlightLines,
+    highlighting::{FontStyle, Theme, ThemeSet},
+    parsing::{SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+  };
+
+  use super::{StyledLine, sanitize};
+
+  static SYNTAXES: LazyLock<SyntaxSet> =
+    LazyLock::new(SyntaxSet::load_defaults_newlines);
+
+  static THEME: LazyLock<Theme> = LazyLock::new(|| {
+    ThemeSet::load_defaults()
+      .themes
+      .remove("base16-ocean.dark")
+      .unwrap_or_default()
+  });
+
+  /// Pick a syntax from the MIME type, from whether the text parses as JSON,
+  /// or from its first line, e.g. a shebang or an XML declaration.
+  fn find_syntax<'a>(
+    syntaxes: &'a SyntaxSet,
+    text: &str,
+    mime: Option<&str>,
+  ) -> Option<&'a SyntaxReference> {
+    let subtype = mime
+      .and_then(|mime| mime.split(';').next())
+      .and_then(|mime| mime.split_once('/'))
+      .map(|(_, subtype)| subtype.trim());
+    if let Some(subtype) = subtype {
+      if subtype == "json" || subtype.ends_with("+json") {
+        return syntaxes.find_syntax_by_name("JSON");
+      }
+      let token = match subtype.strip_prefix("x-").unwrap_or(subtype) {
+        "shellscript" => "sh",
+        token => token,
+      };
+      if token != "plain"
+        && let Some(syntax) = syntaxes.find_syntax_by_token(token)
+      {
+        return Some(syntax);
+      }
+    }
+
+    let trimmed = text.trim_start();
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+      && serde_json::from_str::<serde::de::IgnoredAny>(text).is_ok()
+    {
+      return syntaxes.find_syntax_by_name("JSON");
+    }
+    syntaxes.find_syntax_by_first_line(text.lines().next()?)
+  }
+
+  fn style(style: syntect::highlighting::Style) -> Style {
+    let syntect::highlighting::Color { r, g, b, .. } = style.foreground;
+    let mut converted = Style::default().fg(Color::Rgb(r, g, b));
+    for (font, modifier) in [
+      (FontStyle::BOLD, Modifier::BOLD),
+      (FontStyle::ITALIC, Modifier::ITALIC),
+      (FontStyle::UNDERLINE, Modifier::UNDERLINED),
+    ] {
+      if style.font_style.contains(font) {
+        converted = converted.add_modifier(modifier);
+      }
+    }
+    converted
+  }
+
+  /// Highlight `text` line by line, or `None` if it does not look like
+  /// anything there is a syntax for.
+  pub fn highlight(text: &str, mime: Option<&str>) -> Option<Vec<StyledLine>> {
+    if text.len() > super::MAX_HIGHLIGHTED_BYTES {
+      return None;
+    }
+    let syntax = find_syntax(&SYNTAXES, text, mime)?;
+    let mut highlighter = HighlightLines::new(syntax, &THEME);
+    let mut lines = Vec::new();
+    for line in LinesWithEndings::from(text) {
+      let pieces = match highlighter.highlight_line(line, &SYNTAXES) {
+        Ok(pieces) => pieces,
+        Err(e) => {
+          log::debug!("cannot highlight as {}: {e}", syntax.name);
+          return None;
+        },
+      };
+      lines.push(
+        pieces
+          .into_iter()
+          .map(|(piece_style, piece)| (style(piece_style), sanitize(piece)))
+          .collect(),
+      );
+    }
+    Some(lines)
+  }
+
+  #[cfg(test)]
+  mod tests {
+    use super::*;
+
+    fn syntax_name(text: &str, mime: Option<&str>) -> Option<String> {
+      find_syntax(&SYNTAXES, text, mime).map(|syntax| syntax.name.clone())
+    }
+
+    #[test]
+    fn test_find_syntax() {
+      assert_eq!(
+        syntax_name("{\"a\": [1, 2]}", Some("text/plain")).as_deref(),
+        Some("JSON")
+      );
+      assert_eq!(
+        syntax_name("x = 1", Some("application/ld+json")).as_deref(),
+        Some("JSON")
+      );
+      assert_eq!(
+        syntax_name("#!/bin/sh\necho hi", None).as_deref(),
+        Some("Bourne Again Shell (bash)")
+      );
+      assert_eq!(
+        syntax_name("print(1)", Some("text/x-python")).as_deref(),
+        Some("Python")
+      );
+      // Braces alone do not make JSON.
+      assert_eq!(syntax_name("{ not json }", Some("text/plain")), None);
+      assert_eq!(syntax_name("just some words", None), None);
+    }
+  }
+}
+
+/// Split `text` into lines, highlighted if it looks like code or JSON.
+fn text_lines(text: &str, mime: Option<&str>) -> Vec<StyledLine> {
+  #[cfg(feature = "syntax-highlighting")]
+  if let Some(lines) = highlight::highlight(text, mime) {
+    return lines;
+  }
+  #[cfg(not(feature = "syntax-highlighting"))]
+  let _ = mime;
+  plain_lines(text)
+}
+
+/// Break `line` into lines at most `width` columns wide.
+fn wrap(line: &StyledLine, width: usize) -> Vec<Line<'static>> {
+  let mut lines = Vec::new();
+  let mut spans = Vec::new();
+  let mut used = 0;
+  for (style, piece) in line {
+    let mut current = String::new();
+    for grapheme in piece.graphemes(true) {
+      let grapheme_width = grapheme.width();
+      if used + grapheme_width > width && used > 0 {
+        if !current.is_empty() {
+          spans.push(Span::styled(std::mem::take(&mut current), *style));
+        }
+        lines.push(Line::from(std::mem::take(&mut spans)));
+        used = 0;
+      }
+      current.push_str(grapheme);
+      used += grapheme_width;
+    }
+    if !current.is_empty() {
+      spans.push(Span::styled(current, *style));
+    }
+  }
+  lines.push(Line::from(spans));
+  lines
+}
+
+/// Format a unix timestamp along with how long ago, or how far ahead, it is.
+fn format_time(secs: f64, now: f64) -> String {
+  let stamp = humantime::format_rfc3339_seconds(
+    UNIX_EPOCH + Duration::from_secs_f64(secs.max(0.0)),
+  );
+  let delta = (secs - now).abs();
+  let relative = if delta < 60.0 {
+    format!("{delta:.0}s")
+  } else if delta < 3600.0 {
+    format!("{:.0}m", delta / 60.0)
+  } else if delta < 86400.0 {
+    format!("{:.0}h", delta / 3600.0)
+  } else {
+    format!("{:.0}d", delta / 86400.0)
+  };
+  if secs > now {
+    format!("{stamp} (in {relative})")
+  } else {
+    format!("{stamp} ({relative} ago)")
+  }
+}
+
+/// The metadata lines shown above the contents.
+fn metadata_lines(
+  details: &EntryDetails,
+  size: Option<usize>,
+  now: f64,
+  label: Style,
+) -> Vec<Line<'static>> {
+  let size = size.unwrap_or(details.stored_size);
+  let last_accessed = details
+    .last_accessed
+    .map_or_else(|| "never".to_owned(), |secs| format_time(secs as f64, now));
+  let expires = details
+    .expires_at
+    .map_or_else(|| "never".to_owned(), |secs| format_time(secs, now));
+  let fields = [
+    ("id", details.id.to_string()),
+    ("size", format!("{} ({size} bytes)", size_str(size))),
+    (
+      "mime",
+      details.mime.clone().unwrap_or_else(|| "unknown".to_owned()),
+    ),
+    ("offered", details.mime_types.join(", ")),
+    ("last used", last_accessed),
+    ("expires", expires),
+    (
+      "encrypted",
+      if details.encrypted { "yes" } else { "no" }.to_owned(),
+    ),
+  ];
+  fields
+    .into_iter()
+    .filter(|(_, value)| !value.is_empty())
+    .map(|(name, value)| {
+      Line::from(vec![
+        Span::styled(format!("{name:<10} "), label),
+        Span::raw(value),
+      ])
+    })
+    .collect()
+}
+
+/// What the contents of an entry are shown as.
+enum Body {
+  Text(Vec<StyledLine>),
+  /// Drawn by the image preview.
+  Image,
+  Binary,
+  Error(String),
+}
+
+/// The contents of the entry shown last.
+struct Loaded {
+  id:      i64,
+  /// Whether images were shown as images when loading.
+  images:  bool,
+  /// Size of the decoded contents, if they could be decoded.
+  size:    Option<usize>,
+  body:    Body,
+  /// Text contents wrapped to the width they were last drawn at.
+  wrapped: Option<(usize, Vec<Line<'static>>)>,
+}
+
+/// Detail pane state, kept across frames.
+pub struct DetailPane {
+  loaded: Option<Loaded>,
+  /// First line of the contents shown.
+  scroll: usize,
+  /// How many lines of contents fit in the pane, as of the last frame.
+  page:   usize,
+}
+
+impl DetailPane {
+  pub fn new() -> Self {
+    Self {
+      loaded: None,
+      scroll: 0,
+      page:   1,
+    }
+  }
+
+  /// Scroll the contents by `lines`, down if positive.
+  pub fn scroll_by(&mut self, lines: i64) {
+    let distance = usize::try_from(lines.unsigned_abs()).unwrap_or(usize::MAX);
+    self.scroll = if lines > 0 {
+      self.scroll.saturating_add(distance)
+    } else {
+      self.scroll.saturating_sub(distance)
+    };
+  }
+
+  /// Scroll the contents by `pages` screens, down if positive.
+  pub fn scroll_pages(&mut self, pages: i64) {
+    let page = i64::try_from(self.page).unwrap_or(i64::MAX);
+    self.scroll_by(pages.saturating_mul(page));
+  }
+
+  fn load(&mut self, db: &SqliteClipboardDb, entry: &ListEntry, images: bool) {
+    if self
+      .loaded
+      .as_ref()
+      .is_some_and(|loaded| loaded.id == entry.id && loaded.images == images)
+    {
+      return;
+    }
+    self.scroll = 0;
+    let (size, body) = match db.entry(entry.id) {
+      Ok(decoded) => {
+        let size = decoded.contents.len();
+        let body = if images && ImagePreview::applies_to(entry) {
+          Body::Image
+        } else {
+          match String::from_utf8(decoded.contents) {
+            Ok(text) => Body::Text(text_lines(&text, decoded.mime.as_deref())),
+            Err(_) => Body::Binary,
+          }
+        };
+        (Some(size), body)
+      },
+      Err(e) => (None, Body::Error(e.to_string())),
+    };
+    self.loaded = Some(Loaded {
+      id: entry.id,
+      images,
+      size,
+      body,
+      wrapped: None,
+    });
+  }
+
+  /// Draw the pane for `entry` into `area`. Images are drawn with
+  /// `image_preview`, or left out when it is `None`.
+  pub fn render(
+    &mut self,
+    f: &mut Frame,
+    area: Rect,
+    db: &SqliteClipboardDb,
+    entry: &ListEntry,
+    tui: &TuiState,
+    image_preview: Option<&mut ImagePreview>,
+  ) {
+    self.load(db, entry, image_preview.is_some());
+    let hints = tui.keymap.hints(&[
+      (&[Action::Details], "to close"),
+      (
+        &[
+          Action::DetailDown,
+          Action::DetailUp,
+          Action::DetailPageDown,
+          Action::DetailPageUp,
+        ],
+        "to scroll",
+      ),
+    ]);
+    let title = if hints.is_empty() {
+      format!(" Entry {} ", entry.id)
+    } else {
+      format!(" Entry {} - {hints} ", entry.id)
+    };
+    let block = Block::default()
+      .title(title)
+      .title_style(tui.theme.title)
+      .borders(Borders::ALL)
+      .border_style(tui.theme.border);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    let Some(loaded) = &mut self.loaded else {
+      return;
+    };
+
+    let metadata = match db.entry_details(entry.id) {
+      Ok(Some(details)) => {
+        metadata_lines(
+          &details,
+          loaded.size,
+          SqliteClipboardDb::now(),
+          tui.theme.label,
+        )
+      },
+      Ok(None) => vec![Line::from("the entry was deleted")],
+      Err(e) => vec![Line::from(e.to_string())],
+    };
+    let [metadata_area, contents_area] = Layout::vertical([
+      Constraint::Length(u16::try_from(metadata.len()).unwrap_or(u16::MAX)),
+      Constraint::Fill(1),
+    ])
+    .areas(inner);
+    f.render_widget(Paragraph::new(metadata), metadata_area);
+
+    let message;
+    let lines: &[Line<'static>] = match &loaded.body {
+      Body::Image => {
+        if let Some(image_preview) = image_preview {
+          image_preview.render(f, contents_area, db, entry);
+        }
+        return;
+      },
+      Body::Binary => {
+        message = [Line::from(
+          "binary contents, use `stash decode` to get them".to_owned(),
+        )];
+        &message
+      },
+      Body::Error(e) => {
+        message = [Line::from(format!("cannot show contents: {e}"))];
+        &message
+      },
+      Body::Text(text) => {
+        let width = usize::from(contents_area.width).max(1);
+        match &mut loaded.wrapped {
+          Some((wrapped_width, wrapped)) if *wrapped_width == width => wrapped,
+          slot => {
+            let wrapped =
+              text.iter().flat_map(|line| wrap(line, width)).collect();
+            &slot.insert((width, wrapped)).1
+          },
+        }
+      },
+    };
+
+    let contents_block = Block::default().borders(Borders::TOP);
+    let text_area = contents_block.inner(contents_area);
+    self.page = usize::from(text_area.height).max(1);
+    self.scroll = self
+      .scroll
+      .min(lines.len().saturating_sub(usize::from(text_area.height)));
+    let shown = lines.len().min(self.scroll + self.page);
+    let contents_block = if lines.len() > self.page {
+      contents_block.title(format!(
+        " lines {}-{shown} of {} ",
+        self.scroll + 1,
+        lines.len()
+      ))
+    } else {
+      contents_block
+    };
+    f.render_widget(contents_block, contents_area);
+    f.render_widget(
+      Paragraph::new(lines[self.scroll..shown].to_vec()),
+      text_area,
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use ratatui::style::Color;
+
+  use super::*;
+
+  #[test]
+  fn test_wrap_breaks_at_width() {
+    let line = vec![
+      (Style::default(), "ab".to_owned()),
+      (Style::default().fg(Color::Red), "cde日本".to_owned()),
+    ];
+    let wrapped: Vec<String> = wrap(&line, 4)
+      .iter()
+      .map(|line| line.spans.iter().map(|span| &*span.content).collect())
+      .collect();
+    assert_eq!(wrapped, ["abcd", "e日", "本"]);
+    assert_eq!(wrap(&Vec::new(), 4).len(), 1);
+  }
+
+  #[test]
+  fn test_sanitize_expands_tabs_and_hides_controls() {
+    assert_eq!(sanitize("\tx\x1b[2Jy\r\n"), "    x\u{fffd}[2Jy");
+  }
+
+  #[test]
+  fn test_format_time() {
+    assert_eq!(format_time(0.0, 90.0), "1970-01-01T00:00:00Z (2m ago)");
+    assert_eq!(format_time(7200.0, 0.0), "1970-01-01T02:00:00Z (in 2h)");
+  }
+}
@@ -0,0 +1,543 @@
+//! Key bindings of the list TUI.
+//!
+//! Every action has default keys, which `--keys ACTION=KEY...` (or
+//! `$STASH_TUI_KEYS`, or `keys` in the `[list]` section of the config file)
+//! replaces. A key given there is also taken away from the actions it is
+//! bound to by default, so that rebinding a key does not need unbinding it
+//! elsewhere first.
+
+use std::fmt;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::db::StashError;
+
+fn keymap_err(e: impl ToString) -> StashError {
+  StashError::Keymap(e.to_string().into())
+}
+
+/// Something a key does in the TUI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+  Quit,
+  Down,
+  Up,
+  PageDown,
+  PageUp,
+  Top,
+  Bottom,
+  Copy,
+  Delete,
+  Pin,
+  Selection,
+  Preview,
+  Details,
+  DetailDown,
+  DetailUp,
+  DetailPageDown,
+  DetailPageUp,
+  SwitchTab,
+  Search,
+  Mark,
+  Visual,
+  /// Only applies while entries are marked, so it may share its key with
+  /// another action.
+  Unmark,
+  Export,
+  CopyJoined,
+  Help,
+  SearchAccept,
+  SearchCancel,
+  SearchMode,
+}
+
+impl Action {
+  /// Every action, in the order the help lists them.
+  pub const ALL: [Self; 28] = [
+    Self::Down,
+    Self::Up,
+    Self::PageDown,
+    Self::PageUp,
+    Self::Top,
+    Self::Bottom,
+    Self::Copy,
+    Self::Delete,
+    Self::Pin,
+    Self::Mark,
+    Self::Visual,
+    Self::Unmark,
+    Self::Export,
+    Self::CopyJoined,
+    Self::Search,
+    Self::Selection,
+    Self::SwitchTab,
+    Self::Preview,
+    Self::Details,
+    Self::DetailDown,
+    Self::DetailUp,
+    Self::DetailPageDown,
+    Self::DetailPageUp,
+    Self::Help,
+    Self::Quit,
+    Self::SearchAccept,
+    Self::SearchCancel,
+    Self::SearchMode,
+  ];
+
+  /// Name of the action in `--keys`.
+  pub const fn name(self) -> &'static str {
+    match self {
+      Self::Quit => "quit",
+      Self::Down => "down",
+      Self::Up => "up",
+      Self::PageDown => "page-down",
+      Self::PageUp => "page-up",
+      Self::Top => "top",
+      Self::Bottom => "bottom",
+      Self::Copy => "copy",
+      Self::Delete => "delete",
+      Self::Pin => "pin",
+      Self::Selection => "selection",
+      Self::Preview => "preview",
+      Self::Details => "details",
+      Self::DetailDown => "detail-down",
+      Self::DetailUp => "detail-up",
+      Self::DetailPageDown => "detail-page-down",
+      Self::DetailPageUp => "detail-page-up",
+      Self::SwitchTab => "tab",
+      Self::Search => "search",
+      Self::Mark => "mark",
+      Self::Visual => "visual",
+      Self::Unmark => "unmark",
+      Self::Export => "export",
+      Self::CopyJoined => "copy-joined",
+      Self::Help => "help",
+      Self::SearchAccept => "search-accept",
+      Self::SearchCancel => "search-cancel",
+      Self::SearchMode => "search-mode",
+    }
+  }
+
+  /// What the action does, for the help.
+  pub const fn description(self) -> &'static str {
+    match self {
+      Self::Quit => "quit",
+      Self::Down => "move down",
+      Self::Up => "move up",
+      Self::PageDown => "move a page down",
+      Self::PageUp => "move a page up",
+      Self::Top => "go to the first entry",
+      Self::Bottom => "go to the last entry",
+      Self::Copy => "copy the entry",
+      Self::Delete => "delete the entry or the marked ones (press twice)",
+      Self::Pin => "pin or unpin the entry or the marked ones",
+      Self::Selection => "filter by selection",
+      Self::Preview => "show or hide image previews",
+      Self::Details => "show or hide the detail pane",
+      Self::DetailDown => "scroll the details down",
+      Self::DetailUp => "scroll the details up",
+      Self::DetailPageDown => "scroll the details a page down",
+      Self::DetailPageUp => "scroll the details a page up",
+      Self::SwitchTab => "switch between history and snippets",
+      Self::Search => "search",
+      Self::Mark => "mark or unmark the entry",
+      Self::Visual => "mark a range of entries",
+      Self::Unmark => "drop the marks",
+      Self::Export => "export the entry or the marked ones",
+      Self::CopyJoined => "copy the marked entries joined",
+      Self::Help => "show this help",
+      Self::SearchAccept => "finish the search",
+      Self::SearchCancel => "clear the search",
+      Self::SearchMode => "switch how the search matches",
+    }
+  }
+
+  /// Whether the action applies while typing a search rather than
+  /// otherwise.
+  pub const fn in_search(self) -> bool {
+    matches!(
+      self,
+      Self::SearchAccept | Self::SearchCancel | Self::SearchMode
+    )
+  }
+
+  const fn default_keys(self) -> &'static [&'static str] {
+    match self {
+      Self::Quit => &["q", "esc"],
+      Self::Down => &["j", "down"],
+      Self::Up => &["k", "up"],
+      Self::PageDown => &["pagedown", "ctrl-f"],
+      Self::PageUp => &["pageup", "ctrl-b"],
+      Self::Top => &["g", "home"],
+      Self::Bottom => &["G", "end"],
+      Self::Copy | Self::SearchAccept => &["enter"],
+      Self::Delete => &["D"],
+      Self::Pin => &["p"],
+      Self::Selection => &["s"],
+      Self::Preview => &["i"],
+      Self::Details => &["o"],
+      Self::DetailDown => &["J"],
+      Self::DetailUp => &["K"],
+      Self::DetailPageDown => &["ctrl-d"],
+      Self::DetailPageUp => &["ctrl-u"],
+      Self::SwitchTab | Self::SearchMode => &["tab"],
+      Self::Search => &["/"],
+      Self::Mark => &["space"],
+      Self::Visual => &["V"],
+      Self::Unmark | Self::SearchCancel => &["esc"],
+      Self::Export => &["e"],
+      Self::CopyJoined => &["y"],
+      Self::Help => &["?"],
+    }
+  }
+
+  fn from_name(name: &str) -> Option<Self> {
+    Self::ALL.into_iter().find(|action| action.name() == name)
+  }
+}
+
+/// A key with its modifiers. Shift is part of the character for character
+/// keys, so `D` is Shift+D.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+  code:      KeyCode,
+  modifiers: KeyModifiers,
+}
+
+impl Key {
+  /// The character the key types in a search, if it types one.
+  pub fn typed(self) -> Option<char> {
+    match self.code {
+      KeyCode::Char(c)
+        if !self
+          .modifiers
+          .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+      {
+        Some(c)
+      },
+      _ => None,
+    }
+  }
+
+  pub const fn is_backspace(self) -> bool {
+    matches!(self.code, KeyCode::Backspace)
+  }
+
+  /// Parse a key like `j`, `G`, `ctrl-n`, `alt-v`, `shift-tab` or `pagedown`.
+  pub fn parse(spec: &str) -> Result<Self, StashError> {
+    let mut rest = spec;
+    let mut modifiers = KeyModifiers::NONE;
+    while let Some((prefix, after)) = rest.split_once(['-', '+']) {
+      let modifier = match prefix.to_lowercase().as_str() {
+        "ctrl" | "c" => KeyModifiers::CONTROL,
+        "alt" | "m" => KeyModifiers::ALT,
+        "shift" => KeyModifiers::SHIFT,
+        _ => break,
+      };
+      if after.is_empty() {
+        break;
+      }
+      modifiers |= modifier;
+      rest = after;
+    }
+
+    let mut chars = rest.chars();
+    let code = match (chars.next(), chars.next()) {
+      (Some(c), None) => KeyCode::Char(c),
+      _ => {
+        match rest.to_lowercase().as_str() {
+          "enter" | "return" => KeyCode::Enter,
+          "esc" | "escape" => KeyCode::Esc,
+          "tab" => KeyCode::Tab,
+          "backtab" => KeyCode::BackTab,
+          "backspace" => KeyCode::Backspace,
+          "delete" | "del" => KeyCode::Delete,
+          "insert" | "ins" => KeyCode::Insert,
+          "space" => KeyCode::Char(' '),
+          "up" => KeyCode::Up,
+          "down" => KeyCode::Down,
+          "left" => KeyCode::Left,
+          "right" => KeyCode::Right,
+          "home" => KeyCode::Home,
+          "end" => KeyCode::End,
+          "pageup" | "pgup" => KeyCode::PageUp,
+          "pagedown" | "pgdn" => KeyCode::PageDown,
+          name => {
+            name
+              .strip_prefix('f')
+              .and_then(|n| n.parse().ok())
+              .filter(|n| (1..=24).contains(n))
+              .map(KeyCode::F)
+              .ok_or_else(|| keymap_err(format!("unknown key `{spec}`")))?
+          },
+        }
+      },
+    };
+    Ok(Self::from(KeyEvent::new(code, modifiers)))
+  }
+}
+
+impl From<KeyEvent> for Key {
+  fn from(event: KeyEvent) -> Self {
+    let mut code = event.code;
+    let mut modifiers = event.modifiers
+      & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+    match code {
+      KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => {
+        code = KeyCode::Char(c.to_uppercase().next().unwrap_or(c));
+        modifiers.remove(KeyModifiers::SHIFT);
+      },
+      KeyCode::Tab if modifiers.contains(KeyModifiers::SHIFT) => {
+        code = KeyCode::BackTab;
+        modifiers.remove(KeyModifiers::SHIFT);
+      },
+      KeyCode::BackTab => modifiers.remove(KeyModifiers::SHIFT),
+      _ => {},
+    }
+    Self { code, modifiers }
+  }
+}
+
+impl fmt::Display for Key {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.modifiers.contains(KeyModifiers::CONTROL) {
+      write!(f, "Ctrl+")?;
+    }
+    if self.modifiers.contains(KeyModifiers::ALT) {
+      write!(f, "Alt+")?;
+    }
+    if self.modifiers.contains(KeyModifiers::SHIFT) {
+      write!(f, "Shift+")?;
+    }
+    match self.code {
+      KeyCode::Char(' ') => write!(f, "Space"),
+      KeyCode::Char(c) if c.is_ascii_uppercase() => write!(f, "Shift+{c}"),
+      KeyCode::Char(c) => write!(f, "{c}"),
+      KeyCode::Enter => write!(f, "Enter"),
+      KeyCode::Esc => write!(f, "Esc"),
+      KeyCode::Tab => write!(f, "Tab"),
+      KeyCode::BackTab => write!(f, "Shift+Tab"),
+      KeyCode::Backspace => write!(f, "Backspace"),
+      KeyCode::Delete => write!(f, "Del"),
+      KeyCode::Insert => write!(f, "Ins"),
+      KeyCode::Up => write!(f, "↑"),
+      KeyCode::Down => write!(f, "↓"),
+      KeyCode::Left => write!(f, "←"),
+      KeyCode::Right => write!(f, "→"),
+      KeyCode::Home => write!(f, "Home"),
+      KeyCode::End => write!(f, "End"),
+      KeyCode::PageUp => write!(f, "PgUp"),
+      KeyCode::PageDown => write!(f, "PgDn"),
+      KeyCode::F(n) => write!(f, "F{n}"),
+      code => write!(f, "{code:?}"),
+    }
+  }
+}
+
+/// The keys of every action.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+  /// Keys of each action, in the order of [`Action::ALL`].
+  bindings: Vec<(Action, Vec<Key>)>,
+}
+
+impl Default for Keymap {
+  fn default() -> Self {
+    let bindings = Action::ALL
+      .into_iter()
+      .map(|action| {
+        let keys = action
+          .default_keys()
+          .iter()
+          .map(|key| Key::parse(key).expect("default keys parse"))
+          .collect();
+        (action, keys)
+      })
+      .collect();
+    Self { bindings }
+  }
+}
+
+impl Keymap {
+  /// The default keymap with the bindings of `specs` applied, each one
+  /// `ACTION=KEY KEY...`. An action given no keys is unbound.
+  pub fn parse(specs: &[String]) -> Result<Self, StashError> {
+    let mut keymap = Self::default();
+    // Keys bound here, to tell apart clashes among them from clashes with
+    // defaults, which just lose the key.
+    let mut bound: Vec<(Action, Key)> = Vec::new();
+    for spec in specs.iter().filter(|spec| !spec.trim().is_empty()) {
+      let (name, keys) = spec.split_once('=').ok_or_else(|| {
+        keymap_err(format!("`{spec}` is not of the form ACTION=KEYS"))
+      })?;
+      let action = Action::from_name(name.trim()).ok_or_else(|| {
+        keymap_err(format!("unknown action `{}`", name.trim()))
+      })?;
+      let keys = keys
+        .split_whitespace()
+        .map(Key::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+
+      for &key in &keys {
+        if let Some(&(other, _)) = bound.iter().find(|&&(other, bound_key)| {
+          other != action && bound_key == key && Self::clash(action, other)
+        }) {
+          return Err(keymap_err(format!(
+            "{key} is bound to both `{}` and `{}`",
+            other.name(),
+            action.name()
+          )));
+        }
+        bound.push((action, key));
+      }
+      for (other, other_keys) in &mut keymap.bindings {
+        if *other == action {
+          other_keys.clone_from(&keys);
+        } else if Self::clash(action, *other) {
+          other_keys.retain(|key| !keys.contains(key));
+        }
+      }
+    }
+    Ok(keymap)
+  }
+
+  /// Whether `a` and `b` cannot share a key.
+  fn clash(a: Action, b: Action) -> bool {
+    a.in_search() == b.in_search() && a != Action::Unmark && b != Action::Unmark
+  }
+
+  /// The keys bound to `action`.
+  pub fn keys(&self, action: Action) -> &[Key] {
+    self
+      .bindings
+      .iter()
+      .find(|(bound, _)| *bound == action)
+      .map_or(&[], |(_, keys)| keys)
+  }
+
+  /// The action `key` triggers while typing a search if `search`, otherwise
+  /// when entries are marked if `marked`.
+  pub fn action(&self, key: Key, search: bool, marked: bool) -> Option<Action> {
+    // `unmark` goes first, so that it wins over whatever shares its key.
+    let unmark = (!search && marked)
+      .then_some(Action::Unmark)
+      .filter(|&unmark| self.keys(unmark).contains(&key));
+    unmark.or_else(|| {
+      self
+        .bindings
+        .iter()
+        .find(|(action, keys)| {
+          action.in_search() == search
+            && *action != Action::Unmark
+            && keys.contains(&key)
+        })
+        .map(|&(action, _)| action)
+    })
+  }
+
+  /// The keys of `actions` as shown to the user, e.g. `j/↓`, or `None` if
+  /// none of them is bound.
+  pub fn hint(&self, actions: &[Action]) -> Option<String> {
+    let keys: Vec<String> = actions
+      .iter()
+      .flat_map(|&action| self.keys(action))
+      .map(ToString::to_string)
+      .collect();
+    (!keys.is_empty()).then(|| keys.join("/"))
+  }
+
+  /// Join `hints`, e.g. `j/↓ to move, q to quit`, leaving out those whose
+  /// actions are unbound.
+  pub fn hints(&self, hints: &[(&[Action], &str)]) -> String {
+    hints
+      .iter()
+      .filter_map(|(actions, what)| {
+        self.hint(actions).map(|keys| format!("{keys} {what}"))
+      })
+      .collect::<Vec<_>>()
+      .join(", ")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn key(spec: &str) -> Key {
+    Key::parse(spec).expect("key")
+  }
+
+  fn specs(specs: &[&str]) -> Vec<String> {
+    specs.iter().map(|&spec| spec.to_owned()).collect()
+  }
+
+  #[test]
+  fn test_parse_keys() {
+    assert_eq!(key("shift-d"), key("D"));
+    assert_eq!(
+      key("D"),
+      Key::from(KeyEvent::new(KeyCode::Char('d'), KeyModifiers::SHIFT))
+    );
+    assert_eq!(
+      key("C-n"),
+      Key::from(KeyEvent::new(KeyCode::Char('n'), KeyModifiers::CONTROL))
+    );
+    assert_eq!(key("shift-tab"), key("backtab"));
+    assert_eq!(key("-"), Key::from(KeyEvent::from(KeyCode::Char('-'))));
+    assert_eq!(key("alt--").to_string(), "Alt+-");
+    assert_eq!(key("PgDn"), key("pagedown"));
+    assert_eq!(key("f5").to_string(), "F5");
+    assert_eq!(key("G").to_string(), "Shift+G");
+    assert!(Key::parse("hyper-x").is_err());
+    assert!(Key::parse("f99").is_err());
+  }
+
+  #[test]
+  fn test_rebinding_takes_keys_from_defaults() {
+    let keymap =
+      Keymap::parse(&specs(&["down=ctrl-n n", "search=esc", "pin="]))
+        .expect("keymap");
+    assert_eq!(
+      keymap.action(key("ctrl-n"), false, false),
+      Some(Action::Down)
+    );
+    assert_eq!(keymap.action(key("j"), false, false), None);
+    assert_eq!(keymap.action(key("p"), false, false), None);
+    assert_eq!(keymap.hint(&[Action::Pin]), None);
+    // Esc now searches instead of quitting, but still drops marks first and
+    // still clears a search being typed.
+    assert_eq!(
+      keymap.action(key("esc"), false, false),
+      Some(Action::Search)
+    );
+    assert_eq!(keymap.action(key("esc"), false, true), Some(Action::Unmark));
+    assert_eq!(
+      keymap.action(key("esc"), true, false),
+      Some(Action::SearchCancel)
+    );
+    assert_eq!(keymap.hint(&[Action::Quit]).as_deref(), Some("q"));
+    assert_eq!(
+      keymap.hints(&[
+        (&[Action::Down, Action::Up], "to move"),
+        (&[Action::Pin], "to pin"),
+      ]),
+      "Ctrl+n/n/k/↑ to move"
+    );
+  }
+
+  #[test]
+  fn test_invalid_bindings() {
+    let err =
+      |spec: &str| Keymap::parse(&specs(&[spec])).unwrap_err().to_string();
+    assert!(err("down").contains("ACTION=KEYS"));
+    assert!(err("sideways=x").contains("unknown action `sideways`"));
+    assert!(err("down=hyper").contains("unknown key `hyper`"));
+    let clash = Keymap::parse(&specs(&["down=x j", "quit=j"])).unwrap_err();
+    assert!(
+      clash.to_string().contains("both `down` and `quit`"),
+      "{clash}"
+    );
+    // Keys only clash within the same mode.
+    Keymap::parse(&specs(&["copy=x", "search-accept=x"])).expect("keymap");
+  }
+}
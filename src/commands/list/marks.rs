@@ -0,0 +1,354 @@
+//! Marking entries in the `stash list` TUI, one at a time with `Space` or a
+//! range at a time with `V`, and acting on all marked entries at once. Marks
+//! are entry ids, so they stay on their entries when the window is reloaded,
+//! the list is filtered or new entries push them down.
+
+use std::{
+  fs::{DirBuilder, File, OpenOptions},
+  io::{self, BufWriter},
+  os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+  path::PathBuf,
+};
+
+use wl_clipboard_rs::copy::{MimeType, Options, Source};
+
+use super::{TuiState, keymap::Action, notify};
+#[cfg(feature = "encryption")]
+use crate::db::archive::ArchiveKey;
+use crate::db::{SqliteClipboardDb, StashError, archive::ArchiveExport};
+
+impl TuiState {
+  /// Whether entry `id`, listed at `position`, is marked or inside the visual
+  /// range.
+  pub(super) fn is_marked(&self, id: i64, position: usize) -> bool {
+    self.marked.contains(&id)
+      || self
+        .visual_range()
+        .is_some_and(|(first, last)| (first..=last).contains(&position))
+  }
+
+  /// Whether any entry is marked or a visual range is being selected.
+  pub(super) fn has_marks(&self) -> bool {
+    !self.marked.is_empty() || self.visual_anchor.is_some()
+  }
+
+  /// Positions of the first and last entry of the visual range, while one is
+  /// being selected.
+  fn visual_range(&self) -> Option<(usize, usize)> {
+    self
+      .visual_anchor
+      .map(|(_, anchor)| (anchor.min(self.cursor), anchor.max(self.cursor)))
+  }
+
+  /// Mark the selected entry, or unmark it if it is marked, and move on to
+  /// the next one.
+  pub(super) fn toggle_mark(&mut self) {
+    let Some(id) = self.selected_entry().map(|entry| entry.id) else {
+      return;
+    };
+    if let Some(index) = self.marked.iter().position(|&marked| marked == id) {
+      self.marked.remove(index);
+    } else {
+      self.marked.push(id);
+    }
+    self.move_down();
+  }
+
+  /// Start a visual range at the selected entry. Moving the cursor extends
+  /// it.
+  pub(super) fn start_visual(&mut self) {
+    if let Some(id) = self.selected_entry().map(|entry| entry.id) {
+      self.visual_anchor = Some((id, self.cursor));
+      self.pending_delete = None;
+      self.status = None;
+    }
+  }
+
+  /// Drop every mark and the visual range.
+  pub(super) fn clear_marks(&mut self) {
+    self.marked.clear();
+    self.visual_anchor = None;
+    self.pending_delete = None;
+    self.status = None;
+  }
+
+  /// The entries bulk actions apply to: the marked ones in the order they
+  /// were marked, or else the selected one.
+  fn targets(&self) -> Vec<i64> {
+    if self.marked.is_empty() {
+      self
+        .selected_entry()
+        .map(|entry| entry.id)
+        .into_iter()
+        .collect()
+    } else {
+      self.marked.clone()
+    }
+  }
+
+  /// Forget marks of entries that are gone, and move the start of the visual
+  /// range along with its entry.
+  pub(super) fn refresh_marks(
+    &mut self,
+    db: &SqliteClipboardDb,
+    include_expired: bool,
+  ) -> Result<(), StashError> {
+    self.marked = db.existing_ids(&self.marked)?;
+    if let Some((id, _)) = self.visual_anchor {
+      self.visual_anchor = db
+        .entry_position(
+          id,
+          include_expired,
+          self.search_filter(),
+          self.reverse,
+          self.pinned_first,
+          self.selection,
+          &self.tags,
+        )?
+        .map(|position| (id, position));
+    }
+    Ok(())
+  }
+}
+
+impl SqliteClipboardDb {
+  /// Mark the entries of the visual range, if one is being selected, and end
+  /// it.
+  pub(super) fn commit_visual(
+    &self,
+    tui: &mut TuiState,
+    include_expired: bool,
+  ) -> Result<(), StashError> {
+    let Some((first, last)) = tui.visual_range() else {
+      return Ok(());
+    };
+    tui.visual_anchor = None;
+    let ids = self.entry_ids_window(
+      include_expired,
+      first,
+      last - first + 1,
+      tui.search_filter(),
+      tui.reverse,
+      tui.pinned_first,
+      tui.selection,
+      &tui.tags,
+    )?;
+    for id in ids {
+      if !tui.marked.contains(&id) {
+        tui.marked.push(id);
+      }
+    }
+    Ok(())
+  }
+
+  /// Delete the marked entries once the delete key was pressed twice for them.
+  pub(super) fn delete_marked(
+    &self,
+    tui: &mut TuiState,
+    include_expired: bool,
+  ) -> Result<(), StashError> {
+    self.commit_visual(tui, include_expired)?;
+    if tui.pending_delete.as_ref() != Some(&tui.marked) {
+      tui.pending_delete = Some(tui.marked.clone());
+      tui.status = Some(format!(
+        "press {} again to delete {} marked entries",
+        tui.keymap.hint(&[Action::Delete]).unwrap_or_default(),
+        tui.marked.len()
+      ));
+      return Ok(());
+    }
+
+    let mut deleted = 0;
+    for id in tui.marked.clone() {
+      deleted += tui.delete_entry(self, id)?;
+    }
+    tui.clear_marks();
+    tui.recount(self, include_expired)?;
+    tui.cursor = tui.cursor.min(tui.total.saturating_sub(1));
+    tui.dirty = true;
+    tui.status = Some(format!("deleted {deleted} entries"));
+    notify("stash", &format!("deleted {deleted} entries"));
+    Ok(())
+  }
+
+  /// Pin the marked entries, or unpin them if they are all pinned already.
+  pub(super) fn pin_marked(
+    &self,
+    tui: &mut TuiState,
+    include_expired: bool,
+  ) -> Result<(), StashError> {
+    self.commit_visual(tui, include_expired)?;
+    tui.marked = self.existing_ids(&tui.marked)?;
+    let mut all_pinned = true;
+    for &id in &tui.marked {
+      all_pinned &= self.is_pinned(id)?;
+    }
+    let anchor = tui.selected_entry().map(|entry| entry.id);
+    for id in tui.marked.clone() {
+      tui.set_pinned(self, id, !all_pinned)?;
+    }
+    // Pinning can move the entries when pinned entries sort first.
+    if let Some(anchor) = anchor {
+      tui.follow(self, anchor, include_expired)?;
+    }
+    tui.dirty = true;
+    tui.pending_delete = None;
+    let action = if all_pinned { "unpinned" } else { "pinned" };
+    tui.status = Some(format!("{action} {} entries", tui.marked.len()));
+    Ok(())
+  }
+
+  /// Export the marked entries, or the selected one, to a new archive in
+  /// the state directory for `stash import --type archive`.
+  pub(super) fn export_marked(
+    &self,
+    tui: &mut TuiState,
+    include_expired: bool,
+  ) -> Result<(), StashError> {
+    self.commit_visual(tui, include_expired)?;
+    let targets = tui.targets();
+    if targets.is_empty() {
+      return Ok(());
+    }
+    tui.pending_delete = None;
+
+    tui.status = Some(match self.export_to_new_archive(&targets) {
+      Ok((export, path)) if export.skipped.is_empty() => {
+        format!("exported {} entries to {}", export.exported, path.display())
+      },
+      Ok((export, path)) => {
+        format!(
+          "exported {} entries to {}, left out {} that could not be decrypted",
+          export.exported,
+          path.display(),
+          export.skipped.len()
+        )
+      },
+      Err(e) => {
+        log::error!("{e}");
+        e.to_string()
+      },
+    });
+    Ok(())
+  }
+
+  /// Write the entries with the given ids to a new archive file, encrypted
+  /// when encryption is configured. Returns what was written, and where to.
+  fn export_to_new_archive(
+    &self,
+    ids: &[i64],
+  ) -> Result<(ArchiveExport, PathBuf), StashError> {
+    #[cfg(feature = "encryption")]
+    let key = ArchiveKey::configured()?;
+    #[cfg(feature = "encryption")]
+    let extension = if key.is_some() { "jsonl.age" } else { "jsonl" };
+    #[cfg(not(feature = "encryption"))]
+    let extension = "jsonl";
+
+    let (file, path) = create_export_file(extension)?;
+    let out = BufWriter::new(file);
+    #[cfg(feature = "encryption")]
+    let exported = match key {
+      Some(key) => self.export_entries_encrypted_archive(out, key, ids)?,
+      None => self.export_entries_archive(out, ids)?,
+    };
+    #[cfg(not(feature = "encryption"))]
+    let exported = self.export_entries_archive(out, ids)?;
+    Ok((exported, path))
+  }
+
+  /// Copy the text of the marked entries, or of the selected one, to the
+  /// clipboard as one text joined by `separator`. Entries that are not text
+  /// are left out.
+  pub(super) fn copy_marked_joined(
+    &self,
+    tui: &mut TuiState,
+    include_expired: bool,
+    separator: &str,
+  ) -> Result<(), StashError> {
+    self.commit_visual(tui, include_expired)?;
+    let targets = self.existing_ids(&tui.targets())?;
+    if targets.is_empty() {
+      return Ok(());
+    }
+    tui.pending_delete = None;
+
+    let mut texts = Vec::with_capacity(targets.len());
+    for &id in &targets {
+      let entry = self.entry(id)?;
+      let is_text = entry.mime.as_deref().is_none_or(|mime| {
+        mime.starts_with("text/") || mime == "application/json"
+      });
+      if let (true, Ok(text)) = (is_text, String::from_utf8(entry.contents)) {
+        texts.push(text);
+      }
+    }
+    if texts.is_empty() {
+      tui.status = Some("no text among the marked entries to copy".to_owned());
+      return Ok(());
+    }
+
+    let skipped = targets.len() - texts.len();
+    let joined = texts.join(separator);
+    match Options::new().copy(
+      Source::Bytes(joined.into_bytes().into_boxed_slice()),
+      MimeType::Text,
+    ) {
+      Ok(()) => {
+        let skipped = if skipped > 0 {
+          format!(", skipped {skipped} that are not text")
+        } else {
+          String::new()
+        };
+        tui.status =
+          Some(format!("copied {} entries joined{skipped}", texts.len()));
+        notify("stash", "copied joined entries to clipboard");
+      },
+      Err(e) => {
+        log::error!("failed to copy joined entries to clipboard: {e}");
+        let body = format!("failed to copy to clipboard: {e}");
+        tui.status = Some(body.clone());
+        notify("stash", &body);
+      },
+    }
+    Ok(())
+  }
+}
+
+fn export_err(e: impl ToString) -> StashError {
+  StashError::Export(e.to_string().into())
+}
+
+/// Create a file for a new archive in `$XDG_STATE_HOME/stash/exports`, which
+/// only the user can read. Existing exports are never overwritten.
+fn create_export_file(extension: &str) -> Result<(File, PathBuf), StashError> {
+  let dir = dirs::state_dir()
+    .ok_or_else(|| export_err("could not determine the state directory"))?
+    .join("stash")
+    .join("exports");
+  DirBuilder::new()
+    .recursive(true)
+    .mode(0o700)
+    .create(&dir)
+    .map_err(export_err)?;
+
+  let stamp = SqliteClipboardDb::now() as u64;
+  let mut attempt = 0;
+  loop {
+    let name = match attempt {
+      0 => format!("stash-export-{stamp}.{extension}"),
+      n => format!("stash-export-{stamp}-{n}.{extension}"),
+    };
+    let path = dir.join(name);
+    match OpenOptions::new()
+      .write(true)
+      .create_new(true)
+      .mode(0o600)
+      .open(&path)
+    {
+      Ok(file) => return Ok((file, path)),
+      Err(e) if e.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+      Err(e) => return Err(export_err(e)),
+    }
+  }
+}
@@ -0,0 +1,629 @@
+//! Image previews for the list TUI.
+//!
+//! Image entries are only decoded once they are selected, and the most recent
+//! one is kept around while it stays selected. They are drawn with the kitty
+//! graphics protocol or sixel when the terminal supports either, and with
+//! Unicode half blocks otherwise.
+
+use std::{
+  env,
+  io::{self, Write},
+  os::fd::AsRawFd,
+  time::{Duration, Instant},
+};
+
+use base64::prelude::*;
+use crossterm::{cursor::MoveTo, queue};
+use ratatui::{
+  Frame,
+  buffer::CellDiffOption,
+  layout::Rect,
+  style::{Color, Style},
+  text::{Line, Span},
+  widgets::{Block, Borders, Paragraph, Wrap},
+};
+
+use crate::db::{ListEntry, SqliteClipboardDb};
+
+/// Whether this build can decode images at all.
+pub const SUPPORTED: bool = cfg!(feature = "image-preview");
+
+/// Largest side, in pixels, of the copy kept after decoding. Previews never
+/// need more, and it keeps large screenshots from being held in full.
+#[cfg(feature = "image-preview")]
+const MAX_DECODED_SIDE: u32 = 1024;
+
+/// Cell size in pixels assumed when the terminal does not report its own.
+const FALLBACK_CELL_SIZE: (u32, u32) = (8, 16);
+
+/// How long to wait for the terminal to answer the capability queries.
+const QUERY_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// How images are drawn in the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Graphics {
+  Kitty,
+  Sixel,
+  HalfBlocks,
+}
+
+impl Graphics {
+  /// Pick a protocol: `STASH_IMAGE_PROTOCOL` (`kitty`, `sixel` or
+  /// `halfblocks`) when set, otherwise whatever the terminal says it supports.
+  ///
+  /// Must be called in raw mode, before anything else reads from stdin.
+  pub fn detect() -> Self {
+    match env::var("STASH_IMAGE_PROTOCOL").as_deref() {
+      Ok("kitty") => return Self::Kitty,
+      Ok("sixel") => return Self::Sixel,
+      Ok("halfblocks") => return Self::HalfBlocks,
+      Ok("") | Err(_) => {},
+      Ok(other) => {
+        log::warn!("unknown STASH_IMAGE_PROTOCOL {other:?}, detecting instead");
+      },
+    }
+
+    match query_terminal() {
+      Ok(reply) => Self::from_reply(&reply),
+      Err(e) => {
+        log::debug!("terminal capability query failed: {e}");
+        Self::HalfBlocks
+      },
+    }
+  }
+
+  /// Interpret the terminal's answers to a kitty graphics query followed by a
+  /// primary device attributes (DA1) request.
+  fn from_reply(reply: &[u8]) -> Self {
+    let reply = String::from_utf8_lossy(reply);
+    if reply.contains("_Gi=31;OK") {
+      return Self::Kitty;
+    }
+
+    // DA1 answers `ESC [ ? 62 ; 4 ; ... c`, where attribute 4 is sixel.
+    let sixel = reply.find("\x1b[?").is_some_and(|start| {
+      reply[start + 3..]
+        .split('c')
+        .next()
+        .is_some_and(|attrs| attrs.split(';').any(|attr| attr == "4"))
+    });
+    if sixel { Self::Sixel } else { Self::HalfBlocks }
+  }
+}
+
+/// Ask the terminal whether it supports kitty graphics, then for its device
+/// attributes. Every terminal answers the latter, so the reply is complete
+/// once it has arrived.
+fn query_terminal() -> io::Result<Vec<u8>> {
+  let mut stdout = io::stdout();
+  stdout.write_all(b"\x1b_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1b\\\x1b[c")?;
+  stdout.flush()?;
+
+  let fd = io::stdin().as_raw_fd();
+  let deadline = Instant::now() + QUERY_TIMEOUT;
+  let mut reply = Vec::new();
+  let mut buf = [0u8; 256];
+  loop {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+      break;
+    }
+
+    let mut pollfd = libc::pollfd {
+      fd,
+      events: libc::POLLIN,
+      revents: 0,
+    };
+    let timeout = i32::try_from(remaining.as_millis()).unwrap_or(i32::MAX);
+    // SAFETY: `pollfd` is a single valid pollfd for the duration of the call.
+    let ready = unsafe { libc::poll(&raw mut pollfd, 1, timeout) };
+    if ready < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    if ready == 0 {
+      break;
+    }
+
+    // SAFETY: `buf` is valid for writes of `buf.len()` bytes.
+    let read = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+    let Ok(read) = usize::try_from(read) else {
+      return Err(io::Error::last_os_error());
+    };
+    if read == 0 {
+      break;
+    }
+    reply.extend_from_slice(&buf[..read]);
+    if da1_complete(&reply) {
+      break;
+    }
+  }
+  Ok(reply)
+}
+
+/// Whether `reply` contains a full DA1 answer.
+fn da1_complete(reply: &[u8]) -> bool {
+  reply
+    .windows(3)
+    .position(|w| w == b"\x1b[?")
+    .is_some_and(|start| reply[start..].contains(&b'c'))
+}
+
+/// An image as 8-bit RGB, composited onto black.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Pixels {
+  width:  u32,
+  height: u32,
+  rgb:    Vec<u8>,
+}
+
+impl Pixels {
+  fn get(&self, x: u32, y: u32) -> [u8; 3] {
+    let i = (y as usize * self.width as usize + x as usize) * 3;
+    [self.rgb[i], self.rgb[i + 1], self.rgb[i + 2]]
+  }
+
+  /// Resample to `width` x `height`, averaging the source pixels that fall
+  /// into each target pixel.
+  fn resize(&self, width: u32, height: u32) -> Self {
+    let span = |target: u32, size: u32, target_size: u32| {
+      let start = u64::from(target) * u64::from(size) / u64::from(target_size);
+      let end =
+        (u64::from(target) + 1) * u64::from(size) / u64::from(target_size);
+      (start as u32, (end as u32).max(start as u32 + 1).min(size))
+    };
+
+    let mut rgb = Vec::with_capacity(width as usize * height as usize * 3);
+    for y in 0..height {
+      let (y0, y1) = span(y, self.height, height);
+      for x in 0..width {
+        let (x0, x1) = span(x, self.width, width);
+        let mut sum = [0u32; 3];
+        for sy in y0..y1 {
+          for sx in x0..x1 {
+            for (total, channel) in sum.iter_mut().zip(self.get(sx, sy)) {
+              *total += u32::from(channel);
+            }
+          }
+        }
+        let count = (x1 - x0) * (y1 - y0);
+        rgb.extend(sum.map(|total| (total / count) as u8));
+      }
+    }
+    Self { width, height, rgb }
+  }
+}
+
+/// A decoded image entry.
+struct Decoded {
+  /// Size of the original image.
+  width:  u32,
+  height: u32,
+  pixels: Pixels,
+}
+
+#[cfg(feature = "image-preview")]
+fn decode(contents: &[u8]) -> Result<Decoded, String> {
+  let image = image::load_from_memory(contents).map_err(|e| e.to_string())?;
+  let (width, height) = (image.width(), image.height());
+  let image = if width > MAX_DECODED_SIDE || height > MAX_DECODED_SIDE {
+    image.thumbnail(MAX_DECODED_SIDE, MAX_DECODED_SIDE)
+  } else {
+    image
+  };
+
+  let rgba = image.into_rgba8();
+  let rgb = rgba
+    .pixels()
+    .flat_map(|pixel| {
+      let [r, g, b, a] = pixel.0;
+      [r, g, b].map(|c| (u16::from(c) * u16::from(a) / 255) as u8)
+    })
+    .collect();
+  Ok(Decoded {
+    width,
+    height,
+    pixels: Pixels {
+      width: rgba.width(),
+      height: rgba.height(),
+      rgb,
+    },
+  })
+}
+
+#[cfg(not(feature = "image-preview"))]
+fn decode(_contents: &[u8]) -> Result<Decoded, String> {
+  Err("stash was built without image previews".to_owned())
+}
+
+/// Number of cells `(columns, rows)` an image of `width` x `height` pixels
+/// takes up when scaled to fit into `bounds` cells of `cell` pixels each,
+/// keeping its aspect ratio.
+fn fit(
+  (width, height): (u32, u32),
+  bounds: (u16, u16),
+  cell: (u32, u32),
+) -> (u16, u16) {
+  let bounds_px = (
+    f64::from(bounds.0) * f64::from(cell.0),
+    f64::from(bounds.1) * f64::from(cell.1),
+  );
+  let scale = f64::min(
+    bounds_px.0 / f64::from(width.max(1)),
+    bounds_px.1 / f64::from(height.max(1)),
+  );
+  let cells = |size: u32, cell: u32, bound: u16| {
+    let cells = (f64::from(size) * scale / f64::from(cell)).round();
+    (cells as u16).clamp(1, bound.max(1))
+  };
+  (
+    cells(width, cell.0, bounds.0),
+    cells(height, cell.1, bounds.1),
+  )
+}
+
+fn rgb_color([r, g, b]: [u8; 3]) -> Color {
+  Color::Rgb(r, g, b)
+}
+
+/// Draw `pixels` with upper half blocks, two pixels per cell: the top one as
+/// foreground and the bottom one as background colour.
+fn half_blocks(pixels: &Pixels) -> Vec<Line<'static>> {
+  (0..pixels.height)
+    .step_by(2)
+    .map(|y| {
+      let spans: Vec<Span> = (0..pixels.width)
+        .map(|x| {
+          let top = pixels.get(x, y);
+          let bottom = if y + 1 < pixels.height {
+            pixels.get(x, y + 1)
+          } else {
+            [0, 0, 0]
+          };
+          Span::styled(
+            "▀",
+            Style::default().fg(rgb_color(top)).bg(rgb_color(bottom)),
+          )
+        })
+        .collect();
+      Line::from(spans)
+    })
+    .collect()
+}
+
+/// Palette index of `rgb` in a 6x6x6 colour cube.
+fn cube_index([r, g, b]: [u8; 3]) -> usize {
+  let level = |c: u8| (usize::from(c) * 5 + 127) / 255;
+  level(r) * 36 + level(g) * 6 + level(b)
+}
+
+/// Encode `pixels` as a sixel image, quantized to a 6x6x6 colour cube.
+fn write_sixel(out: &mut impl Write, pixels: &Pixels) -> io::Result<()> {
+  write!(out, "\x1bP0;1;0q\"1;1;{};{}", pixels.width, pixels.height)?;
+  for i in 0..216 {
+    let (r, g, b) = (i / 36, i / 6 % 6, i % 6);
+    write!(out, "#{i};2;{};{};{}", r * 20, g * 20, b * 20)?;
+  }
+
+  let width = pixels.width as usize;
+  for band in (0..pixels.height).step_by(6) {
+    // One row of sixels per colour used in this band of six pixel rows.
+    let mut planes: Vec<Option<Vec<u8>>> = vec![None; 216];
+    for row in 0..6.min(pixels.height - band) {
+      for x in 0..pixels.width {
+        let plane = planes[cube_index(pixels.get(x, band + row))]
+          .get_or_insert_with(|| vec![0; width]);
+        plane[x as usize] |= 1 << row;
+      }
+    }
+
+    for (color, plane) in planes.iter().enumerate() {
+      let Some(plane) = plane else { continue };
+      write!(out, "#{color}")?;
+      let mut x = 0;
+      while x < width {
+        let bits = plane[x];
+        let run = plane[x..].iter().take_while(|&&b| b == bits).count();
+        let sixel = char::from(63 + bits);
+        if run > 3 {
+          write!(out, "!{run}{sixel}")?;
+        } else {
+          for _ in 0..run {
+            write!(out, "{sixel}")?;
+          }
+        }
+        x += run;
+      }
+      out.write_all(b"$")?;
+    }
+    out.write_all(b"-")?;
+  }
+  out.write_all(b"\x1b\\")
+}
+
+/// Transmit and place `pixels` with the kitty graphics protocol, scaled to
+/// `columns` x `rows` cells.
+fn write_kitty(
+  out: &mut impl Write,
+  pixels: &Pixels,
+  columns: u16,
+  rows: u16,
+) -> io::Result<()> {
+  let data = BASE64_STANDARD.encode(&pixels.rgb);
+  let mut chunks = data.as_bytes().chunks(4096).peekable();
+  let mut first = true;
+  while let Some(chunk) = chunks.next() {
+    let more = u8::from(chunks.peek().is_some());
+    if first {
+      write!(
+        out,
+        "\x1b_Ga=T,f=24,s={},v={},c={columns},r={rows},C=1,q=2,m={more};",
+        pixels.width, pixels.height
+      )?;
+      first = false;
+    } else {
+      write!(out, "\x1b_Gm={more};")?;
+    }
+    out.write_all(chunk)?;
+    out.write_all(b"\x1b\\")?;
+  }
+  Ok(())
+}
+
+/// Preview pane state, kept across frames.
+pub struct ImagePreview {
+  graphics:  Graphics,
+  cell_size: (u32, u32),
+
+  /// The most recently decoded entry.
+  decoded: Option<(i64, Result<Decoded, String>)>,
+
+  /// Image to place with escape sequences after this frame, and the one
+  /// currently on screen. Unused for half blocks, which ratatui draws.
+  wanted: Option<(i64, Rect)>,
+  shown:  Option<(i64, Rect)>,
+}
+
+impl ImagePreview {
+  pub fn new(graphics: Graphics) -> Self {
+    let cell_size = crossterm::terminal::window_size()
+      .ok()
+      .filter(|size| size.width > 0 && size.columns > 0 && size.rows > 0)
+      .map_or(FALLBACK_CELL_SIZE, |size| {
+        (
+          u32::from(size.width / size.columns).max(1),
+          u32::from(size.height / size.rows).max(1),
+        )
+      });
+    log::debug!("image previews use {graphics:?}, cells of {cell_size:?} px");
+    Self {
+      graphics,
+      cell_size,
+      decoded: None,
+      wanted: None,
+      shown: None,
+    }
+  }
+
+  /// Whether `entry` gets a preview pane.
+  pub fn applies_to(entry: &ListEntry) -> bool {
+    SUPPORTED && entry.mime.starts_with("image/")
+  }
+
+  /// Forget the image placed in the previous frame; called before drawing.
+  pub fn begin_frame(&mut self) {
+    self.wanted = None;
+  }
+
+  fn load(&mut self, db: &SqliteClipboardDb, id: i64) {
+    if self
+      .decoded
+      .as_ref()
+      .is_some_and(|(loaded, _)| *loaded == id)
+    {
+      return;
+    }
+    let decoded = db
+      .entry(id)
+      .map_err(|e| e.to_string())
+      .and_then(|entry| decode(&entry.contents));
+    if let Err(e) = &decoded {
+      log::debug!("cannot preview entry {id}: {e}");
+    }
+    self.decoded = Some((id, decoded));
+  }
+
+  /// Draw the preview pane for `entry` into `area`, decoding it first if it
+  /// is not the entry shown last.
+  pub fn render(
+    &mut self,
+    f: &mut Frame,
+    area: Rect,
+    db: &SqliteClipboardDb,
+    entry: &ListEntry,
+  ) {
+    self.load(db, entry.id);
+    let Some((id, decoded)) = &self.decoded else {
+      return;
+    };
+
+    let title = match decoded {
+      Ok(decoded) => {
+        format!(" {}x{} {} ", decoded.width, decoded.height, entry.mime)
+      },
+      Err(_) => format!(" {} ", entry.mime),
+    };
+    let block = Block::default().title(title).borders(Borders::ALL);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let decoded = match decoded {
+      Ok(decoded) => decoded,
+      Err(e) => {
+        f.render_widget(
+          Paragraph::new(format!("cannot preview image: {e}"))
+            .wrap(Wrap { trim: true }),
+          inner,
+        );
+        return;
+      },
+    };
+    if inner.width == 0 || inner.height == 0 {
+      return;
+    }
+
+    let (columns, rows) = fit(
+      (decoded.pixels.width, decoded.pixels.height),
+      (inner.width, inner.height),
+      self.cell_size,
+    );
+    let rect = Rect::new(inner.x, inner.y, columns, rows);
+    match self.graphics {
+      Graphics::HalfBlocks => {
+        let pixels = decoded
+          .pixels
+          .resize(u32::from(columns), u32::from(rows) * 2);
+        f.render_widget(Paragraph::new(half_blocks(&pixels)), rect);
+      },
+      Graphics::Kitty | Graphics::Sixel => {
+        // Keep ratatui from drawing over the image placed after the frame.
+        let buf = f.buffer_mut();
+        for y in rect.top()..rect.bottom() {
+          for x in rect.left()..rect.right() {
+            buf[(x, y)].set_diff_option(CellDiffOption::Skip);
+          }
+        }
+        self.wanted = Some((*id, rect));
+      },
+    }
+  }
+
+  /// Place the image requested by the last frame, if it is not already on
+  /// screen. Called after ratatui has drawn the frame, which already
+  /// redrew the cells of a previous image that are no longer covered.
+  pub fn flush(&mut self, out: &mut impl Write) -> io::Result<()> {
+    if self.wanted == self.shown {
+      return Ok(());
+    }
+
+    if self.graphics == Graphics::Kitty && self.shown.is_some() {
+      // Kitty keeps images on their own layer; remove the old placement.
+      out.write_all(b"\x1b_Ga=d,d=a,q=2\x1b\\")?;
+    }
+
+    if let Some((id, rect)) = self.wanted
+      && let Some((_, Ok(decoded))) =
+        self.decoded.as_ref().filter(|(loaded, _)| *loaded == id)
+    {
+      let pixels = decoded.pixels.resize(
+        u32::from(rect.width) * self.cell_size.0,
+        u32::from(rect.height) * self.cell_size.1,
+      );
+      queue!(out, MoveTo(rect.x, rect.y))?;
+      match self.graphics {
+        Graphics::Kitty => write_kitty(out, &pixels, rect.width, rect.height)?,
+        Graphics::Sixel => write_sixel(out, &pixels)?,
+        Graphics::HalfBlocks => {},
+      }
+    }
+    out.flush()?;
+    self.shown = self.wanted;
+    Ok(())
+  }
+
+  /// Remove a placed image before leaving the TUI.
+  pub fn clear(&mut self, out: &mut impl Write) -> io::Result<()> {
+    if self.graphics == Graphics::Kitty && self.shown.take().is_some() {
+      out.write_all(b"\x1b_Ga=d,d=a,q=2\x1b\\")?;
+      out.flush()?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn solid(width: u32, height: u32, rgb: [u8; 3]) -> Pixels {
+    Pixels {
+      width,
+      height,
+      rgb: rgb.repeat((width * height) as usize),
+    }
+  }
+
+  #[test]
+  fn test_detect_from_terminal_reply() {
+    assert_eq!(
+      Graphics::from_reply(b"\x1b_Gi=31;OK\x1b\\\x1b[?62;22c"),
+      Graphics::Kitty
+    );
+    assert_eq!(Graphics::from_reply(b"\x1b[?62;4;6;22c"), Graphics::Sixel);
+    assert_eq!(
+      Graphics::from_reply(b"\x1b[?64;1;2;6;22c"),
+      Graphics::HalfBlocks
+    );
+    assert_eq!(Graphics::from_reply(b""), Graphics::HalfBlocks);
+
+    assert!(!da1_complete(b"\x1b_Gi=31;OK\x1b\\"));
+    assert!(da1_complete(b"\x1b_Gi=31;OK\x1b\\\x1b[?62;4c"));
+  }
+
+  #[test]
+  fn test_fit_keeps_aspect_ratio() {
+    // 16:9 into 40x20 cells of 8x16 px (320x320 px) is bound by the width.
+    assert_eq!(fit((1920, 1080), (40, 20), (8, 16)), (40, 11));
+    // Tall images are bound by the height.
+    assert_eq!(fit((100, 400), (40, 20), (8, 16)), (10, 20));
+    // Never less than a single cell.
+    assert_eq!(fit((1, 10_000), (40, 20), (8, 16)), (1, 20));
+  }
+
+  #[test]
+  fn test_resize_averages_pixels() {
+    let pixels = Pixels {
+      width:  2,
+      height: 1,
+      rgb:    vec![0, 0, 0, 200, 100, 50],
+    };
+    assert_eq!(pixels.resize(1, 1).rgb, vec![100, 50, 25]);
+    assert_eq!(pixels.resize(4, 2).width, 4);
+    assert_eq!(pixels.resize(4, 2).get(3, 1), [200, 100, 50]);
+  }
+
+  #[test]
+  fn test_half_blocks_pair_rows() {
+    let mut pixels = solid(2, 3, [255, 0, 0]);
+    pixels.rgb[6..9].copy_from_slice(&[0, 0, 255]);
+    let lines = half_blocks(&pixels);
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0].spans.len(), 2);
+    assert_eq!(lines[0].spans[0].style.fg, Some(Color::Rgb(255, 0, 0)));
+    assert_eq!(lines[0].spans[0].style.bg, Some(Color::Rgb(0, 0, 255)));
+    assert_eq!(lines[1].spans[0].style.bg, Some(Color::Rgb(0, 0, 0)));
+  }
+
+  #[cfg(feature = "image-preview")]
+  #[test]
+  fn test_decode_composites_alpha() {
+    let mut png = Vec::new();
+    image::RgbaImage::from_pixel(3, 2, image::Rgba([200, 100, 50, 128]))
+      .write_to(&mut io::Cursor::new(&mut png), image::ImageFormat::Png)
+      .expect("encode png");
+
+    let decoded = decode(&png).expect("decode png");
+    assert_eq!((decoded.width, decoded.height), (3, 2));
+    assert_eq!(decoded.pixels.get(2, 1), [100, 50, 25]);
+    assert!(decode(b"not an image").is_err());
+  }
+
+  #[test]
+  fn test_sixel_encoding() {
+    let mut out = Vec::new();
+    write_sixel(&mut out, &solid(5, 6, [255, 255, 255])).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("\x1bP0;1;0q\"1;1;5;6"));
+    // All six rows of the single band are set in white, run-length encoded.
+    assert!(out.ends_with("#215!5~$-\x1b\\"), "{out:?}");
+  }
+}
@@ -0,0 +1,162 @@
+//! Colours of the list TUI.
+//!
+//! `--theme ELEMENT=STYLE` (or `$STASH_TUI_THEME`, or `theme` in the `[list]`
+//! section of the config file) replaces the style of an element. A style is a
+//! list of words: a colour sets the foreground, `bg:COLOR` the background, and
+//! `bold`, `dim`, `italic`, `underlined`, `reversed` and `crossed-out` add
+//! modifiers. Colours are names like `light-blue`, ANSI indices like `12`, or
+//! `#rrggbb`. An empty style leaves the element in the terminal's colours.
+
+use std::str::FromStr;
+
+use ratatui::style::{Color, Modifier, Style};
+
+use crate::db::StashError;
+
+fn theme_err(e: impl ToString) -> StashError {
+  StashError::Theme(e.to_string().into())
+}
+
+/// Styles of the parts of the TUI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+  /// The entry under the cursor.
+  pub selected: Style,
+  /// MIME type of the entry under the cursor.
+  pub mime:     Style,
+  /// Marked entries.
+  pub marked:   Style,
+  /// Entries that just arrived.
+  pub flash:    Style,
+  /// The pin symbol of pinned entries.
+  pub pinned:   Style,
+  /// The symbol of entries copied to the primary selection.
+  pub primary:  Style,
+  pub tags:     Style,
+  /// Field names in the detail pane and keys in the help.
+  pub label:    Style,
+  /// Borders of the list and the panes.
+  pub border:   Style,
+  /// Titles of the list and the panes.
+  pub title:    Style,
+}
+
+impl Default for Theme {
+  fn default() -> Self {
+    Self {
+      selected: Style::default()
+        .fg(Color::Yellow)
+        .add_modifier(Modifier::BOLD),
+      mime:     Style::default().fg(Color::Green),
+      marked:   Style::default()
+        .fg(Color::Cyan)
+        .add_modifier(Modifier::BOLD),
+      flash:    Style::default().bg(Color::DarkGray),
+      pinned:   Style::default().fg(Color::Magenta),
+      primary:  Style::default().fg(Color::Cyan),
+      tags:     Style::default().fg(Color::Magenta),
+      label:    Style::default()
+        .fg(Color::Cyan)
+        .add_modifier(Modifier::BOLD),
+      border:   Style::default(),
+      title:    Style::default(),
+    }
+  }
+}
+
+/// Parse a style like `yellow bold` or `bg:#303030`.
+fn parse_style(spec: &str) -> Result<Style, StashError> {
+  let mut style = Style::default();
+  for word in spec.split_whitespace() {
+    let modifier = match word.to_lowercase().as_str() {
+      "bold" => Some(Modifier::BOLD),
+      "dim" => Some(Modifier::DIM),
+      "italic" => Some(Modifier::ITALIC),
+      "underlined" => Some(Modifier::UNDERLINED),
+      "reversed" => Some(Modifier::REVERSED),
+      "crossed-out" => Some(Modifier::CROSSED_OUT),
+      _ => None,
+    };
+    if let Some(modifier) = modifier {
+      style = style.add_modifier(modifier);
+      continue;
+    }
+
+    let (background, color) = match word.split_once(':') {
+      Some(("bg", color)) => (true, color),
+      Some(("fg", color)) => (false, color),
+      _ => (false, word),
+    };
+    let color = Color::from_str(color)
+      .map_err(|_| theme_err(format!("unknown colour or modifier `{word}`")))?;
+    style = if background {
+      style.bg(color)
+    } else {
+      style.fg(color)
+    };
+  }
+  Ok(style)
+}
+
+impl Theme {
+  /// The default theme with the styles of `specs` applied, each one
+  /// `ELEMENT=STYLE`.
+  pub fn parse(specs: &[String]) -> Result<Self, StashError> {
+    let mut theme = Self::default();
+    for spec in specs.iter().filter(|spec| !spec.trim().is_empty()) {
+      let (element, style) = spec.split_once('=').ok_or_else(|| {
+        theme_err(format!("`{spec}` is not of the form ELEMENT=STYLE"))
+      })?;
+      let slot = match element.trim() {
+        "selected" => &mut theme.selected,
+        "mime" => &mut theme.mime,
+        "marked" => &mut theme.marked,
+        "flash" => &mut theme.flash,
+        "pinned" => &mut theme.pinned,
+        "primary" => &mut theme.primary,
+        "tags" => &mut theme.tags,
+        "label" => &mut theme.label,
+        "border" => &mut theme.border,
+        "title" => &mut theme.title,
+        other => return Err(theme_err(format!("unknown element `{other}`"))),
+      };
+      *slot = parse_style(style)?;
+    }
+    Ok(theme)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_theme() {
+    let theme = Theme::parse(&[
+      "selected=light-blue bold underlined".to_owned(),
+      "flash=bg:#303030".to_owned(),
+      "tags=".to_owned(),
+      "border=fg:8".to_owned(),
+    ])
+    .expect("theme");
+    assert_eq!(
+      theme.selected,
+      Style::default()
+        .fg(Color::LightBlue)
+        .add_modifier(Modifier::BOLD | Modifier::UNDERLINED)
+    );
+    assert_eq!(
+      theme.flash,
+      Style::default().bg(Color::Rgb(0x30, 0x30, 0x30))
+    );
+    assert_eq!(theme.tags, Style::default());
+    assert_eq!(theme.border, Style::default().fg(Color::Indexed(8)));
+    assert_eq!(theme.marked, Theme::default().marked);
+
+    let err =
+      |spec: &str| Theme::parse(&[spec.to_owned()]).unwrap_err().to_string();
+    assert!(err("selected").contains("ELEMENT=STYLE"));
+    assert!(err("cursor=red").contains("unknown element `cursor`"));
+    assert!(err("selected=blinking").contains("`blinking`"));
+  }
+}
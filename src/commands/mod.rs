@@ -1,7 +1,13 @@
+pub mod daemon;
 pub mod decode;
 pub mod delete;
 pub mod import;
 pub mod list;
+pub mod pick;
+pub mod pin;
 pub mod query;
+pub mod search;
+pub mod snippet;
 pub mod store;
+pub mod tag;
 pub mod watch;
@@ -0,0 +1,562 @@
+//! `stash pick`, a fuzzy finder over the history drawn with the same ratatui
+//! setup as the `stash list` TUI, in place of piping `stash list` through
+//! dmenu or rofi and back into `stash decode`.
+
+use std::io::{self, IsTerminal, Write};
+
+use crossterm::{
+  event::{
+    self,
+    DisableFocusChange,
+    EnableFocusChange,
+    Event,
+    KeyCode,
+    KeyEventKind,
+    KeyModifiers,
+  },
+  execute,
+  terminal::{
+    EnterAlternateScreen,
+    LeaveAlternateScreen,
+    disable_raw_mode,
+    enable_raw_mode,
+  },
+};
+use ratatui::{
+  Frame,
+  Terminal,
+  backend::CrosstermBackend,
+  layout::{Constraint, Layout},
+  style::{Color, Modifier, Style},
+  text::{Line, Span},
+  widgets::{Block, Borders, List, ListItem, Paragraph},
+};
+use unicode_width::UnicodeWidthChar;
+
+use crate::{
+  commands::decode::DecodeCommand,
+  db::{ListEntry, SqliteClipboardDb, StashError},
+  fuzzy::Pattern,
+};
+
+/// How much of an entry's text is searched. Previews are always searched as
+/// a whole.
+const MAX_TEXT_CHARS: usize = 8192;
+
+fn pick_err(e: impl ToString) -> StashError {
+  StashError::Pick(e.to_string().into())
+}
+
+pub trait PickCommand {
+  /// Let the user pick an entry with a fuzzy finder drawn on the terminal,
+  /// then copy it to the selection it was recorded from or, with `print`,
+  /// write its contents to `out`. Returns the picked id, or `None` when the
+  /// picker was closed without picking.
+  ///
+  /// With `launcher`, the picker is meant to run in a terminal window of its
+  /// own: it closes when the window loses focus, and keeps serving the copied
+  /// entry after the window is gone.
+  fn pick(
+    &self,
+    out: impl Write,
+    preview_width: u32,
+    print: bool,
+    launcher: bool,
+  ) -> Result<Option<i64>, StashError>;
+}
+
+impl PickCommand for SqliteClipboardDb {
+  fn pick(
+    &self,
+    mut out: impl Write,
+    preview_width: u32,
+    print: bool,
+    launcher: bool,
+  ) -> Result<Option<i64>, StashError> {
+    // The picker is drawn on stderr, leaving stdout to `--print`.
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+      return Err(pick_err(if launcher {
+        "the picker needs a terminal, bind --launcher to something like `foot \
+         stash pick --launcher`"
+      } else {
+        "the picker needs a terminal"
+      }));
+    }
+
+    if launcher && !launched_from_terminal_emulator() {
+      return Err(pick_err(
+        "--launcher only starts from a terminal emulator keybinding, like \
+         `foot stash pick --launcher`; set STASH_LAUNCHER_TERMINAL to the \
+         process name of a terminal emulator stash does not know",
+      ));
+    }
+
+    let mut picker = Picker::new(self.pick_candidates(preview_width)?);
+    let Some(id) = run(&mut picker, print, launcher)? else {
+      return Ok(None);
+    };
+
+    if print {
+      let entry = self.entry(id)?;
+      out
+        .write_all(&entry.contents)
+        .and_then(|()| out.flush())
+        .map_err(pick_err)?;
+    } else {
+      if launcher {
+        // Closing the window hangs up on everything started from it,
+        // including the process serving the copy.
+        // SAFETY: ignoring SIGHUP is process-global and inherited by the
+        // serving child, which is what it is meant for.
+        unsafe {
+          libc::signal(libc::SIGHUP, libc::SIG_IGN);
+        }
+      }
+      self.decode_to_clipboard(io::empty(), Some(id.to_string()))?;
+    }
+    Ok(Some(id))
+  }
+}
+
+impl SqliteClipboardDb {
+  /// Every unexpired entry in display order, with the text to search.
+  fn pick_candidates(
+    &self,
+    preview_width: u32,
+  ) -> Result<Vec<Candidate>, StashError> {
+    let entries =
+      self.fetch_entries_with_text(preview_width, MAX_TEXT_CHARS)?;
+    Ok(
+      entries
+        .into_iter()
+        .map(|(entry, text)| Candidate::new(entry, text))
+        .collect(),
+    )
+  }
+}
+
+/// An entry that can be picked.
+struct Candidate {
+  entry:   ListEntry,
+  preview: Vec<char>,
+  /// The beginning of the entry's text, `None` for binary entries.
+  text:    Option<Vec<char>>,
+}
+
+impl Candidate {
+  fn new(entry: ListEntry, text: Option<String>) -> Self {
+    Self {
+      preview: entry.preview.chars().collect(),
+      entry,
+      text: text.map(|text| text.chars().collect()),
+    }
+  }
+}
+
+/// A candidate matching the query.
+#[derive(Debug, PartialEq, Eq)]
+struct Match {
+  /// Index into the candidates.
+  index:     usize,
+  score:     i32,
+  /// Matched characters of the preview. Empty when only the text beyond the
+  /// preview matched.
+  positions: Vec<usize>,
+}
+
+/// State of the picker.
+struct Picker {
+  candidates: Vec<Candidate>,
+  query:      String,
+  /// Candidates matching `query`, best first.
+  matches:    Vec<Match>,
+  /// Index into `matches` of the highlighted one.
+  cursor:     usize,
+  /// Index into `matches` of the first one shown.
+  offset:     usize,
+}
+
+impl Picker {
+  fn new(candidates: Vec<Candidate>) -> Self {
+    let mut picker = Self {
+      candidates,
+      query: String::new(),
+      matches: Vec::new(),
+      cursor: 0,
+      offset: 0,
+    };
+    picker.refilter();
+    picker
+  }
+
+  fn set_query(&mut self, query: String) {
+    if query != self.query {
+      self.query = query;
+      self.refilter();
+    }
+  }
+
+  /// Score every candidate against the query. Otherwise equal matches keep
+  /// the display order, so an empty query lists the most recent entries
+  /// first.
+  fn refilter(&mut self) {
+    let pattern = Pattern::new(&self.query);
+    self.matches = self
+      .candidates
+      .iter()
+      .enumerate()
+      .filter_map(|(index, candidate)| {
+        if pattern.is_empty() {
+          return Some(Match {
+            index,
+            score: 0,
+            positions: Vec::new(),
+          });
+        }
+        let in_preview = pattern.score(&candidate.preview);
+        let in_text = candidate
+          .text
+          .as_deref()
+          .and_then(|text| pattern.score(text))
+          .map(|(score, _)| score);
+        let (score, positions) = match (in_preview, in_text) {
+          (Some((score, positions)), text) => {
+            (text.map_or(score, |text| text.max(score)), positions)
+          },
+          (None, Some(score)) => (score, Vec::new()),
+          (None, None) => return None,
+        };
+        Some(Match {
+          index,
+          score,
+          positions,
+        })
+      })
+      .collect();
+    // Of equal scores, matches that can be seen in the preview come first.
+    self
+      .matches
+      .sort_by_key(|m| (std::cmp::Reverse(m.score), m.positions.is_empty()));
+    self.cursor = 0;
+    self.offset = 0;
+  }
+
+  fn move_cursor(&mut self, delta: isize) {
+    let last = self.matches.len().saturating_sub(1);
+    self.cursor = self.cursor.saturating_add_signed(delta).min(last);
+  }
+
+  /// Scroll so that the cursor is among the `height` rows shown.
+  fn scroll(&mut self, height: usize) {
+    if self.cursor < self.offset {
+      self.offset = self.cursor;
+    } else if self.cursor >= self.offset + height {
+      self.offset = self.cursor + 1 - height;
+    }
+  }
+
+  fn selected(&self) -> Option<&ListEntry> {
+    self
+      .matches
+      .get(self.cursor)
+      .map(|m| &self.candidates[m.index].entry)
+  }
+}
+
+/// Process names of terminal emulators that can start the picker from a
+/// keybinding. The kernel truncates process names to 15 bytes.
+const TERMINAL_EMULATORS: &[&str] = &[
+  "alacritty",
+  "foot",
+  "ghostty",
+  "gnome-terminal-",
+  "kitty",
+  "konsole",
+  "rio",
+  "st",
+  "urxvt",
+  "wezterm-gui",
+  "xfce4-terminal",
+  "xterm",
+];
+
+/// Whether stash was started directly by a terminal emulator, as a
+/// keybinding like `foot stash pick --launcher` does, rather than from a
+/// shell.
+fn launched_from_terminal_emulator() -> bool {
+  // SAFETY: getppid cannot fail.
+  let parent = unsafe { libc::getppid() };
+  std::fs::read_to_string(format!("/proc/{parent}/comm"))
+    .is_ok_and(|comm| is_terminal_emulator(comm.trim_end()))
+}
+
+fn is_terminal_emulator(comm: &str) -> bool {
+  let extra = std::env::var("STASH_LAUNCHER_TERMINAL").ok();
+  TERMINAL_EMULATORS
+    .iter()
+    .copied()
+    .chain(extra.as_deref())
+    .any(|name| name.get(..15).unwrap_or(name) == comm)
+}
+
+/// Show the picker until an entry is picked or it is closed.
+fn run(
+  picker: &mut Picker,
+  print: bool,
+  launcher: bool,
+) -> Result<Option<i64>, StashError> {
+  enable_raw_mode().map_err(pick_err)?;
+  let mut stderr = io::stderr();
+  let setup = if launcher {
+    execute!(stderr, EnterAlternateScreen, EnableFocusChange)
+  } else {
+    execute!(stderr, EnterAlternateScreen)
+  };
+  if let Err(e) = setup {
+    let _ = disable_raw_mode();
+    return Err(pick_err(e));
+  }
+
+  let mut terminal = Terminal::new(CrosstermBackend::new(stderr));
+  let picked = match &mut terminal {
+    Ok(terminal) => event_loop(terminal, picker, print, launcher),
+    Err(e) => Err(pick_err(e)),
+  };
+
+  // Ignore errors during terminal restore, as we can't recover here.
+  let _ = disable_raw_mode();
+  let mut stderr = io::stderr();
+  if launcher {
+    let _ = execute!(stderr, DisableFocusChange);
+  }
+  let _ = execute!(stderr, LeaveAlternateScreen);
+  if let Ok(terminal) = &mut terminal {
+    let _ = terminal.show_cursor();
+  }
+  picked
+}
+
+fn event_loop(
+  terminal: &mut Terminal<CrosstermBackend<io::Stderr>>,
+  picker: &mut Picker,
+  print: bool,
+  launcher: bool,
+) -> Result<Option<i64>, StashError> {
+  loop {
+    let mut page = 1;
+    terminal
+      .draw(|f| {
+        page = draw(f, picker, print);
+      })
+      .map_err(pick_err)?;
+
+    let key = match event::read().map_err(pick_err)? {
+      Event::Key(key) if key.kind != KeyEventKind::Release => key,
+      Event::FocusLost if launcher => return Ok(None),
+      _ => continue,
+    };
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    match key.code {
+      KeyCode::Esc => return Ok(None),
+      KeyCode::Char('c' | 'g' | 'q') if ctrl => return Ok(None),
+      KeyCode::Enter => {
+        if let Some(entry) = picker.selected() {
+          return Ok(Some(entry.id));
+        }
+      },
+      KeyCode::Up | KeyCode::BackTab => picker.move_cursor(-1),
+      KeyCode::Down | KeyCode::Tab => picker.move_cursor(1),
+      KeyCode::Char('p' | 'k') if ctrl => picker.move_cursor(-1),
+      KeyCode::Char('n' | 'j') if ctrl => picker.move_cursor(1),
+      KeyCode::PageUp => picker.move_cursor(-page),
+      KeyCode::PageDown => picker.move_cursor(page),
+      KeyCode::Home => picker.move_cursor(isize::MIN),
+      KeyCode::End => picker.move_cursor(isize::MAX),
+      KeyCode::Char('u') if ctrl => picker.set_query(String::new()),
+      KeyCode::Char('w') if ctrl => {
+        let query = picker.query.trim_end();
+        let kept = query.rfind(' ').map_or(0, |i| i + 1);
+        picker.set_query(query[..kept].to_owned());
+      },
+      KeyCode::Backspace => {
+        let mut query = picker.query.clone();
+        query.pop();
+        picker.set_query(query);
+      },
+      KeyCode::Char(c)
+        if !key
+          .modifiers
+          .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+      {
+        picker.set_query(format!("{}{c}", picker.query));
+      },
+      _ => {},
+    }
+  }
+}
+
+/// Draw the prompt and the visible matches. Returns how many rows of
+/// matches fit, for paging.
+fn draw(f: &mut Frame, picker: &mut Picker, print: bool) -> isize {
+  let [prompt_area, list_area] =
+    Layout::vertical([Constraint::Length(1), Constraint::Min(1)])
+      .areas(f.area());
+
+  let count = format!("{}/{}", picker.matches.len(), picker.candidates.len());
+  let prompt = Line::from(vec![
+    Span::styled(
+      "> ",
+      Style::default()
+        .fg(Color::Cyan)
+        .add_modifier(Modifier::BOLD),
+    ),
+    Span::raw(picker.query.clone()),
+    Span::raw("  "),
+    Span::styled(count, Style::default().fg(Color::DarkGray)),
+  ]);
+  f.render_widget(Paragraph::new(prompt), prompt_area);
+  let query_width: usize = picker
+    .query
+    .chars()
+    .map(|c| UnicodeWidthChar::width(c).unwrap_or(0))
+    .sum();
+  f.set_cursor_position((
+    prompt_area.x + 2 + u16::try_from(query_width).unwrap_or(u16::MAX),
+    prompt_area.y,
+  ));
+
+  let action = if print { "print" } else { "copy" };
+  let block = Block::default().borders(Borders::ALL).title(format!(
+    "Pick an entry (type to search, ↑/↓ to move, Enter to {action}, Esc to \
+     quit)"
+  ));
+  let inner = block.inner(list_area);
+  f.render_widget(block, list_area);
+
+  let height = usize::from(inner.height).max(1);
+  picker.scroll(height);
+  let id_width = picker
+    .matches
+    .iter()
+    .skip(picker.offset)
+    .take(height)
+    .map(|m| picker.candidates[m.index].entry.id.to_string().len())
+    .max()
+    .unwrap_or(0);
+  // Marker, id and the spaces after them.
+  let preview_width = usize::from(inner.width).saturating_sub(id_width + 3);
+
+  let items: Vec<ListItem> = picker
+    .matches
+    .iter()
+    .enumerate()
+    .skip(picker.offset)
+    .take(height)
+    .map(|(i, m)| {
+      let entry = &picker.candidates[m.index].entry;
+      let selected = i == picker.cursor;
+      let base = if selected {
+        Style::default()
+          .fg(Color::Yellow)
+          .add_modifier(Modifier::BOLD)
+      } else {
+        Style::default()
+      };
+      let matched = base.fg(Color::Green).add_modifier(Modifier::BOLD);
+
+      let mut spans = vec![
+        Span::styled(if selected { ">" } else { " " }, base),
+        Span::styled(format!("{:>id_width$} ", entry.id), base),
+        Span::styled(if entry.pinned { "*" } else { " " }, base),
+      ];
+      let mut width = 0;
+      let mut positions = m.positions.iter().peekable();
+      for (idx, &c) in picker.candidates[m.index].preview.iter().enumerate() {
+        let char_width = UnicodeWidthChar::width(c).unwrap_or(0);
+        if width + char_width > preview_width {
+          spans.push(Span::styled("…", base));
+          break;
+        }
+        width += char_width;
+        let is_match = positions.next_if_eq(&&idx).is_some();
+        spans.push(Span::styled(
+          c.to_string(),
+          if is_match { matched } else { base },
+        ));
+      }
+      ListItem::new(Line::from(spans))
+    })
+    .collect();
+  f.render_widget(List::new(items), inner);
+
+  isize::try_from(height).unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::clipboard::Selection;
+
+  fn candidate(id: i64, preview: &str, text: Option<&str>) -> Candidate {
+    Candidate::new(
+      ListEntry {
+        id,
+        preview: preview.to_owned(),
+        mime: "text/plain".to_owned(),
+        pinned: false,
+        selection: Selection::Regular,
+        tags: Vec::new(),
+      },
+      text.map(str::to_owned),
+    )
+  }
+
+  #[test]
+  fn test_picker_ranks_previews_and_text() {
+    let mut picker = Picker::new(vec![
+      candidate(4, "cargo build --release", None),
+      candidate(3, "long entry…", Some("long entry that mentions cargo")),
+      candidate(2, "[[ binary data 1 KiB image/png ]]", None),
+      candidate(1, "see the cargo book", None),
+    ]);
+    let ids = |picker: &Picker| -> Vec<i64> {
+      picker
+        .matches
+        .iter()
+        .map(|m| picker.candidates[m.index].entry.id)
+        .collect()
+    };
+    assert_eq!(ids(&picker), [4, 3, 2, 1]);
+
+    picker.move_cursor(2);
+    picker.set_query("cargo".to_owned());
+    assert_eq!(picker.cursor, 0);
+    assert_eq!(ids(&picker), [4, 1, 3]);
+    assert_eq!(picker.matches[0].positions, [0, 1, 2, 3, 4]);
+    assert!(picker.matches[2].positions.is_empty());
+
+    picker.set_query("png".to_owned());
+    assert_eq!(ids(&picker), [2]);
+    picker.move_cursor(5);
+    assert_eq!(picker.selected().map(|entry| entry.id), Some(2));
+
+    picker.set_query("nothing matches".to_owned());
+    assert!(picker.selected().is_none());
+  }
+
+  #[test]
+  fn test_launcher_needs_a_terminal_emulator_parent() {
+    assert!(is_terminal_emulator("foot"));
+    assert!(is_terminal_emulator("gnome-terminal-"));
+    assert!(!is_terminal_emulator("bash"));
+    assert!(!is_terminal_emulator("sway"));
+
+    // SAFETY: only this test reads or writes this variable.
+    unsafe {
+      std::env::set_var("STASH_LAUNCHER_TERMINAL", "my-own-terminal-emulator")
+    };
+    assert!(is_terminal_emulator("my-own-terminal"));
+    unsafe { std::env::remove_var("STASH_LAUNCHER_TERMINAL") };
+    assert!(!is_terminal_emulator("my-own-terminal"));
+  }
+}
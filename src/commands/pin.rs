@@ -0,0 +1,28 @@
+use std::io::{BufRead, BufReader, Read};
+
+use crate::db::{SqliteClipboardDb, StashError, extract_id};
+
+pub trait PinCommand {
+  /// Pin (or unpin) every entry whose id starts a line of `input`, accepting
+  /// the same `id<TAB>preview` lines `stash list` prints.
+  fn pin(&self, input: impl Read, pinned: bool) -> Result<usize, StashError>;
+}
+
+impl PinCommand for SqliteClipboardDb {
+  fn pin(&self, input: impl Read, pinned: bool) -> Result<usize, StashError> {
+    let mut updated = 0;
+    for line in BufReader::new(input).lines() {
+      let line =
+        line.map_err(|e| StashError::PinInput(e.to_string().into()))?;
+      if line.trim().is_empty() {
+        continue;
+      }
+      let id = extract_id(&line).map_err(|e| StashError::PinInput(e.into()))?;
+      self.set_pinned(id, pinned)?;
+      updated += 1;
+    }
+    let action = if pinned { "pinned" } else { "unpinned" };
+    log::info!("{action} {updated} entries");
+    Ok(updated)
+  }
+}
@@ -0,0 +1,42 @@
+use std::io::Write;
+
+use crate::db::{SqliteClipboardDb, StashError, preview_entry};
+
+pub trait SearchCommand {
+  fn search(
+    &self,
+    out: impl Write,
+    query: &str,
+    limit: usize,
+    preview_width: u32,
+    include_expired: bool,
+  ) -> Result<usize, StashError>;
+}
+
+impl SearchCommand for SqliteClipboardDb {
+  /// Print matching entries as `id\tpreview` lines, best matches first.
+  fn search(
+    &self,
+    mut out: impl Write,
+    query: &str,
+    limit: usize,
+    preview_width: u32,
+    include_expired: bool,
+  ) -> Result<usize, StashError> {
+    if query.is_empty() {
+      return Err(StashError::Search("query must not be empty".into()));
+    }
+
+    let hits = self.search_entries(query, limit, include_expired)?;
+    let mut listed = 0;
+    for hit in hits {
+      let preview =
+        preview_entry(hit.body.as_bytes(), hit.mime.as_deref(), preview_width);
+      if writeln!(out, "{}\t{preview}", hit.id).is_ok() {
+        listed += 1;
+      }
+    }
+    log::info!("found {listed} entries matching {query:?}");
+    Ok(listed)
+  }
+}
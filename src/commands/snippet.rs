@@ -0,0 +1,196 @@
+use std::io::{Read, Write};
+
+use wl_clipboard_rs::paste::{ClipboardType, MimeType, Seat, get_contents};
+
+use crate::{
+  clipboard::{self, Selection},
+  db::{SqliteClipboardDb, StashError, preview_entry, snippets::Snippet},
+};
+
+pub trait SnippetCommand {
+  /// Save the contents of `input` as snippet `name`.
+  fn snippet_add(
+    &self,
+    name: &str,
+    input: impl Read,
+    tags: &[String],
+    replace: bool,
+  ) -> Result<(), StashError>;
+
+  /// Print a `name<TAB>tags<TAB>preview` line per snippet, optionally only
+  /// those tagged `tag`. Returns the number of snippets printed.
+  fn snippet_list(
+    &self,
+    out: impl Write,
+    tag: Option<&str>,
+    preview_width: u32,
+  ) -> Result<usize, StashError>;
+
+  /// Copy snippet `name` to the clipboard, expanding its placeholders unless
+  /// `expand` is false.
+  fn snippet_copy(&self, name: &str, expand: bool) -> Result<(), StashError>;
+}
+
+impl SnippetCommand for SqliteClipboardDb {
+  fn snippet_add(
+    &self,
+    name: &str,
+    mut input: impl Read,
+    tags: &[String],
+    replace: bool,
+  ) -> Result<(), StashError> {
+    let mut contents = Vec::new();
+    input
+      .read_to_end(&mut contents)
+      .map_err(|e| StashError::Snippet(e.to_string().into()))?;
+    let id = self.add_snippet(name, &contents, tags, replace)?;
+    log::info!("saved snippet `{}` with id {id}", name.trim());
+    Ok(())
+  }
+
+  fn snippet_list(
+    &self,
+    mut out: impl Write,
+    tag: Option<&str>,
+    preview_width: u32,
+  ) -> Result<usize, StashError> {
+    let snippets = self.snippets(tag)?;
+    for snippet in &snippets {
+      writeln!(
+        out,
+        "{}\t{}\t{}",
+        snippet.name,
+        snippet.tags.join(","),
+        snippet_preview(snippet, preview_width)
+      )
+      .map_err(|e| StashError::Snippet(e.to_string().into()))?;
+    }
+    Ok(snippets.len())
+  }
+
+  fn snippet_copy(&self, name: &str, expand: bool) -> Result<(), StashError> {
+    let snippet = self.snippet(name)?;
+    let contents = if expand {
+      expand_placeholders(self, snippet.contents)?
+    } else {
+      snippet.contents
+    };
+    let mime = crate::mime::detect_mime(&contents);
+    clipboard::copy_detached(contents, mime, Vec::new(), Selection::Regular)
+      .map_err(|e| {
+        StashError::Snippet(format!("failed to copy `{name}`: {e}").into())
+      })?;
+    self.mark_snippet_used(snippet.id)?;
+    log::info!("copied snippet `{name}` to the clipboard");
+    Ok(())
+  }
+}
+
+/// A one-line preview of a snippet's contents.
+pub fn snippet_preview(snippet: &Snippet, width: u32) -> String {
+  let mime = crate::mime::detect_mime(&snippet.contents);
+  preview_entry(&snippet.contents, mime.as_deref(), width)
+}
+
+/// Expand the placeholders of snippet contents: `{date}` becomes today's
+/// date and `{clipboard}` the text currently in the clipboard. Anything else
+/// in braces, and contents that are not text, are left as they are.
+pub fn expand_placeholders(
+  db: &SqliteClipboardDb,
+  contents: Vec<u8>,
+) -> Result<Vec<u8>, StashError> {
+  let template = match String::from_utf8(contents) {
+    Ok(template) => template,
+    Err(e) => return Ok(e.into_bytes()),
+  };
+  let mut error = None;
+  let expanded = fill(&template, |name| {
+    match name {
+      "date" => db.local_date().map_err(|e| error = Some(e)).ok(),
+      "clipboard" => Some(current_clipboard()),
+      _ => None,
+    }
+  });
+  match error {
+    Some(e) => Err(e),
+    None => Ok(expanded.into_bytes()),
+  }
+}
+
+/// Replace every `{name}` in `template` for which `value` returns a
+/// replacement. Each placeholder is looked up once.
+fn fill(
+  template: &str,
+  mut value: impl FnMut(&str) -> Option<String>,
+) -> String {
+  let mut values: Vec<(&str, Option<String>)> = Vec::new();
+  let mut out = String::with_capacity(template.len());
+  let mut rest = template;
+  while let Some(start) = rest.find('{') {
+    out.push_str(&rest[..start]);
+    let after = &rest[start + 1..];
+    let name = after
+      .find('}')
+      .map(|end| &after[..end])
+      .filter(|name| !name.is_empty() && !name.contains('{'));
+    let replacement = name.and_then(|name| {
+      if let Some((_, cached)) = values.iter().find(|(n, _)| *n == name) {
+        return cached.clone();
+      }
+      let looked_up = value(name);
+      values.push((name, looked_up.clone()));
+      looked_up
+    });
+    match (name, replacement) {
+      (Some(name), Some(replacement)) => {
+        out.push_str(&replacement);
+        rest = &after[name.len() + 1..];
+      },
+      _ => {
+        out.push('{');
+        rest = after;
+      },
+    }
+  }
+  out.push_str(rest);
+  out
+}
+
+/// The text in the regular clipboard, or nothing if it holds none.
+fn current_clipboard() -> String {
+  let contents =
+    get_contents(ClipboardType::Regular, Seat::Unspecified, MimeType::Text);
+  let mut text = String::new();
+  match contents {
+    Ok((mut reader, _)) => {
+      if let Err(e) = reader.read_to_string(&mut text) {
+        log::warn!("failed to read the clipboard for a snippet: {e}");
+      }
+    },
+    Err(e) => log::debug!("no clipboard text for a snippet: {e}"),
+  }
+  text
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_fill_placeholders() {
+    let mut lookups = 0;
+    let filled = fill(
+      "{date}: {clipboard} {{clipboard}} {unknown} {} {date",
+      |name| {
+        lookups += 1;
+        match name {
+          "date" => Some("2024-05-01".to_owned()),
+          "clipboard" => Some("pasted".to_owned()),
+          _ => None,
+        }
+      },
+    );
+    assert_eq!(filled, "2024-05-01: pasted {pasted} {unknown} {} {date");
+    assert_eq!(lookups, 3);
+  }
+}
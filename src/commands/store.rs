@@ -1,6 +1,9 @@
 use std::io::Read;
 
-use crate::db::{ClipboardDb, SqliteClipboardDb};
+use crate::{
+  clipboard::{Selection, state::ClipboardState},
+  db::{ClipboardDb, SqliteClipboardDb},
+};
 
 #[allow(clippy::too_many_arguments)]
 pub trait StoreCommand {
@@ -9,10 +12,11 @@ pub trait StoreCommand {
     input: impl Read,
     max_dedupe_search: u64,
     max_items: u64,
-    state: Option<String>,
+    state: ClipboardState,
     excluded_apps: &[String],
     min_size: Option<usize>,
     max_size: usize,
+    selection: Selection,
   ) -> Result<(), crate::db::StashError>;
 }
 
@@ -22,28 +26,86 @@ impl StoreCommand for SqliteClipboardDb {
     input: impl Read,
     max_dedupe_search: u64,
     max_items: u64,
-    state: Option<String>,
+    state: ClipboardState,
     excluded_apps: &[String],
     min_size: Option<usize>,
     max_size: usize,
+    selection: Selection,
   ) -> Result<(), crate::db::StashError> {
-    if let Some("sensitive" | "clear") = state.as_deref() {
-      self.delete_last()?;
-      log::info!("entry deleted");
-    } else {
-      self.store_entry(
-        input,
-        max_dedupe_search,
-        max_items,
-        Some(excluded_apps),
-        min_size,
-        max_size,
-        None, // no pre-computed hash for CLI store
-        None, // no mime types for CLI store
-        None, // no selected mime for CLI store
-      )?;
-      log::info!("entry stored");
+    match state {
+      ClipboardState::Clear => {
+        self.delete_last()?;
+        log::info!("entry deleted");
+      },
+      ClipboardState::Sensitive => {
+        log::info!("clipboard entry is marked sensitive, skipping");
+      },
+      ClipboardState::Transient => {
+        log::info!("clipboard entry is marked transient, skipping");
+      },
+      ClipboardState::Data | ClipboardState::Nil => {
+        self.store_offer(
+          input,
+          max_dedupe_search,
+          max_items,
+          Some(excluded_apps),
+          min_size,
+          max_size,
+          None, // no pre-computed hash for CLI store
+          None, // no mime types for CLI store
+          None, // no selected mime for CLI store
+          &[],
+          selection,
+        )?;
+        log::info!("entry stored");
+      },
     }
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::{
+    DEFAULT_MAX_ENTRY_SIZE,
+    tests::{store_text, test_db},
+  };
+
+  #[test]
+  fn test_sensitive_store_keeps_last_entry() {
+    let db = test_db();
+    let previous = store_text(&db, "previous");
+
+    db.store(
+      &b"hunter2"[..],
+      100,
+      1000,
+      ClipboardState::Sensitive,
+      &[],
+      None,
+      DEFAULT_MAX_ENTRY_SIZE,
+      Selection::Regular,
+    )
+    .expect("sensitive store");
+    let ids: Vec<i64> = db
+      .conn
+      .prepare("SELECT id FROM clipboard")
+      .and_then(|mut stmt| stmt.query_map([], |row| row.get(0))?.collect())
+      .expect("ids");
+    assert_eq!(ids, [previous]);
+
+    db.store(
+      &b""[..],
+      100,
+      1000,
+      ClipboardState::Clear,
+      &[],
+      None,
+      DEFAULT_MAX_ENTRY_SIZE,
+      Selection::Regular,
+    )
+    .expect("clear store");
+    assert_eq!(db.count_entries(false, None, None, &[]).expect("count"), 0);
+  }
+}
@@ -0,0 +1,70 @@
+use std::io::Write;
+
+use crate::db::{SqliteClipboardDb, StashError};
+
+pub trait TagCommand {
+  /// Add `tags` to entry `id`, or remove them when `remove` is set (all of
+  /// them if `tags` is empty). Without tags to add, print the tags of the
+  /// entry one per line instead.
+  fn tag(
+    &self,
+    out: impl Write,
+    id: i64,
+    tags: &[String],
+    remove: bool,
+  ) -> Result<(), StashError>;
+
+  /// Set the note of entry `id` to `text`, clear it when `clear` is set, or
+  /// print it when neither is given.
+  fn note(
+    &self,
+    out: impl Write,
+    id: i64,
+    text: Option<&str>,
+    clear: bool,
+  ) -> Result<(), StashError>;
+}
+
+impl TagCommand for SqliteClipboardDb {
+  fn tag(
+    &self,
+    mut out: impl Write,
+    id: i64,
+    tags: &[String],
+    remove: bool,
+  ) -> Result<(), StashError> {
+    if remove {
+      let removed = self.remove_tags(id, tags)?;
+      log::info!("removed {removed} tags from entry {id}");
+    } else if tags.is_empty() {
+      for tag in self.entry_tags(id)? {
+        writeln!(out, "{tag}")
+          .map_err(|e| StashError::Tag(id, e.to_string().into()))?;
+      }
+    } else {
+      let added = self.add_tags(id, tags)?;
+      log::info!("added {added} tags to entry {id}");
+    }
+    Ok(())
+  }
+
+  fn note(
+    &self,
+    mut out: impl Write,
+    id: i64,
+    text: Option<&str>,
+    clear: bool,
+  ) -> Result<(), StashError> {
+    if clear {
+      self.set_note(id, None)?;
+      log::info!("cleared the note of entry {id}");
+    } else if let Some(text) = text {
+      self.set_note(id, Some(text))?;
+      log::info!("updated the note of entry {id}");
+    } else if let Some(note) = self.entry_note(id)? {
+      writeln!(out, "{note}")
+        .map_err(|e| StashError::Note(id, e.to_string().into()))?;
+    }
+    Ok(())
+  }
+}
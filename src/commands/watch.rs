@@ -4,7 +4,6 @@ use smol::Timer;
 use wl_clipboard_rs::{
   copy::{MimeType as CopyMimeType, Options, Source},
   paste::{
-    ClipboardType,
     MimeType as PasteMimeType,
     Seat,
     get_contents,
@@ -13,9 +12,17 @@ use wl_clipboard_rs::{
 };
 
 use crate::{
-  clipboard::{self, ClipboardData},
-  db::{SqliteClipboardDb, nonblocking::AsyncClipboardDb},
+  clipboard::{
+    self,
+    ClipboardData,
+    Selection,
+    state::{self, ClipboardState},
+  },
+  config,
+  db::{Representation, SqliteClipboardDb, nonblocking::AsyncClipboardDb},
   hash::Fnv1aHasher,
+  ipc::{self, server::DaemonInfo},
+  rules::Action,
 };
 
 /// Wrapper to provide [`Ord`] implementation for `f64` by negating values.
@@ -108,6 +115,108 @@ impl ExpirationQueue {
   }
 }
 
+/// Which selections `stash watch` records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum WatchSelection {
+  /// Only the regular clipboard.
+  #[default]
+  Regular,
+
+  /// Only the primary selection.
+  Primary,
+
+  /// Both the regular clipboard and the primary selection.
+  Both,
+}
+
+impl WatchSelection {
+  const fn selections(self) -> &'static [Selection] {
+    match self {
+      Self::Regular => &[Selection::Regular],
+      Self::Primary => &[Selection::Primary],
+      Self::Both => &[Selection::Regular, Selection::Primary],
+    }
+  }
+}
+
+/// Interval between clipboard reads when selection events are unavailable.
+const POLL_INTERVAL_MS: u64 = 500;
+
+/// How the daemon learns that the clipboard may have changed.
+enum ChangeSource {
+  /// Selection events from the compositor's data-control protocol. The
+  /// clipboard is only read after the compositor announces a new offer.
+  #[cfg(feature = "use-toplevel")]
+  Events(smol::channel::Receiver<()>),
+
+  /// Re-read and hash the clipboard on a fixed interval. Used when the
+  /// compositor does not implement `zwlr_data_control_manager_v1`.
+  Poll(Duration),
+}
+
+impl ChangeSource {
+  /// Subscribe to selection events, falling back to polling when that is not
+  /// possible. Primary selection changes are only reported with `primary`.
+  #[cfg_attr(
+    not(feature = "use-toplevel"),
+    expect(unused_variables, reason = "only used for selection events")
+  )]
+  fn detect(poll_interval: Duration, primary: bool) -> Self {
+    #[cfg(feature = "use-toplevel")]
+    if let Some(events) = crate::wayland::data_control::watch_selection(primary)
+    {
+      log::info!("watching clipboard via wlr-data-control selection events");
+      return Self::Events(events);
+    }
+
+    log::info!(
+      "selection events unavailable, polling clipboard every {}ms",
+      poll_interval.as_millis()
+    );
+    Self::Poll(poll_interval)
+  }
+
+  /// Wait until the clipboard may have changed, or `timeout` elapses.
+  ///
+  /// Returns `true` if the clipboard should be read again. A timeout only
+  /// reports a change when polling; with events, it merely wakes the caller
+  /// to process pending expirations.
+  async fn wait(&mut self, timeout: Option<Duration>) -> bool {
+    match self {
+      #[cfg(feature = "use-toplevel")]
+      Self::Events(events) => {
+        let changed = async { Some(events.recv().await.is_ok()) };
+        let timed_out = async {
+          match timeout {
+            Some(duration) => Timer::after(duration).await,
+            None => Timer::never().await,
+          };
+          None
+        };
+
+        match smol::future::or(changed, timed_out).await {
+          Some(true) => true,
+          Some(false) => {
+            // The event loop exited, e.g. the compositor revoked our data
+            // device. Keep the daemon alive by polling from here on.
+            log::warn!(
+              "clipboard selection events stopped, falling back to polling"
+            );
+            *self = Self::Poll(Duration::from_millis(POLL_INTERVAL_MS));
+            true
+          },
+          None => false,
+        }
+      },
+      Self::Poll(interval) => {
+        let sleep = timeout.map_or(*interval, |t| t.min(*interval));
+        Timer::after(sleep).await;
+        true
+      },
+    }
+  }
+}
+
 /// Get clipboard contents using the source application's preferred MIME type.
 ///
 /// See, `MimeType::Any` lets wl-clipboard-rs pick a type in arbitrary order,
@@ -121,8 +230,9 @@ impl ExpirationQueue {
 ///
 /// The two-step approach has a theoretical race (clipboard could change between
 /// the calls), but the wl-clipboard-rs API has no single-call variant that
-/// respects source ordering. A race simply produces an error that the polling
-/// loop handles like any other clipboard-empty/error case.
+/// respects source ordering. A race simply produces an error that the watch
+/// loop handles like any other clipboard-empty/error case; the change that
+/// caused it triggers another read.
 ///
 /// When `preference` is `"text"`, uses `MimeType::Text` directly (single call).
 /// When `preference` is `"image"`, picks the first offered `image/*` type.
@@ -148,15 +258,16 @@ fn is_html_like(mime: &str) -> bool {
 #[expect(clippy::type_complexity)]
 fn negotiate_mime_type(
   preference: &str,
+  selection: Selection,
 ) -> Result<(Box<dyn Read>, String, Vec<String>), wl_clipboard_rs::paste::Error>
 {
   // Get all offered MIME types first (needed for persistence)
   let offered =
-    get_mime_types_ordered(ClipboardType::Regular, Seat::Unspecified)?;
+    get_mime_types_ordered(selection.paste_type(), Seat::Unspecified)?;
 
   if preference == "text" {
     let (reader, mime_str) = get_contents(
-      ClipboardType::Regular,
+      selection.paste_type(),
       Seat::Unspecified,
       PasteMimeType::Text,
     )?;
@@ -168,7 +279,7 @@ fn negotiate_mime_type(
   match chosen {
     Some(mime_str) => {
       let (reader, actual_mime) = get_contents(
-        ClipboardType::Regular,
+        selection.paste_type(),
         Seat::Unspecified,
         PasteMimeType::Specific(mime_str),
       )?;
@@ -194,6 +305,8 @@ pub trait WatchCommand {
     min_size: Option<usize>,
     max_size: usize,
     persist: bool,
+    max_extra_mime_size: usize,
+    selection: WatchSelection,
   );
 }
 
@@ -208,6 +321,8 @@ impl WatchCommand for SqliteClipboardDb {
     min_size: Option<usize>,
     max_size: usize,
     persist: bool,
+    max_extra_mime_size: usize,
+    selection: WatchSelection,
   ) {
     let async_db = AsyncClipboardDb::new(self.db_path.clone());
     log::info!(
@@ -219,6 +334,16 @@ impl WatchCommand for SqliteClipboardDb {
       log::info!("clipboard persistence enabled");
     }
 
+    let selections = selection.selections();
+    if selections.contains(&Selection::Primary) {
+      log::info!("recording the primary selection");
+    }
+
+    let ipc = ipc::server::Server::start(self.db_path.clone(), DaemonInfo {
+      selections: selections.to_vec(),
+      persist,
+    });
+
     // Build expiration queue from existing entries
     let mut exp_queue = ExpirationQueue::new();
 
@@ -237,27 +362,31 @@ impl WatchCommand for SqliteClipboardDb {
       },
     }
 
-    // We use hashes for comparison instead of storing full contents
-    let mut last_hash: Option<u64> = None;
+    // We use hashes for comparison instead of storing full contents. Each
+    // watched selection tracks its own last seen contents.
+    let mut tracked: Vec<(Selection, Option<u64>)> = selections
+      .iter()
+      .map(|&selection| (selection, None))
+      .collect();
     let mut buf = Vec::with_capacity(4096);
 
-    // Helper to hash clipboard contents using FNV-1a (deterministic across
-    // runs)
-    let hash_contents = |data: &[u8]| -> u64 {
-      let mut hasher = Fnv1aHasher::new();
-      hasher.write(data);
-      hasher.finish()
-    };
-
     // Initialize with current clipboard using smart MIME negotiation
-    if let Ok((mut reader, ..)) = negotiate_mime_type(mime_type_preference) {
-      buf.clear();
-      if reader.read_to_end(&mut buf).is_ok() && !buf.is_empty() {
-        last_hash = Some(hash_contents(&buf));
+    for (selection, last_hash) in &mut tracked {
+      if let Ok((mut reader, ..)) =
+        negotiate_mime_type(mime_type_preference, *selection)
+      {
+        buf.clear();
+        if reader.read_to_end(&mut buf).is_ok() && !buf.is_empty() {
+          *last_hash = Some(hash_contents(&buf));
+        }
       }
     }
 
-    let poll_interval = Duration::from_millis(500);
+    let mut changes = ChangeSource::detect(
+      Duration::from_millis(POLL_INTERVAL_MS),
+      selections.contains(&Selection::Primary),
+    );
+    let mut clipboard_changed = true;
 
     loop {
       // Process any pending expirations that are due now
@@ -279,46 +408,59 @@ impl WatchCommand for SqliteClipboardDb {
 
             if let Some(stored_hash) = expired_hash {
               // Mark as expired
-              if let Err(e) = async_db.mark_expired(id).await {
-                log::warn!("failed to mark entry {id} as expired: {e}");
-              } else {
-                log::info!("entry {id} marked as expired");
+              match async_db.mark_expired(id).await {
+                Ok(true) => log::info!("entry {id} marked as expired"),
+                Ok(false) => {
+                  // Pinned since it was queued; pinned entries never expire.
+                  log::debug!("entry {id} is pinned, not expiring it");
+                  continue;
+                },
+                Err(e) => {
+                  log::warn!("failed to mark entry {id} as expired: {e}");
+                },
               }
 
-              // Check if this expired entry is currently in the clipboard
-              if let Ok((mut reader, ..)) =
-                negotiate_mime_type(mime_type_preference)
-              {
+              // Check if this expired entry is currently in a watched
+              // selection
+              for (selection, last_hash) in &mut tracked {
+                let Ok((mut reader, ..)) =
+                  negotiate_mime_type(mime_type_preference, *selection)
+                else {
+                  continue;
+                };
                 let mut current_buf = Vec::new();
-                if reader.read_to_end(&mut current_buf).is_ok()
-                  && !current_buf.is_empty()
+                if reader.read_to_end(&mut current_buf).is_err()
+                  || current_buf.is_empty()
                 {
-                  let current_hash = hash_contents(&current_buf);
-                  // Convert stored i64 to u64 for comparison (preserves bit
-                  // pattern)
-                  if current_hash == stored_hash as u64 {
-                    // Clear the clipboard since expired content is still
-                    // there
-                    let mut opts = Options::new();
-                    opts
-                      .clipboard(wl_clipboard_rs::copy::ClipboardType::Regular);
-                    if opts
-                      .copy(
-                        Source::Bytes(Vec::new().into()),
-                        CopyMimeType::Autodetect,
-                      )
-                      .is_ok()
-                    {
-                      log::info!(
-                        "cleared clipboard containing expired entry {id}"
-                      );
-                      last_hash = None; // reset tracked hash
-                    } else {
-                      log::warn!(
-                        "failed to clear clipboard for expired entry {id}"
-                      );
-                    }
-                  }
+                  continue;
+                }
+                let current_hash = hash_contents(&current_buf);
+                // Convert stored i64 to u64 for comparison (preserves bit
+                // pattern)
+                if current_hash != stored_hash as u64 {
+                  continue;
+                }
+
+                // Clear the selection since expired content is still there
+                let mut opts = Options::new();
+                opts.clipboard(selection.copy_type());
+                if opts
+                  .copy(
+                    Source::Bytes(Vec::new().into()),
+                    CopyMimeType::Autodetect,
+                  )
+                  .is_ok()
+                {
+                  log::info!(
+                    "cleared {} selection containing expired entry {id}",
+                    selection.as_str()
+                  );
+                  *last_hash = None; // reset tracked hash
+                } else {
+                  log::warn!(
+                    "failed to clear {} selection for expired entry {id}",
+                    selection.as_str()
+                  );
                 }
               }
             }
@@ -326,182 +468,389 @@ impl WatchCommand for SqliteClipboardDb {
         }
       }
 
-      // Normal clipboard polling (always run, even when expirations are
-      // pending)
-      match negotiate_mime_type(mime_type_preference) {
-        Ok((mut reader, _mime_type, _all_mimes)) => {
-          buf.clear();
-          if let Err(e) = reader.read_to_end(&mut buf) {
-            log::error!("failed to read clipboard contents: {e}");
-            Timer::after(Duration::from_millis(500)).await;
-            continue;
-          }
+      // Read the clipboard whenever it may have changed (on every tick when
+      // polling), even when expirations are pending.
+      let reads = if clipboard_changed {
+        tracked.as_mut_slice()
+      } else {
+        &mut []
+      };
+      for (selection, last_hash) in reads {
+        let selection = *selection;
+        match negotiate_mime_type(mime_type_preference, selection) {
+          Ok((mut reader, _mime_type, _all_mimes)) => {
+            buf.clear();
+            if let Err(e) = reader.read_to_end(&mut buf) {
+              log::error!("failed to read clipboard contents: {e}");
+              buf.clear();
+            }
 
-          // Only store if changed and not empty
-          if !buf.is_empty() {
-            let current_hash = hash_contents(&buf);
-            if last_hash != Some(current_hash) {
-              // Clone buf for the async operation since it needs 'static
-              let buf_clone = buf.clone();
-              #[expect(
-                clippy::cast_possible_wrap,
-                reason = "stored hash preserves the u64 bit pattern in sqlite"
-              )]
-              let content_hash = Some(current_hash as i64);
-
-              // Clone data for persistence after successful store
-              let buf_for_persist = buf.clone();
-              let mime_types_for_persist = _all_mimes.clone();
-              let selected_mime = _mime_type.clone();
-
-              match async_db
-                .store_entry(
-                  buf_clone,
-                  max_dedupe_search,
-                  max_items,
-                  Some(excluded_apps.to_vec()),
-                  min_size,
-                  max_size,
-                  content_hash,
-                  Some(mime_types_for_persist.clone()),
-                  Some(selected_mime.clone()),
-                )
-                .await
-              {
-                Ok(id) => {
-                  log::info!("stored new clipboard entry (id: {id})");
-                  last_hash = Some(current_hash);
-
-                  // Persist clipboard: fork child to serve data.
-                  if persist {
-                    let clipboard_data = ClipboardData::new(
-                      buf_for_persist,
-                      mime_types_for_persist,
-                      selected_mime,
-                    );
-
-                    if let Err(e) = clipboard_data.is_valid() {
-                      log::debug!("clipboard persistence skipped: {e}");
-                    } else {
-                      smol::spawn(async move {
-                        let result = smol::unblock(move || {
-                          clipboard::persist_clipboard(clipboard_data)
-                        })
-                        .await;
+            // Only store if changed and not empty
+            if !buf.is_empty() {
+              let current_hash = hash_contents(&buf);
+              if *last_hash != Some(current_hash) {
+                let state = ClipboardState::of_offer(&_all_mimes, |hint| {
+                  clipboard::state::read_hint(
+                    selection.paste_type(),
+                    Seat::Unspecified,
+                    hint,
+                  )
+                });
+                if !state.is_recorded() {
+                  log::info!(
+                    "clipboard entry is marked {}, skipping",
+                    state.as_str()
+                  );
+                  *last_hash = Some(current_hash);
+                  continue;
+                }
 
-                        if let Err(e) = result {
-                          log::debug!("clipboard persistence failed: {e}");
-                        }
-                      })
-                      .detach();
+                // Clone buf for the async operation since it needs 'static
+                let buf_clone = buf.clone();
+                #[expect(
+                  clippy::cast_possible_wrap,
+                  reason = "stored hash preserves the u64 bit pattern in \
+                            sqlite"
+                )]
+                let content_hash = Some(current_hash as i64);
+
+                // Clone data for persistence after successful store
+                let buf_for_persist = buf.clone();
+                let mime_types_for_persist = _all_mimes.clone();
+                let selected_mime = _mime_type.clone();
+
+                // The other types are read off the executor, before storing,
+                // so they go through the same filters as the contents.
+                let representations = {
+                  let offered = mime_types_for_persist.clone();
+                  let selected = selected_mime.clone();
+                  smol::unblock(move || {
+                    read_representations(
+                      &offered,
+                      &selected,
+                      current_hash,
+                      max_extra_mime_size,
+                      selection,
+                    )
+                  })
+                  .await
+                };
+
+                match async_db
+                  .store_entry(
+                    buf_clone,
+                    max_dedupe_search,
+                    max_items,
+                    Some(excluded_apps.to_vec()),
+                    min_size,
+                    max_size,
+                    content_hash,
+                    Some(mime_types_for_persist.clone()),
+                    Some(selected_mime.clone()),
+                    representations.clone(),
+                    selection,
+                  )
+                  .await
+                {
+                  Ok(id) => {
+                    log::info!("stored new clipboard entry (id: {id})");
+                    *last_hash = Some(current_hash);
+
+                    if let Some(ipc) = &ipc {
+                      ipc.publish(id).await;
                     }
-                  }
-
-                  // Set expiration if configured
-                  if let Some(duration) = expire_after {
-                    let expires_at =
-                      SqliteClipboardDb::now() + duration.as_secs_f64();
-                    if let Err(e) =
-                      async_db.set_expiration(id, expires_at).await
-                    {
-                      log::warn!(
-                        "failed to set expiration for entry {id}: {e}"
-                      );
+
+                    let rule = match async_db.applied_rule(id).await {
+                      Ok(rule) => rule,
+                      Err(e) => {
+                        log::warn!("failed to load rule of entry {id}: {e}");
+                        None
+                      },
+                    };
+                    let redacted = rule.as_ref().is_some_and(|(name, _)| {
+                      config::rules().iter().any(|rule| {
+                        rule.name == *name && rule.action == Action::Redact
+                      })
+                    });
+
+                    // The other types carry the same content, so a redacted
+                    // entry would leak it through them.
+                    let representations = if redacted {
+                      Vec::new()
                     } else {
-                      exp_queue.push(expires_at, id);
+                      representations
+                    };
+
+                    // Persist clipboard: fork child to serve data.
+                    if persist {
+                      let clipboard_data = ClipboardData::new(
+                        buf_for_persist,
+                        mime_types_for_persist,
+                        selected_mime,
+                      )
+                      .with_representations(representations)
+                      .with_selection(selection);
+
+                      if let Err(e) = clipboard_data.is_valid() {
+                        log::debug!("clipboard persistence skipped: {e}");
+                      } else {
+                        smol::spawn(async move {
+                          let result = smol::unblock(move || {
+                            clipboard::persist_clipboard(clipboard_data)
+                          })
+                          .await;
+
+                          if let Err(e) = result {
+                            log::debug!("clipboard persistence failed: {e}");
+                          }
+                        })
+                        .detach();
+                      }
                     }
-                  }
-                },
-                Err(crate::db::StashError::ExcludedByApp(_)) => {
-                  log::info!("clipboard entry excluded by app filter");
-                  last_hash = Some(current_hash);
-                },
-                Err(crate::db::StashError::Store(ref msg))
-                  if msg.contains("excluded by app filter") =>
-                {
-                  log::info!("clipboard entry excluded by app filter");
-                  last_hash = Some(current_hash);
-                },
-                Err(crate::db::StashError::Store(ref msg))
-                  if msg.contains("filtered by sensitive regex") =>
-                {
-                  log::debug!("clipboard entry excluded by sensitive regex");
-                  last_hash = Some(current_hash);
-                },
-                Err(crate::db::StashError::SensitiveMimeHint) => {
-                  log::debug!(
-                    "clipboard entry excluded by password manager hint, \
-                     skipping"
-                  );
-                  last_hash = Some(current_hash);
-                },
-                Err(crate::db::StashError::AllWhitespace) => {
-                  log::debug!("clipboard entry is all whitespace, skipping");
-                  last_hash = Some(current_hash);
-                },
-                Err(crate::db::StashError::TooSmall(_)) => {
-                  log::debug!("clipboard entry below minimum size, skipping");
-                  last_hash = Some(current_hash);
-                },
-                Err(e) => {
-                  log::error!("failed to store clipboard entry: {e}");
-                  last_hash = Some(current_hash);
-                },
+
+                    // A matching rule decides the lifetime of the entry,
+                    // otherwise set expiration if configured
+                    if let Some((name, expires_at)) = rule {
+                      if let Some(expires_at) = expires_at {
+                        log::debug!(
+                          "entry {id} expires as set by rule `{name}`"
+                        );
+                        exp_queue.push(expires_at, id);
+                      }
+                    } else if let Some(duration) = expire_after {
+                      let expires_at =
+                        SqliteClipboardDb::now() + duration.as_secs_f64();
+                      if let Err(e) =
+                        async_db.set_expiration(id, expires_at).await
+                      {
+                        log::warn!(
+                          "failed to set expiration for entry {id}: {e}"
+                        );
+                      } else {
+                        exp_queue.push(expires_at, id);
+                      }
+                    }
+                  },
+                  Err(crate::db::StashError::ExcludedByApp(_)) => {
+                    log::info!("clipboard entry excluded by app filter");
+                    *last_hash = Some(current_hash);
+                  },
+                  Err(crate::db::StashError::Store(ref msg))
+                    if msg.contains("excluded by app filter") =>
+                  {
+                    log::info!("clipboard entry excluded by app filter");
+                    *last_hash = Some(current_hash);
+                  },
+                  Err(crate::db::StashError::Store(ref msg))
+                    if msg.contains("filtered by sensitive regex") =>
+                  {
+                    log::debug!("clipboard entry excluded by sensitive regex");
+                    *last_hash = Some(current_hash);
+                  },
+                  Err(
+                    crate::db::StashError::DroppedByRule(_)
+                    | crate::db::StashError::SecretDetected(_),
+                  ) => {
+                    *last_hash = Some(current_hash);
+                  },
+                  Err(crate::db::StashError::AllWhitespace) => {
+                    log::debug!("clipboard entry is all whitespace, skipping");
+                    *last_hash = Some(current_hash);
+                  },
+                  Err(crate::db::StashError::TooSmall(_)) => {
+                    log::debug!("clipboard entry below minimum size, skipping");
+                    *last_hash = Some(current_hash);
+                  },
+                  Err(e) => {
+                    log::error!("failed to store clipboard entry: {e}");
+                    *last_hash = Some(current_hash);
+                  },
+                }
               }
             }
-          }
-        },
-        Err(e) => {
-          let error_msg = e.to_string();
-          if !error_msg.contains("empty") {
-            log::error!("failed to get clipboard contents: {e}");
-          }
-        },
+          },
+          Err(wl_clipboard_rs::paste::Error::PrimarySelectionUnsupported) => {
+            log::debug!("compositor does not support the primary selection");
+          },
+          Err(e) => {
+            let error_msg = e.to_string();
+            if !error_msg.contains("empty") {
+              log::error!(
+                "failed to get {} selection contents: {e}",
+                selection.as_str()
+              );
+            }
+          },
+        }
       }
 
-      // Calculate sleep time: min of poll interval and time until next
-      // expiration
-      let sleep_duration = if let Some(next_exp) = exp_queue.peek_next() {
+      // Sleep until the clipboard changes or the next expiration is due,
+      // whichever comes first.
+      let time_to_exp = exp_queue.peek_next().map(|next_exp| {
         let now = SqliteClipboardDb::now();
-        let time_to_exp = (next_exp - now).max(0.0);
-        poll_interval.min(Duration::from_secs_f64(time_to_exp))
-      } else {
-        poll_interval
-      };
-      Timer::after(sleep_duration).await;
+        Duration::from_secs_f64((next_exp - now).max(0.0))
+      });
+      clipboard_changed = changes.wait(time_to_exp).await;
+    }
+  }
+}
+
+/// Hash clipboard contents using FNV-1a (deterministic across runs).
+fn hash_contents(data: &[u8]) -> u64 {
+  let mut hasher = Fnv1aHasher::new();
+  hasher.write(data);
+  hasher.finish()
+}
+
+/// Targets that describe the selection itself rather than carrying data.
+const META_TARGETS: &[&str] =
+  &["TARGETS", "MULTIPLE", "TIMESTAMP", "SAVE_TARGETS", "DELETE"];
+
+/// Names under which plain text is commonly offered. `MimeType::Text` offers
+/// all of them when an entry is copied back, so at most one is worth storing.
+const TEXT_ALIASES: &[&str] = &[
+  "text/plain",
+  "text/plain;charset=utf-8",
+  "UTF8_STRING",
+  "STRING",
+  "TEXT",
+];
+
+/// Offered MIME types worth storing next to the `selected` one, in offer
+/// order.
+///
+/// Skips the selected type, selection metadata targets, duplicates and the
+/// markers of [`clipboard::state`]. Plain text aliases are skipped when the
+/// selected type is one of them; otherwise only the first offered alias is
+/// kept.
+fn representation_candidates<'a>(
+  offered: &'a [String],
+  selected: &str,
+) -> Vec<&'a str> {
+  let mut have_text = TEXT_ALIASES.contains(&selected);
+  let mut candidates: Vec<&str> = Vec::new();
+  for mime in offered {
+    let mime = mime.as_str();
+    if mime == selected
+      || META_TARGETS.contains(&mime)
+      || state::is_marker(mime)
+      || candidates.contains(&mime)
+    {
+      continue;
+    }
+    if TEXT_ALIASES.contains(&mime) {
+      if have_text {
+        continue;
+      }
+      have_text = true;
     }
+    candidates.push(mime);
   }
+  candidates
+}
+
+/// Read the contents of every other offered MIME type, until their combined
+/// size would exceed `budget` bytes. Types that do not fit are skipped so that
+/// smaller ones later in the offer can still be stored.
+///
+/// Every type is a separate read of the selection, so the `selected` type is
+/// read once more afterwards: if it no longer hashes to `selected_hash`, the
+/// selection changed in between and nothing is returned, rather than types
+/// of a later copy.
+fn read_representations(
+  offered: &[String],
+  selected: &str,
+  selected_hash: u64,
+  budget: usize,
+  selection: Selection,
+) -> Vec<Representation> {
+  let mut remaining = budget;
+  let mut representations = Vec::new();
+  for mime in representation_candidates(offered, selected) {
+    if remaining == 0 {
+      break;
+    }
+    let reader = match get_contents(
+      selection.paste_type(),
+      Seat::Unspecified,
+      PasteMimeType::Specific(mime),
+    ) {
+      Ok((reader, _)) => reader,
+      Err(e) => {
+        log::debug!("failed to read {mime} from clipboard: {e}");
+        continue;
+      },
+    };
+
+    let mut contents = Vec::new();
+    if let Err(e) = reader.take(remaining as u64 + 1).read_to_end(&mut contents)
+    {
+      log::debug!("failed to read {mime} from clipboard: {e}");
+      continue;
+    }
+    if contents.len() > remaining {
+      log::debug!("skipping {mime}: exceeds the MIME type size budget");
+      continue;
+    }
+    if contents.is_empty() {
+      continue;
+    }
+
+    remaining -= contents.len();
+    representations.push(Representation {
+      mime: mime.to_owned(),
+      contents,
+    });
+  }
+  if representations.is_empty() {
+    return representations;
+  }
+
+  let mut current = Vec::new();
+  let unchanged = get_contents(
+    selection.paste_type(),
+    Seat::Unspecified,
+    PasteMimeType::Specific(selected),
+  )
+  .is_ok_and(|(mut reader, _)| {
+    reader.read_to_end(&mut current).is_ok()
+      && hash_contents(&current) == selected_hash
+  });
+  if !unchanged {
+    log::debug!("selection changed while reading its MIME types, skipping");
+    return Vec::new();
+  }
+  representations
 }
 
 /// Given ordered offers and a preference, return the chosen MIME type.
 ///
 /// For the default preference, images win over HTML wrappers; otherwise plain
 /// text wins over URI lists and other non-HTML representations when the offer
-/// begins with an HTML wrapper.
+/// begins with an HTML wrapper. The markers of [`clipboard::state`] are never
+/// picked, as they describe the offer rather than hold its contents.
 fn pick_mime<'a>(
   offered: &'a [String],
   preference: &str,
 ) -> Option<&'a String> {
+  let contents = || offered.iter().filter(|m| !state::is_marker(m));
   if preference == "image" {
-    offered
-      .iter()
+    contents()
       .find(|m| m.starts_with("image/"))
-      .or_else(|| offered.first())
+      .or_else(|| contents().next())
   } else {
-    let has_image = offered.iter().any(|m| m.starts_with("image/"));
+    let has_image = contents().any(|m| m.starts_with("image/"));
     if has_image {
-      offered
-        .iter()
+      contents()
         .find(|m| m.starts_with("image/"))
-        .or_else(|| offered.first())
-    } else if offered.first().is_some_and(|m| is_html_like(m)) {
-      offered
-        .iter()
+        .or_else(|| contents().next())
+    } else if contents().next().is_some_and(|m| is_html_like(m)) {
+      contents()
         .find(|m| m.as_str() == "text/plain" || m.starts_with("text/plain;"))
-        .or_else(|| offered.iter().find(|m| !is_html_like(m)))
-        .or_else(|| offered.first())
+        .or_else(|| contents().find(|m| !is_html_like(m)))
+        .or_else(|| contents().next())
     } else {
-      offered.first()
+      contents().next()
     }
   }
 }
@@ -510,6 +859,84 @@ fn pick_mime<'a>(
 mod tests {
   use super::*;
 
+  fn offers(mimes: &[&str]) -> Vec<String> {
+    mimes.iter().map(|m| (*m).to_string()).collect()
+  }
+
+  #[test]
+  fn test_representation_candidates_skip_selected_and_meta() {
+    let offered = offers(&[
+      "TARGETS",
+      "text/html",
+      "image/png",
+      "SAVE_TARGETS",
+      "text/html",
+    ]);
+    assert_eq!(representation_candidates(&offered, "image/png"), vec![
+      "text/html"
+    ]);
+  }
+
+  #[test]
+  fn test_representation_candidates_keep_one_text_alias() {
+    let offered = offers(&[
+      "text/html",
+      "text/plain;charset=utf-8",
+      "UTF8_STRING",
+      "text/plain",
+    ]);
+    assert_eq!(representation_candidates(&offered, "text/html"), vec![
+      "text/plain;charset=utf-8"
+    ]);
+    // Text aliases are re-offered with the entry itself when it is text.
+    assert_eq!(representation_candidates(&offered, "text/plain"), vec![
+      "text/html"
+    ]);
+  }
+
+  #[test]
+  fn test_representation_candidates_skip_password_hint() {
+    let offered = offers(&["text/plain", "x-kde-passwordManagerHint"]);
+    assert!(representation_candidates(&offered, "text/plain").is_empty());
+  }
+
+  #[test]
+  fn test_watch_selection_both_tracks_regular_first() {
+    assert_eq!(WatchSelection::default().selections(), &[
+      Selection::Regular
+    ]);
+    assert_eq!(WatchSelection::Primary.selections(), &[Selection::Primary]);
+    assert_eq!(WatchSelection::Both.selections(), &[
+      Selection::Regular,
+      Selection::Primary
+    ]);
+  }
+
+  #[test]
+  fn test_poll_source_always_reports_change() {
+    // Without selection events every wakeup must trigger a re-read, including
+    // ones cut short by a pending expiration.
+    let mut source = ChangeSource::Poll(Duration::from_millis(1));
+    assert!(smol::block_on(source.wait(None)));
+    assert!(smol::block_on(source.wait(Some(Duration::ZERO))));
+  }
+
+  #[test]
+  fn test_pick_skips_markers() {
+    let offered = offers(&[
+      "x-kde-onlyReplaceEmpty",
+      "x-kde-passwordManagerHint",
+      "text/plain",
+    ]);
+    assert_eq!(pick_mime(&offered, "any").unwrap(), "text/plain");
+    assert_eq!(pick_mime(&offered, "image").unwrap(), "text/plain");
+    assert!(pick_mime(&offers(&["x-kde-onlyReplaceEmpty"]), "any").is_none());
+    assert!(
+      representation_candidates(&offered, "text/plain").is_empty(),
+      "markers are not representations"
+    );
+  }
+
   #[test]
   fn test_pick_first_offered() {
     let offered = vec!["text/uri-list".to_string(), "text/plain".to_string()];
@@ -0,0 +1,613 @@
+//! Optional TOML configuration file.
+//!
+//! Values from `$XDG_CONFIG_HOME/stash/config.toml` (or the file named by
+//! `$STASH_CONFIG`) become the defaults of the matching command line options,
+//! so an option given on the command line wins over its environment variable,
+//! which wins over the file. Top-level keys set global options, and tables
+//! named after a subcommand set that subcommand's options:
+//!
+//! ```toml
+//! max_items = 1000
+//! excluded_apps = ["keepassxc"]
+//!
+//! [watch]
+//! expire_after = "12h"
+//!
+//! [db.wipe]
+//! ask = true
+//! ```
+//!
+//! Settings that are not command line options, like the sensitive regex and
+//! the encryption key sources, can be set at the top level as well, named
+//! after their environment variable without the `STASH_` prefix (see
+//! [`FILE_SETTINGS`]). They are only used when none of the environment
+//! variables of that setting are set.
+//!
+//! Store rules are listed as `[[rules]]` tables, see [`crate::rules`].
+
+use std::{
+  collections::HashMap,
+  env,
+  ffi::OsStr,
+  fs,
+  io::{self, Write},
+  path::{Path, PathBuf},
+  sync::OnceLock,
+};
+
+use clap::{Arg, ArgAction, ArgMatches, Command, parser::ValueSource};
+use toml::{Table, Value};
+
+use crate::{db::StashError, rules::Rule};
+
+/// Environment variables that can also be set in the config file, under their
+/// lowercased name without the `STASH_` prefix.
+pub const FILE_SETTINGS: &[&str] = &[
+  "STASH_SENSITIVE_REGEX",
+  "STASH_SENSITIVE_REGEX_FILE",
+  "STASH_SENSITIVE_REGEX_COMMAND",
+  "STASH_SECRET_DETECTORS",
+  "STASH_ENCRYPTION_PASSPHRASE_FILE",
+  "STASH_ENCRYPTION_PASSPHRASE_COMMAND",
+  "STASH_RECIPIENTS",
+  "STASH_RECIPIENTS_FILE",
+  "STASH_IDENTITY_FILE",
+  "STASH_SEARCH_INDEX_ENCRYPTED",
+];
+
+static INSTALLED: OnceLock<Config> = OnceLock::new();
+
+/// The parsed config file.
+#[derive(Debug, Default)]
+pub struct Config {
+  /// Where the file is expected, if anywhere.
+  path:  Option<PathBuf>,
+  /// Whether the file exists and was read.
+  found: bool,
+  table: Table,
+  rules: Vec<Rule>,
+  /// Why the file was left out, if it could not be used.
+  error: Option<Box<str>>,
+}
+
+fn config_err(path: Option<&Path>, e: impl ToString) -> StashError {
+  let message = match path {
+    Some(path) => format!("{}: {}", path.display(), e.to_string()),
+    None => e.to_string(),
+  };
+  StashError::Config(message.into())
+}
+
+/// Key of the config file entry for the environment variable `var`.
+fn setting_key(var: &str) -> String {
+  var.trim_start_matches("STASH_").to_lowercase()
+}
+
+impl Config {
+  /// Location of the config file: `$STASH_CONFIG`, or `stash/config.toml` in
+  /// `$XDG_CONFIG_HOME`. An empty `$STASH_CONFIG` disables the file.
+  fn default_path() -> Option<PathBuf> {
+    match env::var_os("STASH_CONFIG") {
+      Some(path) if path.is_empty() => None,
+      Some(path) => Some(PathBuf::from(path)),
+      None => {
+        dirs::config_dir().map(|dir| dir.join("stash").join("config.toml"))
+      },
+    }
+  }
+
+  /// Read the config file. A missing file is the same as an empty one.
+  fn load() -> Result<Self, StashError> {
+    let Some(path) = Self::default_path() else {
+      return Ok(Self::default());
+    };
+    match fs::read_to_string(&path) {
+      Ok(contents) => Self::parse(&contents, path),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {
+        Ok(Self {
+          path: Some(path),
+          ..Self::default()
+        })
+      },
+      Err(e) => Err(config_err(Some(&path), e)),
+    }
+  }
+
+  fn parse(contents: &str, path: PathBuf) -> Result<Self, StashError> {
+    let table = contents
+      .parse::<Table>()
+      .map_err(|e| config_err(Some(&path), e))?;
+    for var in FILE_SETTINGS {
+      if let Some(value) = table.get(&setting_key(var))
+        && !value.is_str()
+      {
+        return Err(config_err(
+          Some(&path),
+          format!("`{}` must be a string", setting_key(var)),
+        ));
+      }
+    }
+    let rules = match table.get("rules") {
+      Some(rules) => {
+        Rule::parse_all(rules).map_err(|e| config_err(Some(&path), e))?
+      },
+      None => Vec::new(),
+    };
+    Ok(Self {
+      path: Some(path),
+      found: true,
+      table,
+      rules,
+      error: None,
+    })
+  }
+
+  /// Read the config file and make its values the defaults of `command`. A
+  /// file that cannot be read or applied is left out, so that `--help`,
+  /// `--version` and `config show` keep working; [`Self::check`] reports it.
+  pub fn load_into(command: Command) -> (Self, Command) {
+    let applied = Self::load().and_then(|config| {
+      let applied = config.apply(command.clone())?;
+      Ok((config, applied))
+    });
+    match applied {
+      Ok(applied) => applied,
+      Err(e) => {
+        let error = match e {
+          StashError::Config(message) => message,
+          e => e.to_string().into(),
+        };
+        let config = Self {
+          path: Self::default_path(),
+          error: Some(error),
+          ..Self::default()
+        };
+        (config, command)
+      },
+    }
+  }
+
+  /// Fail if the config file could not be used.
+  pub fn check(&self) -> Result<(), StashError> {
+    match &self.error {
+      Some(error) => Err(StashError::Config(error.clone())),
+      None => Ok(()),
+    }
+  }
+
+  /// Make the values in the file the defaults of the matching arguments of
+  /// `command` and its subcommands. Keys that match no option are an error,
+  /// so typos do not go unnoticed.
+  pub fn apply(&self, command: Command) -> Result<Command, StashError> {
+    apply_table(command, &self.table, "")
+      .map_err(|e| config_err(self.path.as_deref(), e))
+  }
+
+  /// Make this configuration available to [`file_setting`] and [`rules`].
+  pub fn install(self) -> &'static Self {
+    INSTALLED.get_or_init(|| self)
+  }
+
+  /// The entry for `id` in the section of the subcommand at `path`.
+  fn lookup(&self, path: &[&str], id: &str) -> Option<&Value> {
+    let mut table = &self.table;
+    for name in path {
+      table = table.get(*name)?.as_table()?;
+    }
+    table.get(id)
+  }
+
+  /// Print every option that has a value, with the value in effect and where
+  /// it came from. `matches` are the parsed global options.
+  pub fn show(
+    &self,
+    mut out: impl Write,
+    command: &Command,
+    matches: &ArgMatches,
+  ) -> io::Result<()> {
+    match (&self.path, self.found, &self.error) {
+      (_, _, Some(error)) => writeln!(out, "# config file ignored: {error}")?,
+      (Some(path), true, None) => {
+        writeln!(out, "# config file: {}", path.display())?;
+      },
+      (Some(path), false, None) => {
+        writeln!(out, "# config file: {} (not found)", path.display())?;
+      },
+      (None, ..) => writeln!(out, "# config file: disabled")?,
+    }
+
+    for arg in configurable_args(command) {
+      let id = arg.get_id().as_str();
+      let Some(values) = matches.get_raw(id) else {
+        continue;
+      };
+      let source = match matches.value_source(id) {
+        Some(ValueSource::CommandLine) => "command line".to_owned(),
+        Some(ValueSource::EnvVariable) => env_source(arg),
+        _ if self.lookup(&[], id).is_some() => "config file".to_owned(),
+        _ => "default".to_owned(),
+      };
+      write_value(&mut out, id, takes_list(arg), values, &source)?;
+    }
+
+    for var in FILE_SETTINGS {
+      let key = setting_key(var);
+      if let Some(value) = env::var_os(var) {
+        write_value(
+          &mut out,
+          &key,
+          false,
+          [value.as_os_str()],
+          &format!("env {var}"),
+        )?;
+      } else if let Some(value) = file_value(&self.table, var) {
+        write_value(&mut out, &key, false, [OsStr::new(value)], "config file")?;
+      }
+    }
+
+    self.show_subcommands(&mut out, command, &mut Vec::new())?;
+
+    if let Some(Value::Array(rules)) = self.table.get("rules") {
+      for rule in rules {
+        let rule = toml::to_string(rule).map_err(io::Error::other)?;
+        write!(out, "\n[[rules]]\n{rule}")?;
+      }
+    }
+    Ok(())
+  }
+
+  fn show_subcommands<'a>(
+    &self,
+    out: &mut impl Write,
+    command: &'a Command,
+    path: &mut Vec<&'a str>,
+  ) -> io::Result<()> {
+    for subcommand in command.get_subcommands() {
+      path.push(subcommand.get_name());
+
+      let mut header = false;
+      for arg in configurable_args(subcommand) {
+        let id = arg.get_id().as_str();
+        let env = arg.get_env().and_then(env::var_os);
+        let (values, source): (Vec<&OsStr>, String) = if let Some(env) = &env {
+          (vec![env.as_os_str()], env_source(arg))
+        } else if self.lookup(path, id).is_some() {
+          let defaults = arg.get_default_values().iter().map(AsRef::as_ref);
+          (defaults.collect(), "config file".to_owned())
+        } else if !arg.get_default_values().is_empty() {
+          let defaults = arg.get_default_values().iter().map(AsRef::as_ref);
+          (defaults.collect(), "default".to_owned())
+        } else {
+          continue;
+        };
+
+        if !header {
+          writeln!(out, "\n[{}]", path.join("."))?;
+          header = true;
+        }
+        write_value(out, id, takes_list(arg), values, &source)?;
+      }
+
+      self.show_subcommands(out, subcommand, path)?;
+      path.pop();
+    }
+    Ok(())
+  }
+}
+
+/// Store rules from the config file, in order.
+pub fn rules() -> &'static [Rule] {
+  INSTALLED.get().map_or(&[], |config| &config.rules)
+}
+
+/// Value of the config file entry for the environment variable `var`, one of
+/// [`FILE_SETTINGS`]. Callers check the environment first.
+pub fn file_setting(var: &str) -> Option<String> {
+  file_value(&INSTALLED.get()?.table, var).map(str::to_owned)
+}
+
+fn file_value<'a>(table: &'a Table, var: &str) -> Option<&'a str> {
+  debug_assert!(FILE_SETTINGS.contains(&var), "{var} is not a file setting");
+  table.get(&setting_key(var))?.as_str()
+}
+
+/// Named options that take a value or are plain flags.
+fn configurable_args(command: &Command) -> impl Iterator<Item = &Arg> {
+  command.get_arguments().filter(|arg| {
+    !arg.is_positional()
+      && matches!(
+        arg.get_action(),
+        ArgAction::Set
+          | ArgAction::Append
+          | ArgAction::SetTrue
+          | ArgAction::SetFalse
+      )
+  })
+}
+
+fn takes_list(arg: &Arg) -> bool {
+  matches!(arg.get_action(), ArgAction::Append)
+}
+
+fn env_source(arg: &Arg) -> String {
+  arg
+    .get_env()
+    .map_or_else(|| "env".to_owned(), |var| format!("env {}", var.display()))
+}
+
+fn apply_table(
+  mut command: Command,
+  table: &Table,
+  section: &str,
+) -> Result<Command, String> {
+  let mut defaults = HashMap::new();
+  for (key, value) in table {
+    let qualified = if section.is_empty() {
+      key.clone()
+    } else {
+      format!("{section}.{key}")
+    };
+
+    if let Value::Table(subtable) = value {
+      if command.find_subcommand(key).is_none() {
+        return Err(format!("unknown section `[{qualified}]`"));
+      }
+      let mut result = Ok(());
+      command = command.mut_subcommand(key, |subcommand| {
+        match apply_table(subcommand.clone(), subtable, &qualified) {
+          Ok(subcommand) => subcommand,
+          Err(e) => {
+            result = Err(e);
+            subcommand
+          },
+        }
+      });
+      result?;
+      continue;
+    }
+
+    if section.is_empty()
+      && (key == "rules"
+        || FILE_SETTINGS.iter().any(|var| setting_key(var) == *key))
+    {
+      continue;
+    }
+
+    if !configurable_args(&command).any(|arg| arg.get_id() == key) {
+      return Err(format!("unknown option `{qualified}`"));
+    }
+    let values = default_values(value)
+      .map_err(|e| format!("invalid value for `{qualified}`: {e}"))?;
+    defaults.insert(key.as_str(), values);
+  }
+
+  // Unlike `mut_arg`, this keeps the arguments in their declared order.
+  Ok(command.mut_args(|arg| {
+    match defaults.remove(arg.get_id().as_str()) {
+      Some(values) => arg.default_values(values),
+      None => arg,
+    }
+  }))
+}
+
+/// Turn a config value into the strings clap parses for an option.
+fn default_values(value: &Value) -> Result<Vec<String>, String> {
+  let scalar = |value: &Value| {
+    match value {
+      Value::String(s) => Ok(s.clone()),
+      Value::Integer(i) => Ok(i.to_string()),
+      Value::Float(f) => Ok(f.to_string()),
+      Value::Boolean(b) => Ok(b.to_string()),
+      other => Err(format!("unsupported {} value", other.type_str())),
+    }
+  };
+  match value {
+    Value::Array(items) => items.iter().map(scalar).collect(),
+    value => scalar(value).map(|value| vec![value]),
+  }
+}
+
+/// Write `key = value  # source`, quoting values that are not plain numbers
+/// or booleans. Values of options taking several are written as an array.
+fn write_value<'a>(
+  out: &mut impl Write,
+  key: &str,
+  list: bool,
+  values: impl IntoIterator<Item = &'a OsStr>,
+  source: &str,
+) -> io::Result<()> {
+  let values: Vec<String> = values
+    .into_iter()
+    .map(|value| {
+      let value = value.to_string_lossy();
+      if value.parse::<i64>().is_ok()
+        || value.parse::<u64>().is_ok()
+        || value == "true"
+        || value == "false"
+      {
+        value.into_owned()
+      } else {
+        Value::String(value.into_owned()).to_string()
+      }
+    })
+    .collect();
+  let value = if list {
+    format!("[{}]", values.join(", "))
+  } else {
+    values.join(" ")
+  };
+  writeln!(out, "{key} = {value}  # {source}")
+}
+
+#[cfg(test)]
+mod tests {
+  use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+
+  use super::*;
+
+  #[derive(Parser, Debug)]
+  struct TestCli {
+    #[arg(long, default_value_t = 10)]
+    max_items: u64,
+
+    #[arg(
+      long,
+      env = "STASH_TEST_CONFIG_PREVIEW_WIDTH",
+      default_value_t = 100
+    )]
+    preview_width: u32,
+
+    #[arg(long, value_delimiter = ',')]
+    excluded_apps: Vec<String>,
+
+    #[command(subcommand)]
+    command: Option<TestCommand>,
+  }
+
+  #[derive(Subcommand, Debug)]
+  enum TestCommand {
+    Watch {
+      #[arg(long)]
+      expire_after: Option<String>,
+
+      #[arg(long)]
+      persist: bool,
+    },
+  }
+
+  fn parse(config: &str, args: &[&str]) -> Result<TestCli, String> {
+    let config = Config::parse(config, PathBuf::from("config.toml"))
+      .map_err(|e| e.to_string())?;
+    let command = config
+      .apply(TestCli::command())
+      .map_err(|e| e.to_string())?;
+    let matches = command
+      .try_get_matches_from(args)
+      .map_err(|e| e.to_string())?;
+    TestCli::from_arg_matches(&matches).map_err(|e| e.to_string())
+  }
+
+  #[test]
+  fn test_file_values_become_defaults() {
+    let config = r#"
+      max_items = 5
+      excluded_apps = ["keepassxc", "Bitwarden"]
+      sensitive_regex = "hunter2"
+      search_index_encrypted = "on"
+
+      [watch]
+      expire_after = "12h"
+      persist = true
+    "#;
+
+    let cli = parse(config, &["stash", "watch"]).expect("parse");
+    assert_eq!(cli.max_items, 5);
+    assert_eq!(cli.excluded_apps, ["keepassxc", "Bitwarden"]);
+    let Some(TestCommand::Watch {
+      expire_after,
+      persist,
+    }) = cli.command
+    else {
+      panic!("expected watch");
+    };
+    assert_eq!(expire_after.as_deref(), Some("12h"));
+    assert!(persist);
+
+    // The command line still wins.
+    let cli = parse(config, &["stash", "--max-items", "7"]).expect("parse");
+    assert_eq!(cli.max_items, 7);
+  }
+
+  #[test]
+  fn test_env_wins_over_file() {
+    // SAFETY: only this test reads or writes this variable.
+    unsafe { env::set_var("STASH_TEST_CONFIG_PREVIEW_WIDTH", "42") };
+    let cli = parse("preview_width = 80", &["stash"]).expect("parse");
+    unsafe { env::remove_var("STASH_TEST_CONFIG_PREVIEW_WIDTH") };
+    assert_eq!(cli.preview_width, 42);
+
+    let cli = parse("preview_width = 80", &["stash"]).expect("parse");
+    assert_eq!(cli.preview_width, 80);
+  }
+
+  #[test]
+  fn test_unknown_keys_are_rejected() {
+    let err = parse("max_itmes = 5", &["stash"]).unwrap_err();
+    assert!(err.contains("unknown option `max_itmes`"), "{err}");
+
+    let err = parse("[watch]\nexpire = \"1h\"", &["stash"]).unwrap_err();
+    assert!(err.contains("unknown option `watch.expire`"), "{err}");
+
+    let err = parse("[wacth]\npersist = true", &["stash"]).unwrap_err();
+    assert!(err.contains("unknown section `[wacth]`"), "{err}");
+
+    let err = parse("sensitive_regex = 5", &["stash"]).unwrap_err();
+    assert!(err.contains("must be a string"), "{err}");
+  }
+
+  #[test]
+  fn test_rules_are_not_options() {
+    let config = "max_items = 5\n[[rules]]\nmime = \"image/*\"";
+    let cli = parse(config, &["stash"]).expect("parse");
+    assert_eq!(cli.max_items, 5);
+
+    let err = parse("[[rules]]\naction = \"shred\"", &["stash"]).unwrap_err();
+    assert!(err.contains("rule 1"), "{err}");
+  }
+
+  #[test]
+  fn test_show_reports_sources() {
+    let config =
+      Config::parse("max_items = 5\n[watch]\npersist = true", "c".into())
+        .expect("parse");
+    let command = config.apply(TestCli::command()).expect("apply");
+    let matches = command
+      .clone()
+      .try_get_matches_from(["stash", "--excluded-apps", "a,b"])
+      .expect("matches");
+
+    let mut out = Vec::new();
+    config.show(&mut out, &command, &matches).expect("show");
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("max_items = 5  # config file\n"), "{out}");
+    assert!(out.contains("preview_width = 100  # default\n"), "{out}");
+    assert!(
+      out.contains("excluded_apps = [\"a\", \"b\"]  # command line\n"),
+      "{out}"
+    );
+    assert!(
+      out.contains("[watch]\npersist = true  # config file\n"),
+      "{out}"
+    );
+  }
+
+  #[test]
+  fn test_broken_file_is_reported_after_parsing() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let path = dir.path().join("config.toml");
+    fs::write(&path, "max_items = [").expect("write config");
+    // SAFETY: only this test reads or writes this variable.
+    unsafe { env::set_var("STASH_CONFIG", &path) };
+    let (config, command) = Config::load_into(TestCli::command());
+    unsafe { env::remove_var("STASH_CONFIG") };
+
+    // `--help` and `--version` are still handled by clap.
+    let err = command
+      .clone()
+      .try_get_matches_from(["stash", "--help"])
+      .unwrap_err();
+    assert_eq!(err.kind(), clap::error::ErrorKind::DisplayHelp);
+
+    let matches = command
+      .clone()
+      .try_get_matches_from(["stash"])
+      .expect("matches");
+    let mut out = Vec::new();
+    config.show(&mut out, &command, &matches).expect("show");
+    let out = String::from_utf8(out).unwrap();
+    let header = format!("# config file ignored: {}:", path.display());
+    assert!(out.starts_with(&header), "{out}");
+    assert!(out.contains("max_items = 10  # default\n"), "{out}");
+    assert!(matches!(config.check(), Err(StashError::Config(_))));
+  }
+}
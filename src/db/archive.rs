@@ -0,0 +1,777 @@
+//! Lossless archives of the clipboard history, for backups and moving the
+//! history to another machine.
+//!
+//! An archive is JSON Lines: a header naming the format and its version,
+//! followed by one line per entry with every column, its tags, the additional
+//! MIME representations, and contents in base64. Entries are written in id
+//! order and decrypted, so an archive can be imported under different
+//! encryption settings; the archive as a whole can be age-encrypted instead.
+//!
+//! Importing dedupes on `content_hash`: an entry that is already stored keeps
+//! its id and takes the later `last_accessed` of the two, so the imported
+//! history is merged into the existing order rather than appended to it. It
+//! gains the archived tags, and the archived note if it has none.
+
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+
+use base64::prelude::*;
+use rusqlite::{OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+
+use super::{
+  EntryEncoding,
+  SqliteClipboardDb,
+  StashError,
+  content_hash_of,
+  representations::Representation,
+  tags::normalize_tags,
+};
+
+/// Name in the header line of every archive.
+const FORMAT: &str = "stash-archive";
+/// Version of the archive format written by this version of Stash. Archives
+/// of later versions are rejected.
+const VERSION: u32 = 1;
+/// First line of an age-encrypted file.
+const AGE_MAGIC: &[u8] = b"age-encryption.org/v1\n";
+
+/// First line of an archive.
+#[derive(Serialize, Deserialize)]
+struct Header {
+  format:      String,
+  version:     u32,
+  exported_at: f64,
+}
+
+/// One entry of an archive.
+#[derive(Serialize, Deserialize)]
+struct Record {
+  id:              i64,
+  contents:        String,
+  mime:            Option<String>,
+  content_hash:    Option<i64>,
+  last_accessed:   Option<i64>,
+  expires_at:      Option<f64>,
+  #[serde(default)]
+  is_expired:      bool,
+  mime_types:      Option<Vec<String>>,
+  #[serde(default)]
+  pinned:          bool,
+  #[serde(default = "default_selection")]
+  selection:       String,
+  rule:            Option<String>,
+  #[serde(default)]
+  representations: Vec<RecordRepresentation>,
+  #[serde(default)]
+  tags:            Vec<String>,
+  #[serde(default)]
+  note:            Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RecordRepresentation {
+  mime:     String,
+  contents: String,
+}
+
+fn default_selection() -> String {
+  "regular".to_owned()
+}
+
+/// What an archive import did.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ArchiveImport {
+  /// Entries added to the history.
+  pub imported: usize,
+  /// Entries that were already stored and were merged into the existing
+  /// ones.
+  pub merged:   usize,
+}
+
+/// What an archive export did.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ArchiveExport {
+  /// Entries written to the archive.
+  pub exported: usize,
+  /// Ids of the entries left out because they could not be decrypted.
+  pub skipped:  Vec<i64>,
+}
+
+/// Key an archive is encrypted with, given to `stash export`.
+#[cfg(feature = "encryption")]
+pub enum ArchiveKey {
+  /// The passphrase or recipients configured for entries.
+  Configured,
+  /// A passphrase of its own.
+  Passphrase(age::secrecy::SecretString),
+  /// A recipient list, in the same format as `STASH_RECIPIENTS`.
+  Recipients(String),
+}
+
+#[cfg(feature = "encryption")]
+impl ArchiveKey {
+  /// [`ArchiveKey::Configured`], if a passphrase or recipients are
+  /// configured to encrypt entries with.
+  pub fn configured() -> Result<Option<Self>, StashError> {
+    Ok(super::EncryptionKey::configured()?.map(|_| Self::Configured))
+  }
+}
+
+fn export_err(e: impl ToString) -> StashError {
+  StashError::Export(e.to_string().into())
+}
+
+fn import_err(e: impl ToString) -> StashError {
+  StashError::Import(e.to_string().into())
+}
+
+impl SqliteClipboardDb {
+  /// Write every entry, expired ones included, to `out` as an archive.
+  /// Entries that cannot be decrypted are left out, and reported as skipped.
+  pub fn export_archive(
+    &self,
+    out: impl Write,
+  ) -> Result<ArchiveExport, StashError> {
+    self.write_archive(out, None)
+  }
+
+  /// Write the entries with the given ids to `out` as an archive, like
+  /// [`Self::export_archive`].
+  pub fn export_entries_archive(
+    &self,
+    out: impl Write,
+    ids: &[i64],
+  ) -> Result<ArchiveExport, StashError> {
+    self.write_archive(out, Some(ids))
+  }
+
+  /// Write the entries with the given ids, or all of them, as an archive.
+  fn write_archive(
+    &self,
+    mut out: impl Write,
+    ids: Option<&[i64]>,
+  ) -> Result<ArchiveExport, StashError> {
+    let header = Header {
+      format:      FORMAT.to_owned(),
+      version:     VERSION,
+      exported_at: Self::now(),
+    };
+    write_line(&mut out, &header)?;
+
+    let mut stmt = self
+      .conn
+      .prepare(
+        "SELECT id, contents, mime, content_hash, last_accessed, expires_at, \
+         is_expired, mime_types, pinned, selection, rule, note FROM clipboard \
+         WHERE ?1 IS NULL OR id IN (SELECT value FROM json_each(?1)) ORDER BY \
+         id",
+      )
+      .map_err(export_err)?;
+    let ids = ids
+      .map(serde_json::to_string)
+      .transpose()
+      .map_err(export_err)?;
+    let mut rows = stmt.query(params![ids]).map_err(export_err)?;
+
+    let mut export = ArchiveExport::default();
+    while let Some(row) = rows.next().map_err(export_err)? {
+      let id: i64 = row.get(0).map_err(export_err)?;
+      let contents =
+        match EntryEncoding::classify(row.get(1).map_err(export_err)?).decode()
+        {
+          Ok(contents) => contents,
+          Err(e) => {
+            log::warn!("skipping entry {id}: {e}");
+            export.skipped.push(id);
+            continue;
+          },
+        };
+      let mime_types: Option<String> = row.get(7).map_err(export_err)?;
+      let representations = self
+        .representations(id)?
+        .into_iter()
+        .map(|rep| {
+          RecordRepresentation {
+            mime:     rep.mime,
+            contents: BASE64_STANDARD.encode(rep.contents),
+          }
+        })
+        .collect();
+
+      let record = Record {
+        id,
+        contents: BASE64_STANDARD.encode(contents),
+        mime: row.get(2).map_err(export_err)?,
+        content_hash: row.get(3).map_err(export_err)?,
+        last_accessed: row.get(4).map_err(export_err)?,
+        expires_at: row.get(5).map_err(export_err)?,
+        is_expired: row
+          .get::<_, Option<bool>>(6)
+          .map_err(export_err)?
+          .unwrap_or_default(),
+        mime_types: mime_types
+          .and_then(|json| serde_json::from_str(&json).ok()),
+        pinned: row.get(8).map_err(export_err)?,
+        selection: row.get(9).map_err(export_err)?,
+        rule: row.get(10).map_err(export_err)?,
+        representations,
+        tags: self.entry_tags(id)?,
+        note: row.get(11).map_err(export_err)?,
+      };
+      write_line(&mut out, &record)?;
+      export.exported += 1;
+    }
+
+    out.flush().map_err(export_err)?;
+    Ok(export)
+  }
+
+  /// Write an archive like [`Self::export_archive`], age-encrypted with `key`.
+  #[cfg(feature = "encryption")]
+  pub fn export_encrypted_archive(
+    &self,
+    out: impl Write,
+    key: ArchiveKey,
+  ) -> Result<ArchiveExport, StashError> {
+    self.write_encrypted_archive(out, key, None)
+  }
+
+  /// Write the entries with the given ids to `out` as an archive encrypted
+  /// with `key`, like [`Self::export_encrypted_archive`].
+  #[cfg(feature = "encryption")]
+  pub fn export_entries_encrypted_archive(
+    &self,
+    out: impl Write,
+    key: ArchiveKey,
+    ids: &[i64],
+  ) -> Result<ArchiveExport, StashError> {
+    self.write_encrypted_archive(out, key, Some(ids))
+  }
+
+  /// Write the entries with the given ids, or all of them, as an archive
+  /// encrypted with `key`.
+  #[cfg(feature = "encryption")]
+  fn write_encrypted_archive(
+    &self,
+    out: impl Write,
+    key: ArchiveKey,
+    ids: Option<&[i64]>,
+  ) -> Result<ArchiveExport, StashError> {
+    use super::{EncryptionKey, parse_recipients};
+
+    let recipients;
+    let key = match key {
+      ArchiveKey::Configured => {
+        EncryptionKey::configured()?.ok_or_else(|| {
+          export_err("no passphrase or recipients configured to encrypt with")
+        })?
+      },
+      ArchiveKey::Passphrase(passphrase) => {
+        EncryptionKey::Passphrase(passphrase)
+      },
+      ArchiveKey::Recipients(list) => {
+        recipients = parse_recipients(&list).map_err(export_err)?;
+        if recipients.is_empty() {
+          return Err(export_err("no recipients given"));
+        }
+        EncryptionKey::Recipients(&recipients)
+      },
+    };
+
+    let encryptor = match key {
+      EncryptionKey::Passphrase(passphrase) => {
+        age::Encryptor::with_user_passphrase(passphrase)
+      },
+      EncryptionKey::Recipients(recipients) => {
+        age::Encryptor::with_recipients(
+          recipients.iter().map(|r| r.as_ref() as &dyn age::Recipient),
+        )
+        .map_err(|e| StashError::Encryption(e.to_string().into()))?
+      },
+    };
+    let mut writer = encryptor
+      .wrap_output(out)
+      .map_err(|e| StashError::Encryption(e.to_string().into()))?;
+    let export = self.write_archive(&mut writer, ids)?;
+    writer
+      .finish()
+      .map_err(|e| StashError::Encryption(e.to_string().into()))?;
+    Ok(export)
+  }
+
+  /// Import an archive written by [`Self::export_archive`]. Encrypted
+  /// archives are decrypted with the configured identity or passphrase.
+  ///
+  /// The import happens in one transaction, so an invalid archive leaves the
+  /// history untouched. Imported entries are not subject to the filters and
+  /// rules applied when storing, but are encrypted according to the current
+  /// settings.
+  pub fn import_archive(
+    &self,
+    input: impl Read,
+  ) -> Result<ArchiveImport, StashError> {
+    let mut input = BufReader::new(input);
+    let first = read_first_line(&mut input)?;
+    if first == AGE_MAGIC {
+      #[cfg(feature = "encryption")]
+      return self.import_records(decrypt_archive(first, input, None)?);
+      #[cfg(not(feature = "encryption"))]
+      return Err(import_err(
+        "the archive is encrypted, but stash was built without encryption \
+         support",
+      ));
+    }
+    self.import_records(Cursor::new(first).chain(input))
+  }
+
+  /// Import an archive like [`Self::import_archive`], decrypting it with
+  /// `passphrase` as well as the configured keys.
+  #[cfg(feature = "encryption")]
+  pub fn import_encrypted_archive(
+    &self,
+    input: impl Read,
+    passphrase: age::secrecy::SecretString,
+  ) -> Result<ArchiveImport, StashError> {
+    let mut input = BufReader::new(input);
+    let first = read_first_line(&mut input)?;
+    if first != AGE_MAGIC {
+      return Err(import_err("the archive is not encrypted"));
+    }
+    self.import_records(decrypt_archive(first, input, Some(passphrase))?)
+  }
+
+  fn import_records(
+    &self,
+    input: impl BufRead,
+  ) -> Result<ArchiveImport, StashError> {
+    let mut lines = input.lines();
+    let header = lines
+      .next()
+      .transpose()
+      .map_err(import_err)?
+      .ok_or_else(|| import_err("the archive is empty"))?;
+    let header: Header = serde_json::from_str(&header)
+      .ok()
+      .filter(|header: &Header| header.format == FORMAT)
+      .ok_or_else(|| import_err("not a stash archive"))?;
+    if header.version > VERSION {
+      return Err(import_err(format!(
+        "archive version {} is newer than the supported version {VERSION}",
+        header.version
+      )));
+    }
+
+    let tx = self.conn.unchecked_transaction().map_err(import_err)?;
+    let mut result = ArchiveImport::default();
+    for (index, line) in lines.enumerate() {
+      let line = line.map_err(import_err)?;
+      if line.trim().is_empty() {
+        continue;
+      }
+      // Line numbers are 1-based, after the header.
+      let invalid = |e: &dyn ToString| {
+        import_err(format!("line {}: {}", index + 2, e.to_string()))
+      };
+      let record: Record =
+        serde_json::from_str(&line).map_err(|e| invalid(&e))?;
+      if self.import_record(record).map_err(|e| invalid(&e))? {
+        result.imported += 1;
+      } else {
+        result.merged += 1;
+      }
+    }
+    tx.commit().map_err(import_err)?;
+    Ok(result)
+  }
+
+  /// Store one archived entry. Returns whether it was added, rather than
+  /// merged into an entry with the same contents.
+  fn import_record(&self, record: Record) -> Result<bool, StashError> {
+    let contents = BASE64_STANDARD
+      .decode(&record.contents)
+      .map_err(|e| import_err(format!("invalid contents: {e}")))?;
+    let content_hash = content_hash_of(&contents);
+    if record.content_hash.is_some_and(|hash| hash != content_hash) {
+      return Err(import_err("contents do not match their content_hash"));
+    }
+
+    let existing: Option<i64> = self
+      .conn
+      .query_row(
+        "SELECT id FROM clipboard WHERE content_hash = ?1 ORDER BY pinned \
+         DESC, id DESC LIMIT 1",
+        params![content_hash],
+        |row| row.get(0),
+      )
+      .optional()
+      .map_err(|e| StashError::DeduplicationRead(e.to_string().into()))?;
+    let tags = normalize_tags(&record.tags).map_err(import_err)?;
+    if let Some(id) = existing {
+      self
+        .conn
+        .execute(
+          "UPDATE clipboard SET last_accessed = MAX(COALESCE(last_accessed, \
+           0), COALESCE(?2, 0)), pinned = pinned OR ?3, note = COALESCE(note, \
+           ?4) WHERE id = ?1",
+          params![id, record.last_accessed, record.pinned, record.note],
+        )
+        .map_err(import_err)?;
+      self.insert_tags(id, &tags).map_err(import_err)?;
+      return Ok(false);
+    }
+
+    let representations = record
+      .representations
+      .into_iter()
+      .map(|rep| {
+        Ok(Representation {
+          contents: BASE64_STANDARD.decode(&rep.contents).map_err(|e| {
+            import_err(format!("invalid {} contents: {e}", rep.mime))
+          })?,
+          mime:     rep.mime,
+        })
+      })
+      .collect::<Result<Vec<_>, StashError>>()?;
+    let mime_types = record
+      .mime_types
+      .map(|types| serde_json::to_string(&types))
+      .transpose()
+      .map_err(import_err)?;
+
+    let encoding = EntryEncoding::encode(&contents)?;
+    let encrypted = encoding.is_encrypted();
+    self
+      .conn
+      .execute(
+        "INSERT INTO clipboard (contents, mime, content_hash, last_accessed, \
+         expires_at, is_expired, mime_types, pinned, selection, rule, note) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+          encoding.into_raw(),
+          record.mime,
+          content_hash,
+          record.last_accessed,
+          record.expires_at,
+          record.is_expired,
+          mime_types,
+          record.pinned,
+          record.selection,
+          record.rule,
+          record.note,
+        ],
+      )
+      .map_err(import_err)?;
+    let id = self.conn.last_insert_rowid();
+    self.index_entry(id, &contents, encrypted)?;
+    self.insert_tags(id, &tags).map_err(import_err)?;
+
+    self.store_representations(id, &representations, EntryEncoding::encode)?;
+    Ok(true)
+  }
+}
+
+fn write_line(
+  out: &mut impl Write,
+  value: &impl Serialize,
+) -> Result<(), StashError> {
+  serde_json::to_writer(&mut *out, value).map_err(export_err)?;
+  out.write_all(b"\n").map_err(export_err)
+}
+
+/// Read the first line of `input`, including the newline.
+fn read_first_line(input: &mut impl BufRead) -> Result<Vec<u8>, StashError> {
+  let mut line = Vec::new();
+  input.read_until(b'\n', &mut line).map_err(import_err)?;
+  Ok(line)
+}
+
+/// Decrypt an age-encrypted archive whose first line was already read, with
+/// the configured keys and `passphrase`.
+#[cfg(feature = "encryption")]
+fn decrypt_archive(
+  first: Vec<u8>,
+  rest: impl BufRead,
+  passphrase: Option<age::secrecy::SecretString>,
+) -> Result<impl BufRead, StashError> {
+  use super::{load_encryption_identities, load_encryption_passphrase};
+
+  let decryption_err = |e: &dyn ToString| {
+    StashError::Decryption(format!("archive: {}", e.to_string()).into())
+  };
+  let scrypt: Vec<age::scrypt::Identity> = passphrase
+    .into_iter()
+    .chain(load_encryption_passphrase())
+    .map(age::scrypt::Identity::new)
+    .collect();
+  let identities = load_encryption_identities()
+    .into_iter()
+    .flatten()
+    .map(|i| i.as_ref() as &dyn age::Identity)
+    .chain(scrypt.iter().map(|i| i as &dyn age::Identity));
+
+  let decryptor = age::Decryptor::new(Cursor::new(first).chain(rest))
+    .map_err(|e| decryption_err(&e))?;
+  let reader = decryptor
+    .decrypt(identities)
+    .map_err(|e| decryption_err(&e))?;
+  Ok(BufReader::new(reader))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+
+  use rusqlite::Connection;
+
+  use super::*;
+  use crate::db::ClipboardDb;
+
+  /// Contents, MIME type, last access, expiry and pin state of an entry.
+  type Row = (Vec<u8>, Option<String>, Option<i64>, Option<f64>, bool);
+
+  fn test_db() -> SqliteClipboardDb {
+    let conn = Connection::open_in_memory().expect("open db");
+    SqliteClipboardDb::new(conn, PathBuf::from(":memory:")).expect("new db")
+  }
+
+  fn store(db: &SqliteClipboardDb, contents: &[u8]) -> i64 {
+    db.store_entry(contents, 100, 1000, None, None, 1 << 20, None, None, None)
+      .expect("store")
+  }
+
+  #[test]
+  fn test_archive_round_trips_every_column() {
+    let source = test_db();
+    let text = store(&source, b"hello");
+    let png = store(
+      &source,
+      b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01\x08\x06\0\0\0",
+    );
+    source.set_pinned(text, true).expect("pin");
+    source.set_expiration(png, 12345.5).expect("expire");
+    source
+      .add_tags(text, &["work".to_owned(), "mail".to_owned()])
+      .expect("tag");
+    source.set_note(png, Some("logo")).expect("note");
+    source
+      .store_representations(
+        text,
+        &[Representation {
+          mime:     "text/html".to_owned(),
+          contents: b"<b>hello</b>".to_vec(),
+        }],
+        EntryEncoding::encode,
+      )
+      .expect("representations");
+
+    let mut archive = Vec::new();
+    assert_eq!(
+      source.export_archive(&mut archive).expect("export"),
+      ArchiveExport {
+        exported: 2,
+        skipped:  Vec::new(),
+      }
+    );
+    let lines: Vec<&[u8]> = archive.split(|&b| b == b'\n').collect();
+    assert!(lines[0].starts_with(br#"{"format":"stash-archive","version":1"#));
+
+    let target = test_db();
+    let result = target.import_archive(&archive[..]).expect("import");
+    assert_eq!(result, ArchiveImport {
+      imported: 2,
+      merged:   0,
+    });
+
+    let columns = |db: &SqliteClipboardDb| -> Vec<Row> {
+      db.conn
+        .prepare(
+          "SELECT contents, mime, last_accessed, expires_at, pinned FROM \
+           clipboard ORDER BY id",
+        )
+        .expect("prepare")
+        .query_map([], |row| {
+          Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+          ))
+        })
+        .expect("query")
+        .collect::<Result<_, _>>()
+        .expect("rows")
+    };
+    assert_eq!(columns(&source), columns(&target));
+    assert_eq!(
+      target.representations(1).expect("representations"),
+      source.representations(text).expect("representations")
+    );
+    assert_eq!(target.entry_tags(1).expect("tags"), ["mail", "work"]);
+    assert_eq!(target.entry_note(2).expect("note").as_deref(), Some("logo"));
+
+    // Importing again merges everything into the existing entries.
+    let again = target.import_archive(&archive[..]).expect("import");
+    assert_eq!(again, ArchiveImport {
+      imported: 0,
+      merged:   2,
+    });
+    assert_eq!(columns(&source), columns(&target));
+
+    // Exporting chosen entries leaves the others out.
+    let mut archive = Vec::new();
+    assert_eq!(
+      source
+        .export_entries_archive(&mut archive, &[png, 9999])
+        .expect("export")
+        .exported,
+      1
+    );
+    let target = test_db();
+    target.import_archive(&archive[..]).expect("import");
+    assert_eq!(columns(&target), columns(&source)[1..]);
+  }
+
+  #[test]
+  fn test_import_merges_into_existing_order() {
+    let target = test_db();
+    let existing = store(&target, b"shared");
+    target
+      .conn
+      .execute(
+        "UPDATE clipboard SET last_accessed = 100 WHERE id = ?1",
+        params![existing],
+      )
+      .expect("backdate");
+
+    let archive = format!(
+      "{{\"format\":\"stash-archive\",\"version\":1,\"exported_at\":0}}\n{{\"\
+       id\":7,\"contents\":\"{}\",\"mime\":\"text/plain\",\"content_hash\":\
+       null,\"last_accessed\":200,\"expires_at\":null,\"mime_types\":null,\"\
+       pinned\":true,\"selection\":\"primary\",\"rule\":null}}\n{{\"id\":8,\"\
+       contents\":\"{}\",\"mime\":\"text/plain\",\"content_hash\":null,\"\
+       last_accessed\":150,\"expires_at\":null,\"mime_types\":null,\"rule\":\
+       null}}\n",
+      BASE64_STANDARD.encode("shared"),
+      BASE64_STANDARD.encode("new"),
+    );
+    let result = target.import_archive(archive.as_bytes()).expect("import");
+    assert_eq!(result, ArchiveImport {
+      imported: 1,
+      merged:   1,
+    });
+
+    let rows: Vec<(Vec<u8>, i64, bool, String)> = target
+      .conn
+      .prepare(
+        "SELECT contents, last_accessed, pinned, selection FROM clipboard \
+         ORDER BY last_accessed DESC",
+      )
+      .expect("prepare")
+      .query_map([], |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+      })
+      .expect("query")
+      .collect::<Result<_, _>>()
+      .expect("rows");
+    assert_eq!(rows, [
+      (b"shared".to_vec(), 200, true, "regular".to_owned()),
+      (b"new".to_vec(), 150, false, "regular".to_owned()),
+    ]);
+  }
+
+  #[test]
+  fn test_invalid_archives_change_nothing() {
+    let db = test_db();
+    store(&db, b"kept");
+    let header = r#"{"format":"stash-archive","version":1,"exported_at":0}"#;
+    let entry = format!(
+      r#"{{"id":1,"contents":"{}","mime":null,"content_hash":null,"last_accessed":null,"expires_at":null,"mime_types":null,"rule":null}}"#,
+      BASE64_STANDARD.encode("imported")
+    );
+
+    for archive in [
+      String::new(),
+      "id\tcontents\n".to_owned(),
+      r#"{"format":"stash-archive","version":2,"exported_at":0}"#.to_owned(),
+      format!("{header}\n{entry}\nnot json\n"),
+      format!("{header}\n{}\n", entry.replace("null,\"last", "1,\"last")),
+    ] {
+      assert!(
+        matches!(
+          db.import_archive(archive.as_bytes()),
+          Err(StashError::Import(_))
+        ),
+        "{archive:?}"
+      );
+    }
+    let count: i64 = db
+      .conn
+      .query_row("SELECT COUNT(*) FROM clipboard", [], |row| row.get(0))
+      .expect("count");
+    assert_eq!(count, 1);
+  }
+
+  #[cfg(feature = "encryption")]
+  #[test]
+  fn test_export_reports_entries_it_cannot_decrypt() {
+    let db = test_db();
+    store(&db, b"kept");
+    let mut unreadable = EntryEncoding::AGE_HEADER.to_vec();
+    unreadable.extend_from_slice(b"not a real age file");
+    db.conn
+      .execute("INSERT INTO clipboard (contents) VALUES (?1)", params![
+        unreadable
+      ])
+      .expect("insert");
+    let lost = db.conn.last_insert_rowid();
+
+    let mut archive = Vec::new();
+    assert_eq!(
+      db.export_archive(&mut archive).expect("export"),
+      ArchiveExport {
+        exported: 1,
+        skipped:  vec![lost],
+      }
+    );
+    let target = test_db();
+    target.import_archive(&archive[..]).expect("import");
+    assert_eq!(target.entry(1).expect("entry").contents, b"kept");
+  }
+
+  #[cfg(feature = "encryption")]
+  #[test]
+  fn test_encrypted_archive() {
+    use age::secrecy::SecretString;
+
+    let source = test_db();
+    store(&source, b"secret history");
+
+    let mut archive = Vec::new();
+    source
+      .export_encrypted_archive(
+        &mut archive,
+        ArchiveKey::Passphrase(SecretString::from("correct horse".to_owned())),
+      )
+      .expect("export");
+    assert!(archive.starts_with(AGE_MAGIC));
+    assert!(!archive.windows(6).any(|w| w == b"secret"));
+
+    let target = test_db();
+    let wrong = target.import_encrypted_archive(
+      &archive[..],
+      SecretString::from("wrong".to_owned()),
+    );
+    assert!(matches!(wrong, Err(StashError::Decryption(_))));
+    let result = target
+      .import_encrypted_archive(
+        &archive[..],
+        SecretString::from("correct horse".to_owned()),
+      )
+      .expect("import");
+    assert_eq!(result, ArchiveImport {
+      imported: 1,
+      merged:   0,
+    });
+  }
+}
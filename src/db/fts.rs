@@ -0,0 +1,430 @@
+//! Full-text search index over clipboard history.
+//!
+//! Entries whose contents are valid UTF-8, whatever their MIME type, are
+//! mirrored into the `clipboard_fts` FTS5 table (keyed by the `clipboard` row
+//! id) using the trigram tokenizer, so arbitrary
+//! substrings can be looked up through the index instead of scanning and
+//! casting every blob. Deleting a `clipboard` row removes its index entry via
+//! the `clipboard_fts_delete` trigger, which covers every delete path (trim,
+//! expiry cleanup, query deletes, the TUI and wipes) without each of them
+//! having to remember it.
+//!
+//! Encrypted entries are only indexed when [`index_encrypted_entries`] opts in,
+//! since the index necessarily holds their plaintext.
+//!
+//! Fuzzy and regex searches of `stash list` scan the indexed text with SQL
+//! functions registered on every connection, see [`register_functions`].
+
+use std::{env, error::Error};
+
+use regex::Regex;
+use rusqlite::{Connection, functions::FunctionFlags, params};
+use serde::{Deserialize, Serialize};
+
+use super::{EntryEncoding, SqliteClipboardDb, StashError};
+use crate::{config, fuzzy::Pattern};
+
+/// Search a trigram index with a phrase query once the query is long enough
+/// to form a trigram; shorter queries fall back to a `LIKE` scan over the
+/// (already plaintext) index contents.
+const MIN_TRIGRAM_CHARS: usize = 3;
+
+/// How a search query is matched against the index.
+pub(super) enum SearchPattern {
+  /// FTS5 phrase query. With the trigram tokenizer a phrase matches any
+  /// case-insensitive substring of the indexed text.
+  Phrase(String),
+
+  /// Escaped `LIKE` pattern for queries shorter than a trigram.
+  Like(String),
+
+  /// Fuzzy pattern, scored by the `fuzzy_score` function.
+  Fuzzy(String),
+
+  /// Regular expression, matched by the `regexp` function.
+  Regex(String),
+}
+
+impl SearchPattern {
+  pub(super) fn new(query: &str) -> Self {
+    if query.chars().count() >= MIN_TRIGRAM_CHARS {
+      Self::Phrase(format!("\"{}\"", query.replace('"', "\"\"")))
+    } else {
+      Self::Like(format!("%{}%", like_escape(query)))
+    }
+  }
+
+  pub(super) fn with_mode(mode: SearchMode, text: &str) -> Self {
+    match mode {
+      SearchMode::Substring => Self::new(text),
+      SearchMode::Fuzzy => Self::Fuzzy(text.to_owned()),
+      SearchMode::Regex => Self::Regex(text.to_owned()),
+    }
+  }
+
+  /// Subquery selecting `rowid, body, rank` for every indexed entry that
+  /// matches, with the pattern bound to `?1`.
+  pub(super) fn matching_rows(&self) -> &'static str {
+    match self {
+      Self::Phrase(_) => {
+        "SELECT rowid, body, rank FROM clipboard_fts WHERE clipboard_fts MATCH \
+         ?1"
+      },
+      Self::Like(_) => {
+        "SELECT rowid, body, 0 AS rank FROM clipboard_fts WHERE body LIKE ?1 \
+         ESCAPE '!'"
+      },
+      Self::Fuzzy(_) => {
+        "SELECT rowid, body, -fuzzy_score(?1, body) AS rank FROM clipboard_fts \
+         WHERE fuzzy_score(?1, body) IS NOT NULL"
+      },
+      Self::Regex(_) => {
+        "SELECT rowid, body, 0 AS rank FROM clipboard_fts WHERE body REGEXP ?1"
+      },
+    }
+  }
+
+  /// Expression ranking the `clipboard` row of a listing by how well it
+  /// matches, best first, for patterns whose matches are not all equal.
+  pub(super) fn rank_order(&self) -> Option<&'static str> {
+    match self {
+      Self::Fuzzy(_) => {
+        Some(
+          "(SELECT fuzzy_score(?1, body) FROM clipboard_fts WHERE rowid = \
+           clipboard.id) DESC",
+        )
+      },
+      Self::Phrase(_) | Self::Like(_) | Self::Regex(_) => None,
+    }
+  }
+
+  pub(super) fn param(&self) -> &str {
+    match self {
+      Self::Phrase(p) | Self::Like(p) | Self::Fuzzy(p) | Self::Regex(p) => p,
+    }
+  }
+}
+
+/// Escape `LIKE` wildcards in `text`, with `!` as the escape character.
+pub(super) fn like_escape(text: &str) -> String {
+  text
+    .replace('!', "!!")
+    .replace('%', "!%")
+    .replace('_', "!_")
+}
+
+/// How the text of a `stash list` search is matched.
+#[derive(
+  Debug,
+  Clone,
+  Copy,
+  PartialEq,
+  Eq,
+  Default,
+  clap::ValueEnum,
+  Serialize,
+  Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+  /// Case-insensitive substring.
+  #[default]
+  Substring,
+
+  /// fzf-style fuzzy matching, best matches first.
+  Fuzzy,
+
+  /// Regular expression, in the syntax of the `regex` crate.
+  Regex,
+}
+
+impl SearchMode {
+  pub const fn as_str(self) -> &'static str {
+    match self {
+      Self::Substring => "substring",
+      Self::Fuzzy => "fuzzy",
+      Self::Regex => "regex",
+    }
+  }
+
+  /// The mode after this one, for cycling through them in the TUI.
+  pub const fn next(self) -> Self {
+    match self {
+      Self::Substring => Self::Fuzzy,
+      Self::Fuzzy => Self::Regex,
+      Self::Regex => Self::Substring,
+    }
+  }
+}
+
+/// A parsed `stash list` search.
+///
+/// Terms of the form `mime:<type>` restrict the MIME type instead of being
+/// searched for: an entry passes when its type contains any of them, so
+/// `mime:image` lists images and `mime:json` JSON. The rest of the query is
+/// matched against the indexed text of entries according to `mode`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchQuery {
+  pub mode:  SearchMode,
+  pub text:  String,
+  #[serde(default)]
+  pub mimes: Vec<String>,
+}
+
+impl SearchQuery {
+  /// Parse `query`. Returns `None` if it does not filter anything, and fails
+  /// if it is not a valid regular expression in regex mode.
+  pub fn parse(
+    mode: SearchMode,
+    query: &str,
+  ) -> Result<Option<Self>, StashError> {
+    let mut mimes = Vec::new();
+    let mut terms = Vec::new();
+    let mut stripped = false;
+    for term in query.split(' ') {
+      match term.get(..5) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("mime:") => {
+          stripped = true;
+          if term.len() > 5 {
+            mimes.push(term[5..].to_ascii_lowercase());
+          }
+        },
+        _ => terms.push(term),
+      }
+    }
+    // Spaces are significant to substring and regex searches, except those
+    // left around the `mime:` terms.
+    let mut text = terms.join(" ");
+    if stripped {
+      text = text.trim().to_owned();
+    }
+
+    if mode == SearchMode::Regex
+      && let Err(e) = Regex::new(&text)
+    {
+      // Keep the gist of multi-line syntax errors for the TUI search bar.
+      let e = e.to_string();
+      let reason = e.lines().last().unwrap_or_default();
+      return Err(StashError::Search(
+        reason.trim_start_matches("error: ").into(),
+      ));
+    }
+
+    if text.is_empty() && mimes.is_empty() {
+      return Ok(None);
+    }
+    Ok(Some(Self { mode, text, mimes }))
+  }
+}
+
+/// Register the functions behind the regex and fuzzy search modes on `conn`:
+/// `regexp(pattern, text)`, which SQLite calls for `text REGEXP pattern`, and
+/// `fuzzy_score(pattern, text)`, the score of the best fuzzy match of
+/// `pattern` in `text`, or `NULL` if there is none. Patterns are compiled
+/// once per statement.
+pub(super) fn register_functions(conn: &Connection) -> rusqlite::Result<()> {
+  let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+  conn.create_scalar_function("regexp", 2, flags, |ctx| {
+    let regex = ctx.get_or_create_aux(0, |pattern| {
+      Ok::<_, Box<dyn Error + Send + Sync>>(Regex::new(pattern.as_str()?)?)
+    })?;
+    Ok(
+      ctx
+        .get_raw(1)
+        .as_str_or_null()?
+        .map(|text| regex.is_match(text)),
+    )
+  })?;
+  conn.create_scalar_function("fuzzy_score", 2, flags, |ctx| {
+    let pattern = ctx.get_or_create_aux(0, |pattern| {
+      Ok::<_, Box<dyn Error + Send + Sync>>(Pattern::new(pattern.as_str()?))
+    })?;
+    Ok(ctx.get_raw(1).as_str_or_null()?.and_then(|text| {
+      let text: Vec<char> = text.chars().collect();
+      pattern.score(&text).map(|(score, _)| score)
+    }))
+  })
+}
+
+/// A single search hit. `body` is the indexed plaintext, so callers can
+/// render previews without touching (or decrypting) the stored blob.
+pub struct SearchHit {
+  pub id:   i64,
+  pub body: String,
+  pub mime: Option<String>,
+}
+
+/// Whether plaintext of encrypted entries may be written to the search index.
+///
+/// Controlled by `STASH_SEARCH_INDEX_ENCRYPTED`, or `search_index_encrypted`
+/// in the config file (`1`, `true`, `yes` or `on`). Off by default: with it
+/// enabled, anyone who can read the database file can read the indexed text of
+/// encrypted entries. Run `stash db reindex` after changing it to add or purge
+/// the plaintext of existing entries.
+pub fn index_encrypted_entries() -> bool {
+  env::var("STASH_SEARCH_INDEX_ENCRYPTED")
+    .ok()
+    .or_else(|| config::file_setting("STASH_SEARCH_INDEX_ENCRYPTED"))
+    .is_some_and(|v| {
+      matches!(
+        v.trim().to_ascii_lowercase().as_str(),
+        "1" | "true" | "yes" | "on"
+      )
+    })
+}
+
+fn index_err(e: rusqlite::Error) -> StashError {
+  StashError::SearchIndex(e.to_string().into())
+}
+
+/// Index the plaintext entries that are valid UTF-8 and not indexed yet.
+/// Encrypted ones need the key to index, and are picked up by
+/// `stash db reindex` when opted in.
+pub(super) fn backfill_index(conn: &Connection) -> rusqlite::Result<()> {
+  let mut stmt = conn.prepare(
+    "SELECT id, contents FROM clipboard WHERE contents NOT GLOB \
+     'age-encryption.org/v1' || char(10) || '*' AND id NOT IN (SELECT rowid \
+     FROM clipboard_fts)",
+  )?;
+  let mut rows = stmt.query([])?;
+  while let Some(row) = rows.next()? {
+    let id: i64 = row.get(0)?;
+    let contents: Vec<u8> = row.get(1)?;
+    if let Ok(body) = std::str::from_utf8(&contents) {
+      conn.execute(
+        "INSERT INTO clipboard_fts (rowid, body) VALUES (?1, ?2)",
+        params![id, body],
+      )?;
+    }
+  }
+  Ok(())
+}
+
+impl SqliteClipboardDb {
+  /// Replace the index entry for `id` with `plaintext`.
+  ///
+  /// Invalid UTF-8 and encrypted entries (unless opted in) are left out of
+  /// the index; any previous index entry is still removed so changed contents
+  /// cannot leave stale text behind.
+  pub(super) fn index_entry(
+    &self,
+    id: i64,
+    plaintext: &[u8],
+    encrypted: bool,
+  ) -> Result<(), StashError> {
+    self
+      .conn
+      .execute("DELETE FROM clipboard_fts WHERE rowid = ?1", params![id])
+      .map_err(index_err)?;
+
+    if encrypted && !index_encrypted_entries() {
+      return Ok(());
+    }
+    let Ok(body) = std::str::from_utf8(plaintext) else {
+      return Ok(());
+    };
+
+    self
+      .conn
+      .execute(
+        "INSERT INTO clipboard_fts (rowid, body) VALUES (?1, ?2)",
+        params![id, body],
+      )
+      .map_err(index_err)?;
+    Ok(())
+  }
+
+  /// Rebuild the search index from scratch, decrypting entries as needed.
+  ///
+  /// Returns the number of indexed entries. Entries that cannot be decrypted
+  /// are skipped with a warning.
+  pub fn reindex(&self) -> Result<usize, StashError> {
+    let tx = self.conn.unchecked_transaction().map_err(index_err)?;
+    tx.execute("DELETE FROM clipboard_fts", [])
+      .map_err(index_err)?;
+
+    let mut stmt = tx
+      .prepare("SELECT id, contents FROM clipboard")
+      .map_err(index_err)?;
+    let mut rows = stmt.query([]).map_err(index_err)?;
+    while let Some(row) = rows.next().map_err(index_err)? {
+      let id: i64 = row.get(0).map_err(index_err)?;
+      let contents: Vec<u8> = row.get(1).map_err(index_err)?;
+
+      let encoding = EntryEncoding::classify(contents);
+      let encrypted = encoding.is_encrypted();
+      if encrypted && !index_encrypted_entries() {
+        continue;
+      }
+      match encoding.decode() {
+        Ok(plaintext) => {
+          self.index_entry(id, &plaintext, encrypted)?;
+        },
+        Err(e) => log::warn!("not indexing entry {id}: {e}"),
+      }
+    }
+    drop(rows);
+    drop(stmt);
+
+    // Merge the index into a single b-tree so removed text does not linger
+    // in old segments.
+    tx.execute(
+      "INSERT INTO clipboard_fts (clipboard_fts) VALUES ('optimize')",
+      [],
+    )
+    .map_err(index_err)?;
+    let indexed = self.indexed_count()?;
+    tx.commit().map_err(index_err)?;
+    Ok(indexed)
+  }
+
+  /// Number of entries currently present in the search index.
+  pub fn indexed_count(&self) -> Result<usize, StashError> {
+    let count: i64 = self
+      .conn
+      .query_row("SELECT COUNT(*) FROM clipboard_fts", [], |r| r.get(0))
+      .map_err(index_err)?;
+    Ok(count.max(0) as usize)
+  }
+
+  /// Search indexed entries, best matches first.
+  ///
+  /// Phrase queries are ranked by BM25 relevance; queries too short for the
+  /// trigram index are ordered by recency, like `stash list`. Ties fall back
+  /// to recency as well.
+  pub fn search_entries(
+    &self,
+    query: &str,
+    limit: usize,
+    include_expired: bool,
+  ) -> Result<Vec<SearchHit>, StashError> {
+    let pattern = SearchPattern::new(query);
+    let expired_filter = if include_expired {
+      ""
+    } else {
+      "WHERE (c.is_expired IS NULL OR c.is_expired = 0)"
+    };
+    let sql = format!(
+      "SELECT c.id, f.body, c.mime FROM ({}) AS f JOIN clipboard c ON c.id = \
+       f.rowid {expired_filter} ORDER BY f.rank, COALESCE(c.last_accessed, 0) \
+       DESC, c.id DESC LIMIT ?2",
+      pattern.matching_rows()
+    );
+
+    let mut stmt = self.conn.prepare(&sql).map_err(index_err)?;
+    stmt
+      .query_map(
+        params![pattern.param(), i64::try_from(limit).unwrap_or(i64::MAX)],
+        |row| {
+          Ok(SearchHit {
+            id:   row.get(0)?,
+            body: row.get(1)?,
+            mime: row.get(2)?,
+          })
+        },
+      )
+      .map_err(index_err)?
+      .collect::<Result<_, _>>()
+      .map_err(index_err)
+  }
+}
@@ -2,7 +2,10 @@ use std::path::PathBuf;
 
 use rusqlite::OptionalExtension;
 
-use crate::db::{ClipboardDb, SqliteClipboardDb, StashError};
+use crate::{
+  clipboard::Selection,
+  db::{Representation, SqliteClipboardDb, StashError},
+};
 
 /// Async wrapper for database operations that runs blocking operations
 /// on a thread pool to avoid blocking the async runtime. Since
@@ -30,11 +33,13 @@ impl AsyncClipboardDb {
     content_hash: Option<i64>,
     mime_types: Option<Vec<String>>,
     selected_mime: Option<String>,
+    representations: Vec<Representation>,
+    selection: Selection,
   ) -> Result<i64, StashError> {
     let path = self.db_path.clone();
     blocking::unblock(move || {
       let db = Self::open_db_internal(&path)?;
-      db.store_entry(
+      db.store_offer(
         std::io::Cursor::new(data),
         max_dedupe_search,
         max_items,
@@ -44,6 +49,8 @@ impl AsyncClipboardDb {
         content_hash,
         mime_types.as_deref(),
         selected_mime.as_deref(),
+        &representations,
+        selection,
       )
     })
     .await
@@ -62,6 +69,31 @@ impl AsyncClipboardDb {
     .await
   }
 
+  /// Run `f` against a fresh connection on the blocking thread pool.
+  pub async fn with_db<T: Send + 'static>(
+    &self,
+    f: impl FnOnce(&SqliteClipboardDb) -> Result<T, StashError> + Send + 'static,
+  ) -> Result<T, StashError> {
+    let path = self.db_path.clone();
+    blocking::unblock(move || {
+      let db = Self::open_db_internal(&path)?;
+      f(&db)
+    })
+    .await
+  }
+
+  pub async fn applied_rule(
+    &self,
+    id: i64,
+  ) -> Result<Option<(String, Option<f64>)>, StashError> {
+    let path = self.db_path.clone();
+    blocking::unblock(move || {
+      let db = Self::open_db_internal(&path)?;
+      db.applied_rule(id)
+    })
+    .await
+  }
+
   pub async fn load_all_expirations(
     &self,
   ) -> Result<Vec<(f64, i64)>, StashError> {
@@ -72,7 +104,8 @@ impl AsyncClipboardDb {
         .conn
         .prepare(
           "SELECT expires_at, id FROM clipboard WHERE expires_at IS NOT NULL \
-           AND (is_expired IS NULL OR is_expired = 0) ORDER BY expires_at ASC",
+           AND (is_expired IS NULL OR is_expired = 0) AND pinned = 0 ORDER BY \
+           expires_at ASC",
         )
         .map_err(|e| StashError::ListDecode(e.to_string().into()))?;
       stmt
@@ -105,14 +138,20 @@ impl AsyncClipboardDb {
     .await
   }
 
-  pub async fn mark_expired(&self, id: i64) -> Result<(), StashError> {
+  /// Mark an entry as expired. Returns `false` if the entry was pinned (or
+  /// no longer exists) and was therefore left alone.
+  pub async fn mark_expired(&self, id: i64) -> Result<bool, StashError> {
     let path = self.db_path.clone();
     blocking::unblock(move || {
       let db = Self::open_db_internal(&path)?;
-      db.conn
-        .execute("UPDATE clipboard SET is_expired = 1 WHERE id = ?1", [id])
+      let updated = db
+        .conn
+        .execute(
+          "UPDATE clipboard SET is_expired = 1 WHERE id = ?1 AND pinned = 0",
+          [id],
+        )
         .map_err(|e| StashError::Store(e.to_string().into()))?;
-      Ok(())
+      Ok(updated > 0)
     })
     .await
   }
@@ -167,6 +206,8 @@ mod tests {
           None,
           None,
           None,
+          Vec::new(),
+          Selection::Regular,
         )
         .await
         .expect("Failed to store entry");
@@ -206,6 +247,8 @@ mod tests {
           None,
           None,
           None,
+          Vec::new(),
+          Selection::Regular,
         )
         .await
         .expect("Failed to store entry");
@@ -248,6 +291,8 @@ mod tests {
           None,
           None,
           None,
+          Vec::new(),
+          Selection::Regular,
         )
         .await
         .expect("Failed to store entry");
@@ -305,6 +350,8 @@ mod tests {
           None,
           None,
           None,
+          Vec::new(),
+          Selection::Regular,
         )
         .await
         .expect("Failed with original");
@@ -320,6 +367,8 @@ mod tests {
           None,
           None,
           None,
+          Vec::new(),
+          Selection::Regular,
         )
         .await
         .expect("Failed with clone");
@@ -360,7 +409,17 @@ mod tests {
           let data = format!("concurrent test {}", i).into_bytes();
           smol::spawn(async move {
             db.store_entry(
-              data, 100, 1000, None, None, 5_000_000, None, None, None,
+              data,
+              100,
+              1000,
+              None,
+              None,
+              5_000_000,
+              None,
+              None,
+              None,
+              Vec::new(),
+              Selection::Regular,
             )
             .await
           })
@@ -0,0 +1,285 @@
+//! Re-encoding stored entries after encryption settings change.
+//!
+//! Entries are decrypted with whatever keys are currently configured (the
+//! same ones `stash list` and `stash decode` use) and written back with the
+//! target key, or as plaintext. Everything happens in one transaction, so an
+//! entry that cannot be decrypted aborts the run without leaving the history
+//! half re-encoded.
+//!
+//! Entries stored by an `encrypt` rule with recipients of its own are left
+//! alone: they are encrypted to the rule's recipients rather than the
+//! configured key, and usually cannot be decrypted here anyway.
+
+use age::secrecy::{ExposeSecret, SecretString};
+use rusqlite::params;
+
+use super::{
+  Action,
+  EncryptionKey,
+  EntryEncoding,
+  SqliteClipboardDb,
+  StashError,
+  parse_recipients,
+};
+
+/// What `stash db rekey` should do with the stored entries.
+pub enum RekeyMode {
+  /// Encrypt plaintext entries with the configured key. Entries that are
+  /// already encrypted are left as they are.
+  EncryptExisting,
+
+  /// Decrypt every encrypted entry and store it as plaintext.
+  ToPlaintext,
+
+  /// Re-encrypt every entry with a new passphrase.
+  Passphrase(SecretString),
+
+  /// Re-encrypt every entry to a new recipient list, in the same format as
+  /// `STASH_RECIPIENTS`.
+  Recipients(String),
+}
+
+/// Which entries a rekey rewrites.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Rewrite {
+  All,
+  Plaintext,
+  Encrypted,
+}
+
+impl Rewrite {
+  /// SQL condition on the `contents` column selecting the rows to rewrite.
+  fn condition(self) -> &'static str {
+    match self {
+      Self::All => "1",
+      Self::Plaintext => {
+        "contents NOT GLOB 'age-encryption.org/v1' || char(10) || '*'"
+      },
+      Self::Encrypted => {
+        "contents GLOB 'age-encryption.org/v1' || char(10) || '*'"
+      },
+    }
+  }
+}
+
+fn rekey_err(e: impl ToString) -> StashError {
+  StashError::Rekey(e.to_string().into())
+}
+
+impl SqliteClipboardDb {
+  /// Re-encode stored entries according to `mode`.
+  ///
+  /// `progress` is called after each entry with the number of entries
+  /// processed so far and the total. Returns the number of rewritten entries.
+  pub fn rekey(
+    &self,
+    mode: RekeyMode,
+    progress: impl FnMut(usize, usize),
+  ) -> Result<usize, StashError> {
+    let recipients;
+    let (target, rewrite) = match mode {
+      RekeyMode::EncryptExisting => {
+        let key = EncryptionKey::configured()?.ok_or_else(|| {
+          rekey_err("no passphrase or recipients configured to encrypt with")
+        })?;
+        (Some(key), Rewrite::Plaintext)
+      },
+      RekeyMode::ToPlaintext => (None, Rewrite::Encrypted),
+      RekeyMode::Passphrase(passphrase) => {
+        if passphrase.expose_secret().is_empty() {
+          return Err(rekey_err("the new passphrase is empty"));
+        }
+        (Some(EncryptionKey::Passphrase(passphrase)), Rewrite::All)
+      },
+      RekeyMode::Recipients(list) => {
+        recipients = parse_recipients(&list).map_err(rekey_err)?;
+        if recipients.is_empty() {
+          return Err(rekey_err("no recipients given"));
+        }
+        (Some(EncryptionKey::Recipients(&recipients)), Rewrite::All)
+      },
+    };
+    self.rekey_to(target.as_ref(), rewrite, progress)
+  }
+
+  /// Re-encode the entries `rewrite` selects with `target`, or as plaintext.
+  pub(super) fn rekey_to(
+    &self,
+    target: Option<&EncryptionKey>,
+    rewrite: Rewrite,
+    progress: impl FnMut(usize, usize),
+  ) -> Result<usize, StashError> {
+    let secure_delete: i64 = self
+      .conn
+      .pragma_query_value(None, "secure_delete", |row| row.get(0))
+      .map_err(rekey_err)?;
+
+    // Overwrite the old contents on disk instead of only unlinking them, so
+    // plaintext (or ciphertext under the old key) does not linger in free
+    // pages.
+    self
+      .conn
+      .pragma_update(None, "secure_delete", true)
+      .map_err(rekey_err)?;
+    let rekeyed = self.rewrite_entries(target, rewrite, progress);
+    // Put the setting back even if the rekey failed.
+    let restored = self
+      .conn
+      .pragma_update(None, "secure_delete", secure_delete)
+      .map_err(rekey_err);
+    let rekeyed = rekeyed?;
+    restored?;
+    Ok(rekeyed)
+  }
+
+  /// Names of the `encrypt` rules with recipients of their own, as a JSON
+  /// array. Their entries are not rekeyed.
+  fn rules_with_recipients(&self) -> Result<String, StashError> {
+    let names: Vec<&str> = self
+      .rules
+      .iter()
+      .filter(|rule| rule.action == Action::Encrypt)
+      .filter(|rule| rule.recipients().is_some())
+      .map(|rule| rule.name.as_str())
+      .collect();
+    serde_json::to_string(&names).map_err(rekey_err)
+  }
+
+  fn rewrite_entries(
+    &self,
+    target: Option<&EncryptionKey>,
+    rewrite: Rewrite,
+    mut progress: impl FnMut(usize, usize),
+  ) -> Result<usize, StashError> {
+    let condition = rewrite.condition();
+    let rules = self.rules_with_recipients()?;
+    let tx = self.conn.unchecked_transaction().map_err(rekey_err)?;
+    let skipped: i64 = tx
+      .query_row(
+        &format!(
+          "SELECT COUNT(*) FROM clipboard WHERE {condition} AND rule IN \
+           (SELECT value FROM json_each(?1))"
+        ),
+        params![rules],
+        |row| row.get(0),
+      )
+      .map_err(rekey_err)?;
+    if skipped > 0 {
+      log::info!(
+        "leaving {skipped} entries encrypted to the recipients of their rule"
+      );
+    }
+    let ids: Vec<i64> = tx
+      .prepare(&format!(
+        "SELECT id FROM clipboard WHERE {condition} AND (rule IS NULL OR rule \
+         NOT IN (SELECT value FROM json_each(?1))) ORDER BY id"
+      ))
+      .and_then(|mut stmt| {
+        stmt
+          .query_map(params![rules], |row| row.get(0))?
+          .collect::<Result<_, _>>()
+      })
+      .map_err(rekey_err)?;
+
+    let total = ids.len();
+    for (done, id) in ids.into_iter().enumerate() {
+      let contents: Vec<u8> = tx
+        .query_row(
+          "SELECT contents FROM clipboard WHERE id = ?1",
+          params![id],
+          |row| row.get(0),
+        )
+        .map_err(rekey_err)?;
+
+      let plaintext = EntryEncoding::classify(contents)
+        .decode()
+        .map_err(|e| rekey_err(format!("entry {id}: {e}")))?;
+      let encoding = match target {
+        Some(key) => EntryEncoding::encrypt(&plaintext, key)?,
+        None => EntryEncoding::Plain(plaintext.clone()),
+      };
+      let encrypted = encoding.is_encrypted();
+
+      tx.execute("UPDATE clipboard SET contents = ?1 WHERE id = ?2", params![
+        encoding.into_raw(),
+        id
+      ])
+      .map_err(rekey_err)?;
+      self.index_entry(id, &plaintext, encrypted)?;
+
+      progress(done + 1, total);
+    }
+
+    // Additional MIME representations are encoded independently of their
+    // entry, so they get the same treatment in a separate pass.
+    let representations: Vec<(i64, String)> = tx
+      .prepare(&format!(
+        "SELECT entry_id, mime FROM clipboard_mime WHERE {condition} AND \
+         entry_id NOT IN (SELECT id FROM clipboard WHERE rule IN (SELECT \
+         value FROM json_each(?1)))"
+      ))
+      .and_then(|mut stmt| {
+        stmt
+          .query_map(params![rules], |row| Ok((row.get(0)?, row.get(1)?)))?
+          .collect::<Result<_, _>>()
+      })
+      .map_err(rekey_err)?;
+    for (id, mime) in representations {
+      let contents: Vec<u8> = tx
+        .query_row(
+          "SELECT contents FROM clipboard_mime WHERE entry_id = ?1 AND mime = \
+           ?2",
+          params![id, mime],
+          |row| row.get(0),
+        )
+        .map_err(rekey_err)?;
+      let plaintext = EntryEncoding::classify(contents)
+        .decode()
+        .map_err(|e| rekey_err(format!("entry {id} ({mime}): {e}")))?;
+      let encoding = match target {
+        Some(key) => EntryEncoding::encrypt(&plaintext, key)?,
+        None => EntryEncoding::Plain(plaintext),
+      };
+      tx.execute(
+        "UPDATE clipboard_mime SET contents = ?1 WHERE entry_id = ?2 AND mime \
+         = ?3",
+        params![encoding.into_raw(), id, mime],
+      )
+      .map_err(rekey_err)?;
+    }
+
+    // Snippets are encoded like entries too.
+    let snippets: Vec<i64> = tx
+      .prepare(&format!("SELECT id FROM snippets WHERE {condition}"))
+      .and_then(|mut stmt| {
+        stmt
+          .query_map([], |row| row.get(0))?
+          .collect::<Result<_, _>>()
+      })
+      .map_err(rekey_err)?;
+    for id in snippets {
+      let (name, contents): (String, Vec<u8>) = tx
+        .query_row(
+          "SELECT name, contents FROM snippets WHERE id = ?1",
+          params![id],
+          |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .map_err(rekey_err)?;
+      let plaintext = EntryEncoding::classify(contents)
+        .decode()
+        .map_err(|e| rekey_err(format!("snippet `{name}`: {e}")))?;
+      let encoding = match target {
+        Some(key) => EntryEncoding::encrypt(&plaintext, key)?,
+        None => EntryEncoding::Plain(plaintext),
+      };
+      tx.execute("UPDATE snippets SET contents = ?1 WHERE id = ?2", params![
+        encoding.into_raw(),
+        id
+      ])
+      .map_err(rekey_err)?;
+    }
+
+    tx.commit().map_err(rekey_err)?;
+    Ok(total)
+  }
+}
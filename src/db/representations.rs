@@ -0,0 +1,90 @@
+//! Additional MIME representations of clipboard entries.
+//!
+//! Applications usually offer a copy in several formats at once (e.g.
+//! `text/html` next to `text/plain`, or `image/png` with a `text/uri-list`).
+//! The `clipboard` row holds the one type picked for display and search; the
+//! others are kept in `clipboard_mime` so that re-copying an entry offers the
+//! same set of types as the original. Rows are removed together with their
+//! entry by the `clipboard_mime_delete` trigger.
+
+use rusqlite::params;
+
+use super::{EntryEncoding, SqliteClipboardDb, StashError};
+
+/// One additional MIME type offered for an entry, with its contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Representation {
+  pub mime:     String,
+  pub contents: Vec<u8>,
+}
+
+fn representation_err(e: impl ToString) -> StashError {
+  StashError::Representation(e.to_string().into())
+}
+
+impl SqliteClipboardDb {
+  /// Replace the additional representations stored for entry `id`, encoded
+  /// by `encode` the way the entry's contents were.
+  ///
+  /// Offer order is preserved. The representations are replaced all at once
+  /// or not at all, also within a transaction such as that of an import.
+  pub(super) fn store_representations(
+    &self,
+    id: i64,
+    representations: &[Representation],
+    encode: impl Fn(&[u8]) -> Result<EntryEncoding, StashError>,
+  ) -> Result<(), StashError> {
+    self.atomically(|| {
+      self
+        .conn
+        .execute("DELETE FROM clipboard_mime WHERE entry_id = ?1", params![
+          id
+        ])
+        .map_err(representation_err)?;
+      for (position, rep) in representations.iter().enumerate() {
+        let encoding = encode(&rep.contents)?;
+        self
+          .conn
+          .execute(
+            "INSERT OR REPLACE INTO clipboard_mime (entry_id, position, mime, \
+             contents) VALUES (?1, ?2, ?3, ?4)",
+            params![id, position as i64, rep.mime, encoding.into_raw()],
+          )
+          .map_err(representation_err)?;
+      }
+      Ok(())
+    })
+  }
+
+  /// Load the additional representations of entry `id`, in offer order.
+  ///
+  /// Representations that cannot be decrypted are skipped with a warning, so
+  /// the entry itself can still be copied.
+  pub fn representations(
+    &self,
+    id: i64,
+  ) -> Result<Vec<Representation>, StashError> {
+    let mut stmt = self
+      .conn
+      .prepare(
+        "SELECT mime, contents FROM clipboard_mime WHERE entry_id = ?1 ORDER \
+         BY position",
+      )
+      .map_err(representation_err)?;
+    let rows = stmt
+      .query_map(params![id], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+      })
+      .map_err(representation_err)?;
+
+    let mut representations = Vec::new();
+    for row in rows {
+      let (mime, contents) = row.map_err(representation_err)?;
+      match EntryEncoding::classify(contents).decode() {
+        Ok(contents) => representations.push(Representation { mime, contents }),
+        Err(e) => log::warn!("skipping {mime} representation of {id}: {e}"),
+      }
+    }
+    Ok(representations)
+  }
+}
@@ -0,0 +1,250 @@
+//! Snippets: named contents kept for reuse, apart from the clipboard history.
+//!
+//! Snippets live in their own table, so trimming, expiry and `stash db wipe`
+//! never touch them. Their contents are encrypted the same way as entries;
+//! names and tags are stored as plain text so they can be listed and matched
+//! without a key. Tags are kept as a JSON array.
+
+use rusqlite::{OptionalExtension, params};
+
+use super::{EntryEncoding, SqliteClipboardDb, StashError};
+
+/// A saved snippet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+  pub id:       i64,
+  pub name:     String,
+  pub contents: Vec<u8>,
+  pub tags:     Vec<String>,
+}
+
+fn snippet_err(e: impl ToString) -> StashError {
+  StashError::Snippet(e.to_string().into())
+}
+
+/// Trim `tags`, dropping empty and repeated ones.
+fn normalize_tags(tags: &[String]) -> Vec<String> {
+  let mut normalized: Vec<String> = Vec::with_capacity(tags.len());
+  for tag in tags.iter().map(|tag| tag.trim()) {
+    if !tag.is_empty() && !normalized.iter().any(|t| t == tag) {
+      normalized.push(tag.to_owned());
+    }
+  }
+  normalized
+}
+
+impl SqliteClipboardDb {
+  /// Save a snippet under `name`. An existing snippet of that name is only
+  /// replaced, keeping its id, when `replace` is set.
+  pub fn add_snippet(
+    &self,
+    name: &str,
+    contents: &[u8],
+    tags: &[String],
+    replace: bool,
+  ) -> Result<i64, StashError> {
+    let name = name.trim();
+    if name.is_empty() || name.contains(['\t', '\n', '\r']) {
+      return Err(snippet_err(
+        "names must not be empty or contain tabs or line breaks",
+      ));
+    }
+    if contents.is_empty() {
+      return Err(snippet_err("snippets must not be empty"));
+    }
+    if !replace && self.snippet_id(name)?.is_some() {
+      return Err(StashError::SnippetExists(name.into()));
+    }
+
+    let tags =
+      serde_json::to_string(&normalize_tags(tags)).map_err(snippet_err)?;
+    let encoding = EntryEncoding::encode(contents)?;
+    self
+      .conn
+      .execute(
+        "INSERT INTO snippets (name, contents, tags, created) VALUES (?1, ?2, \
+         ?3, ?4) ON CONFLICT(name) DO UPDATE SET contents = \
+         excluded.contents, tags = excluded.tags",
+        params![name, encoding.into_raw(), tags, Self::now() as i64],
+      )
+      .map_err(snippet_err)?;
+    self
+      .snippet_id(name)?
+      .ok_or_else(|| StashError::NoSuchSnippet(name.into()))
+  }
+
+  fn snippet_id(&self, name: &str) -> Result<Option<i64>, StashError> {
+    self
+      .conn
+      .query_row(
+        "SELECT id FROM snippets WHERE name = ?1",
+        params![name],
+        |row| row.get(0),
+      )
+      .optional()
+      .map_err(snippet_err)
+  }
+
+  /// All snippets, or those tagged `tag`, ordered by name.
+  ///
+  /// Snippets that cannot be decrypted are skipped with a warning, like
+  /// entries in `stash list`.
+  pub fn snippets(
+    &self,
+    tag: Option<&str>,
+  ) -> Result<Vec<Snippet>, StashError> {
+    let mut stmt = self
+      .conn
+      .prepare(
+        "SELECT id, name, contents, tags FROM snippets WHERE ?1 IS NULL OR \
+         EXISTS (SELECT 1 FROM json_each(snippets.tags) WHERE value = ?1) \
+         ORDER BY name",
+      )
+      .map_err(snippet_err)?;
+    let rows = stmt
+      .query_map(params![tag], |row| {
+        Ok((
+          row.get::<_, i64>(0)?,
+          row.get::<_, String>(1)?,
+          row.get::<_, Vec<u8>>(2)?,
+          row.get::<_, String>(3)?,
+        ))
+      })
+      .map_err(snippet_err)?;
+
+    let mut snippets = Vec::new();
+    for row in rows {
+      let (id, name, contents, tags) = row.map_err(snippet_err)?;
+      match EntryEncoding::classify(contents).decode() {
+        Ok(contents) => {
+          let tags = serde_json::from_str(&tags).map_err(snippet_err)?;
+          snippets.push(Snippet {
+            id,
+            name,
+            contents,
+            tags,
+          });
+        },
+        Err(e) => log::warn!("skipping snippet `{name}`: {e}"),
+      }
+    }
+    Ok(snippets)
+  }
+
+  /// The snippet named `name`.
+  pub fn snippet(&self, name: &str) -> Result<Snippet, StashError> {
+    let row: Option<(i64, Vec<u8>, String)> = self
+      .conn
+      .query_row(
+        "SELECT id, contents, tags FROM snippets WHERE name = ?1",
+        params![name],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+      )
+      .optional()
+      .map_err(snippet_err)?;
+    let Some((id, contents, tags)) = row else {
+      return Err(StashError::NoSuchSnippet(name.into()));
+    };
+    Ok(Snippet {
+      id,
+      name: name.to_owned(),
+      contents: EntryEncoding::classify(contents).decode()?,
+      tags: serde_json::from_str(&tags).map_err(snippet_err)?,
+    })
+  }
+
+  /// Delete the snippet named `name`.
+  pub fn remove_snippet(&self, name: &str) -> Result<(), StashError> {
+    let removed = self
+      .conn
+      .execute("DELETE FROM snippets WHERE name = ?1", params![name])
+      .map_err(snippet_err)?;
+    if removed == 0 {
+      return Err(StashError::NoSuchSnippet(name.into()));
+    }
+    Ok(())
+  }
+
+  /// Record that snippet `id` was just copied.
+  pub fn mark_snippet_used(&self, id: i64) -> Result<(), StashError> {
+    self
+      .conn
+      .execute("UPDATE snippets SET last_used = ?2 WHERE id = ?1", params![
+        id,
+        Self::now() as i64
+      ])
+      .map_err(snippet_err)?;
+    Ok(())
+  }
+
+  /// Today's date in the local time zone, as `YYYY-MM-DD`.
+  pub fn local_date(&self) -> Result<String, StashError> {
+    self
+      .conn
+      .query_row("SELECT date('now', 'localtime')", [], |row| row.get(0))
+      .map_err(snippet_err)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rusqlite::Connection;
+
+  use super::*;
+  use crate::db::ClipboardDb;
+
+  #[test]
+  fn test_snippets_survive_trim_and_wipe() {
+    let conn = Connection::open_in_memory().expect("open db");
+    let db = SqliteClipboardDb::new(conn, ":memory:".into()).expect("new db");
+    let tags = |tags: &[&str]| -> Vec<String> {
+      tags.iter().map(|&tag| tag.to_owned()).collect()
+    };
+
+    let sig = db
+      .add_snippet(" sig ", b"-- \nme", &tags(&["mail", " mail", ""]), false)
+      .expect("add");
+    db.add_snippet("addr", b"1 Main St", &tags(&["mail", "home"]), false)
+      .expect("add");
+    assert!(matches!(
+      db.add_snippet("sig", b"other", &[], false),
+      Err(StashError::SnippetExists(_))
+    ));
+    assert!(db.add_snippet("bad\tname", b"x", &[], false).is_err());
+    assert_eq!(
+      db.add_snippet("sig", b"-- \nyou", &tags(&["mail"]), true)
+        .expect("replace"),
+      sig
+    );
+
+    db.import_entry(b"entry").expect("entry");
+    db.trim_db(0).expect("trim");
+    db.wipe_db().expect("wipe");
+
+    let names = |tag| -> Vec<String> {
+      db.snippets(tag)
+        .expect("snippets")
+        .into_iter()
+        .map(|snippet| snippet.name)
+        .collect()
+    };
+    assert_eq!(names(None), ["addr", "sig"]);
+    assert_eq!(names(Some("home")), ["addr"]);
+    assert!(names(Some("work")).is_empty());
+
+    let snippet = db.snippet("sig").expect("snippet");
+    assert_eq!(snippet.contents, b"-- \nyou");
+    assert_eq!(snippet.tags, ["mail"]);
+
+    db.remove_snippet("sig").expect("remove");
+    assert!(matches!(
+      db.snippet("sig"),
+      Err(StashError::NoSuchSnippet(_))
+    ));
+    assert!(matches!(
+      db.remove_snippet("sig"),
+      Err(StashError::NoSuchSnippet(_))
+    ));
+    assert_eq!(db.local_date().expect("date").len(), 10);
+  }
+}
@@ -0,0 +1,259 @@
+//! Tags and notes: user metadata attached to clipboard entries.
+//!
+//! Tags are kept in `entry_tags`, one row per tag, and are dropped together
+//! with their entry. A note is a line of free text in the entry's `note`
+//! column. Both are stored as plain text, also when entries are encrypted, so
+//! that they can be listed and filtered on without a key.
+
+use rusqlite::{OptionalExtension, params};
+
+use super::{SqliteClipboardDb, StashError};
+
+fn tag_err(id: i64, e: impl ToString) -> StashError {
+  StashError::Tag(id, e.to_string().into())
+}
+
+/// Trim `tags`, dropping empty and repeated ones. Tags must not contain
+/// whitespace or commas, which separate them on the command line and in
+/// output.
+pub fn normalize_tags(tags: &[String]) -> Result<Vec<String>, String> {
+  let mut normalized: Vec<String> = Vec::with_capacity(tags.len());
+  for tag in tags.iter().map(|tag| tag.trim()) {
+    if tag.contains(|c: char| c.is_whitespace() || c == ',') {
+      return Err(format!("`{tag}` contains whitespace or a comma"));
+    }
+    if !tag.is_empty() && !normalized.iter().any(|t| t == tag) {
+      normalized.push(tag.to_owned());
+    }
+  }
+  Ok(normalized)
+}
+
+/// Split the comma-separated tags of a `group_concat(tag, ',')` column.
+pub(super) fn split_tags(tags: Option<String>) -> Vec<String> {
+  tags
+    .map(|tags| tags.split(',').map(str::to_owned).collect())
+    .unwrap_or_default()
+}
+
+impl SqliteClipboardDb {
+  fn entry_exists(&self, id: i64) -> rusqlite::Result<bool> {
+    self
+      .conn
+      .query_row("SELECT 1 FROM clipboard WHERE id = ?1", params![id], |_| {
+        Ok(())
+      })
+      .optional()
+      .map(|row| row.is_some())
+  }
+
+  /// Tag entry `id` with `tags`. Returns how many of them are new.
+  pub fn add_tags(
+    &self,
+    id: i64,
+    tags: &[String],
+  ) -> Result<usize, StashError> {
+    let tags = normalize_tags(tags).map_err(|e| tag_err(id, e))?;
+    if !self.entry_exists(id).map_err(|e| tag_err(id, e))? {
+      return Err(tag_err(id, "no such entry"));
+    }
+    self.insert_tags(id, &tags).map_err(|e| tag_err(id, e))
+  }
+
+  /// Insert already normalized tags, ignoring those entry `id` already has.
+  pub(super) fn insert_tags(
+    &self,
+    id: i64,
+    tags: &[String],
+  ) -> rusqlite::Result<usize> {
+    let tags = serde_json::to_string(tags)
+      .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    self.conn.execute(
+      "INSERT OR IGNORE INTO entry_tags (entry_id, tag) SELECT ?1, value FROM \
+       json_each(?2)",
+      params![id, tags],
+    )
+  }
+
+  /// Remove `tags` from entry `id`, or all of its tags if `tags` is empty.
+  /// Returns how many were removed.
+  pub fn remove_tags(
+    &self,
+    id: i64,
+    tags: &[String],
+  ) -> Result<usize, StashError> {
+    let tags = normalize_tags(tags).map_err(|e| tag_err(id, e))?;
+    if !self.entry_exists(id).map_err(|e| tag_err(id, e))? {
+      return Err(tag_err(id, "no such entry"));
+    }
+    let tags = serde_json::to_string(&tags).map_err(|e| tag_err(id, e))?;
+    self
+      .conn
+      .execute(
+        "DELETE FROM entry_tags WHERE entry_id = ?1 AND \
+         (json_array_length(?2) = 0 OR tag IN (SELECT value FROM \
+         json_each(?2)))",
+        params![id, tags],
+      )
+      .map_err(|e| tag_err(id, e))
+  }
+
+  /// The tags of entry `id`, sorted.
+  pub fn entry_tags(&self, id: i64) -> Result<Vec<String>, StashError> {
+    if !self.entry_exists(id).map_err(|e| tag_err(id, e))? {
+      return Err(tag_err(id, "no such entry"));
+    }
+    let mut stmt = self
+      .conn
+      .prepare("SELECT tag FROM entry_tags WHERE entry_id = ?1 ORDER BY tag")
+      .map_err(|e| tag_err(id, e))?;
+    stmt
+      .query_map(params![id], |row| row.get(0))
+      .and_then(|rows| rows.collect())
+      .map_err(|e| tag_err(id, e))
+  }
+
+  /// Set the note of entry `id`, or clear it when `note` is `None` or blank.
+  pub fn set_note(
+    &self,
+    id: i64,
+    note: Option<&str>,
+  ) -> Result<(), StashError> {
+    let note = note.map(str::trim).filter(|note| !note.is_empty());
+    let updated = self
+      .conn
+      .execute("UPDATE clipboard SET note = ?2 WHERE id = ?1", params![
+        id, note
+      ])
+      .map_err(|e| StashError::Note(id, e.to_string().into()))?;
+    if updated == 0 {
+      return Err(StashError::Note(id, "no such entry".into()));
+    }
+    Ok(())
+  }
+
+  /// The note of entry `id`, if it has one.
+  pub fn entry_note(&self, id: i64) -> Result<Option<String>, StashError> {
+    self
+      .conn
+      .query_row(
+        "SELECT note FROM clipboard WHERE id = ?1",
+        params![id],
+        |row| row.get(0),
+      )
+      .optional()
+      .map_err(|e| StashError::Note(id, e.to_string().into()))?
+      .ok_or_else(|| StashError::Note(id, "no such entry".into()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rusqlite::Connection;
+
+  use super::*;
+  use crate::db::ClipboardDb;
+
+  fn tags(tags: &[&str]) -> Vec<String> {
+    tags.iter().map(|&tag| tag.to_owned()).collect()
+  }
+
+  #[test]
+  fn test_tags_filter_and_protect_entries() {
+    let conn = Connection::open_in_memory().expect("open db");
+    let db = SqliteClipboardDb::new(conn, ":memory:".into())
+      .expect("new db")
+      .with_protect_tagged(true);
+    let first = db.import_entry(b"first").expect("store").expect("id");
+    let second = db.import_entry(b"second").expect("store").expect("id");
+    let third = db.import_entry(b"third").expect("store").expect("id");
+
+    assert_eq!(
+      db.add_tags(first, &tags(&["work", " work", "", "o'brien"]))
+        .expect("tag"),
+      2
+    );
+    assert_eq!(db.add_tags(second, &tags(&["work"])).expect("tag"), 1);
+    assert_eq!(db.add_tags(second, &tags(&["work"])).expect("tag"), 0);
+    assert!(db.add_tags(second, &tags(&["two words"])).is_err());
+    assert!(db.add_tags(9999, &tags(&["work"])).is_err());
+    assert_eq!(db.entry_tags(first).expect("tags"), ["o'brien", "work"]);
+
+    let ids = |filter: &[&str]| -> Vec<i64> {
+      db.fetch_entries_window(
+        false,
+        0,
+        10,
+        100,
+        None,
+        false,
+        false,
+        None,
+        &tags(filter),
+      )
+      .expect("window")
+      .into_iter()
+      .map(|entry| entry.id)
+      .collect()
+    };
+    assert_eq!(ids(&[]), [third, second, first]);
+    assert_eq!(ids(&["work"]), [second, first]);
+    assert_eq!(ids(&["work", "o'brien"]), [first]);
+    assert_eq!(
+      db.count_entries(false, None, None, &tags(&["o'brien"]))
+        .expect("count"),
+      1
+    );
+    // Tags and `mime:` terms are bound after the search pattern.
+    let search = |query: &str| {
+      crate::db::SearchQuery::parse(crate::db::SearchMode::Substring, query)
+        .expect("parse")
+        .expect("search")
+    };
+    assert_eq!(
+      db.count_entries(
+        false,
+        Some(&search("fir mime:text")),
+        None,
+        &tags(&["o'brien"])
+      )
+      .expect("count"),
+      1
+    );
+    assert_eq!(
+      db.count_entries(false, Some(&search("mime:it's")), None, &[])
+        .expect("count"),
+      0
+    );
+
+    db.set_note(second, Some("  from the wiki ")).expect("note");
+    assert_eq!(
+      db.entry_note(second).expect("note").as_deref(),
+      Some("from the wiki")
+    );
+    let json: serde_json::Value = serde_json::from_str(
+      &db
+        .list_json(false, false, false, None, &tags(&["work"]), None)
+        .expect("json"),
+    )
+    .expect("valid json");
+    assert_eq!(json[0]["tags"], serde_json::json!(["work"]));
+    assert_eq!(json[0]["note"], "from the wiki");
+    assert_eq!(json[1]["tags"], serde_json::json!(["o'brien", "work"]));
+    assert!(json[1]["note"].is_null());
+
+    // Tagged entries are kept by trimming, and their tags go with them when
+    // they are deleted.
+    db.trim_db(0).expect("trim");
+    assert_eq!(ids(&[]), [second, first]);
+    assert_eq!(db.remove_tags(first, &[]).expect("untag"), 2);
+    db.trim_db(0).expect("trim");
+    assert_eq!(ids(&[]), [second]);
+    db.wipe_db().expect("wipe");
+    let left: i64 = db
+      .conn
+      .query_row("SELECT COUNT(*) FROM entry_tags", [], |row| row.get(0))
+      .expect("count");
+    assert_eq!(left, 0);
+  }
+}
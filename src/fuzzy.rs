@@ -0,0 +1,277 @@
+//! fzf-style fuzzy matching.
+//!
+//! A pattern is split on spaces into terms that must all match. A term
+//! matches when its characters appear in the text in order. The occurrence
+//! scored is found the way fzf's v1 algorithm does: scanning forward for the
+//! first match, then backward from its end for the shortest one. Matched
+//! characters earn more when they are consecutive or start a word, and gaps
+//! between them cost a little. Terms without uppercase characters ignore
+//! case.
+
+const SCORE_MATCH: i32 = 16;
+const SCORE_GAP_START: i32 = -3;
+const SCORE_GAP_EXTENSION: i32 = -1;
+
+/// Characters after a non-word character start a word.
+const BONUS_BOUNDARY: i32 = SCORE_MATCH / 2;
+/// Words after whitespace or a delimiter weigh a bit more than after other
+/// punctuation.
+const BONUS_BOUNDARY_WHITE: i32 = BONUS_BOUNDARY + 2;
+const BONUS_BOUNDARY_DELIMITER: i32 = BONUS_BOUNDARY + 1;
+/// Non-word characters are worth matching as well.
+const BONUS_NON_WORD: i32 = SCORE_MATCH / 2;
+/// Humps of camelCase words and the start of numbers.
+const BONUS_CAMEL_123: i32 = BONUS_BOUNDARY - 1;
+/// The least a character of a consecutive run earns, as much as the gap it
+/// avoids costs.
+const BONUS_CONSECUTIVE: i32 = -(SCORE_GAP_START + SCORE_GAP_EXTENSION);
+/// The bonus of the first character of a term counts this many times.
+const BONUS_FIRST_CHAR_MULTIPLIER: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+  White,
+  NonWord,
+  Delimiter,
+  Lower,
+  Upper,
+  Number,
+}
+
+impl CharClass {
+  fn of(c: char) -> Self {
+    if c.is_lowercase() {
+      Self::Lower
+    } else if c.is_uppercase() {
+      Self::Upper
+    } else if c.is_numeric() {
+      Self::Number
+    } else if c.is_alphabetic() {
+      // Letters without case, e.g. CJK.
+      Self::Lower
+    } else if c.is_whitespace() {
+      Self::White
+    } else if matches!(c, '/' | ',' | ':' | ';' | '|') {
+      Self::Delimiter
+    } else {
+      Self::NonWord
+    }
+  }
+
+  const fn is_word(self) -> bool {
+    matches!(self, Self::Lower | Self::Upper | Self::Number)
+  }
+}
+
+/// The bonus of a matched character of class `class` following one of class
+/// `prev`.
+const fn bonus(prev: CharClass, class: CharClass) -> i32 {
+  if class.is_word() {
+    match prev {
+      CharClass::White => return BONUS_BOUNDARY_WHITE,
+      CharClass::Delimiter => return BONUS_BOUNDARY_DELIMITER,
+      CharClass::NonWord => return BONUS_BOUNDARY,
+      _ => {},
+    }
+  }
+  if matches!((prev, class), (CharClass::Lower, CharClass::Upper))
+    || (!matches!(prev, CharClass::Number)
+      && matches!(class, CharClass::Number))
+  {
+    return BONUS_CAMEL_123;
+  }
+  match class {
+    CharClass::NonWord | CharClass::Delimiter => BONUS_NON_WORD,
+    CharClass::White => BONUS_BOUNDARY_WHITE,
+    _ => 0,
+  }
+}
+
+fn fold(c: char) -> char {
+  if c.is_ascii() {
+    c.to_ascii_lowercase()
+  } else {
+    c.to_lowercase().next().unwrap_or(c)
+  }
+}
+
+struct Term {
+  chars:          Vec<char>,
+  case_sensitive: bool,
+}
+
+impl Term {
+  fn eq(&self, text: char, pattern: char) -> bool {
+    if self.case_sensitive {
+      text == pattern
+    } else {
+      fold(text) == pattern
+    }
+  }
+
+  /// Score of the best occurrence of the term in `text`, adding the indices
+  /// of its characters to `positions`.
+  fn score(&self, text: &[char], positions: &mut Vec<usize>) -> Option<i32> {
+    let first = *self.chars.first()?;
+
+    // Forward: the end of the first occurrence.
+    let mut pidx = 0;
+    let mut start = None;
+    let mut end = None;
+    for (idx, &c) in text.iter().enumerate() {
+      if self.eq(c, self.chars[pidx]) {
+        if pidx == 0 {
+          start = Some(idx);
+        }
+        pidx += 1;
+        if pidx == self.chars.len() {
+          end = Some(idx + 1);
+          break;
+        }
+      }
+    }
+    let (mut start, end) = (start?, end?);
+
+    // Backward: the shortest occurrence ending there.
+    let mut pidx = self.chars.len();
+    for idx in (start..end).rev() {
+      if self.eq(text[idx], self.chars[pidx - 1]) {
+        pidx -= 1;
+        if pidx == 0 {
+          start = idx;
+          break;
+        }
+      }
+    }
+    debug_assert!(self.eq(text[start], first));
+
+    let mut score = 0;
+    let mut pidx = 0;
+    let mut in_gap = false;
+    let mut consecutive = 0;
+    let mut first_bonus = 0;
+    let mut prev = if start == 0 {
+      CharClass::White
+    } else {
+      CharClass::of(text[start - 1])
+    };
+    for (idx, &c) in text.iter().enumerate().take(end).skip(start) {
+      let class = CharClass::of(c);
+      if pidx < self.chars.len() && self.eq(c, self.chars[pidx]) {
+        positions.push(idx);
+        score += SCORE_MATCH;
+        let mut char_bonus = bonus(prev, class);
+        if consecutive == 0 {
+          first_bonus = char_bonus;
+        } else {
+          // A run keeps the bonus of the word boundary it started at.
+          if char_bonus >= BONUS_BOUNDARY && char_bonus > first_bonus {
+            first_bonus = char_bonus;
+          }
+          char_bonus = char_bonus.max(first_bonus).max(BONUS_CONSECUTIVE);
+        }
+        score += if pidx == 0 {
+          char_bonus * BONUS_FIRST_CHAR_MULTIPLIER
+        } else {
+          char_bonus
+        };
+        in_gap = false;
+        consecutive += 1;
+        pidx += 1;
+      } else {
+        score += if in_gap {
+          SCORE_GAP_EXTENSION
+        } else {
+          SCORE_GAP_START
+        };
+        in_gap = true;
+        consecutive = 0;
+        first_bonus = 0;
+      }
+      prev = class;
+    }
+    Some(score)
+  }
+}
+
+/// A parsed search pattern.
+pub struct Pattern {
+  terms: Vec<Term>,
+}
+
+impl Pattern {
+  pub fn new(query: &str) -> Self {
+    let terms = query
+      .split(' ')
+      .filter(|term| !term.is_empty())
+      .map(|term| {
+        let case_sensitive = term.chars().any(char::is_uppercase);
+        Term {
+          chars: if case_sensitive {
+            term.chars().collect()
+          } else {
+            term.chars().map(fold).collect()
+          },
+          case_sensitive,
+        }
+      })
+      .collect();
+    Self { terms }
+  }
+
+  /// Whether the pattern matches everything.
+  pub fn is_empty(&self) -> bool {
+    self.terms.is_empty()
+  }
+
+  /// Score `text`, higher being better, along with the sorted indices of the
+  /// matched characters. `None` if a term does not match.
+  pub fn score(&self, text: &[char]) -> Option<(i32, Vec<usize>)> {
+    let mut positions = Vec::new();
+    let mut score = 0;
+    for term in &self.terms {
+      score += term.score(text, &mut positions)?;
+    }
+    positions.sort_unstable();
+    positions.dedup();
+    Some((score, positions))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn score(query: &str, text: &str) -> Option<(i32, Vec<usize>)> {
+    Pattern::new(query).score(&text.chars().collect::<Vec<_>>())
+  }
+
+  #[test]
+  fn test_fuzzy_scoring() {
+    assert_eq!(score("fb", "foo bar").map(|(_, pos)| pos), Some(vec![0, 4]));
+    // The shortest occurrence is highlighted, not the first `f`.
+    assert_eq!(
+      score("fbr", "f f bar").map(|(_, pos)| pos),
+      Some(vec![2, 4, 6])
+    );
+    assert!(score("xyz", "foo bar").is_none());
+    assert!(score("foo qux", "foo bar").is_none());
+    assert_eq!(
+      score("bar foo", "foo bar").map(|(_, pos)| pos),
+      Some(vec![0, 1, 2, 4, 5, 6])
+    );
+
+    // Smart case.
+    assert!(score("FOO", "foo").is_none());
+    assert!(score("foo", "FOO").is_some());
+    assert!(score("Ünï", "ünï").is_none());
+    assert!(score("ünï", "ÜNÏ").is_some());
+
+    // Consecutive and word-start matches beat scattered ones.
+    let rank = |text| score("git", text).expect("match").0;
+    assert!(rank("git push") > rank("great insight"));
+    assert!(rank("a git repo") > rank("digit"));
+    assert!(rank("GitHub") > rank("gadget it"));
+    assert!(rank("src/git.rs") > rank("srcgitrs"));
+  }
+}
@@ -0,0 +1,483 @@
+//! Local IPC between the CLI and a running `stash watch` daemon.
+//!
+//! The daemon listens on a Unix socket (see [`socket_path`]) and speaks a
+//! JSON-lines protocol: every line a client writes is one [`Request`], and
+//! every line the daemon writes back is one [`Response`]. After a
+//! [`Request::Subscribe`] the daemon keeps the connection open and writes a
+//! [`Response::NewEntry`] line for each entry it stores.
+//!
+//! Commands that modify or read single entries go through the daemon when one
+//! is serving the same database, so the daemon stays the only writer. Without
+//! a daemon they fall back to opening the database directly.
+
+pub mod server;
+
+use std::{
+  env,
+  fs,
+  io::{BufRead, BufReader, Write},
+  os::unix::net::UnixStream,
+  path::{Path, PathBuf},
+  time::Duration,
+};
+
+use base64::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  clipboard::Selection,
+  db::{ListEntry, SearchQuery, StashError, snippets::Snippet},
+};
+
+/// How long the CLI waits for the daemon to answer a request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Path of the daemon socket: `$STASH_SOCKET`, or `stash.sock` in
+/// `$XDG_RUNTIME_DIR`. Setting `STASH_SOCKET` to an empty string disables
+/// IPC.
+pub fn socket_path() -> Option<PathBuf> {
+  match env::var_os("STASH_SOCKET") {
+    Some(path) if path.is_empty() => None,
+    Some(path) => Some(PathBuf::from(path)),
+    None => dirs::runtime_dir().map(|dir| dir.join("stash.sock")),
+  }
+}
+
+/// Most entries a single [`Request::List`] returns.
+pub const MAX_LIST_LIMIT: usize = 1000;
+
+/// A request line sent to the daemon.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Request {
+  /// List a page of entry previews in display order, along with how many
+  /// entries match in total.
+  List {
+    #[serde(default)]
+    include_expired: bool,
+    #[serde(default)]
+    reverse:         bool,
+    #[serde(default)]
+    pinned_first:    bool,
+    #[serde(default)]
+    selection:       Option<Selection>,
+    #[serde(default)]
+    tags:            Vec<String>,
+    #[serde(default)]
+    search:          Option<SearchQuery>,
+    #[serde(default = "default_preview_width")]
+    preview_width:   u32,
+    /// Position of the first entry to return.
+    #[serde(default)]
+    offset:          usize,
+    /// How many entries to return at most, capped at [`MAX_LIST_LIMIT`].
+    #[serde(default = "default_list_limit")]
+    limit:           usize,
+  },
+
+  /// Fetch the full contents of an entry.
+  Get { id: i64 },
+
+  /// Copy an entry back to the selection it was recorded from.
+  Copy { id: i64 },
+
+  /// Delete an entry.
+  Delete { id: i64 },
+
+  /// Pin or unpin an entry.
+  Pin { id: i64, pinned: bool },
+
+  /// Position of an entry in display order, under the same filters as
+  /// [`Request::List`].
+  Position {
+    id:              i64,
+    #[serde(default)]
+    include_expired: bool,
+    #[serde(default)]
+    reverse:         bool,
+    #[serde(default)]
+    pinned_first:    bool,
+    #[serde(default)]
+    selection:       Option<Selection>,
+    #[serde(default)]
+    tags:            Vec<String>,
+    #[serde(default)]
+    search:          Option<SearchQuery>,
+  },
+
+  /// Report whether the database changed, and which entries were stored
+  /// after entry `after`.
+  Changes {
+    #[serde(default)]
+    after: i64,
+  },
+
+  /// List the saved snippets.
+  Snippets,
+
+  /// Stream new entries as they are stored.
+  Subscribe,
+
+  /// Describe the running daemon.
+  Status,
+}
+
+const fn default_preview_width() -> u32 {
+  100
+}
+
+const fn default_list_limit() -> usize {
+  MAX_LIST_LIMIT
+}
+
+/// A response line written by the daemon.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+  Entries {
+    entries: Vec<ListEntry>,
+    /// How many entries match, beyond the returned page.
+    total:   usize,
+  },
+
+  Entry {
+    id:        i64,
+    mime:      Option<String>,
+    selection: Selection,
+    /// Base64 encoded, so binary contents survive the JSON round trip.
+    contents:  String,
+  },
+
+  /// The request was carried out.
+  Done {
+    id: i64,
+  },
+
+  Position {
+    /// `None` when the entry is not listed.
+    position: Option<usize>,
+  },
+
+  Changes(Changes),
+
+  Snippets {
+    snippets: Vec<SnippetEntry>,
+  },
+
+  /// The connection now receives [`Response::NewEntry`] lines.
+  Subscribed,
+
+  NewEntry {
+    entry: ListEntry,
+  },
+
+  Status(DaemonStatus),
+
+  Error {
+    message: String,
+  },
+}
+
+/// What [`Request::Status`] reports about the daemon.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DaemonStatus {
+  pub pid:         u32,
+  pub version:     String,
+  pub db_path:     PathBuf,
+  pub entries:     usize,
+  pub selections:  Vec<Selection>,
+  pub persist:     bool,
+  pub subscribers: usize,
+}
+
+/// What [`Request::Changes`] reports.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Changes {
+  /// Changes whenever anything is committed to the database, by any process.
+  pub version:   i64,
+  /// Largest entry id, or 0 when there are no entries.
+  pub newest_id: i64,
+  /// Ids of the entries stored after the requested one.
+  pub new_ids:   Vec<i64>,
+}
+
+/// A saved snippet, as [`Request::Snippets`] lists it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnippetEntry {
+  pub id:       i64,
+  pub name:     String,
+  pub tags:     Vec<String>,
+  /// Base64 encoded, like [`Response::Entry`].
+  pub contents: String,
+}
+
+impl From<Snippet> for SnippetEntry {
+  fn from(snippet: Snippet) -> Self {
+    Self {
+      id:       snippet.id,
+      name:     snippet.name,
+      tags:     snippet.tags,
+      contents: BASE64_STANDARD.encode(snippet.contents),
+    }
+  }
+}
+
+fn ipc_err(e: impl ToString) -> StashError {
+  StashError::Ipc(e.to_string().into())
+}
+
+/// Blocking client for the daemon socket, used by the CLI.
+pub struct Client {
+  reader: BufReader<UnixStream>,
+  writer: UnixStream,
+}
+
+impl Client {
+  /// Connect to a daemon serving the database at `db_path`.
+  ///
+  /// Returns `None` when no daemon is listening, or when the daemon serves a
+  /// different database; callers then use the database directly.
+  pub fn connect(db_path: &Path) -> Option<Self> {
+    let path = socket_path()?;
+    let stream = UnixStream::connect(&path).ok()?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT)).ok()?;
+    let mut client = Self {
+      reader: BufReader::new(stream.try_clone().ok()?),
+      writer: stream,
+    };
+
+    match client.request(&Request::Status) {
+      Ok(Response::Status(status)) if same_file(&status.db_path, db_path) => {
+        log::debug!("using watch daemon at {}", path.display());
+        Some(client)
+      },
+      Ok(Response::Status(status)) => {
+        log::debug!(
+          "watch daemon serves {}, not {}; using the database directly",
+          status.db_path.display(),
+          db_path.display()
+        );
+        None
+      },
+      Ok(other) => {
+        log::warn!("unexpected status response from watch daemon: {other:?}");
+        None
+      },
+      Err(e) => {
+        log::warn!("{e}; using the database directly");
+        None
+      },
+    }
+  }
+
+  /// Send one request and wait for its response.
+  pub fn request(&mut self, request: &Request) -> Result<Response, StashError> {
+    let mut line = serde_json::to_string(request).map_err(ipc_err)?;
+    line.push('\n');
+    self.writer.write_all(line.as_bytes()).map_err(ipc_err)?;
+    self.next_response()
+  }
+
+  /// Read the next response line, e.g. the next entry after subscribing.
+  pub fn next_response(&mut self) -> Result<Response, StashError> {
+    let mut line = String::new();
+    if self.reader.read_line(&mut line).map_err(ipc_err)? == 0 {
+      return Err(ipc_err("daemon closed the connection"));
+    }
+    match serde_json::from_str(&line).map_err(ipc_err)? {
+      Response::Error { message } => Err(StashError::Ipc(message.into())),
+      response => Ok(response),
+    }
+  }
+
+  /// Send a request that is answered with [`Response::Done`].
+  fn request_done(&mut self, request: &Request) -> Result<i64, StashError> {
+    match self.request(request)? {
+      Response::Done { id } => Ok(id),
+      other => Err(ipc_err(format!("unexpected response: {other:?}"))),
+    }
+  }
+
+  /// Fetch up to `limit` entries starting at `offset`, and how many entries
+  /// match in total.
+  #[expect(
+    clippy::too_many_arguments,
+    reason = "list filters mirror the CLI options"
+  )]
+  pub fn list(
+    &mut self,
+    include_expired: bool,
+    reverse: bool,
+    pinned_first: bool,
+    selection: Option<Selection>,
+    tags: &[String],
+    search: Option<&SearchQuery>,
+    preview_width: u32,
+    offset: usize,
+    limit: usize,
+  ) -> Result<(Vec<ListEntry>, usize), StashError> {
+    match self.request(&Request::List {
+      include_expired,
+      reverse,
+      pinned_first,
+      selection,
+      tags: tags.to_vec(),
+      search: search.cloned(),
+      preview_width,
+      offset,
+      limit,
+    })? {
+      Response::Entries { entries, total } => Ok((entries, total)),
+      other => Err(ipc_err(format!("unexpected response: {other:?}"))),
+    }
+  }
+
+  /// Fetch the decoded contents of entry `id`.
+  pub fn get(&mut self, id: i64) -> Result<Vec<u8>, StashError> {
+    match self.request(&Request::Get { id })? {
+      Response::Entry { contents, .. } => {
+        BASE64_STANDARD.decode(contents).map_err(ipc_err)
+      },
+      other => Err(ipc_err(format!("unexpected response: {other:?}"))),
+    }
+  }
+
+  pub fn copy(&mut self, id: i64) -> Result<i64, StashError> {
+    self.request_done(&Request::Copy { id })
+  }
+
+  pub fn delete(&mut self, id: i64) -> Result<i64, StashError> {
+    self.request_done(&Request::Delete { id })
+  }
+
+  pub fn pin(&mut self, id: i64, pinned: bool) -> Result<i64, StashError> {
+    self.request_done(&Request::Pin { id, pinned })
+  }
+
+  /// Position of entry `id` among the entries matching the filters.
+  #[expect(
+    clippy::too_many_arguments,
+    reason = "list filters mirror the CLI options"
+  )]
+  pub fn position(
+    &mut self,
+    id: i64,
+    include_expired: bool,
+    reverse: bool,
+    pinned_first: bool,
+    selection: Option<Selection>,
+    tags: &[String],
+    search: Option<&SearchQuery>,
+  ) -> Result<Option<usize>, StashError> {
+    match self.request(&Request::Position {
+      id,
+      include_expired,
+      reverse,
+      pinned_first,
+      selection,
+      tags: tags.to_vec(),
+      search: search.cloned(),
+    })? {
+      Response::Position { position } => Ok(position),
+      other => Err(ipc_err(format!("unexpected response: {other:?}"))),
+    }
+  }
+
+  /// Check for changes, and for entries stored after entry `after`.
+  pub fn changes(&mut self, after: i64) -> Result<Changes, StashError> {
+    match self.request(&Request::Changes { after })? {
+      Response::Changes(changes) => Ok(changes),
+      other => Err(ipc_err(format!("unexpected response: {other:?}"))),
+    }
+  }
+
+  /// Fetch all saved snippets.
+  pub fn snippets(&mut self) -> Result<Vec<Snippet>, StashError> {
+    match self.request(&Request::Snippets)? {
+      Response::Snippets { snippets } => {
+        snippets
+          .into_iter()
+          .map(|snippet| {
+            Ok(Snippet {
+              id:       snippet.id,
+              name:     snippet.name,
+              tags:     snippet.tags,
+              contents: BASE64_STANDARD
+                .decode(snippet.contents)
+                .map_err(ipc_err)?,
+            })
+          })
+          .collect()
+      },
+      other => Err(ipc_err(format!("unexpected response: {other:?}"))),
+    }
+  }
+
+  /// Subscribe to new entries. Afterwards, read them with
+  /// [`Client::next_response`].
+  pub fn subscribe(&mut self) -> Result<(), StashError> {
+    match self.request(&Request::Subscribe)? {
+      Response::Subscribed => {
+        // New entries may be far apart.
+        self
+          .reader
+          .get_ref()
+          .set_read_timeout(None)
+          .map_err(ipc_err)
+      },
+      other => Err(ipc_err(format!("unexpected response: {other:?}"))),
+    }
+  }
+
+  pub fn status(&mut self) -> Result<DaemonStatus, StashError> {
+    match self.request(&Request::Status)? {
+      Response::Status(status) => Ok(status),
+      other => Err(ipc_err(format!("unexpected response: {other:?}"))),
+    }
+  }
+}
+
+/// Whether two paths refer to the same file, comparing them literally when
+/// they cannot be resolved.
+fn same_file(a: &Path, b: &Path) -> bool {
+  match (fs::canonicalize(a), fs::canonicalize(b)) {
+    (Ok(a), Ok(b)) => a == b,
+    _ => a == b,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_request_wire_format() {
+    let request: Request =
+      serde_json::from_str(r#"{"cmd":"list","selection":"primary"}"#)
+        .expect("parse list request");
+    assert_eq!(request, Request::List {
+      include_expired: false,
+      reverse:         false,
+      pinned_first:    false,
+      selection:       Some(Selection::Primary),
+      tags:            Vec::new(),
+      search:          None,
+      preview_width:   100,
+      offset:          0,
+      limit:           MAX_LIST_LIMIT,
+    });
+
+    assert_eq!(
+      serde_json::to_string(&Request::Pin {
+        id:     7,
+        pinned: true,
+      })
+      .unwrap(),
+      r#"{"cmd":"pin","id":7,"pinned":true}"#
+    );
+    assert_eq!(
+      serde_json::to_string(&Response::Done { id: 7 }).unwrap(),
+      r#"{"type":"done","id":7}"#
+    );
+  }
+}
@@ -0,0 +1,575 @@
+//! The daemon side of the IPC socket, run by `stash watch`.
+
+use std::{
+  fs,
+  io,
+  os::unix::fs::{DirBuilderExt, PermissionsExt},
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+
+use base64::prelude::*;
+use smol::{
+  Timer,
+  channel::{self, Sender},
+  io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+  net::unix::{UnixListener, UnixStream},
+  stream::StreamExt,
+};
+
+use super::{
+  Changes,
+  DaemonStatus,
+  MAX_LIST_LIMIT,
+  Request,
+  Response,
+  SnippetEntry,
+  default_preview_width,
+};
+use crate::{
+  clipboard::{self, Selection},
+  db::{
+    ClipboardDb,
+    ListEntry,
+    SqliteClipboardDb,
+    StashError,
+    nonblocking::AsyncClipboardDb,
+  },
+};
+
+/// New entries buffered per subscriber before further ones are dropped for
+/// it.
+const SUBSCRIBER_BACKLOG: usize = 64;
+
+/// Daemon settings reported by [`Request::Status`].
+#[derive(Debug, Clone)]
+pub struct DaemonInfo {
+  pub selections: Vec<Selection>,
+  pub persist:    bool,
+}
+
+/// Handle to the running socket server.
+#[derive(Clone)]
+pub struct Server {
+  db:          AsyncClipboardDb,
+  db_path:     PathBuf,
+  info:        DaemonInfo,
+  subscribers: Arc<Mutex<Vec<Sender<ListEntry>>>>,
+  /// Connection kept open to answer [`Request::Changes`]: its
+  /// `data_version` moves whenever any other connection commits, including
+  /// the ones opened for each request.
+  monitor:     Arc<Mutex<SqliteClipboardDb>>,
+}
+
+impl Server {
+  /// Listen on [`super::socket_path`] and serve requests in the background.
+  ///
+  /// Returns `None`, after logging why, when there is no socket path or it
+  /// cannot be bound, e.g. because another daemon is already listening. The
+  /// watch daemon keeps running without IPC in that case.
+  pub fn start(db_path: PathBuf, info: DaemonInfo) -> Option<Self> {
+    let path = super::socket_path()?;
+    let listener = match bind(&path) {
+      Ok(listener) => listener,
+      Err(e) => {
+        log::warn!("not serving IPC on {}: {e}", path.display());
+        return None;
+      },
+    };
+    let monitor = rusqlite::Connection::open(&db_path)
+      .map_err(|e| StashError::Store(e.to_string().into()))
+      .and_then(|conn| SqliteClipboardDb::new(conn, db_path.clone()));
+    let monitor = match monitor {
+      Ok(monitor) => monitor,
+      Err(e) => {
+        log::warn!("not serving IPC on {}: {e}", path.display());
+        return None;
+      },
+    };
+    log::info!("serving IPC on {}", path.display());
+
+    let server = Self {
+      db: AsyncClipboardDb::new(db_path.clone()),
+      db_path,
+      info,
+      subscribers: Arc::new(Mutex::new(Vec::new())),
+      monitor: Arc::new(Mutex::new(monitor)),
+    };
+    let accepting = server.clone();
+    smol::spawn(async move { accepting.accept_loop(listener).await }).detach();
+    Some(server)
+  }
+
+  /// Send entry `id` to every subscriber.
+  pub async fn publish(&self, id: i64) {
+    if self.subscriber_count() == 0 {
+      return;
+    }
+
+    let entry = self
+      .db
+      .with_db(move |db| db.list_entry(id, default_preview_width()))
+      .await;
+    let entry = match entry {
+      Ok(Some(entry)) => entry,
+      Ok(None) => return,
+      Err(e) => {
+        log::warn!("failed to load entry {id} for subscribers: {e}");
+        return;
+      },
+    };
+
+    let subscribers =
+      self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+    for subscriber in subscribers.iter() {
+      if subscriber.try_send(entry.clone()).is_err() {
+        log::debug!("IPC subscriber is not keeping up, dropping entry {id}");
+      }
+    }
+  }
+
+  /// Number of connected subscribers. Disconnected ones are dropped first.
+  fn subscriber_count(&self) -> usize {
+    let mut subscribers =
+      self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+    subscribers.retain(|subscriber| !subscriber.is_closed());
+    subscribers.len()
+  }
+
+  async fn accept_loop(self, listener: UnixListener) {
+    loop {
+      match listener.accept().await {
+        Ok((stream, _)) => {
+          let server = self.clone();
+          smol::spawn(async move {
+            if let Err(e) = server.serve_connection(stream).await {
+              log::debug!("IPC connection ended: {e}");
+            }
+          })
+          .detach();
+        },
+        Err(e) => {
+          log::warn!("failed to accept IPC connection: {e}");
+          Timer::after(Duration::from_millis(100)).await;
+        },
+      }
+    }
+  }
+
+  async fn serve_connection(&self, stream: UnixStream) -> io::Result<()> {
+    let mut lines = BufReader::new(stream.clone()).lines();
+    let mut writer = stream;
+
+    while let Some(line) = lines.next().await {
+      let line = line?;
+      if line.trim().is_empty() {
+        continue;
+      }
+
+      let response = match serde_json::from_str::<Request>(&line) {
+        Ok(Request::Subscribe) => return self.stream_new_entries(writer).await,
+        Ok(request) => self.handle(request).await,
+        Err(e) => {
+          Response::Error {
+            message: format!("invalid request: {e}"),
+          }
+        },
+      };
+      write_response(&mut writer, &response).await?;
+    }
+    Ok(())
+  }
+
+  /// Turn the connection into a stream of new entries, until the client
+  /// disconnects.
+  async fn stream_new_entries(&self, mut writer: UnixStream) -> io::Result<()> {
+    let (tx, rx) = channel::bounded(SUBSCRIBER_BACKLOG);
+    self
+      .subscribers
+      .lock()
+      .unwrap_or_else(|e| e.into_inner())
+      .push(tx);
+
+    write_response(&mut writer, &Response::Subscribed).await?;
+    while let Ok(entry) = rx.recv().await {
+      write_response(&mut writer, &Response::NewEntry { entry }).await?;
+    }
+    Ok(())
+  }
+
+  async fn handle(&self, request: Request) -> Response {
+    let response = match request {
+      Request::Changes { after } => self.changes(after).await,
+      request => {
+        let db_path = self.db_path.clone();
+        let info = self.info.clone();
+        let subscribers = self.subscriber_count();
+        self
+          .db
+          .with_db(move |db| {
+            handle_request(db, request, db_path, info, subscribers)
+          })
+          .await
+      },
+    };
+    response.unwrap_or_else(|e| {
+      Response::Error {
+        message: e.to_string(),
+      }
+    })
+  }
+
+  /// Answer [`Request::Changes`] on the monitor connection.
+  async fn changes(&self, after: i64) -> Result<Response, StashError> {
+    let monitor = Arc::clone(&self.monitor);
+    blocking::unblock(move || {
+      let db = monitor.lock().unwrap_or_else(|e| e.into_inner());
+      Ok(Response::Changes(Changes {
+        version:   db.data_version()?,
+        newest_id: db.max_id()?,
+        new_ids:   db.ids_after(after)?,
+      }))
+    })
+    .await
+  }
+}
+
+fn handle_request(
+  db: &SqliteClipboardDb,
+  request: Request,
+  db_path: PathBuf,
+  info: DaemonInfo,
+  subscribers: usize,
+) -> Result<Response, StashError> {
+  let response = match request {
+    Request::List {
+      include_expired,
+      reverse,
+      pinned_first,
+      selection,
+      tags,
+      search,
+      preview_width,
+      offset,
+      limit,
+    } => {
+      let total =
+        db.count_entries(include_expired, search.as_ref(), selection, &tags)?;
+      let limit = limit.min(MAX_LIST_LIMIT).min(total.saturating_sub(offset));
+      let entries = if limit == 0 {
+        Vec::new()
+      } else {
+        db.fetch_entries_window(
+          include_expired,
+          offset,
+          limit,
+          preview_width,
+          search.as_ref(),
+          reverse,
+          pinned_first,
+          selection,
+          &tags,
+        )?
+      };
+      Response::Entries { entries, total }
+    },
+    Request::Get { id } => {
+      let entry = db.entry(id)?;
+      Response::Entry {
+        id,
+        mime: entry.mime,
+        selection: db.selection(id)?,
+        contents: BASE64_STANDARD.encode(entry.contents),
+      }
+    },
+    Request::Copy { id } => {
+      let selection = db.selection(id)?;
+      let (id, contents, mime, representations) = db.copy_entry(id)?;
+      clipboard::copy_detached(contents, mime, representations, selection)
+        .map_err(|e| StashError::Copy(id, e.to_string().into()))?;
+      Response::Done { id }
+    },
+    Request::Delete { id } => {
+      db.delete_entries(format!("{id}\n").as_bytes())?;
+      Response::Done { id }
+    },
+    Request::Pin { id, pinned } => {
+      db.set_pinned(id, pinned)?;
+      Response::Done { id }
+    },
+    Request::Position {
+      id,
+      include_expired,
+      reverse,
+      pinned_first,
+      selection,
+      tags,
+      search,
+    } => {
+      Response::Position {
+        position: db.entry_position(
+          id,
+          include_expired,
+          search.as_ref(),
+          reverse,
+          pinned_first,
+          selection,
+          &tags,
+        )?,
+      }
+    },
+    Request::Snippets => {
+      Response::Snippets {
+        snippets: db
+          .snippets(None)?
+          .into_iter()
+          .map(SnippetEntry::from)
+          .collect(),
+      }
+    },
+    Request::Status => {
+      Response::Status(DaemonStatus {
+        pid: std::process::id(),
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        db_path,
+        entries: db.count_entries(false, None, None, &[])?,
+        selections: info.selections,
+        persist: info.persist,
+        subscribers,
+      })
+    },
+    Request::Subscribe => {
+      // Handled by the connection itself, as it changes what is sent back.
+      Response::Error {
+        message: "subscribe is not a single request".to_owned(),
+      }
+    },
+    Request::Changes { .. } => {
+      // Handled by the server, which keeps the connection that notices them.
+      Response::Error {
+        message: "changes are answered by the server".to_owned(),
+      }
+    },
+  };
+  Ok(response)
+}
+
+async fn write_response(
+  writer: &mut UnixStream,
+  response: &Response,
+) -> io::Result<()> {
+  let mut line = serde_json::to_vec(response)?;
+  line.push(b'\n');
+  writer.write_all(&line).await
+}
+
+/// Bind the socket at `path`, replacing a stale socket file left behind by a
+/// daemon that is no longer running.
+fn bind(path: &Path) -> io::Result<UnixListener> {
+  if std::os::unix::net::UnixStream::connect(path).is_ok() {
+    return Err(io::Error::new(
+      io::ErrorKind::AddrInUse,
+      "another daemon is already listening",
+    ));
+  }
+  match fs::remove_file(path) {
+    Ok(()) => log::debug!("removed stale socket {}", path.display()),
+    Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+    Err(e) => return Err(e),
+  }
+  let parent = path
+    .parent()
+    .filter(|parent| !parent.as_os_str().is_empty())
+    .unwrap_or(Path::new("."));
+  fs::create_dir_all(parent)?;
+
+  // Bind inside a directory only we can enter, so nobody can connect before
+  // the socket is restricted to us, and only then move it into place.
+  let private = parent.join(format!(".stash.sock.{}", std::process::id()));
+  match fs::remove_dir_all(&private) {
+    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+    _ => {},
+  }
+  fs::DirBuilder::new().mode(0o700).create(&private)?;
+  let staged = private.join("sock");
+  let listener = UnixListener::bind(&staged).and_then(|listener| {
+    fs::set_permissions(&staged, fs::Permissions::from_mode(0o600))?;
+    fs::rename(&staged, path)?;
+    Ok(listener)
+  });
+  if let Err(e) = fs::remove_dir_all(&private) {
+    log::debug!("failed to remove {}: {e}", private.display());
+  }
+  listener
+}
+
+#[cfg(test)]
+mod tests {
+  use rusqlite::Connection;
+
+  use super::*;
+  use crate::db::{
+    DEFAULT_MAX_ENTRY_SIZE,
+    tests::{store_text, two_connections},
+  };
+
+  #[test]
+  fn test_bind_restricts_socket_to_owner() {
+    let dir = tempfile::tempdir().expect("create temp dir");
+    let path = dir.path().join("run").join("stash.sock");
+
+    let listener = smol::block_on(async { bind(&path) }).expect("bind");
+    let mode = fs::metadata(&path).expect("socket metadata").permissions();
+    assert_eq!(mode.mode() & 0o777, 0o600);
+    let leftovers: Vec<_> = fs::read_dir(path.parent().unwrap())
+      .unwrap()
+      .map(|entry| entry.unwrap().file_name())
+      .collect();
+    assert_eq!(leftovers, ["stash.sock"]);
+
+    assert!(std::os::unix::net::UnixStream::connect(&path).is_ok());
+    drop(listener);
+  }
+
+  #[test]
+  fn test_handle_request_round_trip() {
+    let conn = Connection::open_in_memory().expect("open in-memory db");
+    let db = SqliteClipboardDb::new(conn, PathBuf::from(":memory:"))
+      .expect("create test database");
+    let id = db
+      .store_entry(
+        io::Cursor::new(b"hello daemon".to_vec()),
+        100,
+        1000,
+        None,
+        None,
+        DEFAULT_MAX_ENTRY_SIZE,
+        None,
+        None,
+        None,
+      )
+      .expect("store entry");
+    let info = DaemonInfo {
+      selections: vec![Selection::Regular],
+      persist:    false,
+    };
+    let handle = |request| {
+      handle_request(&db, request, PathBuf::from(":memory:"), info.clone(), 0)
+        .expect("handle request")
+    };
+
+    let list = |offset, limit| {
+      handle(Request::List {
+        include_expired: false,
+        reverse: false,
+        pinned_first: false,
+        selection: None,
+        tags: Vec::new(),
+        search: None,
+        preview_width: 100,
+        offset,
+        limit,
+      })
+    };
+    let Response::Entries { entries, total } = list(0, MAX_LIST_LIMIT) else {
+      panic!("expected entries");
+    };
+    assert_eq!(total, 1);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].id, id);
+    assert_eq!(entries[0].preview, "hello daemon");
+    assert_eq!(list(1, MAX_LIST_LIMIT), Response::Entries {
+      entries: Vec::new(),
+      total:   1,
+    });
+    assert_eq!(list(0, 0), Response::Entries {
+      entries: Vec::new(),
+      total:   1,
+    });
+
+    let Response::Entry { contents, .. } = handle(Request::Get { id }) else {
+      panic!("expected entry");
+    };
+    assert_eq!(BASE64_STANDARD.decode(contents).unwrap(), b"hello daemon");
+
+    assert_eq!(handle(Request::Pin { id, pinned: true }), Response::Done {
+      id,
+    });
+    assert!(db.list_entry(id, 100).unwrap().unwrap().pinned);
+    assert_eq!(
+      handle(Request::Position {
+        id,
+        include_expired: false,
+        reverse: false,
+        pinned_first: false,
+        selection: None,
+        tags: Vec::new(),
+        search: None,
+      }),
+      Response::Position { position: Some(0) }
+    );
+
+    db.add_snippet("greeting", b"hello", &[], false)
+      .expect("add snippet");
+    let Response::Snippets { snippets } = handle(Request::Snippets) else {
+      panic!("expected snippets");
+    };
+    assert_eq!(snippets.len(), 1);
+    assert_eq!(snippets[0].name, "greeting");
+    assert_eq!(
+      BASE64_STANDARD.decode(&snippets[0].contents).unwrap(),
+      b"hello"
+    );
+
+    let Response::Status(status) = handle(Request::Status) else {
+      panic!("expected status");
+    };
+    assert_eq!(status.entries, 1);
+
+    assert_eq!(handle(Request::Delete { id }), Response::Done { id });
+    assert!(db.list_entry(id, 100).unwrap().is_none());
+  }
+
+  #[test]
+  fn test_changes_notice_commits_by_other_connections() {
+    let (dir, monitor, writer) = two_connections();
+    let db_path = dir.path().join("stash.db");
+    let server = Server {
+      db: AsyncClipboardDb::new(db_path.clone()),
+      db_path,
+      info: DaemonInfo {
+        selections: vec![Selection::Regular],
+        persist:    false,
+      },
+      subscribers: Arc::new(Mutex::new(Vec::new())),
+      monitor: Arc::new(Mutex::new(monitor)),
+    };
+    let changes = |after| {
+      match smol::block_on(server.changes(after)).expect("changes") {
+        Response::Changes(changes) => changes,
+        other => panic!("expected changes, got {other:?}"),
+      }
+    };
+
+    let first = store_text(&writer, "first");
+    let before = changes(i64::MAX);
+    assert_eq!(before.newest_id, first);
+    assert!(before.new_ids.is_empty());
+    assert_eq!(changes(i64::MAX).version, before.version);
+
+    let second = store_text(&writer, "second");
+    let after = changes(first);
+    assert_ne!(after.version, before.version);
+    assert_eq!(after.newest_id, second);
+    assert_eq!(after.new_ids, [second]);
+
+    // Requests are served on their own connections, which the monitor
+    // notices too.
+    smol::block_on(server.handle(Request::Pin {
+      id:     first,
+      pinned: true,
+    }));
+    assert_ne!(changes(second).version, after.version);
+  }
+}
@@ -1,37 +1,56 @@
 mod clipboard;
 mod commands;
+mod config;
 mod db;
+mod fuzzy;
 mod hash;
+mod ipc;
 mod mime;
 mod multicall;
+mod rules;
+mod secrets;
 
 use std::{
+  cell::OnceCell,
   env,
   io::{self, IsTerminal},
   path::PathBuf,
   time::Duration,
 };
 
-use clap::{CommandFactory, Parser, Subcommand};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use color_eyre::eyre::{self, bail};
 use humantime::parse_duration;
 use inquire::Confirm;
 
-// The Wayland module is only compiled for focused-window detection, which
-// depends on low-level compositor protocols.
+// The Wayland module is only compiled for focused-window detection and
+// selection change events, which depend on low-level compositor protocols.
 #[cfg(feature = "use-toplevel")] mod wayland;
 
 use crate::{
+  clipboard::{Selection, state::ClipboardState},
   commands::{
+    daemon::{DaemonCommand, require_daemon},
     decode::DecodeCommand,
     delete::DeleteCommand,
-    import::ImportCommand,
+    import::{HistoryFormat, ImportCommand, StoreLimits, TsvOptions},
     list::ListCommand,
+    pick::PickCommand,
+    pin::PinCommand,
     query::QueryCommand,
+    search::SearchCommand,
+    snippet::SnippetCommand,
     store::StoreCommand,
-    watch::WatchCommand,
+    tag::TagCommand,
+    watch::{WatchCommand, WatchSelection},
+  },
+  db::{
+    ClipboardDb,
+    DEFAULT_MAX_ENTRY_SIZE,
+    SearchMode,
+    SearchQuery,
+    SqliteClipboardDb,
   },
-  db::{ClipboardDb, DEFAULT_MAX_ENTRY_SIZE},
 };
 
 #[derive(Parser)]
@@ -74,6 +93,11 @@ struct Cli {
   #[arg(long, value_delimiter = ',', env = "STASH_EXCLUDED_APPS")]
   excluded_apps: Vec<String>,
 
+  /// Keep tagged entries when trimming the history to --max-items, like
+  /// pinned ones
+  #[arg(long, env = "STASH_PROTECT_TAGGED")]
+  protect_tagged: bool,
+
   /// Ask for confirmation before destructive operations
   #[arg(long)]
   ask: bool,
@@ -85,7 +109,12 @@ struct Cli {
 #[derive(Subcommand)]
 enum Command {
   /// Store clipboard contents
-  Store,
+  Store {
+    /// Selection the contents were copied to, e.g. "primary" for
+    /// `wl-paste --primary --watch stash store`
+    #[arg(long, value_enum, default_value_t, env = "STASH_SELECTION")]
+    selection: Selection,
+  },
 
   /// List clipboard history
   List {
@@ -100,10 +129,115 @@ enum Command {
     /// Reverse the order of entries (oldest first instead of newest first)
     #[arg(long)]
     reverse: bool,
+
+    /// List pinned entries before all others
+    #[arg(long, env = "STASH_PINNED_FIRST")]
+    pinned_first: bool,
+
+    /// Only list entries copied to this selection
+    #[arg(long, value_enum)]
+    selection: Option<Selection>,
+
+    /// Only list entries with this tag (comma-separated or repeated to
+    /// require several)
+    #[arg(long, value_delimiter = ',')]
+    tag: Vec<String>,
+
+    /// Only list entries matching this search, which the TUI starts with.
+    /// `mime:<type>` terms filter by MIME type instead, e.g. `mime:image`
+    #[arg(long)]
+    search: Option<String>,
+
+    /// How the text of `--search` is matched, and the TUI's initial mode
+    #[arg(long, value_enum, default_value_t, env = "STASH_SEARCH_MODE")]
+    search_mode: SearchMode,
+
+    /// Text put between marked entries when the TUI copies them joined
+    #[arg(long, default_value = "\n", env = "STASH_JOIN_SEPARATOR")]
+    join_separator: String,
+
+    /// Keys of a TUI action as `ACTION=KEY...`, e.g. `down=j ctrl-n`,
+    /// replacing its default keys (repeated or `;`-separated for several)
+    #[arg(long, value_delimiter = ';', env = "STASH_TUI_KEYS")]
+    keys: Vec<String>,
+
+    /// Style of a TUI element as `ELEMENT=STYLE`, e.g. `selected=blue bold`
+    /// (repeated or `;`-separated for several)
+    #[arg(long, value_delimiter = ';', env = "STASH_TUI_THEME")]
+    theme: Vec<String>,
+  },
+
+  /// Pick an entry with a fuzzy finder and copy it to the clipboard
+  Pick {
+    /// Print the picked entry to stdout instead of copying it
+    #[arg(long)]
+    print: bool,
+
+    /// Run as a launcher in a terminal window of its own, started from a
+    /// keybinding like `foot stash pick --launcher`: close when the window
+    /// loses focus and keep serving the copied entry after it closes. Refuses
+    /// to start unless a terminal emulator started stash directly
+    #[arg(long)]
+    launcher: bool,
   },
 
   /// Decode and output clipboard entry by id
-  Decode { input: Option<String> },
+  Decode {
+    input: Option<String>,
+
+    /// Copy the entry back to the selection it was recorded from instead of
+    /// printing it
+    #[arg(long)]
+    copy: bool,
+  },
+
+  /// Search clipboard history, best matches first
+  Search {
+    /// Text to search for (case-insensitive substring)
+    query: String,
+
+    /// Maximum number of results to print
+    #[arg(long, default_value_t = 50)]
+    limit: usize,
+
+    /// Include expired entries in the results
+    #[arg(long)]
+    expired: bool,
+  },
+
+  /// Pin entries by id so they are never trimmed or expired. Reads ids (or
+  /// `stash list` lines) from stdin when no id is given.
+  Pin { id: Option<String> },
+
+  /// Unpin entries by id. Reads ids (or `stash list` lines) from stdin when no
+  /// id is given.
+  Unpin { id: Option<String> },
+
+  /// Tag an entry, or print its tags when no tags are given
+  Tag {
+    id: i64,
+
+    /// Tags to add (comma-separated or separate arguments)
+    #[arg(value_delimiter = ',')]
+    tags: Vec<String>,
+
+    /// Remove the given tags instead, or all tags if none are given
+    #[arg(long)]
+    remove: bool,
+  },
+
+  /// Attach a note to an entry, or print its note when no text is given
+  Note {
+    id: i64,
+
+    /// Text of the note
+    #[arg(num_args = 1.., conflicts_with = "clear")]
+    text: Vec<String>,
+
+    /// Remove the note
+    #[arg(long)]
+    clear: bool,
+  },
 
   /// Delete clipboard entry by id (if numeric), or entries matching a query (if
   /// not). Numeric arguments are treated as ids. Use --type to specify
@@ -127,17 +261,88 @@ enum Command {
     action: DbAction,
   },
 
+  /// Manage snippets, named contents kept apart from the history that are
+  /// never trimmed or expired
+  Snippet {
+    #[command(subcommand)]
+    action: SnippetAction,
+  },
+
   /// Import clipboard data from stdin (default: TSV format)
   Import {
-    /// Explicitly specify format: "tsv" (default)
-    #[arg(long, value_parser = ["tsv"])]
+    /// Explicitly specify format: "tsv" (default), "archive", or the history
+    /// of another clipboard manager: "cliphist-db", "clipman", "gpaste",
+    /// "klipper" or "copyq"
+    #[arg(long, value_parser = [
+      "tsv", "archive", "cliphist-db", "clipman", "gpaste", "klipper", "copyq",
+    ])]
     r#type: Option<String>,
 
+    /// Read from this file instead of stdin. Other clipboard managers'
+    /// histories are read from their default location, except for CopyQ
+    /// exports
+    path: Option<PathBuf>,
+
+    /// Decrypt an encrypted archive with the passphrase stored in this file,
+    /// besides the configured keys
+    #[cfg(feature = "encryption")]
+    #[arg(long, value_name = "PATH")]
+    passphrase_file: Option<PathBuf>,
+
+    /// Report how many TSV entries would be imported, already stored or
+    /// skipped, without importing them
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Store TSV entries in the order of their ids instead of the order of
+    /// the lines, keeping the order of a newest-first `list` output
+    #[arg(long)]
+    preserve_order: bool,
+
+    /// Import TSV values as they are, without decoding `\n`, `\t`, `\r` and
+    /// `\\`
+    #[arg(long)]
+    raw: bool,
+
     /// Ask for confirmation before importing
     #[arg(long)]
     ask: bool,
   },
 
+  /// Export the whole clipboard history as an archive for `stash import --type
+  /// archive`, to stdout or a file
+  #[cfg_attr(
+    feature = "encryption",
+    command(group(
+      clap::ArgGroup::new("key")
+        .args(["encrypt", "passphrase_file", "recipients", "recipients_file"]),
+    ))
+  )]
+  Export {
+    /// Write the archive to this file instead of stdout
+    path: Option<PathBuf>,
+
+    /// Encrypt the archive with the configured passphrase or recipients
+    #[cfg(feature = "encryption")]
+    #[arg(long)]
+    encrypt: bool,
+
+    /// Encrypt the archive with the passphrase stored in this file
+    #[cfg(feature = "encryption")]
+    #[arg(long, value_name = "PATH")]
+    passphrase_file: Option<PathBuf>,
+
+    /// Encrypt the archive to these age or SSH recipients (comma-separated)
+    #[cfg(feature = "encryption")]
+    #[arg(long, value_name = "RECIPIENTS")]
+    recipients: Option<String>,
+
+    /// Encrypt the archive to the recipients listed in this file
+    #[cfg(feature = "encryption")]
+    #[arg(long, value_name = "PATH")]
+    recipients_file: Option<PathBuf>,
+  },
+
   /// Start a process to watch clipboard for changes and store automatically.
   Watch {
     /// Expire new entries after duration (e.g., "3s", "500ms", "1h30m").
@@ -151,6 +356,81 @@ enum Command {
     /// Persist clipboard contents after the source application closes.
     #[arg(long)]
     persist: bool,
+
+    /// Total size budget in bytes for the additional MIME types stored with
+    /// each entry (0 stores only the selected type).
+    #[arg(
+      long,
+      default_value_t = DEFAULT_MAX_ENTRY_SIZE,
+      env = "STASH_MAX_EXTRA_MIME_SIZE"
+    )]
+    max_extra_mime_size: usize,
+
+    /// Which selections to record: "regular" (default), "primary" or "both".
+    #[arg(long, value_enum, default_value_t, env = "STASH_WATCH_SELECTION")]
+    selection: WatchSelection,
+  },
+
+  /// Show the status of the watch daemon serving the database
+  Status,
+
+  /// Print entries stored by the watch daemon as JSON lines, as they arrive
+  Subscribe,
+
+  /// Inspect the configuration file and effective settings
+  Config {
+    #[command(subcommand)]
+    action: ConfigAction,
+  },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+  /// Print the effective value of every option and where it came from
+  Show,
+}
+
+#[derive(Subcommand)]
+enum SnippetAction {
+  /// Save the contents of stdin as a snippet
+  Add {
+    /// Name to copy the snippet by
+    name: String,
+
+    /// Tags to group the snippet under (comma-separated or repeated)
+    #[arg(long, value_delimiter = ',')]
+    tag: Vec<String>,
+
+    /// Replace an existing snippet of the same name
+    #[arg(long)]
+    force: bool,
+  },
+
+  /// List snippets as `name<TAB>tags<TAB>preview` lines
+  List {
+    /// Only list snippets with this tag
+    #[arg(long)]
+    tag: Option<String>,
+  },
+
+  /// Copy a snippet to the clipboard, replacing `{date}` with today's date
+  /// and `{clipboard}` with the current clipboard text
+  Copy {
+    name: String,
+
+    /// Copy the snippet as it is, without expanding placeholders
+    #[arg(long)]
+    no_expand: bool,
+  },
+
+  /// Delete a snippet
+  #[command(visible_alias = "remove")]
+  Rm {
+    name: String,
+
+    /// Ask for confirmation before deleting
+    #[arg(long)]
+    ask: bool,
   },
 }
 
@@ -179,6 +459,71 @@ enum DbAction {
 
   /// Show database statistics
   Stats,
+
+  /// Rebuild the full-text search index
+  Reindex,
+
+  /// Re-encode stored entries after changing encryption settings
+  ///
+  /// Entries stored by an `encrypt` rule with recipients of its own are left
+  /// untouched, still encrypted to that rule's recipients.
+  #[cfg(feature = "encryption")]
+  #[command(group(
+    clap::ArgGroup::new("target")
+      .required(true)
+      .args(["encrypt_existing", "to_plaintext", "new_passphrase_file", "new_recipients", "new_recipients_file"]),
+  ))]
+  Rekey {
+    /// Encrypt plaintext entries with the configured passphrase or
+    /// recipients, leaving already encrypted entries untouched
+    #[arg(long)]
+    encrypt_existing: bool,
+
+    /// Decrypt every entry and store it as plaintext
+    #[arg(long)]
+    to_plaintext: bool,
+
+    /// Re-encrypt every entry with the passphrase stored in this file
+    #[arg(long, value_name = "PATH")]
+    new_passphrase_file: Option<PathBuf>,
+
+    /// Re-encrypt every entry to these age or SSH recipients
+    /// (comma-separated)
+    #[arg(long, value_name = "RECIPIENTS")]
+    new_recipients: Option<String>,
+
+    /// Re-encrypt every entry to the recipients listed in this file
+    #[arg(long, value_name = "PATH")]
+    new_recipients_file: Option<PathBuf>,
+
+    /// Ask for confirmation before rewriting entries
+    #[arg(long)]
+    ask: bool,
+  },
+}
+
+/// The clipboard database, opened the first time a command needs it.
+/// Commands a running watch daemon serves go through it instead, and never
+/// open the database themselves.
+struct LazyDb {
+  path:           PathBuf,
+  protect_tagged: bool,
+  db:             OnceCell<SqliteClipboardDb>,
+}
+
+impl LazyDb {
+  fn get(&self) -> eyre::Result<&SqliteClipboardDb> {
+    if let Some(db) = self.db.get() {
+      return Ok(db);
+    }
+    if let Some(parent) = self.path.parent() {
+      std::fs::create_dir_all(parent)?;
+    }
+    let conn = rusqlite::Connection::open(&self.path)?;
+    let db = SqliteClipboardDb::new(conn, self.path.clone())?
+      .with_protect_tagged(self.protect_tagged);
+    Ok(self.db.get_or_init(|| db))
+  }
 }
 
 fn confirm(prompt: &str) -> bool {
@@ -225,12 +570,26 @@ fn main() -> eyre::Result<()> {
 
   // Normal CLI handling
   smol::block_on(async {
-    let cli = Cli::parse();
+    // Values from the config file become defaults, below the command line and
+    // environment variables.
+    let (config, command) = config::Config::load_into(Cli::command());
+    let config = config.install();
+    let matches = command.clone().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
     let global_ask = cli.ask;
     env_logger::Builder::new()
       .filter_level(cli.verbosity.into())
       .init();
 
+    if let Some(Command::Config {
+      action: ConfigAction::Show,
+    }) = cli.command
+    {
+      config.show(io::stdout(), &command, &matches)?;
+      return Ok(config.check()?);
+    }
+    config.check()?;
+
     let db_path = match cli.db_path {
       Some(path) => path,
       None => {
@@ -244,55 +603,180 @@ fn main() -> eyre::Result<()> {
       },
     };
 
-    if let Some(parent) = db_path.parent() {
-      std::fs::create_dir_all(parent)?;
-    }
-
-    let conn = rusqlite::Connection::open(&db_path)?;
-    let db = db::SqliteClipboardDb::new(conn, db_path)?;
+    let db = LazyDb {
+      path:           db_path.clone(),
+      protect_tagged: cli.protect_tagged,
+      db:             OnceCell::new(),
+    };
+    // Commands a running watch daemon serves go through it when it serves
+    // this database, so the daemon stays the only writer.
+    let pin = |input: &mut dyn io::Read, pinned| -> eyre::Result<usize> {
+      Ok(match ipc::Client::connect(&db_path) {
+        Some(mut daemon) => daemon.pin_input(input, pinned)?,
+        None => db.get()?.pin(input, pinned)?,
+      })
+    };
+    let delete = |input: &mut dyn io::Read| -> eyre::Result<usize> {
+      Ok(match ipc::Client::connect(&db_path) {
+        Some(mut daemon) => daemon.delete_input(input)?,
+        None => db.get()?.delete(input)?,
+      })
+    };
 
     match cli.command {
-      Some(Command::Store) => {
-        let state = env::var("STASH_CLIPBOARD_STATE").ok();
-        db.store(
+      Some(Command::Store { selection }) => {
+        db.get()?.store(
           io::stdin(),
           cli.max_dedupe_search,
           cli.max_items,
-          state,
+          ClipboardState::from_env(),
           #[cfg(feature = "use-toplevel")]
           &cli.excluded_apps,
           #[cfg(not(feature = "use-toplevel"))]
           &[],
           cli.min_size,
           cli.max_size,
+          selection,
         )?;
       },
       Some(Command::List {
         format,
         expired,
         reverse,
+        pinned_first,
+        selection,
+        tag,
+        search,
+        search_mode,
+        join_separator,
+        keys,
+        theme,
       }) => {
+        let query = search.unwrap_or_default();
+        let search = SearchQuery::parse(search_mode, &query)?;
+        let list_tsv = || -> eyre::Result<()> {
+          match ipc::Client::connect(&db_path) {
+            Some(mut daemon) => {
+              daemon.list_previews(
+                io::stdout(),
+                cli.preview_width,
+                expired,
+                reverse,
+                pinned_first,
+                selection,
+                &tag,
+                search.as_ref(),
+              )?;
+            },
+            None => {
+              db.get()?.list(
+                io::stdout(),
+                cli.preview_width,
+                expired,
+                reverse,
+                pinned_first,
+                selection,
+                &tag,
+                search.as_ref(),
+              )?;
+            },
+          }
+          Ok(())
+        };
         match format.as_deref() {
           Some("tsv") => {
-            db.list(io::stdout(), cli.preview_width, expired, reverse)?;
+            list_tsv()?;
           },
           Some("json") => {
-            println!("{}", db.list_json(expired, reverse)?);
+            println!(
+              "{}",
+              db.get()?.list_json(
+                expired,
+                reverse,
+                pinned_first,
+                selection,
+                &tag,
+                search.as_ref()
+              )?
+            );
           },
           Some(other) => {
             bail!("unsupported format: {other}");
           },
           None => {
             if std::io::stdout().is_terminal() {
-              db.list_tui(cli.preview_width, expired, reverse)?;
+              db.get()?.list_tui(
+                cli.preview_width,
+                expired,
+                reverse,
+                pinned_first,
+                selection,
+                &tag,
+                search_mode,
+                &query,
+                &join_separator,
+                &keys,
+                &theme,
+              )?;
             } else {
-              db.list(io::stdout(), cli.preview_width, expired, reverse)?;
+              list_tsv()?;
             }
           },
         }
       },
-      Some(Command::Decode { input }) => {
-        db.decode(io::stdin(), io::stdout(), input)?;
+      Some(Command::Pin { id }) => {
+        match id {
+          Some(id) => pin(&mut io::Cursor::new(id), true)?,
+          None => pin(&mut io::stdin(), true)?,
+        };
+      },
+      Some(Command::Unpin { id }) => {
+        match id {
+          Some(id) => pin(&mut io::Cursor::new(id), false)?,
+          None => pin(&mut io::stdin(), false)?,
+        };
+      },
+      Some(Command::Pick { print, launcher }) => {
+        if db
+          .get()?
+          .pick(io::stdout(), cli.preview_width, print, launcher)?
+          .is_none()
+        {
+          // Like fzf, so scripts can tell that nothing was picked.
+          std::process::exit(130);
+        }
+      },
+      Some(Command::Tag { id, tags, remove }) => {
+        db.get()?.tag(io::stdout(), id, &tags, remove)?;
+      },
+      Some(Command::Note { id, text, clear }) => {
+        let text = (!text.is_empty()).then(|| text.join(" "));
+        db.get()?.note(io::stdout(), id, text.as_deref(), clear)?;
+      },
+      Some(Command::Decode { input, copy }) => {
+        match (ipc::Client::connect(&db_path), copy) {
+          (Some(mut daemon), true) => daemon.copy_input(io::stdin(), input)?,
+          (Some(mut daemon), false) => {
+            daemon.decode_input(io::stdin(), io::stdout(), input)?;
+          },
+          (None, true) => db.get()?.decode_to_clipboard(io::stdin(), input)?,
+          (None, false) => {
+            db.get()?.decode(io::stdin(), io::stdout(), input)?;
+          },
+        }
+      },
+      Some(Command::Search {
+        query,
+        limit,
+        expired,
+      }) => {
+        db.get()?.search(
+          io::stdout(),
+          &query,
+          limit,
+          cli.preview_width,
+          expired,
+        )?;
       },
       Some(Command::Delete { arg, r#type, ask }) => {
         let mut should_proceed = true;
@@ -311,21 +795,21 @@ fn main() -> eyre::Result<()> {
                 .parse::<u64>()
                 .map_err(|_| eyre::eyre!("argument is not a valid id"))?;
               use std::io::Cursor;
-              db.delete(Cursor::new(format!("{id}\n")))?;
+              delete(&mut Cursor::new(format!("{id}\n")))?;
             },
             (Some(s), Some("query")) => {
-              db.query_delete(&s)?;
+              db.get()?.query_delete(&s)?;
             },
             (Some(s), None) => {
               if let Ok(id) = s.parse::<u64>() {
                 use std::io::Cursor;
-                db.delete(Cursor::new(format!("{id}\n")))?;
+                delete(&mut Cursor::new(format!("{id}\n")))?;
               } else {
-                db.query_delete(&s)?;
+                db.get()?.query_delete(&s)?;
               }
             },
             (None, _) => {
-              db.delete(io::stdin())?;
+              delete(&mut io::stdin())?;
             },
             (_, Some(_)) => {
               bail!("unknown type for --type. use \"id\" or \"query\"");
@@ -351,7 +835,7 @@ fn main() -> eyre::Result<()> {
             }
             if should_proceed {
               if expired {
-                match db.cleanup_expired() {
+                match db.get()?.cleanup_expired() {
                   Ok(count) => {
                     log::info!("wiped {count} expired entries");
                   },
@@ -360,7 +844,7 @@ fn main() -> eyre::Result<()> {
                   },
                 }
               } else {
-                db.wipe_db()?;
+                db.get()?.wipe_db()?;
               }
             }
           },
@@ -371,7 +855,7 @@ fn main() -> eyre::Result<()> {
                  a TTL?",
               );
             if should_proceed {
-              match db.expire_ttl_entries() {
+              match db.get()?.expire_ttl_entries() {
                 Ok(0) => {
                   println!("no entries with a TTL to expire");
                 },
@@ -387,16 +871,81 @@ fn main() -> eyre::Result<()> {
             }
           },
           DbAction::Vacuum => {
-            db.vacuum()?;
+            db.get()?.vacuum()?;
             log::info!("database optimized successfully");
           },
           DbAction::Stats => {
-            println!("{}", db.stats()?);
+            println!("{}", db.get()?.stats()?);
+          },
+          DbAction::Reindex => {
+            let indexed = db.get()?.reindex()?;
+            println!("indexed {indexed} entries for search");
+          },
+          #[cfg(feature = "encryption")]
+          DbAction::Rekey {
+            encrypt_existing,
+            to_plaintext,
+            new_passphrase_file,
+            new_recipients,
+            new_recipients_file,
+            ask,
+          } => {
+            use crate::db::rekey::RekeyMode;
+
+            let mode = if encrypt_existing {
+              RekeyMode::EncryptExisting
+            } else if to_plaintext {
+              RekeyMode::ToPlaintext
+            } else if let Some(path) = new_passphrase_file {
+              let passphrase = std::fs::read_to_string(&path)?;
+              RekeyMode::Passphrase(passphrase.trim().to_owned().into())
+            } else if let Some(path) = new_recipients_file {
+              RekeyMode::Recipients(std::fs::read_to_string(&path)?)
+            } else if let Some(list) = new_recipients {
+              RekeyMode::Recipients(list)
+            } else {
+              bail!("no rekey target given");
+            };
+
+            if (global_ask || ask)
+              && !confirm(
+                "Are you sure you want to rewrite every affected clipboard \
+                 entry?",
+              )
+            {
+              log::info!("db rekey command aborted by user");
+            } else {
+              let show_progress = io::stderr().is_terminal();
+              let rewritten = db.get()?.rekey(mode, |done, total| {
+                if show_progress {
+                  eprint!("\rrekeying entries: {done}/{total}");
+                  if done == total {
+                    eprintln!();
+                  }
+                }
+              })?;
+              println!("rewrote {rewritten} entries");
+              if !encrypt_existing {
+                println!(
+                  "update (or remove) your encryption settings to match \
+                   before storing new entries"
+                );
+              }
+            }
           },
         }
       },
 
-      Some(Command::Import { r#type, ask }) => {
+      Some(Command::Import {
+        r#type,
+        path,
+        #[cfg(feature = "encryption")]
+        passphrase_file,
+        dry_run,
+        preserve_order,
+        raw,
+        ask,
+      }) => {
         let mut should_proceed = true;
         if global_ask || ask {
           should_proceed = confirm(
@@ -409,35 +958,191 @@ fn main() -> eyre::Result<()> {
         }
         if should_proceed {
           let format = r#type.as_deref().unwrap_or("tsv");
+          if format != "tsv" && (dry_run || preserve_order || raw) {
+            bail!("--dry-run, --preserve-order and --raw only apply to TSV");
+          }
           match format {
             "tsv" => {
-              ImportCommand::import_tsv(&db, io::stdin(), cli.max_items)?;
+              let input: Box<dyn io::Read> = match path {
+                Some(path) => Box::new(std::fs::File::open(path)?),
+                None => Box::new(io::stdin()),
+              };
+              let limits = StoreLimits {
+                max_dedupe_search: cli.max_dedupe_search,
+                max_items:         cli.max_items,
+                min_size:          cli.min_size,
+                max_size:          cli.max_size,
+              };
+              let options = TsvOptions {
+                preserve_order,
+                raw,
+                dry_run,
+              };
+              let counts =
+                ImportCommand::import_tsv(db.get()?, input, limits, options)?;
+              println!(
+                "{} {} entries, {} already stored, {} skipped",
+                if dry_run { "would import" } else { "imported" },
+                counts.imported,
+                counts.already_stored,
+                counts.skipped
+              );
+            },
+            "archive" => {
+              let input: Box<dyn io::Read> = match path {
+                Some(path) => Box::new(std::fs::File::open(path)?),
+                None => Box::new(io::stdin()),
+              };
+              #[cfg(feature = "encryption")]
+              let result = match passphrase_file {
+                Some(path) => {
+                  let passphrase = std::fs::read_to_string(&path)?;
+                  db.get()?.import_encrypted_archive(
+                    input,
+                    passphrase.trim().to_owned().into(),
+                  )?
+                },
+                None => db.get()?.import_archive(input)?,
+              };
+              #[cfg(not(feature = "encryption"))]
+              let result = db.get()?.import_archive(input)?;
+              db.get()?.trim_db(cli.max_items)?;
+              println!(
+                "imported {} entries, merged {} already stored",
+                result.imported, result.merged
+              );
             },
             _ => {
-              bail!("unsupported import format: {format}");
+              let Some(format) = HistoryFormat::from_type(format) else {
+                bail!("unsupported import format: {format}");
+              };
+              let Some(path) = path.or_else(|| format.default_path()) else {
+                bail!("pass the path of the {format} history to import");
+              };
+              let counts = ImportCommand::import_history(
+                db.get()?,
+                format,
+                &path,
+                cli.max_items,
+                cli.max_size,
+              )?;
+              println!(
+                "imported {} entries from {format}, {} already stored, {} \
+                 skipped",
+                counts.imported, counts.already_stored, counts.skipped
+              );
             },
           }
         }
       },
+      Some(Command::Export {
+        path,
+        #[cfg(feature = "encryption")]
+        encrypt,
+        #[cfg(feature = "encryption")]
+        passphrase_file,
+        #[cfg(feature = "encryption")]
+        recipients,
+        #[cfg(feature = "encryption")]
+        recipients_file,
+      }) => {
+        let out: Box<dyn io::Write> = match path {
+          Some(path) => Box::new(std::fs::File::create(path)?),
+          None => Box::new(io::stdout().lock()),
+        };
+        let out = io::BufWriter::new(out);
+
+        #[cfg(feature = "encryption")]
+        let key = {
+          use crate::db::archive::ArchiveKey;
+
+          if let Some(path) = passphrase_file {
+            let passphrase = std::fs::read_to_string(&path)?;
+            Some(ArchiveKey::Passphrase(passphrase.trim().to_owned().into()))
+          } else if let Some(path) = recipients_file {
+            Some(ArchiveKey::Recipients(std::fs::read_to_string(&path)?))
+          } else if let Some(list) = recipients {
+            Some(ArchiveKey::Recipients(list))
+          } else {
+            encrypt.then_some(ArchiveKey::Configured)
+          }
+        };
+        #[cfg(feature = "encryption")]
+        let export = match key {
+          Some(key) => db.get()?.export_encrypted_archive(out, key)?,
+          None => db.get()?.export_archive(out)?,
+        };
+        #[cfg(not(feature = "encryption"))]
+        let export = db.get()?.export_archive(out)?;
+        log::info!("exported {} entries", export.exported);
+        if !export.skipped.is_empty() {
+          let ids: Vec<String> =
+            export.skipped.iter().map(ToString::to_string).collect();
+          bail!(
+            "left out {} entries that could not be decrypted: {}",
+            export.skipped.len(),
+            ids.join(", ")
+          );
+        }
+      },
       Some(Command::Watch {
         expire_after,
         mime_type,
         persist,
+        max_extra_mime_size,
+        selection,
       }) => {
-        db.watch(
-          cli.max_dedupe_search,
-          cli.max_items,
-          #[cfg(feature = "use-toplevel")]
-          &cli.excluded_apps,
-          #[cfg(not(feature = "use-toplevel"))]
-          &[],
-          expire_after,
-          &mime_type,
-          cli.min_size,
-          cli.max_size,
-          persist,
-        )
-        .await;
+        db.get()?
+          .watch(
+            cli.max_dedupe_search,
+            cli.max_items,
+            #[cfg(feature = "use-toplevel")]
+            &cli.excluded_apps,
+            #[cfg(not(feature = "use-toplevel"))]
+            &[],
+            expire_after,
+            &mime_type,
+            cli.min_size,
+            cli.max_size,
+            persist,
+            max_extra_mime_size,
+            selection,
+          )
+          .await;
+      },
+      // Handled before opening the database.
+      Some(Command::Config { .. }) => {},
+      Some(Command::Snippet { action }) => {
+        match action {
+          SnippetAction::Add { name, tag, force } => {
+            db.get()?.snippet_add(&name, io::stdin(), &tag, force)?;
+          },
+          SnippetAction::List { tag } => {
+            db.get()?.snippet_list(
+              io::stdout(),
+              tag.as_deref(),
+              cli.preview_width,
+            )?;
+          },
+          SnippetAction::Copy { name, no_expand } => {
+            db.get()?.snippet_copy(&name, !no_expand)?;
+          },
+          SnippetAction::Rm { name, ask } => {
+            if (global_ask || ask)
+              && !confirm(&format!("Are you sure you want to delete `{name}`?"))
+            {
+              log::info!("snippet removal aborted by user");
+            } else {
+              db.get()?.remove_snippet(&name)?;
+            }
+          },
+        }
+      },
+      Some(Command::Status) => {
+        require_daemon(&db_path)?.daemon_status(io::stdout())?;
+      },
+      Some(Command::Subscribe) => {
+        require_daemon(&db_path)?.print_new_entries(io::stdout())?;
       },
 
       None => {
@@ -23,6 +23,8 @@ use wl_clipboard_rs::paste::{
   get_mime_types,
 };
 
+use crate::clipboard::state::{self, ClipboardState};
+
 // Watch mode timing constants
 const WATCH_POLL_INTERVAL_MS: u64 = 500;
 const WATCH_DEBOUNCE_INTERVAL_MS: u64 = 1000;
@@ -255,21 +257,32 @@ fn validate_command_name(cmd: &str) -> Result<()> {
   Ok(())
 }
 
-/// Set environment variable safely with validation
-fn set_clipboard_state_env(has_content: bool) -> Result<()> {
-  let value = if has_content { "data" } else { "nil" };
-
-  // Validate the environment variable value
-  if !matches!(value, "data" | "nil") {
-    bail!("invalid clipboard state value: {value}");
+/// State of the clipboard holding `content`, from the markers in its offer.
+fn clipboard_state(
+  content: &[u8],
+  clipboard: PasteClipboardType,
+  seat: PasteSeat,
+) -> ClipboardState {
+  if content.is_empty() {
+    return ClipboardState::Nil;
   }
+  let offered: Vec<String> = get_mime_types(clipboard, seat)
+    .map(|types| types.into_iter().collect())
+    .unwrap_or_default();
+  ClipboardState::of_offer(&offered, |hint| {
+    state::read_hint(clipboard, seat, hint)
+  })
+}
 
+/// Pass `state` to the watch command, under wl-clipboard's `CLIPBOARD_STATE`
+/// as well as `STASH_CLIPBOARD_STATE`.
+fn set_clipboard_state_env(state: ClipboardState) {
   // SAFETY: watch mode is single-threaded here and mutates the environment
   // immediately before spawning the child command.
   unsafe {
-    std::env::set_var("STASH_CLIPBOARD_STATE", value);
+    std::env::set_var("CLIPBOARD_STATE", state.as_str());
+    std::env::set_var("STASH_CLIPBOARD_STATE", state.as_str());
   }
-  Ok(())
 }
 
 fn execute_watch_command(
@@ -315,8 +328,7 @@ fn execute_watch_command(
         content.extend_from_slice(&temp_buffer[..bytes_read]);
       }
 
-      // Set environment variable safely
-      set_clipboard_state_env(!content.is_empty())?;
+      set_clipboard_state_env(clipboard_state(&content, clipboard, seat));
 
       // Spawn the command with the content as stdin
       cmd.stdin(Stdio::piped());
@@ -342,8 +354,7 @@ fn execute_watch_command(
       }
     },
     Err(PasteError::ClipboardEmpty) => {
-      // Set environment variable safely
-      set_clipboard_state_env(false)?;
+      set_clipboard_state_env(ClipboardState::Nil);
 
       // Run command with /dev/null as stdin
       cmd.stdin(Stdio::null());
@@ -0,0 +1,425 @@
+//! Ordered store rules, read from `[[rules]]` tables in the config file.
+//!
+//! A rule matches entries on the focused application, MIME type, size and
+//! content, and says what to do with them. The first rule matching an entry
+//! decides; entries that match no rule are stored as usual.
+//!
+//! ```toml
+//! [[rules]]
+//! name = "password manager"
+//! app = "org.keepassxc.*"
+//! action = "drop"
+//!
+//! [[rules]]
+//! name = "terminal"
+//! app = ["foot", "kitty", "Alacritty"]
+//! ttl = "1h"
+//!
+//! [[rules]]
+//! name = "images"
+//! mime = "image/*"
+//! ```
+
+use std::{cell::OnceCell, time::Duration};
+
+use regex::Regex;
+use serde::Deserialize;
+use toml::Value;
+
+/// Text that replaces the matches of a `redact` rule by default.
+const DEFAULT_REPLACEMENT: &str = "[redacted]";
+
+/// What happens to an entry a rule matches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+  /// Store the entry as usual.
+  #[default]
+  Store,
+  /// Do not store the entry.
+  Drop,
+  /// Store the entry encrypted: to the rule's recipients if it has any,
+  /// otherwise with the configured key. Without either the entry is not
+  /// stored, so it never reaches the database in plaintext.
+  Encrypt,
+  /// Store the entry with the matches of the rule's regex replaced.
+  Redact,
+}
+
+/// One `[[rules]]` table.
+#[derive(Debug)]
+pub struct Rule {
+  pub name:    String,
+  pub action:  Action,
+  /// How long entries stored by this rule are kept. `None` keeps them until
+  /// they are trimmed, even when `stash watch --expire-after` is given.
+  pub ttl:     Option<Duration>,
+  apps:        Vec<String>,
+  mimes:       Vec<String>,
+  min_size:    Option<usize>,
+  max_size:    Option<usize>,
+  regex:       Option<Regex>,
+  replacement: Option<String>,
+  /// Comma-separated age or SSH recipients of an `encrypt` rule.
+  #[cfg(feature = "encryption")]
+  recipients:  Option<String>,
+}
+
+/// A rule as written in the config file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRule {
+  name:        Option<String>,
+  app:         Option<Patterns>,
+  mime:        Option<Patterns>,
+  min_size:    Option<usize>,
+  max_size:    Option<usize>,
+  regex:       Option<String>,
+  #[serde(default)]
+  action:      Action,
+  ttl:         Option<String>,
+  replacement: Option<String>,
+  recipients:  Option<Patterns>,
+}
+
+/// A single pattern or a list of them.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Patterns {
+  One(String),
+  Many(Vec<String>),
+}
+
+impl Patterns {
+  fn into_vec(patterns: Option<Self>) -> Vec<String> {
+    match patterns {
+      None => Vec::new(),
+      Some(Self::One(pattern)) => vec![pattern],
+      Some(Self::Many(patterns)) => patterns,
+    }
+  }
+}
+
+impl Rule {
+  /// Parse the value of the top-level `rules` key, keeping rule order.
+  pub fn parse_all(value: &Value) -> Result<Vec<Self>, String> {
+    let Value::Array(tables) = value else {
+      return Err("`rules` must be an array of tables, see `[[rules]]`".into());
+    };
+
+    let mut rules: Vec<Self> = Vec::with_capacity(tables.len());
+    for (index, table) in tables.iter().enumerate() {
+      let raw: RawRule = table
+        .clone()
+        .try_into()
+        .map_err(|e| format!("rule {}: {}", index + 1, e.message()))?;
+      let rule = Self::from_raw(raw, index)?;
+      if rules.iter().any(|other| other.name == rule.name) {
+        return Err(format!("rule `{}` is defined twice", rule.name));
+      }
+      rules.push(rule);
+    }
+    Ok(rules)
+  }
+
+  fn from_raw(raw: RawRule, index: usize) -> Result<Self, String> {
+    let name = raw.name.unwrap_or_else(|| format!("rule {}", index + 1));
+    let invalid = |e: String| format!("rule `{name}`: {e}");
+
+    let regex = raw
+      .regex
+      .map(|pattern| Regex::new(&pattern))
+      .transpose()
+      .map_err(|e| invalid(format!("invalid regex: {e}")))?;
+    let ttl = raw
+      .ttl
+      .map(|ttl| humantime::parse_duration(&ttl))
+      .transpose()
+      .map_err(|e| invalid(format!("invalid ttl: {e}")))?;
+
+    match raw.action {
+      Action::Redact if regex.is_none() => {
+        return Err(invalid("`redact` needs a `regex` to replace".into()));
+      },
+      Action::Drop if ttl.is_some() => {
+        return Err(invalid("`ttl` has no effect on dropped entries".into()));
+      },
+      action if action != Action::Redact && raw.replacement.is_some() => {
+        return Err(invalid("`replacement` is only used by `redact`".into()));
+      },
+      action if action != Action::Encrypt && raw.recipients.is_some() => {
+        return Err(invalid("`recipients` are only used by `encrypt`".into()));
+      },
+      _ => {},
+    }
+
+    let recipients = raw
+      .recipients
+      .map(|recipients| Patterns::into_vec(Some(recipients)).join(","));
+    if let Some(recipients) = &recipients {
+      #[cfg(feature = "encryption")]
+      crate::db::parse_recipients(recipients)
+        .map_err(|e| invalid(format!("invalid recipients: {e}")))?;
+      #[cfg(not(feature = "encryption"))]
+      return Err(invalid(format!(
+        "`recipients` ({recipients}) need the `encryption` feature"
+      )));
+    }
+
+    Ok(Self {
+      action: raw.action,
+      ttl,
+      apps: Patterns::into_vec(raw.app),
+      mimes: Patterns::into_vec(raw.mime),
+      min_size: raw.min_size,
+      max_size: raw.max_size,
+      regex,
+      replacement: raw.replacement,
+      #[cfg(feature = "encryption")]
+      recipients,
+      name,
+    })
+  }
+
+  /// Whether this rule applies to an entry. `app` is only called when the
+  /// rule matches on the focused application and nothing else ruled it out,
+  /// since looking that up takes a compositor round trip.
+  fn matches<'a>(
+    &self,
+    mime: Option<&str>,
+    size: usize,
+    text: Option<&str>,
+    app: impl FnOnce() -> Option<&'a str>,
+  ) -> bool {
+    if self.min_size.is_some_and(|min| size < min)
+      || self.max_size.is_some_and(|max| size > max)
+    {
+      return false;
+    }
+
+    if !self.mimes.is_empty()
+      && !mime.is_some_and(|mime| {
+        self.mimes.iter().any(|pattern| mime_matches(mime, pattern))
+      })
+    {
+      return false;
+    }
+
+    if let Some(regex) = &self.regex
+      && !text.is_some_and(|text| regex.is_match(text))
+    {
+      return false;
+    }
+
+    self.apps.is_empty()
+      || app().is_some_and(|app| crate::db::app_matches(app, &self.apps))
+  }
+
+  /// The recipients an `encrypt` rule encrypts entries to, instead of the
+  /// configured key.
+  #[cfg(feature = "encryption")]
+  pub fn recipients(&self) -> Option<&str> {
+    self.recipients.as_deref()
+  }
+
+  /// Replace the matches of the rule's regex in `text`.
+  pub fn redact(&self, text: &str) -> String {
+    let replacement =
+      self.replacement.as_deref().unwrap_or(DEFAULT_REPLACEMENT);
+    match &self.regex {
+      Some(regex) => regex.replace_all(text, replacement).into_owned(),
+      None => text.to_owned(),
+    }
+  }
+}
+
+/// The first of `rules` matching an entry, if any. `focused_app` is called at
+/// most once.
+pub fn first_match<'a>(
+  rules: &'a [Rule],
+  mime: Option<&str>,
+  contents: &[u8],
+  focused_app: impl Fn() -> Option<String>,
+) -> Option<&'a Rule> {
+  let app = OnceCell::new();
+  let text = std::str::from_utf8(contents).ok();
+  // `redact` rules also look for their regex in contents that are not quite
+  // UTF-8, which the store drops since it cannot redact them.
+  let lossy = String::from_utf8_lossy(contents);
+  rules.iter().find(|rule| {
+    let text = if rule.action == Action::Redact {
+      Some(lossy.as_ref())
+    } else {
+      text
+    };
+    rule.matches(mime, contents.len(), text, || {
+      app.get_or_init(&focused_app).as_deref()
+    })
+  })
+}
+
+/// Match a MIME type against `type/subtype`, `type/*` or `*`, ignoring case
+/// and parameters such as `;charset=utf-8`.
+fn mime_matches(mime: &str, pattern: &str) -> bool {
+  if pattern == "*" {
+    return true;
+  }
+  let mime = mime.split(';').next().unwrap_or(mime).trim();
+  match pattern.strip_suffix("/*") {
+    Some(kind) => {
+      mime
+        .split_once('/')
+        .is_some_and(|(mime_kind, _)| mime_kind.eq_ignore_ascii_case(kind))
+    },
+    None => mime.eq_ignore_ascii_case(pattern),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(toml: &str) -> Result<Vec<Rule>, String> {
+    let table: toml::Table = toml.parse().expect("valid toml");
+    Rule::parse_all(&table["rules"])
+  }
+
+  #[test]
+  fn test_first_matching_rule_decides() {
+    let rules = parse(
+      r#"
+      [[rules]]
+      name = "keepass"
+      app = "org.keepassxc.*"
+      action = "drop"
+
+      [[rules]]
+      name = "terminal"
+      app = ["foot", "kitty"]
+      ttl = "1h"
+
+      [[rules]]
+      name = "images"
+      mime = "image/*"
+      "#,
+    )
+    .expect("valid rules");
+
+    let name = |mime, contents: &[u8], app: Option<&str>| {
+      first_match(&rules, mime, contents, || app.map(str::to_owned))
+        .map(|rule| rule.name.as_str())
+    };
+    assert_eq!(
+      name(
+        Some("text/plain"),
+        b"hunter2",
+        Some("org.keepassxc.KeePassXC")
+      ),
+      Some("keepass")
+    );
+    assert_eq!(
+      name(Some("text/plain"), b"ls -la", Some("Kitty")),
+      Some("terminal")
+    );
+    assert_eq!(
+      name(Some("image/png"), b"\x89PNG", Some("kitty")),
+      Some("terminal")
+    );
+    assert_eq!(name(Some("image/png"), b"\x89PNG", None), Some("images"));
+    assert_eq!(name(Some("text/plain"), b"hello", Some("firefox")), None);
+
+    let terminal = &rules[1];
+    assert_eq!(terminal.action, Action::Store);
+    assert_eq!(terminal.ttl, Some(Duration::from_secs(3600)));
+  }
+
+  #[test]
+  fn test_focused_app_looked_up_only_when_needed() {
+    let rules = parse(
+      r#"
+      [[rules]]
+      mime = "image/*"
+      app = "gimp"
+
+      [[rules]]
+      max_size = 4
+      app = "foot"
+      "#,
+    )
+    .expect("valid rules");
+
+    let lookups = std::cell::Cell::new(0);
+    let app = || {
+      lookups.set(lookups.get() + 1);
+      Some("foot".to_owned())
+    };
+    assert!(
+      first_match(&rules, Some("text/plain"), b"too long", app).is_none()
+    );
+    assert_eq!(lookups.get(), 0);
+
+    let rule = first_match(&rules, Some("image/png"), b"png", app);
+    assert_eq!(rule.map(|rule| rule.name.as_str()), Some("rule 2"));
+    assert_eq!(lookups.get(), 1);
+  }
+
+  #[test]
+  fn test_regex_and_redact() {
+    let rules = parse(
+      r#"
+      [[rules]]
+      name = "tokens"
+      regex = "ghp_[A-Za-z0-9]+"
+      action = "redact"
+      "#,
+    )
+    .expect("valid rules");
+
+    let text = b"token ghp_abc123 here";
+    let rule = first_match(&rules, None, text, || None).expect("rule matches");
+    assert_eq!(
+      rule.redact("token ghp_abc123 here"),
+      "token [redacted] here"
+    );
+    assert!(first_match(&rules, None, b"no token", || None).is_none());
+    assert!(first_match(&rules, None, b"\xffghp_abc", || None).is_some());
+  }
+
+  #[test]
+  fn test_invalid_rules_are_rejected() {
+    let error = |toml| parse(toml).expect_err("invalid rules");
+    assert!(
+      error("[[rules]]\naction = \"redact\"").contains("needs a `regex`")
+    );
+    assert!(
+      error("[[rules]]\naction = \"drop\"\nttl = \"1h\"").contains("`ttl`")
+    );
+    assert!(error("[[rules]]\nregex = \"(\"").contains("invalid regex"));
+    assert!(error("[[rules]]\nttl = \"soon\"").contains("invalid ttl"));
+    assert!(error("[[rules]]\naction = \"keep\"").contains("rule 1"));
+    assert!(error("[[rules]]\nmime_type = \"text/plain\"").contains("rule 1"));
+    assert!(
+      error("[[rules]]\nname = \"a\"\n[[rules]]\nname = \"a\"")
+        .contains("defined twice")
+    );
+    assert!(error("rules = 1").contains("array of tables"));
+    assert!(
+      error("[[rules]]\nrecipients = \"age1abc\"").contains("`recipients`")
+    );
+    #[cfg(feature = "encryption")]
+    assert!(
+      error("[[rules]]\naction = \"encrypt\"\nrecipients = \"age1abc\"")
+        .contains("invalid recipients")
+    );
+  }
+
+  #[test]
+  fn test_mime_patterns() {
+    assert!(mime_matches("image/png", "image/*"));
+    assert!(mime_matches("text/plain;charset=utf-8", "text/plain"));
+    assert!(mime_matches("TEXT/HTML", "text/*"));
+    assert!(mime_matches("application/json", "*"));
+    assert!(!mime_matches("text/plain", "image/*"));
+    assert!(!mime_matches("text/plain", "text/html"));
+  }
+}
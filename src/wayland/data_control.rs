@@ -0,0 +1,204 @@
+use log::{debug, warn};
+use smol::channel::{self, Receiver, Sender};
+use wayland_client::{
+  Connection as WaylandConnection,
+  Dispatch,
+  Proxy,
+  QueueHandle,
+  globals::{GlobalListContents, registry_queue_init},
+  protocol::{wl_registry, wl_seat::WlSeat},
+};
+use wayland_protocols_wlr::data_control::v1::client::{
+  zwlr_data_control_device_v1::{self, ZwlrDataControlDeviceV1},
+  zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+  zwlr_data_control_offer_v1::{self, ZwlrDataControlOfferV1},
+};
+
+/// Subscribe to clipboard selection changes via `zwlr_data_control_v1`.
+///
+/// Binds the data-control manager and the first advertised seat, then runs
+/// the Wayland event loop on a background thread. Every time the compositor
+/// announces a new (non-empty) selection, a unit value is sent on the returned
+/// channel. With `primary` set, changes to the primary selection are reported
+/// the same way. The channel holds at most one pending notification, so bursts
+/// of selection changes collapse into a single wakeup for the reader.
+///
+/// # Returns
+///
+/// `None` when there is no compositor connection or it does not implement the
+/// protocol, in which case the caller should fall back to polling. The
+/// channel is closed when the event loop exits (e.g. the compositor went
+/// away or revoked the device).
+pub fn watch_selection(primary: bool) -> Option<Receiver<()>> {
+  let conn = match WaylandConnection::connect_to_env() {
+    Ok(conn) => conn,
+    Err(e) => {
+      debug!("failed to connect to wayland: {e}");
+      return None;
+    },
+  };
+
+  let (globals, mut event_queue) =
+    match registry_queue_init::<SelectionState>(&conn) {
+      Ok(init) => init,
+      Err(e) => {
+        debug!("failed to initialize wayland registry: {e}");
+        return None;
+      },
+    };
+  let qh = event_queue.handle();
+
+  let manager: ZwlrDataControlManagerV1 = match globals.bind(&qh, 1..=2, ()) {
+    Ok(manager) => manager,
+    Err(e) => {
+      debug!("zwlr_data_control_manager_v1 unavailable: {e}");
+      return None;
+    },
+  };
+  let seat: WlSeat = match globals.bind(&qh, 1..=1, ()) {
+    Ok(seat) => seat,
+    Err(e) => {
+      debug!("no wl_seat to watch: {e}");
+      return None;
+    },
+  };
+  let _device = manager.get_data_device(&seat, &qh, ());
+
+  let (tx, rx) = channel::bounded(1);
+  let mut state = SelectionState {
+    tx,
+    selection: None,
+    watch_primary: primary,
+    primary: None,
+    finished: false,
+  };
+
+  std::thread::spawn(move || {
+    while !state.finished {
+      if let Err(e) = event_queue.blocking_dispatch(&mut state) {
+        warn!("clipboard selection event loop stopped: {e}");
+        break;
+      }
+    }
+    // Dropping `state` closes the channel, telling the reader to fall back.
+  });
+
+  Some(rx)
+}
+
+struct SelectionState {
+  tx:            Sender<()>,
+  /// Offer backing the current regular selection. Replaced (and destroyed)
+  /// whenever the compositor announces a new one.
+  selection:     Option<ZwlrDataControlOfferV1>,
+  /// Whether primary selection changes wake the reader.
+  watch_primary: bool,
+  /// Offer backing the current primary selection, when it is watched.
+  primary:       Option<ZwlrDataControlOfferV1>,
+  /// Set once the compositor has invalidated our data device.
+  finished:      bool,
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for SelectionState {
+  fn event(
+    _state: &mut Self,
+    _registry: &wl_registry::WlRegistry,
+    _event: wl_registry::Event,
+    _data: &GlobalListContents,
+    _conn: &WaylandConnection,
+    _qh: &QueueHandle<Self>,
+  ) {
+  }
+}
+
+impl Dispatch<WlSeat, ()> for SelectionState {
+  fn event(
+    _state: &mut Self,
+    _seat: &WlSeat,
+    _event: <WlSeat as Proxy>::Event,
+    _data: &(),
+    _conn: &WaylandConnection,
+    _qh: &QueueHandle<Self>,
+  ) {
+  }
+}
+
+impl Dispatch<ZwlrDataControlManagerV1, ()> for SelectionState {
+  fn event(
+    _state: &mut Self,
+    _manager: &ZwlrDataControlManagerV1,
+    _event: <ZwlrDataControlManagerV1 as Proxy>::Event,
+    _data: &(),
+    _conn: &WaylandConnection,
+    _qh: &QueueHandle<Self>,
+  ) {
+  }
+}
+
+impl Dispatch<ZwlrDataControlDeviceV1, ()> for SelectionState {
+  fn event(
+    state: &mut Self,
+    _device: &ZwlrDataControlDeviceV1,
+    event: zwlr_data_control_device_v1::Event,
+    _data: &(),
+    _conn: &WaylandConnection,
+    _qh: &QueueHandle<Self>,
+  ) {
+    match event {
+      zwlr_data_control_device_v1::Event::Selection { id } => {
+        if let Some(old) = state.selection.take() {
+          old.destroy();
+        }
+        if id.is_some() {
+          debug!("clipboard selection changed");
+          // A full channel already has a wakeup pending for this change.
+          let _ = state.tx.try_send(());
+        }
+        state.selection = id;
+      },
+      zwlr_data_control_device_v1::Event::PrimarySelection { id }
+        if state.watch_primary =>
+      {
+        if let Some(old) = state.primary.take() {
+          old.destroy();
+        }
+        if id.is_some() {
+          debug!("primary selection changed");
+          let _ = state.tx.try_send(());
+        }
+        state.primary = id;
+      },
+      zwlr_data_control_device_v1::Event::PrimarySelection {
+        id: Some(offer),
+      } => {
+        // Primary selection is not watched; release its offers right away.
+        offer.destroy();
+      },
+      zwlr_data_control_device_v1::Event::Finished => {
+        debug!("data control device finished");
+        state.finished = true;
+      },
+      _ => {},
+    }
+  }
+
+  fn event_created_child(
+    _opcode: u16,
+    qhandle: &QueueHandle<Self>,
+  ) -> std::sync::Arc<dyn wayland_client::backend::ObjectData> {
+    qhandle.make_data::<ZwlrDataControlOfferV1, ()>(())
+  }
+}
+
+impl Dispatch<ZwlrDataControlOfferV1, ()> for SelectionState {
+  fn event(
+    _state: &mut Self,
+    _offer: &ZwlrDataControlOfferV1,
+    _event: zwlr_data_control_offer_v1::Event,
+    _data: &(),
+    _conn: &WaylandConnection,
+    _qh: &QueueHandle<Self>,
+  ) {
+    // MIME types are negotiated by the reader when it fetches the contents.
+  }
+}
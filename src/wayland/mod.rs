@@ -1,3 +1,5 @@
+pub mod data_control;
+
 use std::{
   collections::HashMap,
   sync::{Arc, LazyLock, Mutex},